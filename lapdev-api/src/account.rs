@@ -13,14 +13,18 @@ use chrono::Utc;
 use hyper::StatusCode;
 use lapdev_common::{
     console::{MeUser, Organization},
-    NewSshKey, SshKey, UserRole,
+    url_host, AuthProvider, GitToken, NewGitToken, NewSshKey, SshKey, UserRole,
 };
+use lapdev_conductor::cipher::{KeyCipher, ENCRYPTED_PREFIX};
 use lapdev_db::{api::DbApi, entities};
 use lapdev_rpc::error::ApiError;
 use russh_keys::PublicKeyBase64;
-use sea_orm::{prelude::Uuid, ActiveModelTrait, ActiveValue};
+use sea_orm::{
+    prelude::Uuid, ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
 
-use crate::state::CoreState;
+use crate::{organization::org_activity_ports, state::CoreState};
 
 pub async fn me(
     State(state): State<CoreState>,
@@ -31,12 +35,15 @@ pub async fn me(
     let all_orgs = all_user_organizations(&user, &state.db)
         .await
         .unwrap_or_default();
+    let activity_ports = org_activity_ports(&org);
     Ok(Json(MeUser {
         login: user.provider_login,
         avatar_url: user.avatar_url,
         email: user.email,
         name: user.name,
         cluster_admin: user.cluster_admin,
+        local: user.provider == AuthProvider::Local.to_string(),
+        totp_enabled: user.totp_enabled,
         organization: Organization {
             id: org.id,
             name: org.name,
@@ -45,10 +52,14 @@ pub async fn me(
             auto_stop: org.auto_stop,
             allow_workspace_change_auto_start: org.allow_workspace_change_auto_start,
             allow_workspace_change_auto_stop: org.allow_workspace_change_auto_stop,
+            ssh_session_recording: org.ssh_session_recording,
+            allow_ssh_agent_forwarding: org.allow_ssh_agent_forwarding,
+            activity_ports,
         },
         all_organizations: all_orgs
             .into_iter()
             .filter_map(|(org, member)| {
+                let activity_ports = org_activity_ports(&org);
                 Some(Organization {
                     id: org.id,
                     name: org.name,
@@ -57,6 +68,9 @@ pub async fn me(
                     auto_stop: org.auto_stop,
                     allow_workspace_change_auto_start: org.allow_workspace_change_auto_start,
                     allow_workspace_change_auto_stop: org.allow_workspace_change_auto_stop,
+                    ssh_session_recording: org.ssh_session_recording,
+                    allow_ssh_agent_forwarding: org.allow_ssh_agent_forwarding,
+                    activity_ports,
                 })
             })
             .collect(),
@@ -242,3 +256,196 @@ pub async fn delete_ssh_key(
 
     Ok(StatusCode::NO_CONTENT.into_response())
 }
+
+pub async fn create_git_token(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+    Json(git_token): Json<NewGitToken>,
+) -> Result<Response, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    let host = git_token.host.trim();
+    // the host can be given as a url as well
+    let host = url_host(host)
+        .unwrap_or(host)
+        .trim_end_matches('/')
+        .to_lowercase();
+    if host.is_empty() || host.contains(['/', ' ']) {
+        return Err(ApiError::InvalidRequest("git host is invalid".to_string()));
+    }
+    let username = git_token.username.trim();
+    if username.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "git username can't be empty".to_string(),
+        ));
+    }
+    let token = git_token.token.trim();
+    if token.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "git token can't be empty".to_string(),
+        ));
+    }
+
+    let now = Utc::now();
+    let txn = state.db.conn.begin().await?;
+    // a new token for the same host replaces the old one
+    if let Some(existing) = state.db.get_user_git_token(user.id, &host).await? {
+        entities::user_git_token::ActiveModel {
+            id: ActiveValue::Set(existing.id),
+            deleted_at: ActiveValue::Set(Some(now.into())),
+            ..Default::default()
+        }
+        .update(&txn)
+        .await?;
+    }
+    let model = entities::user_git_token::ActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        created_at: ActiveValue::Set(now.into()),
+        deleted_at: ActiveValue::Set(None),
+        user_id: ActiveValue::Set(user.id),
+        host: ActiveValue::Set(host),
+        username: ActiveValue::Set(username.to_string()),
+        token: ActiveValue::Set(state.key_cipher.encrypt(token)?),
+    }
+    .insert(&txn)
+    .await?;
+    txn.commit().await?;
+
+    Ok(Json(GitToken {
+        id: model.id,
+        host: model.host,
+        username: model.username,
+        created_at: model.created_at,
+    })
+    .into_response())
+}
+
+pub async fn all_git_tokens(
+    State(state): State<CoreState>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Response, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    let tokens = state.db.get_all_user_git_tokens(user.id).await?;
+    Ok(Json(
+        tokens
+            .into_iter()
+            .map(|token| GitToken {
+                id: token.id,
+                host: token.host,
+                username: token.username,
+                created_at: token.created_at,
+            })
+            .collect::<Vec<_>>(),
+    )
+    .into_response())
+}
+
+pub async fn delete_git_token(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path(token_id): Path<Uuid>,
+    State(state): State<CoreState>,
+) -> Result<Response, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    let token = state.db.get_git_token(token_id).await?;
+    if token.user_id != user.id {
+        return Err(ApiError::Unauthorized);
+    }
+
+    entities::user_git_token::ActiveModel {
+        id: ActiveValue::Set(token.id),
+        deleted_at: ActiveValue::Set(Some(Utc::now().into())),
+        ..Default::default()
+    }
+    .update(&state.db.conn)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Encrypt the git tokens that were stored in plain text before the tokens were encrypted
+pub async fn encrypt_stored_git_tokens(db: &DbApi, cipher: &KeyCipher) -> anyhow::Result<()> {
+    let tokens = entities::user_git_token::Entity::find()
+        .filter(entities::user_git_token::Column::Token.not_like(format!("{ENCRYPTED_PREFIX}%")))
+        .all(&db.conn)
+        .await?;
+    for token in &tokens {
+        entities::user_git_token::ActiveModel {
+            id: ActiveValue::Set(token.id),
+            token: ActiveValue::Set(cipher.encrypt(&token.token)?),
+            ..Default::default()
+        }
+        .update(&db.conn)
+        .await?;
+    }
+    if !tokens.is_empty() {
+        tracing::info!("encrypted {} stored git tokens", tokens.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lapdev_common::ProviderUser;
+    use lapdev_conductor::cipher::is_encrypted;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_encrypt_stored_git_tokens() {
+        let db = lapdev_db::tests::prepare_db().await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let cipher = KeyCipher::load(&dir.path().join("secret.key"), true)
+            .await
+            .unwrap();
+
+        let txn = db.conn.begin().await.unwrap();
+        let user = db
+            .create_new_user(
+                &txn,
+                &AuthProvider::Local.to_string(),
+                ProviderUser {
+                    id: 0,
+                    subject: Some("user".to_string()),
+                    login: "user".to_string(),
+                    name: None,
+                    email: None,
+                    avatar_url: None,
+                },
+                String::new(),
+            )
+            .await
+            .unwrap();
+        txn.commit().await.unwrap();
+
+        let encrypted = cipher.encrypt("token2").unwrap();
+        for (host, token) in [("github.com", "token1"), ("gitlab.com", encrypted.as_str())] {
+            entities::user_git_token::ActiveModel {
+                id: ActiveValue::Set(Uuid::new_v4()),
+                created_at: ActiveValue::Set(Utc::now().into()),
+                deleted_at: ActiveValue::Set(None),
+                user_id: ActiveValue::Set(user.id),
+                host: ActiveValue::Set(host.to_string()),
+                username: ActiveValue::Set("user".to_string()),
+                token: ActiveValue::Set(token.to_string()),
+            }
+            .insert(&db.conn)
+            .await
+            .unwrap();
+        }
+
+        encrypt_stored_git_tokens(&db, &cipher).await.unwrap();
+        let token = db
+            .get_user_git_token(user.id, "github.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(is_encrypted(&token.token));
+        assert_eq!(cipher.decrypt(&token.token).unwrap(), "token1");
+        // the encrypted ones are kept
+        let token = db
+            .get_user_git_token(user.id, "gitlab.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(token.token, encrypted);
+    }
+}
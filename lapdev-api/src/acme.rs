@@ -0,0 +1,784 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt,
+    NewAccount, NewOrder, OrderStatus,
+};
+use lapdev_common::{AcmeDnsProvider, AcmeSettings, LAPDEV_ACME, PATH_ROUTING_SUBDOMAIN};
+use lapdev_conductor::cipher::KeyCipher;
+use lapdev_db::api::DbApi;
+use rcgen::{CertificateParams, DistinguishedName};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::cert::{cert_info, save_certs, stored_certs};
+
+/// The certificates issued through ACME, in the same format as `LAPDEV_CERTS`
+pub const LAPDEV_ACME_CERTS: &str = "lapdev-acme-certs";
+const LAPDEV_ACME_ACCOUNT: &str = "lapdev-acme-account";
+/// The key authorizations of the pending http-01 challenges by their tokens,
+/// so that every api server can answer the validation requests
+pub const LAPDEV_ACME_HTTP_CHALLENGES: &str = "lapdev-acme-http-challenges";
+/// Only the api server that holds the lease renews the certificates
+const LAPDEV_ACME_RENEW_LEASE: &str = "lapdev-acme-renew-lease";
+const RENEW_LEASE_MINUTES: i64 = 30;
+/// The path the ACME server validates the http-01 challenges on
+pub const HTTP_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+/// Renew the certificates when they expire in less than this many days
+const RENEW_BEFORE_DAYS: i64 = 30;
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+const ORDER_POLL_ATTEMPTS: usize = 10;
+
+/// Creates the TXT records of the DNS-01 challenges,
+/// which are the only way to get the wildcard certificates of the workspace hostnames
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// `name` is the full record name, e.g. `_acme-challenge.example.com`
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<()>;
+
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<()>;
+
+    /// How long to wait for the records to be visible to the ACME server
+    fn propagation_delay(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+}
+
+pub fn dns_provider(provider: &AcmeDnsProvider) -> Box<dyn DnsProvider> {
+    match provider {
+        AcmeDnsProvider::Cloudflare { api_token } => Box::new(CloudflareDns {
+            client: reqwest::Client::new(),
+            api_token: api_token.clone(),
+        }),
+        AcmeDnsProvider::Webhook { url } => Box::new(WebhookDns {
+            client: reqwest::Client::new(),
+            url: url.clone(),
+        }),
+    }
+}
+
+struct CloudflareDns {
+    client: reqwest::Client,
+    api_token: String,
+}
+
+#[derive(Deserialize)]
+struct CloudflareList {
+    result: Vec<CloudflareId>,
+}
+
+#[derive(Deserialize)]
+struct CloudflareId {
+    id: String,
+}
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+
+impl CloudflareDns {
+    /// Find the zone of the record by trying its parent domains
+    async fn zone_id(&self, name: &str) -> Result<String> {
+        let mut domain = name;
+        while let Some((_, parent)) = domain.split_once('.') {
+            let zones: CloudflareList = self
+                .client
+                .get(format!("{CLOUDFLARE_API}/zones"))
+                .query(&[("name", parent)])
+                .bearer_auth(&self.api_token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if let Some(zone) = zones.result.into_iter().next() {
+                return Ok(zone.id);
+            }
+            domain = parent;
+        }
+        Err(anyhow!("can't find the cloudflare zone of {name}"))
+    }
+}
+
+#[async_trait]
+impl DnsProvider for CloudflareDns {
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        let zone_id = self.zone_id(name).await?;
+        self.client
+            .post(format!("{CLOUDFLARE_API}/zones/{zone_id}/dns_records"))
+            .bearer_auth(&self.api_token)
+            .json(&json!({
+                "type": "TXT",
+                "name": name,
+                "content": value,
+                "ttl": 60,
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        let zone_id = self.zone_id(name).await?;
+        let records: CloudflareList = self
+            .client
+            .get(format!("{CLOUDFLARE_API}/zones/{zone_id}/dns_records"))
+            .query(&[("type", "TXT"), ("name", name), ("content", value)])
+            .bearer_auth(&self.api_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        for record in records.result {
+            self.client
+                .delete(format!(
+                    "{CLOUDFLARE_API}/zones/{zone_id}/dns_records/{}",
+                    record.id
+                ))
+                .bearer_auth(&self.api_token)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+}
+
+struct WebhookDns {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookDns {
+    async fn call(&self, action: &str, name: &str, value: &str) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(&json!({
+                "action": action,
+                "name": name,
+                "value": value,
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for WebhookDns {
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        self.call("create", name, value).await
+    }
+
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<()> {
+        self.call("delete", name, value).await
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredAccount {
+    directory_url: String,
+    /// The encrypted json of the `AccountCredentials`, which has the account's private key
+    credentials: String,
+}
+
+/// Keeps the certificates of the hostnames issued and renewed through ACME.
+/// The certificates are saved to the `LAPDEV_ACME_CERTS` config,
+/// and the config update notification swaps them into the `CertStore`.
+#[derive(Clone)]
+pub struct Acme {
+    db: DbApi,
+    cipher: KeyCipher,
+    /// Who holds the renewal lease when it's this server
+    id: Uuid,
+    /// The `LAPDEV_ACME_HTTP_CHALLENGES`, which the config update notification keeps up to date
+    http_challenges: Arc<RwLock<HashMap<String, String>>>,
+    /// Wakes up the renewal when the settings or the hostnames change
+    notify: Arc<Notify>,
+}
+
+impl Acme {
+    pub fn new(db: DbApi, cipher: KeyCipher) -> Self {
+        Self {
+            db,
+            cipher,
+            id: Uuid::new_v4(),
+            http_challenges: Default::default(),
+            notify: Default::default(),
+        }
+    }
+
+    pub async fn http_challenge_response(&self, token: &str) -> Option<String> {
+        if let Some(key_authorization) = self.http_challenges.read().ok()?.get(token) {
+            return Some(key_authorization.clone());
+        }
+        // the validation can come before the notification of the challenge
+        let challenges = self.db.get_config(LAPDEV_ACME_HTTP_CHALLENGES).await.ok()?;
+        serde_json::from_str::<HashMap<String, String>>(&challenges)
+            .ok()?
+            .remove(token)
+    }
+
+    /// Swap in the challenges from the config update notification
+    pub fn update_http_challenges(&self, challenges: &str) {
+        let challenges = serde_json::from_str(challenges).unwrap_or_default();
+        if let Ok(mut current) = self.http_challenges.write() {
+            *current = challenges;
+        }
+    }
+
+    /// Check the certificates now instead of waiting for the next interval
+    pub fn trigger(&self) {
+        self.notify.notify_one();
+    }
+
+    pub async fn settings(&self) -> Result<AcmeSettings> {
+        let settings = match self.db.get_config(LAPDEV_ACME).await {
+            Ok(settings) => serde_json::from_str(&settings)?,
+            Err(_) => AcmeSettings::default(),
+        };
+        Ok(settings)
+    }
+
+    pub async fn run(
+        self,
+        hostnames: Arc<tokio::sync::RwLock<HashMap<String, String>>>,
+        path_routing: Arc<tokio::sync::RwLock<bool>>,
+    ) {
+        loop {
+            let current = hostnames.read().await.clone();
+            let path_routing = *path_routing.read().await;
+            if let Err(e) = self
+                .renew_with_lease(current.into_values().collect(), path_routing)
+                .await
+            {
+                tracing::error!("acme renew certificates error: {e:#}");
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = self.notify.notified() => {}
+            }
+        }
+    }
+
+    /// Renew unless another api server is renewing at the moment,
+    /// which would order the same certificates again
+    async fn renew_with_lease(&self, hostnames: Vec<String>, path_routing: bool) -> Result<()> {
+        let holder = self.id.to_string();
+        if !self
+            .db
+            .take_config_lease(
+                LAPDEV_ACME_RENEW_LEASE,
+                &holder,
+                chrono::Duration::minutes(RENEW_LEASE_MINUTES),
+            )
+            .await?
+        {
+            return Ok(());
+        }
+        let result = self.renew(hostnames, path_routing).await;
+        self.db
+            .release_config_lease(LAPDEV_ACME_RENEW_LEASE, &holder)
+            .await?;
+        result
+    }
+
+    async fn renew(&self, hostnames: Vec<String>, path_routing: bool) -> Result<()> {
+        let settings = self.settings().await?;
+        if !settings.enabled {
+            return Ok(());
+        }
+        let dns = settings.dns_provider.as_ref().map(dns_provider);
+
+        let mut certs = stored_certs(&self.db, LAPDEV_ACME_CERTS).await?;
+        let mut account = None;
+        let mut changed = false;
+        let hostnames: HashSet<String> = hostnames
+            .into_iter()
+            .map(|h| h.trim().to_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        for hostname in hostnames {
+            let mut names = vec![hostname.clone()];
+            if dns.is_some() {
+                names.push(format!("*.{hostname}"));
+            } else if path_routing {
+                // the only other name the workspaces need without the wildcard
+                names.push(format!("{PATH_ROUTING_SUBDOMAIN}.{hostname}"));
+            }
+
+            let existing = certs
+                .iter()
+                .position(|(cert, _)| cert_covers(cert, &names).unwrap_or(false));
+            if let Some(i) = existing {
+                if !needs_renewal(&certs[i].0).unwrap_or(true) {
+                    continue;
+                }
+            }
+
+            if account.is_none() {
+                account = Some(self.account(&settings).await?);
+            }
+            let Some(account) = account.as_ref() else {
+                continue;
+            };
+            tracing::info!("acme issue certificate for {names:?}");
+            match self.issue(account, &names, dns.as_deref()).await {
+                Ok((cert, key)) => {
+                    let cert = (cert, self.cipher.encrypt(&key)?);
+                    match existing {
+                        Some(i) => certs[i] = cert,
+                        None => certs.push(cert),
+                    }
+                    changed = true;
+                }
+                Err(e) => {
+                    tracing::error!("acme issue certificate for {names:?} error: {e:#}");
+                }
+            }
+        }
+
+        if changed {
+            save_certs(&self.db, LAPDEV_ACME_CERTS, &certs).await?;
+        }
+        Ok(())
+    }
+
+    /// Load the ACME account, or register a new one if the directory has changed
+    async fn account(&self, settings: &AcmeSettings) -> Result<Account> {
+        let directory_url = if settings.directory_url.trim().is_empty() {
+            LetsEncrypt::Production.url().to_string()
+        } else {
+            settings.directory_url.trim().to_string()
+        };
+        if let Ok(stored) = self.db.get_config(LAPDEV_ACME_ACCOUNT).await {
+            if let Ok(stored) = serde_json::from_str::<StoredAccount>(&stored) {
+                if stored.directory_url == directory_url {
+                    let credentials: AccountCredentials =
+                        serde_json::from_str(&self.cipher.decrypt(&stored.credentials)?)?;
+                    return Ok(Account::from_credentials(credentials).await?);
+                }
+            }
+        }
+
+        let (account, credentials) = new_account(&directory_url, &settings.email).await?;
+        let stored = StoredAccount {
+            directory_url,
+            credentials: self.cipher.encrypt(&serde_json::to_string(&credentials)?)?,
+        };
+        self.db
+            .update_config(LAPDEV_ACME_ACCOUNT, &serde_json::to_string(&stored)?)
+            .await?;
+        Ok(account)
+    }
+
+    async fn issue(
+        &self,
+        account: &Account,
+        names: &[String],
+        dns: Option<&dyn DnsProvider>,
+    ) -> Result<(String, String)> {
+        let mut solver = Solver {
+            db: &self.db,
+            http_challenges: self.http_challenges.clone(),
+            dns,
+            tokens: vec![],
+            records: vec![],
+        };
+        let result = issue_certificate(account, names, &mut solver).await;
+        solver.cleanup().await;
+        result
+    }
+}
+
+async fn new_account(directory_url: &str, email: &str) -> Result<(Account, AccountCredentials)> {
+    let contact = format!("mailto:{}", email.trim());
+    let contact: Vec<&str> = if email.trim().is_empty() {
+        vec![]
+    } else {
+        vec![contact.as_str()]
+    };
+    let (account, credentials) = Account::create(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        directory_url,
+        None,
+    )
+    .await?;
+    Ok((account, credentials))
+}
+
+/// Provisions the challenges of an order, and cleans them up afterwards.
+/// The http-01 challenges are saved to `LAPDEV_ACME_HTTP_CHALLENGES`,
+/// since the ACME server can send the validation to any of the api servers.
+struct Solver<'a> {
+    db: &'a DbApi,
+    http_challenges: Arc<RwLock<HashMap<String, String>>>,
+    dns: Option<&'a dyn DnsProvider>,
+    tokens: Vec<String>,
+    records: Vec<(String, String)>,
+}
+
+impl Solver<'_> {
+    async fn add_http(&mut self, token: &str, key_authorization: &str) -> Result<()> {
+        let challenges = {
+            let mut challenges = self
+                .http_challenges
+                .write()
+                .map_err(|_| anyhow!("http challenges lock poisoned"))?;
+            challenges.insert(token.to_string(), key_authorization.to_string());
+            serde_json::to_string(&*challenges)?
+        };
+        self.tokens.push(token.to_string());
+        self.db
+            .update_config(LAPDEV_ACME_HTTP_CHALLENGES, &challenges)
+            .await?;
+        Ok(())
+    }
+
+    async fn add_dns(&mut self, domain: &str, value: &str) -> Result<()> {
+        let dns = self
+            .dns
+            .ok_or_else(|| anyhow!("{domain} needs a dns provider for the dns-01 challenge"))?;
+        let name = format!("_acme-challenge.{domain}");
+        dns.create_txt_record(&name, value).await?;
+        self.records.push((name, value.to_string()));
+        Ok(())
+    }
+
+    async fn cleanup(self) {
+        if !self.tokens.is_empty() {
+            let challenges = self.http_challenges.write().ok().map(|mut challenges| {
+                for token in &self.tokens {
+                    challenges.remove(token);
+                }
+                serde_json::to_string(&*challenges).unwrap_or_default()
+            });
+            if let Some(challenges) = challenges {
+                if let Err(e) = self
+                    .db
+                    .update_config(LAPDEV_ACME_HTTP_CHALLENGES, &challenges)
+                    .await
+                {
+                    tracing::warn!("acme remove http challenges error: {e:#}");
+                }
+            }
+        }
+        if let Some(dns) = self.dns {
+            for (name, value) in &self.records {
+                if let Err(e) = dns.delete_txt_record(name, value).await {
+                    tracing::warn!("acme delete txt record {name} error: {e:#}");
+                }
+            }
+        }
+    }
+}
+
+/// Go through an ACME order for the names, and return the certificate chain and the private key in PEM.
+/// The names are validated with http-01 when possible, and dns-01 for the wildcards.
+async fn issue_certificate(
+    account: &Account,
+    names: &[String],
+    solver: &mut Solver<'_>,
+) -> Result<(String, String)> {
+    let identifiers: Vec<Identifier> = names.iter().map(|n| Identifier::Dns(n.clone())).collect();
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &identifiers,
+        })
+        .await?;
+
+    let authorizations = order.authorizations().await?;
+    let mut ready = vec![];
+    for authz in &authorizations {
+        match &authz.status {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => continue,
+            status => bail!("authorization is {status:?}"),
+        }
+        let Identifier::Dns(domain) = &authz.identifier;
+
+        // the wildcard authorizations only offer dns-01
+        let find = |ty: ChallengeType| authz.challenges.iter().find(|c| c.r#type == ty);
+        if let Some(challenge) = find(ChallengeType::Http01) {
+            let key_authorization = order.key_authorization(challenge);
+            solver
+                .add_http(&challenge.token, key_authorization.as_str())
+                .await?;
+            ready.push(challenge.url.clone());
+        } else if let Some(challenge) = find(ChallengeType::Dns01) {
+            let key_authorization = order.key_authorization(challenge);
+            solver
+                .add_dns(domain, &key_authorization.dns_value())
+                .await?;
+            ready.push(challenge.url.clone());
+        } else {
+            bail!("no supported challenge for {domain}");
+        }
+    }
+
+    if !solver.records.is_empty() {
+        if let Some(dns) = solver.dns {
+            tokio::time::sleep(dns.propagation_delay()).await;
+        }
+    }
+    for url in &ready {
+        order.set_challenge_ready(url).await?;
+    }
+
+    let mut delay = Duration::from_millis(500);
+    let mut attempts = 0;
+    loop {
+        tokio::time::sleep(delay).await;
+        let state = order.refresh().await?;
+        match state.status {
+            OrderStatus::Ready => break,
+            OrderStatus::Invalid => bail!("order is invalid"),
+            _ => {}
+        }
+        attempts += 1;
+        if attempts >= ORDER_POLL_ATTEMPTS {
+            bail!("order isn't ready after {attempts} attempts");
+        }
+        delay = (delay * 2).min(Duration::from_secs(10));
+    }
+
+    let mut params = CertificateParams::new(names.to_vec());
+    params.distinguished_name = DistinguishedName::new();
+    let cert = rcgen::Certificate::from_params(params)?;
+    order.finalize(&cert.serialize_request_der()?).await?;
+
+    let mut attempts = 0;
+    let chain = loop {
+        if let Some(chain) = order.certificate().await? {
+            break chain;
+        }
+        attempts += 1;
+        if attempts >= ORDER_POLL_ATTEMPTS {
+            bail!("certificate isn't issued after {attempts} attempts");
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    };
+
+    Ok((chain, cert.serialize_private_key_pem()))
+}
+
+fn cert_covers(chain: &str, names: &[String]) -> Result<bool> {
+    let info = cert_info(chain, true, RENEW_BEFORE_DAYS)?;
+    Ok(names.iter().all(|name| info.dns_names.contains(name)))
+}
+
+fn needs_renewal(chain: &str) -> Result<bool> {
+    Ok(cert_info(chain, true, RENEW_BEFORE_DAYS)?.expiring)
+}
+
+/// These run against a local Pebble ACME server:
+///
+/// ```sh
+/// pebble-challtestsrv -defaultIPv4 127.0.0.1 &
+/// PEBBLE_VA_NOSLEEP=1 pebble -config test/config/pebble-config.json -dnsserver 127.0.0.1:8053 &
+/// SSL_CERT_FILE=test/certs/pebble.minica.pem cargo test -p lapdev-api acme -- --ignored
+/// ```
+///
+/// Pebble validates http-01 on port 5002, which is where the challenges are served in the test.
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use axum::{extract::Path, routing::get, Router};
+    use serde_json::json;
+
+    use lapdev_conductor::cipher::KeyCipher;
+
+    use super::{
+        issue_certificate, new_account, Acme, DnsProvider, Solver, LAPDEV_ACME_HTTP_CHALLENGES,
+        LAPDEV_ACME_RENEW_LEASE, RENEW_LEASE_MINUTES,
+    };
+    use crate::cert::cert_info;
+
+    const PEBBLE_DIRECTORY: &str = "https://localhost:14000/dir";
+    const CHALLTESTSRV: &str = "http://localhost:8055";
+
+    /// Sets the TXT records on pebble-challtestsrv, which Pebble uses as its DNS server
+    struct ChallTestSrv;
+
+    #[async_trait]
+    impl DnsProvider for ChallTestSrv {
+        async fn create_txt_record(&self, name: &str, value: &str) -> Result<()> {
+            reqwest::Client::new()
+                .post(format!("{CHALLTESTSRV}/set-txt"))
+                .json(&json!({ "host": format!("{name}."), "value": value }))
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        }
+
+        async fn delete_txt_record(&self, name: &str, _value: &str) -> Result<()> {
+            reqwest::Client::new()
+                .post(format!("{CHALLTESTSRV}/clear-txt"))
+                .json(&json!({ "host": format!("{name}.") }))
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        }
+
+        fn propagation_delay(&self) -> Duration {
+            Duration::ZERO
+        }
+    }
+
+    async fn serve_http_challenges(challenges: Arc<std::sync::RwLock<HashMap<String, String>>>) {
+        let app = Router::new().route(
+            "/.well-known/acme-challenge/:token",
+            get(move |Path(token): Path<String>| async move {
+                challenges
+                    .read()
+                    .unwrap()
+                    .get(&token)
+                    .cloned()
+                    .unwrap_or_default()
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:5002").await.unwrap();
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a local pebble server"]
+    async fn test_issue_with_pebble() {
+        let challenges: Arc<std::sync::RwLock<HashMap<String, String>>> = Default::default();
+        serve_http_challenges(challenges.clone()).await;
+        let (account, _) = new_account(PEBBLE_DIRECTORY, "admin@example.com")
+            .await
+            .unwrap();
+
+        let names = vec![
+            "lapdev.example.com".to_string(),
+            "*.lapdev.example.com".to_string(),
+        ];
+        let db = lapdev_db::tests::prepare_db().await.unwrap();
+        let dns = ChallTestSrv;
+        let mut solver = Solver {
+            db: &db,
+            http_challenges: challenges.clone(),
+            dns: Some(&dns),
+            tokens: vec![],
+            records: vec![],
+        };
+        let result = issue_certificate(&account, &names, &mut solver).await;
+        solver.cleanup().await;
+        let (chain, key) = result.unwrap();
+
+        let info = cert_info(&chain, true, 0).unwrap();
+        assert!(names.iter().all(|name| info.dns_names.contains(name)));
+        assert!(!info.expiring);
+        assert!(challenges.read().unwrap().is_empty());
+        crate::cert::load_cert(&chain, &key).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_challenges_across_servers() {
+        let db = lapdev_db::tests::prepare_db().await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let cipher = KeyCipher::load(&dir.path().join("secret.key"), true)
+            .await
+            .unwrap();
+        let server1 = Acme::new(db.clone(), cipher.clone());
+        let server2 = Acme::new(db.clone(), cipher);
+
+        let mut solver = Solver {
+            db: &db,
+            http_challenges: server1.http_challenges.clone(),
+            dns: None,
+            tokens: vec![],
+            records: vec![],
+        };
+        solver.add_http("token", "token.key").await.unwrap();
+        // the other server answers from the db before the notification
+        assert_eq!(
+            server2.http_challenge_response("token").await.as_deref(),
+            Some("token.key")
+        );
+        server2.update_http_challenges(&db.get_config(LAPDEV_ACME_HTTP_CHALLENGES).await.unwrap());
+        assert_eq!(
+            server2
+                .http_challenges
+                .read()
+                .unwrap()
+                .get("token")
+                .map(|s| s.as_str()),
+            Some("token.key")
+        );
+
+        solver.cleanup().await;
+        assert_eq!(server1.http_challenge_response("token").await, None);
+        assert_eq!(
+            server2.http_challenge_response("token").await,
+            Some("token.key".to_string())
+        );
+        server2.update_http_challenges(&db.get_config(LAPDEV_ACME_HTTP_CHALLENGES).await.unwrap());
+        assert_eq!(server2.http_challenge_response("token").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_renew_lease() {
+        let db = lapdev_db::tests::prepare_db().await.unwrap();
+        let lease = chrono::Duration::minutes(RENEW_LEASE_MINUTES);
+        assert!(db
+            .take_config_lease(LAPDEV_ACME_RENEW_LEASE, "server1", lease)
+            .await
+            .unwrap());
+        // another server can't renew at the same time
+        assert!(!db
+            .take_config_lease(LAPDEV_ACME_RENEW_LEASE, "server2", lease)
+            .await
+            .unwrap());
+        // it isn't released by the server that doesn't hold it
+        db.release_config_lease(LAPDEV_ACME_RENEW_LEASE, "server2")
+            .await
+            .unwrap();
+        assert!(!db
+            .take_config_lease(LAPDEV_ACME_RENEW_LEASE, "server2", lease)
+            .await
+            .unwrap());
+        db.release_config_lease(LAPDEV_ACME_RENEW_LEASE, "server1")
+            .await
+            .unwrap();
+        assert!(db
+            .take_config_lease(LAPDEV_ACME_RENEW_LEASE, "server2", lease)
+            .await
+            .unwrap());
+
+        // an expired lease can be taken over
+        assert!(db
+            .take_config_lease(
+                LAPDEV_ACME_RENEW_LEASE,
+                "server2",
+                chrono::Duration::minutes(-1)
+            )
+            .await
+            .unwrap());
+        assert!(db
+            .take_config_lease(LAPDEV_ACME_RENEW_LEASE, "server1", lease)
+            .await
+            .unwrap());
+    }
+}
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
 use anyhow::anyhow;
 use axum::{
@@ -10,9 +13,11 @@ use axum_extra::{headers::Cookie, TypedHeader};
 use chrono::Utc;
 use hyper::StatusCode;
 use lapdev_common::{
-    AuthProvider, ClusterInfo, ClusterUser, ClusterUserResult, EnterpriseLicense, MachineType,
-    NewLicense, NewLicenseKey, NewWorkspaceHost, OauthSettings, UpdateClusterUser,
-    UpdateWorkspaceHost, WorkspaceHost, WorkspaceHostStatus, LAPDEV_BASE_HOSTNAME,
+    AcmeDnsProvider, AcmeSettings, AuthProvider, CertificateInfo, ClusterInfo, ClusterUser,
+    ClusterUserResult, EnterpriseLicense, LoginProvider, MachineType, NewCertificate, NewLicense,
+    NewLicenseKey, NewWorkspaceHost, OauthProvider, OauthSettings, OidcSettings, UpdateClusterUser,
+    UpdateWorkspaceHost, UserRole, WorkspaceHost, WorkspaceHostStatus, LAPDEV_ACME,
+    LAPDEV_BASE_HOSTNAME, LAPDEV_OAUTH_PROVIDERS, LAPDEV_OIDC, LAPDEV_PATH_ROUTING,
 };
 use lapdev_conductor::scheduler::{self, LAPDEV_CPU_OVERCOMMIT};
 use lapdev_db::entities;
@@ -25,7 +30,8 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    auth::AuthConfig,
+    cert::{cert_info, load_cert, save_certs, stored_certs},
+    oidc::OidcClient,
     state::{CoreState, LAPDEV_CERTS},
 };
 
@@ -235,26 +241,7 @@ pub async fn get_oauth(
 ) -> Result<Json<OauthSettings>, ApiError> {
     state.authenticate_cluster_admin(&cookie).await?;
     let setting = OauthSettings {
-        github_client_id: state
-            .db
-            .get_config(AuthConfig::GITHUB.client_id)
-            .await
-            .unwrap_or_default(),
-        github_client_secret: state
-            .db
-            .get_config(AuthConfig::GITHUB.client_secret)
-            .await
-            .unwrap_or_default(),
-        gitlab_client_id: state
-            .db
-            .get_config(AuthConfig::GITLAB.client_id)
-            .await
-            .unwrap_or_default(),
-        gitlab_client_secret: state
-            .db
-            .get_config(AuthConfig::GITLAB.client_secret)
-            .await
-            .unwrap_or_default(),
+        providers: state.db.get_oauth_providers().await?,
     };
     Ok(Json(setting))
 }
@@ -274,78 +261,235 @@ pub async fn update_oauth(
         // we don't need authentication if cluster is not initiated
         state.authenticate_cluster_admin(&cookie).await?;
     }
+
+    let mut providers = update_oauth.providers;
+    for provider in providers.iter_mut() {
+        provider.id = provider.id.trim().to_string();
+        provider.name = provider.name.trim().to_string();
+        provider.base_url = provider.base_url().to_string();
+        provider.client_id = provider.client_id.trim().to_string();
+        provider.client_secret = provider.client_secret.trim().to_string();
+        if provider.kind == AuthProvider::Oidc || provider.kind == AuthProvider::Local {
+            return Err(ApiError::InvalidRequest(format!(
+                "{} isn't an OAuth provider",
+                provider.kind
+            )));
+        }
+        let url = reqwest::Url::parse(&provider.base_url).map_err(|_| {
+            ApiError::InvalidRequest(format!("{} isn't a valid url", provider.base_url))
+        })?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err(ApiError::InvalidRequest(format!(
+                "{} isn't a valid http url",
+                provider.base_url
+            )));
+        }
+        if provider.kind == AuthProvider::Bitbucket
+            && provider.base_url != OauthProvider::BITBUCKET_URL
+        {
+            return Err(ApiError::InvalidRequest(
+                "only Bitbucket Cloud on https://bitbucket.org is supported".to_string(),
+            ));
+        }
+        if provider.name.is_empty() {
+            provider.name = OauthProvider::new(provider.kind.clone()).name;
+        }
+    }
+
+    // the ids of the new instances are generated from their kind, e.g. `Gitlab2`,
+    // never reusing the plain `Github` and `Gitlab` that the existing users of github.com and gitlab.com have
+    let mut ids: HashSet<String> = HashSet::new();
+    for provider in providers.iter().filter(|p| !p.id.is_empty()) {
+        if !provider
+            .id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ApiError::InvalidRequest(format!(
+                "provider id {} can only have letters, numbers, - and _",
+                provider.id
+            )));
+        }
+        if provider.id == AuthProvider::Oidc.to_string()
+            || provider.id == AuthProvider::Local.to_string()
+            || !ids.insert(provider.id.clone())
+        {
+            return Err(ApiError::InvalidRequest(format!(
+                "provider id {} is already used",
+                provider.id
+            )));
+        }
+    }
+    for provider in providers.iter_mut().filter(|p| p.id.is_empty()) {
+        let legacy = matches!(provider.kind, AuthProvider::Github | AuthProvider::Gitlab);
+        let id = (1..)
+            .filter(|i| *i > 1 || !legacy)
+            .map(|i| match i {
+                1 => provider.kind.to_string(),
+                i => format!("{}{i}", provider.kind),
+            })
+            .find(|id| !ids.contains(id))
+            .unwrap_or_default();
+        ids.insert(id.clone());
+        provider.id = id;
+    }
+
     state
         .db
-        .update_config(AuthConfig::GITHUB.client_id, &update_oauth.github_client_id)
-        .await?;
-    state
-        .db
-        .update_config(
-            AuthConfig::GITHUB.client_secret,
-            &update_oauth.github_client_secret,
-        )
-        .await?;
-    state
-        .db
-        .update_config(AuthConfig::GITLAB.client_id, &update_oauth.gitlab_client_id)
+        .update_config(LAPDEV_OAUTH_PROVIDERS, &serde_json::to_string(&providers)?)
         .await?;
+    state.auth.resync(&state.db).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn get_oidc(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+) -> Result<Json<OidcSettings>, ApiError> {
+    state.authenticate_cluster_admin(&cookie).await?;
+    let settings = match state.db.get_config(LAPDEV_OIDC).await {
+        Ok(settings) => serde_json::from_str(&settings)?,
+        Err(_) => OidcSettings::default(),
+    };
+    Ok(Json(settings))
+}
+
+pub async fn update_oidc(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+    Json(mut settings): Json<OidcSettings>,
+) -> Result<StatusCode, ApiError> {
+    let is_cluster_initiated = {
+        let txn = state.db.conn.begin().await?;
+        let is_cluster_initiated = state.db.is_cluster_initiated(&txn).await;
+        txn.commit().await?;
+        is_cluster_initiated
+    };
+    if is_cluster_initiated {
+        // we don't need authentication if cluster is not initiated
+        state.authenticate_cluster_admin(&cookie).await?;
+    }
+
+    settings.issuer_url = settings.issuer_url.trim().trim_end_matches('/').to_string();
+    settings.client_id = settings.client_id.trim().to_string();
+    settings.client_secret = settings.client_secret.trim().to_string();
+    settings.groups_claim = settings
+        .groups_claim
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty());
+    if settings.enabled {
+        if settings.display_name.trim().is_empty() {
+            return Err(ApiError::InvalidRequest(
+                "display name can't be empty".to_string(),
+            ));
+        }
+        if settings.client_id.is_empty() || settings.client_secret.is_empty() {
+            return Err(ApiError::InvalidRequest(
+                "client id and secret can't be empty".to_string(),
+            ));
+        }
+        for mapping in &settings.group_mappings {
+            if mapping.role == UserRole::Owner {
+                return Err(ApiError::InvalidRequest(
+                    "groups can't be mapped to the organization owner".to_string(),
+                ));
+            }
+            state
+                .db
+                .get_organization(mapping.organization)
+                .await
+                .map_err(|_| {
+                    ApiError::InvalidRequest(format!(
+                        "organization {} doesn't exist",
+                        mapping.organization
+                    ))
+                })?;
+        }
+        // check the provider is reachable before enabling it
+        OidcClient::discover(settings.clone()).await.map_err(|e| {
+            ApiError::InvalidRequest(format!("can't discover the OpenID Connect provider: {e}"))
+        })?;
+    }
+
     state
         .db
-        .update_config(
-            AuthConfig::GITLAB.client_secret,
-            &update_oauth.gitlab_client_secret,
-        )
+        .update_config(LAPDEV_OIDC, &serde_json::to_string(&settings)?)
         .await?;
     state.auth.resync(&state.db).await;
-    Ok(StatusCode::NO_CONTENT.into_response())
+    Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn get_certs(
     TypedHeader(cookie): TypedHeader<Cookie>,
     State(state): State<CoreState>,
-) -> Result<Json<Vec<(String, String)>>, ApiError> {
+) -> Result<Json<Vec<CertificateInfo>>, ApiError> {
     state.authenticate_cluster_admin(&cookie).await?;
-    let certs = state.db.get_config(LAPDEV_CERTS).await?;
-    let certs: Vec<(String, String)> = serde_json::from_str(&certs)?;
+    let certs = state.cert_infos().await?;
     Ok(Json(certs))
 }
 
-pub async fn update_certs(
+pub async fn create_cert(
     TypedHeader(cookie): TypedHeader<Cookie>,
     State(state): State<CoreState>,
-    Json(update_body): Json<Vec<(String, String)>>,
+    Json(new_cert): Json<NewCertificate>,
 ) -> Result<StatusCode, ApiError> {
     state.authenticate_cluster_admin(&cookie).await?;
-    let certs = serde_json::to_string(&update_body)?;
-    state.db.update_config(LAPDEV_CERTS, &certs).await?;
+    let cert = new_cert.cert.trim();
+    let key = new_cert.key.trim();
+    let (dns_names, _) = load_cert(cert, key)
+        .map_err(|_| ApiError::InvalidRequest("invalid certificate or key".to_string()))?;
+    if dns_names.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "the certificate doesn't have any DNS names".to_string(),
+        ));
+    }
+    let info = cert_info(cert, false, state.cert_expiry_warning_days)
+        .map_err(|_| ApiError::InvalidRequest("invalid certificate".to_string()))?;
+
+    let mut certs = stored_certs(&state.db, LAPDEV_CERTS).await?;
+    certs.retain(|(cert, _)| {
+        cert_info(cert, false, 0)
+            .map(|i| i.fingerprint != info.fingerprint)
+            .unwrap_or(true)
+    });
+    certs.push((cert.to_string(), state.key_cipher.encrypt(key)?));
+    save_certs(&state.db, LAPDEV_CERTS, &certs).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_cert(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path(fingerprint): Path<String>,
+    State(state): State<CoreState>,
+) -> Result<StatusCode, ApiError> {
+    state.authenticate_cluster_admin(&cookie).await?;
+    let mut certs = stored_certs(&state.db, LAPDEV_CERTS).await?;
+    let len = certs.len();
+    certs.retain(|(cert, _)| {
+        cert_info(cert, false, 0)
+            .map(|i| i.fingerprint != fingerprint)
+            .unwrap_or(true)
+    });
+    if certs.len() == len {
+        return Err(ApiError::InvalidRequest(
+            "certificate doesn't exist".to_string(),
+        ));
+    }
+    save_certs(&state.db, LAPDEV_CERTS, &certs).await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn get_auth_providers(
     State(state): State<CoreState>,
-) -> Result<Json<Vec<AuthProvider>>, ApiError> {
-    let providers: Vec<AuthProvider> = state
-        .auth
-        .clients
-        .read()
-        .await
-        .keys()
-        .map(|k| k.to_owned())
-        .collect();
+) -> Result<Json<Vec<LoginProvider>>, ApiError> {
+    let providers = state.auth.providers().await;
     Ok(Json(providers))
 }
 
 pub async fn get_cluster_info(
     State(state): State<CoreState>,
 ) -> Result<Json<ClusterInfo>, ApiError> {
-    let providers: Vec<AuthProvider> = state
-        .auth
-        .clients
-        .read()
-        .await
-        .keys()
-        .map(|k| k.to_owned())
-        .collect();
+    let providers = state.auth.providers().await;
     let machine_types = state.db.get_all_machine_types().await?;
     let machine_types: Vec<MachineType> = machine_types
         .into_iter()
@@ -366,6 +510,8 @@ pub async fn get_cluster_info(
         has_enterprise,
         hostnames: state.conductor.hostnames.read().await.clone(),
         ssh_proxy_port: state.ssh_proxy_port,
+        path_routing: *state.path_routing.read().await,
+        local_login: state.db.has_local_users().await.unwrap_or(false),
     }))
 }
 
@@ -392,12 +538,18 @@ pub async fn get_cluster_users(
     let result = query.paginate(&state.db.conn, page_size);
     let items_and_pages = result.num_items_and_pages().await?;
     let users = result.fetch_page(page).await?;
+    let providers = state.db.get_oauth_providers().await.unwrap_or_default();
     let users = users
         .into_iter()
         .filter_map(|user| {
+            // the provider of a user is the id of the instance they signed in with
+            let auth_provider = match providers.iter().find(|p| p.id == user.provider) {
+                Some(provider) => provider.kind.clone(),
+                None => AuthProvider::from_str(&user.provider).ok()?,
+            };
             Some(ClusterUser {
                 id: user.id,
-                auth_provider: AuthProvider::from_str(&user.provider).ok()?,
+                auth_provider,
                 avatar_url: user.avatar_url,
                 name: user.name,
                 email: user.email,
@@ -482,6 +634,71 @@ pub async fn update_cpu_overcommit(
     Ok(StatusCode::NO_CONTENT)
 }
 
+pub async fn update_path_routing(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path(enabled): Path<bool>,
+    State(state): State<CoreState>,
+) -> Result<StatusCode, ApiError> {
+    state.authenticate_cluster_admin(&cookie).await?;
+    *state.path_routing.write().await = enabled;
+    state
+        .db
+        .update_config(LAPDEV_PATH_ROUTING, if enabled { "yes" } else { "no" })
+        .await?;
+    state.acme.trigger();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_acme(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+) -> Result<Json<AcmeSettings>, ApiError> {
+    state.authenticate_cluster_admin(&cookie).await?;
+    let mut settings = state.acme.settings().await?;
+    // the api token is a secret, it's only ever sent to cloudflare
+    if let Some(AcmeDnsProvider::Cloudflare { api_token }) = settings.dns_provider.as_mut() {
+        api_token.clear();
+    }
+    Ok(Json(settings))
+}
+
+pub async fn update_acme(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+    Json(mut settings): Json<AcmeSettings>,
+) -> Result<StatusCode, ApiError> {
+    state.authenticate_cluster_admin(&cookie).await?;
+    if let Some(AcmeDnsProvider::Cloudflare { api_token }) = settings.dns_provider.as_mut() {
+        // the token isn't sent back to the dashboard, so an empty one keeps the stored token
+        if api_token.trim().is_empty() {
+            if let Some(AcmeDnsProvider::Cloudflare { api_token: stored }) =
+                state.acme.settings().await?.dns_provider
+            {
+                *api_token = stored;
+            }
+        }
+    }
+    match settings.dns_provider.as_ref() {
+        Some(AcmeDnsProvider::Cloudflare { api_token }) if api_token.trim().is_empty() => {
+            return Err(ApiError::InvalidRequest(
+                "Cloudflare API token can't be empty".to_string(),
+            ));
+        }
+        Some(AcmeDnsProvider::Webhook { url }) if reqwest::Url::parse(url).is_err() => {
+            return Err(ApiError::InvalidRequest(
+                "DNS webhook url is invalid".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    state
+        .db
+        .update_config(LAPDEV_ACME, &serde_json::to_string(&settings)?)
+        .await?;
+    state.acme.trigger();
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub async fn get_hostnames(
     State(state): State<CoreState>,
 ) -> Result<Json<HashMap<String, String>>, ApiError> {
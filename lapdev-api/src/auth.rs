@@ -1,99 +1,184 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{borrow::Cow, sync::Arc};
 
 use anyhow::Result;
-use lapdev_common::AuthProvider;
+use chrono::Utc;
+use lapdev_common::{AuthProvider, LoginProvider, OauthProvider, OidcSettings, LAPDEV_OIDC};
+use lapdev_conductor::oauth::{oauth_client, OauthToken};
 use lapdev_db::api::DbApi;
-use oauth2::{
-    basic::BasicClient, AccessToken, AuthUrl, AuthorizationCode, ClientId, ClientSecret,
-    RedirectUrl, TokenResponse, TokenUrl,
-};
+use oauth2::{basic::BasicClient, AuthorizationCode, RedirectUrl};
 use tokio::sync::RwLock;
 
-use crate::gitlab::GitlabClient;
+use crate::oidc::{OidcClient, OIDC_REDISCOVER_INTERVAL};
 
 pub struct AuthConfig {
-    pub client_id: &'static str,
-    pub client_secret: &'static str,
-    pub auth_url: &'static str,
-    pub token_url: &'static str,
     pub scopes: &'static [&'static str],
     pub read_repo_scopes: &'static [&'static str],
 }
 
 impl AuthConfig {
     pub const GITHUB: Self = AuthConfig {
-        client_id: "github-client-id",
-        client_secret: "github-client-secret",
-        auth_url: "https://github.com/login/oauth/authorize",
-        token_url: "https://github.com/login/oauth/access_token",
         scopes: &["read:user", "user:email"],
         read_repo_scopes: &["read:user", "user:email", "repo"],
     };
     pub const GITLAB: Self = AuthConfig {
-        client_id: "gitlab-client-id",
-        client_secret: "gitlab-client-secret",
-        auth_url: "https://gitlab.com/oauth/authorize",
-        token_url: "https://gitlab.com/oauth/token",
         scopes: &["read_user"],
         read_repo_scopes: &["read_user", "read_repository"],
     };
+    pub const GITEA: Self = AuthConfig {
+        scopes: &["read:user"],
+        read_repo_scopes: &["read:user", "read:repository"],
+    };
+    /// The scopes of Bitbucket are set on the OAuth consumer instead of requested
+    pub const BITBUCKET: Self = AuthConfig {
+        scopes: &[],
+        read_repo_scopes: &[],
+    };
+
+    pub fn of(kind: &AuthProvider) -> Option<&'static AuthConfig> {
+        match kind {
+            AuthProvider::Github => Some(&Self::GITHUB),
+            AuthProvider::Gitlab => Some(&Self::GITLAB),
+            AuthProvider::Gitea => Some(&Self::GITEA),
+            AuthProvider::Bitbucket => Some(&Self::BITBUCKET),
+            AuthProvider::Oidc | AuthProvider::Local => None,
+        }
+    }
 }
 
 pub struct Auth {
-    pub clients: RwLock<HashMap<AuthProvider, (BasicClient, AuthConfig)>>,
-    pub gitlab_client: GitlabClient,
+    /// The configured OAuth provider instances, in the order they are shown on the login page
+    pub clients: RwLock<Vec<(BasicClient, OauthProvider)>>,
+    /// The settings of the OpenID Connect provider if it's enabled
+    pub oidc_settings: RwLock<Option<OidcSettings>>,
+    /// Discovered on the first login, so that an unreachable provider doesn't block the startup
+    oidc_client: RwLock<Option<Arc<OidcClient>>>,
 }
 
 impl Auth {
     pub async fn new(db: &DbApi) -> Self {
         Self {
             clients: RwLock::new(Self::get_clients(db).await),
-            gitlab_client: GitlabClient::new(),
+            oidc_settings: RwLock::new(Self::get_oidc_settings(db).await),
+            oidc_client: RwLock::new(None),
         }
     }
 
     pub async fn resync(&self, db: &DbApi) {
         let clients = Self::get_clients(db).await;
         *self.clients.write().await = clients;
+        *self.oidc_settings.write().await = Self::get_oidc_settings(db).await;
+        *self.oidc_client.write().await = None;
     }
 
-    async fn get_clients(db: &DbApi) -> HashMap<AuthProvider, (BasicClient, AuthConfig)> {
-        let mut clients = HashMap::new();
-        if let Ok(client) = Self::build_auth(db, &AuthConfig::GITHUB).await {
-            clients.insert(AuthProvider::Github, (client, AuthConfig::GITHUB));
+    /// The enabled login providers
+    pub async fn providers(&self) -> Vec<LoginProvider> {
+        let mut providers: Vec<LoginProvider> = self
+            .clients
+            .read()
+            .await
+            .iter()
+            .map(|(_, provider)| LoginProvider {
+                id: provider.id.clone(),
+                kind: provider.kind.clone(),
+                name: provider.name.clone(),
+            })
+            .collect();
+        if let Some(settings) = self.oidc_settings.read().await.as_ref() {
+            providers.push(LoginProvider {
+                id: AuthProvider::Oidc.to_string(),
+                kind: AuthProvider::Oidc,
+                name: settings.display_name.clone(),
+            });
         }
-        if let Ok(client) = Self::build_auth(db, &AuthConfig::GITLAB).await {
-            clients.insert(AuthProvider::Gitlab, (client, AuthConfig::GITLAB));
+        providers
+    }
+
+    /// The OAuth provider instance with the id
+    pub async fn provider(&self, id: &str) -> Option<OauthProvider> {
+        self.clients
+            .read()
+            .await
+            .iter()
+            .find(|(_, provider)| provider.id == id)
+            .map(|(_, provider)| provider.clone())
+    }
+
+    async fn get_oidc_settings(db: &DbApi) -> Option<OidcSettings> {
+        let settings = db.get_config(LAPDEV_OIDC).await.ok()?;
+        let settings: OidcSettings = match serde_json::from_str(&settings) {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::error!("invalid oidc settings: {e:?}");
+                return None;
+            }
+        };
+        if settings.enabled {
+            Some(settings)
+        } else {
+            None
         }
-        clients
     }
 
-    async fn build_auth(db: &DbApi, config: &AuthConfig) -> Result<BasicClient> {
-        let client_id = db.get_config(config.client_id).await?;
-        let client_secret = db.get_config(config.client_secret).await?;
-        if client_id.trim().is_empty() || client_secret.trim().is_empty() {
-            return Err(anyhow::anyhow!("client id or secret is empty"));
+    pub async fn oidc_client(&self) -> Result<Arc<OidcClient>> {
+        if let Some(client) = self.oidc_client.read().await.as_ref() {
+            if client.discovered_at.elapsed() < OIDC_REDISCOVER_INTERVAL {
+                return Ok(client.clone());
+            }
         }
 
-        let client = BasicClient::new(
-            ClientId::new(client_id),
-            Some(ClientSecret::new(client_secret)),
-            AuthUrl::new(config.auth_url.to_string())?,
-            Some(TokenUrl::new(config.token_url.to_string())?),
-        );
-        Ok(client)
+        let settings = self
+            .oidc_settings
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("OpenID Connect isn't enabled"))?;
+        let mut current = self.oidc_client.write().await;
+        match OidcClient::discover(settings).await {
+            Ok(client) => {
+                let client = Arc::new(client);
+                *current = Some(client.clone());
+                Ok(client)
+            }
+            Err(e) => {
+                tracing::error!("discover oidc provider error: {e:#}");
+                // keep using the previous metadata until the provider is reachable again
+                current
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("can't reach the OpenID Connect provider"))
+            }
+        }
+    }
+
+    async fn get_clients(db: &DbApi) -> Vec<(BasicClient, OauthProvider)> {
+        let providers = match db.get_oauth_providers().await {
+            Ok(providers) => providers,
+            Err(e) => {
+                tracing::error!("get oauth providers error: {e:?}");
+                return Vec::new();
+            }
+        };
+        providers
+            .into_iter()
+            .filter_map(|provider| {
+                let client = oauth_client(&provider).ok()?;
+                Some((client, provider))
+            })
+            .collect()
     }
 
     pub async fn authorize_url(
         &self,
-        provider: AuthProvider,
+        provider: &str,
         redirect_url: &str,
         no_read_repo: bool,
     ) -> Result<(String, String)> {
         let clients = self.clients.read().await;
-        let (client, config) = clients
-            .get(&provider)
+        let (client, provider) = clients
+            .iter()
+            .find(|(_, p)| p.id == provider)
             .ok_or_else(|| anyhow::anyhow!("can't find provider"))?;
+        let config =
+            AuthConfig::of(&provider.kind).ok_or_else(|| anyhow::anyhow!("can't find provider"))?;
         let mut client = client.authorize_url(oauth2::CsrfToken::new_random);
         for scope in if no_read_repo {
             config.scopes
@@ -110,13 +195,14 @@ impl Auth {
 
     pub async fn exchange_code(
         &self,
-        provider: &AuthProvider,
+        provider: &str,
         code: String,
         redirect_url: String,
-    ) -> Result<AccessToken> {
+    ) -> Result<OauthToken> {
         let clients = self.clients.read().await;
         let (client, _) = clients
-            .get(provider)
+            .iter()
+            .find(|(_, p)| p.id == provider)
             .ok_or_else(|| anyhow::anyhow!("can't find provider"))?;
         let token = match client
             .exchange_code(AuthorizationCode::new(code))
@@ -130,6 +216,6 @@ impl Auth {
                 return Err(e)?;
             }
         };
-        Ok(token.access_token().to_owned())
+        Ok(OauthToken::new(&token, Utc::now()))
     }
 }
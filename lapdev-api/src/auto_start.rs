@@ -0,0 +1,238 @@
+use axum::{
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use axum_extra::headers::Cookie;
+use lapdev_common::WorkspaceStatus;
+use lapdev_db::{api::DbApi, entities};
+use lapdev_rpc::error::ApiError;
+
+use crate::{
+    port::workspace_hostname,
+    state::{CoreState, RequestInfo},
+};
+
+/// The websocket on lapdev's own hostname that the progress page listens to the workspace
+/// updates on, so that it can't clash with the paths of the workspace
+pub const WORKSPACE_UPDATES_PATH: &str = "/workspace_start_ws";
+/// Set on the progress page responses, so that the page can tell when it gets the workspace itself
+const PROGRESS_HEADER: &str = "x-lapdev-workspace-progress";
+const PROGRESS_PAGE: &str = include_str!("auto_start.html");
+
+/// The main workspace of a compose service, which is the one that's started and stopped
+pub async fn main_workspace(
+    state: &CoreState,
+    ws: &entities::workspace::Model,
+) -> Result<entities::workspace::Model, ApiError> {
+    if ws.is_compose {
+        if let Some(parent) = ws.compose_parent {
+            return Ok(state.db.get_workspace(parent).await?);
+        }
+    }
+    Ok(ws.clone())
+}
+
+/// Auto start a stopped workspace when it's opened in the browser, the same as the ssh proxy does.
+/// Returns the progress page while the workspace is starting,
+/// or None if the request can be forwarded to the workspace.
+pub async fn auto_start_workspace(
+    state: &CoreState,
+    cookie: &Cookie,
+    ws: &entities::workspace::Model,
+    method: &Method,
+    headers: &HeaderMap,
+    info: &RequestInfo,
+) -> Result<Option<Response>, ApiError> {
+    let main_ws = main_workspace(state, ws).await?;
+    if main_ws.status == WorkspaceStatus::Stopped.to_string() {
+        // visitors of public ports and share links can't start the workspace,
+        // only its owner and the members of its organization
+        let user = state.authenticate(cookie).await?;
+        check_can_start(&state.db, &user, &main_ws).await?;
+        if !state.conductor.enterprise.has_valid_license().await
+            || !state
+                .conductor
+                .enterprise
+                .auto_start_stop
+                .can_workspace_auto_start(&main_ws)
+                .await
+                .unwrap_or(false)
+        {
+            return Err(ApiError::InvalidRequest(
+                "The workspace is stopped".to_string(),
+            ));
+        }
+        // only the page load starts the workspace, not the requests fired alongside it
+        if !is_page_load(method, headers) {
+            return Ok(Some(unavailable()));
+        }
+
+        let _lock = state.auto_start_lock.lock().await;
+        // another request could have started it while we were waiting for the lock
+        let main_ws = state.db.get_workspace(main_ws.id).await?;
+        if main_ws.status == WorkspaceStatus::Stopped.to_string() {
+            tracing::info!("auto start workspace {} from http", main_ws.name);
+            state
+                .conductor
+                .start_workspace(main_ws, false, info.ip.clone(), info.user_agent.clone())
+                .await?;
+        }
+    } else if main_ws.status != WorkspaceStatus::Starting.to_string() {
+        return Ok(None);
+    }
+
+    if !is_page_load(method, headers) {
+        return Ok(Some(unavailable()));
+    }
+    let hostname = workspace_hostname(state, ws).await?;
+    let scheme = if info.scheme == "https" { "wss" } else { "ws" };
+    let updates_url = format!(
+        "{scheme}://{hostname}{WORKSPACE_UPDATES_PATH}?name={}",
+        ws.name
+    );
+    Ok(Some(progress_page(&ws.name, &updates_url)?))
+}
+
+async fn check_can_start(
+    db: &DbApi,
+    user: &entities::user::Model,
+    ws: &entities::workspace::Model,
+) -> Result<(), ApiError> {
+    if user.id != ws.user_id {
+        db.get_organization_member(user.id, ws.organization_id)
+            .await
+            .map_err(|_| ApiError::Unauthorized)?;
+    }
+    Ok(())
+}
+
+fn is_page_load(method: &Method, headers: &HeaderMap) -> bool {
+    method == Method::GET
+        && headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.contains("text/html"))
+            .unwrap_or(false)
+}
+
+fn unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [
+            (header::RETRY_AFTER, "2"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        [(PROGRESS_HEADER, "1")],
+    )
+        .into_response()
+}
+
+fn progress_page(ws_name: &str, updates_url: &str) -> Result<Response, ApiError> {
+    let page = PROGRESS_PAGE
+        .replace("{workspace}", &serde_json::to_string(ws_name)?)
+        .replace("{updates_url}", &serde_json::to_string(updates_url)?)
+        .replace(
+            "{progress_header}",
+            &serde_json::to_string(PROGRESS_HEADER)?,
+        );
+    Ok((
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::CACHE_CONTROL, "no-store")],
+        [(PROGRESS_HEADER, "1")],
+        Html(page),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use lapdev_common::{AuthProvider, ProviderUser, UserRole};
+    use sea_orm::{ActiveModelTrait, ActiveValue, TransactionTrait};
+    use uuid::Uuid;
+
+    use super::*;
+
+    async fn create_user(db: &DbApi, login: &str) -> entities::user::Model {
+        let txn = db.conn.begin().await.unwrap();
+        let user = db
+            .create_new_user(
+                &txn,
+                &AuthProvider::Local.to_string(),
+                ProviderUser {
+                    id: 0,
+                    subject: Some(login.to_string()),
+                    login: login.to_string(),
+                    name: None,
+                    email: None,
+                    avatar_url: None,
+                },
+                String::new(),
+            )
+            .await
+            .unwrap();
+        txn.commit().await.unwrap();
+        user
+    }
+
+    #[tokio::test]
+    async fn test_check_can_start() {
+        let db = lapdev_db::tests::prepare_db().await.unwrap();
+        let owner = create_user(&db, "owner").await;
+        let other = create_user(&db, "other").await;
+
+        let ws = entities::workspace::Model {
+            id: Uuid::new_v4(),
+            created_at: Utc::now().into(),
+            deleted_at: None,
+            organization_id: owner.current_organization,
+            user_id: owner.id,
+            project_id: None,
+            prebuild_id: None,
+            name: "ws".to_string(),
+            service: None,
+            status: WorkspaceStatus::Stopped.to_string(),
+            repo_url: String::new(),
+            repo_name: String::new(),
+            branch: String::new(),
+            commit: String::new(),
+            host_id: Uuid::new_v4(),
+            osuser: String::new(),
+            ssh_port: None,
+            ide_port: None,
+            ssh_private_key: String::new(),
+            ssh_public_key: String::new(),
+            ssh_host_public_key: None,
+            env: None,
+            cores: String::new(),
+            usage_id: None,
+            machine_type_id: Uuid::new_v4(),
+            build_output: None,
+            last_inactivity: None,
+            auto_start: true,
+            auto_stop: None,
+            is_compose: false,
+            compose_parent: None,
+        };
+        check_can_start(&db, &owner, &ws).await.unwrap();
+        // a user from a different organization can't start it
+        assert!(matches!(
+            check_can_start(&db, &other, &ws).await,
+            Err(ApiError::Unauthorized)
+        ));
+
+        // but a member of the workspace's organization can
+        entities::organization_member::ActiveModel {
+            created_at: ActiveValue::Set(Utc::now().into()),
+            deleted_at: ActiveValue::Set(None),
+            organization_id: ActiveValue::Set(owner.current_organization),
+            user_id: ActiveValue::Set(other.id),
+            role: ActiveValue::Set(UserRole::Member.to_string()),
+            ..Default::default()
+        }
+        .insert(&db.conn)
+        .await
+        .unwrap();
+        check_can_start(&db, &other, &ws).await.unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use oauth2::AccessToken;
+use reqwest::{header, Client, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+
+const LAPDEV_USER_AGENT: &str = "lapdev";
+
+#[derive(Debug, Deserialize)]
+pub struct BitbucketUser {
+    /// Bitbucket doesn't have numeric user ids, the uuid is the stable one
+    pub uuid: String,
+    pub nickname: String,
+    pub display_name: Option<String>,
+    pub links: BitbucketUserLinks,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BitbucketUserLinks {
+    pub avatar: Option<BitbucketLink>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BitbucketLink {
+    pub href: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BitbucketUserEmail {
+    pub email: String,
+    pub is_primary: bool,
+    pub is_confirmed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketPage<T> {
+    values: Vec<T>,
+}
+
+/// The client of Bitbucket Cloud, Bitbucket Data Center has a different API
+#[derive(Clone)]
+pub struct BitbucketClient {
+    base_url: String,
+    client: Client,
+}
+
+impl BitbucketClient {
+    pub fn new(base_url: String) -> Self {
+        let client = reqwest::Client::new();
+        Self { base_url, client }
+    }
+
+    async fn request<T>(&self, url: &str, auth: &AccessToken) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let result = self.get(url, auth).await?.json().await?;
+        Ok(result)
+    }
+
+    async fn get(&self, url: &str, auth: &AccessToken) -> Result<reqwest::Response> {
+        let url = format!("{}{}", self.base_url, url);
+
+        let resp = self
+            .client
+            .get(&url)
+            .header(header::AUTHORIZATION, format!("Bearer {}", auth.secret()))
+            .header(header::USER_AGENT, LAPDEV_USER_AGENT)
+            .send()
+            .await?;
+        Ok(resp)
+    }
+
+    pub async fn current_user(&self, auth: &AccessToken) -> Result<BitbucketUser> {
+        self.request("/user", auth).await
+    }
+
+    pub async fn user_email(&self, auth: &AccessToken) -> Result<Vec<BitbucketUserEmail>> {
+        let page: BitbucketPage<BitbucketUserEmail> = self.request("/user/emails", auth).await?;
+        Ok(page.values)
+    }
+
+    /// If the user can read the repository at `path`, e.g. `workspace/repo`
+    pub async fn repo_access(&self, auth: &AccessToken, path: &str) -> Result<bool> {
+        let resp = self.get(&format!("/repositories/{path}"), auth).await?;
+        match resp.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(false),
+            _ => {
+                resp.error_for_status()?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::Path,
+        http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+        routing::get,
+        Json, Router,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    fn authorized(headers: &HeaderMap) -> bool {
+        headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()) == Some("Bearer the-token")
+    }
+
+    async fn mock_bitbucket() -> BitbucketClient {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route(
+                "/2.0/user",
+                get(|headers: HeaderMap| async move {
+                    if !authorized(&headers) {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+                    Ok(Json(json!({
+                        "uuid": "{6a1b2c3d-0000-0000-0000-000000000000}",
+                        "nickname": "alice",
+                        "display_name": "Alice",
+                        "account_id": "557058:1234",
+                        "links": {
+                            "avatar": { "href": "https://bitbucket.org/account/alice/avatar/" },
+                        },
+                    })))
+                }),
+            )
+            .route(
+                "/2.0/user/emails",
+                get(|| async {
+                    Json(json!({
+                        "pagelen": 10,
+                        "values": [
+                            { "email": "alice@example.com", "is_primary": true, "is_confirmed": true, "type": "email" },
+                        ],
+                    }))
+                }),
+            )
+            .route(
+                "/2.0/repositories/:workspace/:repo",
+                get(
+                    |Path((workspace, repo)): Path<(String, String)>, headers: HeaderMap| async move {
+                        if !authorized(&headers) {
+                            return StatusCode::UNAUTHORIZED;
+                        }
+                        match (workspace.as_str(), repo.as_str()) {
+                            ("team", "lapdev") => StatusCode::OK,
+                            ("team", "private") => StatusCode::FORBIDDEN,
+                            _ => StatusCode::NOT_FOUND,
+                        }
+                    },
+                ),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        BitbucketClient::new(format!("http://{addr}/2.0"))
+    }
+
+    #[tokio::test]
+    async fn test_bitbucket_client() {
+        let client = mock_bitbucket().await;
+        let token = AccessToken::new("the-token".to_string());
+
+        let user = client.current_user(&token).await.unwrap();
+        assert_eq!(user.uuid, "{6a1b2c3d-0000-0000-0000-000000000000}");
+        assert_eq!(user.nickname, "alice");
+        assert_eq!(user.display_name.as_deref(), Some("Alice"));
+        assert_eq!(
+            user.links.avatar.map(|a| a.href).as_deref(),
+            Some("https://bitbucket.org/account/alice/avatar/")
+        );
+
+        let emails = client.user_email(&token).await.unwrap();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].email, "alice@example.com");
+
+        assert!(client.repo_access(&token, "team/lapdev").await.unwrap());
+        assert!(!client.repo_access(&token, "team/private").await.unwrap());
+        assert!(!client.repo_access(&token, "team/missing").await.unwrap());
+
+        let expired = AccessToken::new("expired-token".to_string());
+        assert!(client.current_user(&expired).await.is_err());
+    }
+}
@@ -1,10 +1,17 @@
 use std::{
     collections::HashMap,
     io::{BufReader, Cursor},
+    path::Path,
     sync::{Arc, RwLock},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use lapdev_common::CertificateInfo;
+use lapdev_conductor::cipher::{is_encrypted, KeyCipher, ENCRYPTED_PREFIX};
+use lapdev_db::{api::DbApi, entities};
+use sea_orm::{ActiveValue, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter};
+use sha2::{Digest, Sha256};
 use tokio_rustls::rustls::{
     crypto::ring::sign::any_supported_type,
     server::{ClientHello, ResolvesServerCert},
@@ -13,6 +20,8 @@ use tokio_rustls::rustls::{
 };
 use webpki::EndEntityCert;
 
+use crate::{acme::LAPDEV_ACME_CERTS, state::LAPDEV_CERTS};
+
 pub type CertStore = Arc<RwLock<Arc<HashMap<String, Arc<CertifiedKey>>>>>;
 
 pub fn tls_config(certs: CertStore) -> Result<ServerConfig> {
@@ -80,3 +89,233 @@ pub fn load_cert(cert: &str, key: &str) -> Result<(Vec<String>, CertifiedKey)> {
     let key = CertifiedKey::new(cert, key);
     Ok((dns_names, key))
 }
+
+/// A known value encrypted with the secret key, so that an api server
+/// can tell if its key is the one the stored keys are encrypted with
+const LAPDEV_SECRET_KEY_CHECK: &str = "lapdev-secret-key-check";
+
+/// Load the secret key shared by the api servers. A new key is only generated
+/// when nothing in the db is encrypted yet, and the key is checked against the db,
+/// so that an api server with a different key doesn't start.
+pub async fn load_key_cipher(db: &DbApi, path: &Path) -> Result<KeyCipher> {
+    let check = db.get_config(LAPDEV_SECRET_KEY_CHECK).await.ok();
+    let generate = check.is_none() && !has_encrypted_keys(db).await?;
+    let cipher = KeyCipher::load(path, generate).await?;
+    let check = match check {
+        Some(check) => check,
+        None => {
+            let check = cipher.encrypt(LAPDEV_SECRET_KEY_CHECK)?;
+            // another api server that generated its own key could have stored its check first
+            if entities::config::Entity::insert(entities::config::ActiveModel {
+                name: ActiveValue::Set(LAPDEV_SECRET_KEY_CHECK.to_string()),
+                value: ActiveValue::Set(check.clone()),
+            })
+            .exec(&db.conn)
+            .await
+            .is_ok()
+            {
+                return Ok(cipher);
+            }
+            db.get_config(LAPDEV_SECRET_KEY_CHECK).await?
+        }
+    };
+    if cipher.decrypt(&check).ok().as_deref() != Some(LAPDEV_SECRET_KEY_CHECK) {
+        bail!(
+            "the secret key in {} isn't the one the db is encrypted with, \
+            all the api servers must use the same secret key file",
+            path.to_string_lossy()
+        );
+    }
+    Ok(cipher)
+}
+
+/// Whether anything in the db was encrypted with the secret key
+async fn has_encrypted_keys(db: &DbApi) -> Result<bool> {
+    for name in [LAPDEV_CERTS, LAPDEV_ACME_CERTS] {
+        if stored_certs(db, name)
+            .await?
+            .iter()
+            .any(|(_, key)| is_encrypted(key))
+        {
+            return Ok(true);
+        }
+    }
+    let totp_users = entities::user::Entity::find()
+        .filter(entities::user::Column::TotpSecret.is_not_null())
+        .count(&db.conn)
+        .await?;
+    let git_tokens = entities::user_git_token::Entity::find()
+        .filter(entities::user_git_token::Column::Token.starts_with(ENCRYPTED_PREFIX))
+        .count(&db.conn)
+        .await?;
+    let deploy_keys = entities::project_deploy_key::Entity::find()
+        .filter(entities::project_deploy_key::Column::PrivateKey.starts_with(ENCRYPTED_PREFIX))
+        .count(&db.conn)
+        .await?;
+    let project_tokens = entities::project::Entity::find()
+        .filter(entities::project::Column::GitCredentialToken.is_not_null())
+        .count(&db.conn)
+        .await?;
+    Ok(totp_users > 0 || git_tokens > 0 || deploy_keys > 0 || project_tokens > 0)
+}
+
+/// The `(cert, encrypted key)` pairs in the config, empty if it's not set
+pub async fn stored_certs(db: &DbApi, name: &str) -> Result<Vec<(String, String)>> {
+    match db.get_config(name).await {
+        Ok(certs) => Ok(serde_json::from_str(&certs)?),
+        Err(_) => Ok(vec![]),
+    }
+}
+
+pub async fn save_certs(db: &DbApi, name: &str, certs: &[(String, String)]) -> Result<()> {
+    db.update_config(name, &serde_json::to_string(certs)?)
+        .await?;
+    Ok(())
+}
+
+/// Encrypt the keys that were stored in plain text before the keys were encrypted
+pub async fn encrypt_stored_keys(db: &DbApi, cipher: &KeyCipher, name: &str) -> Result<()> {
+    let mut certs = stored_certs(db, name).await?;
+    if certs.iter().all(|(_, key)| is_encrypted(key)) {
+        return Ok(());
+    }
+    for (_, key) in certs.iter_mut() {
+        if !is_encrypted(key) {
+            *key = cipher.encrypt(key)?;
+        }
+    }
+    save_certs(db, name, &certs).await?;
+    tracing::info!("encrypted the stored private keys of {name}");
+    Ok(())
+}
+
+/// The metadata of the leaf certificate in the chain,
+/// it's expiring if it expires within `warning_days`.
+pub fn cert_info(chain: &str, acme: bool, warning_days: i64) -> Result<CertificateInfo> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(chain.as_bytes())?;
+    let cert = pem.parse_x509()?;
+    let dns_names = cert
+        .subject_alternative_name()?
+        .map(|san| {
+            san.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    x509_parser::extensions::GeneralName::DNSName(name) => {
+                        Some(name.to_lowercase())
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let validity = cert.validity();
+    let not_before = chrono::DateTime::from_timestamp(validity.not_before.timestamp(), 0)
+        .ok_or_else(|| anyhow!("invalid certificate validity"))?;
+    let not_after = chrono::DateTime::from_timestamp(validity.not_after.timestamp(), 0)
+        .ok_or_else(|| anyhow!("invalid certificate validity"))?;
+    let fingerprint = base16ct::lower::encode_string(&Sha256::digest(&pem.contents));
+    Ok(CertificateInfo {
+        fingerprint,
+        acme,
+        dns_names,
+        issuer: cert.issuer().to_string(),
+        not_before,
+        not_after,
+        expiring: not_after - Utc::now() < chrono::Duration::days(warning_days),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{date_time_ymd, Certificate, CertificateParams};
+
+    use super::*;
+
+    fn test_cert(not_before: (i32, u8, u8), not_after: (i32, u8, u8)) -> String {
+        let mut params = CertificateParams::new(vec!["Lapdev.example.com".to_string()]);
+        params.not_before = date_time_ymd(not_before.0, not_before.1, not_before.2);
+        params.not_after = date_time_ymd(not_after.0, not_after.1, not_after.2);
+        Certificate::from_params(params)
+            .unwrap()
+            .serialize_pem()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_stored_keys() {
+        let db = lapdev_db::tests::prepare_db().await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let cipher = KeyCipher::load(&dir.path().join("secret.key"), true)
+            .await
+            .unwrap();
+
+        let encrypted = cipher.encrypt("key2").unwrap();
+        save_certs(
+            &db,
+            LAPDEV_CERTS,
+            &[
+                ("cert1".to_string(), "key1".to_string()),
+                ("cert2".to_string(), encrypted.clone()),
+            ],
+        )
+        .await
+        .unwrap();
+        assert!(has_encrypted_keys(&db).await.unwrap());
+
+        encrypt_stored_keys(&db, &cipher, LAPDEV_CERTS)
+            .await
+            .unwrap();
+        let certs = stored_certs(&db, LAPDEV_CERTS).await.unwrap();
+        assert_eq!(certs.len(), 2);
+        assert_eq!(certs[0].0, "cert1");
+        assert!(is_encrypted(&certs[0].1));
+        assert_eq!(cipher.decrypt(&certs[0].1).unwrap(), "key1");
+        // the encrypted ones are kept
+        assert_eq!(certs[1].1, encrypted);
+    }
+
+    #[tokio::test]
+    async fn test_load_key_cipher() {
+        let db = lapdev_db::tests::prepare_db().await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.key");
+        assert!(!has_encrypted_keys(&db).await.unwrap());
+
+        let cipher = load_key_cipher(&db, &path).await.unwrap();
+        let encrypted = cipher.encrypt("key").unwrap();
+        let loaded = load_key_cipher(&db, &path).await.unwrap();
+        assert_eq!(loaded.decrypt(&encrypted).unwrap(), "key");
+
+        // another api server without the key file doesn't generate its own
+        let missing = dir.path().join("missing.key");
+        assert!(load_key_cipher(&db, &missing).await.is_err());
+        assert!(!missing.exists());
+
+        let other = dir.path().join("other.key");
+        KeyCipher::load(&other, true).await.unwrap();
+        assert!(load_key_cipher(&db, &other).await.is_err());
+    }
+
+    #[test]
+    fn test_cert_info() {
+        let cert = test_cert((2020, 1, 1), (2099, 1, 1));
+        let info = cert_info(&cert, true, 14).unwrap();
+        assert!(info.acme);
+        assert_eq!(info.dns_names, vec!["lapdev.example.com".to_string()]);
+        assert_eq!(info.fingerprint.len(), 64);
+        assert_eq!(info.not_before.to_rfc3339(), "2020-01-01T00:00:00+00:00");
+        assert_eq!(info.not_after.to_rfc3339(), "2099-01-01T00:00:00+00:00");
+        assert!(!info.expiring);
+
+        // expires within the warning days
+        let info = cert_info(&cert, false, 365 * 100).unwrap();
+        assert!(!info.acme);
+        assert!(info.expiring);
+
+        let expired = test_cert((2000, 1, 1), (2001, 1, 1));
+        assert!(cert_info(&expired, false, 14).unwrap().expiring);
+
+        assert!(cert_info("not a certificate", false, 14).is_err());
+    }
+}
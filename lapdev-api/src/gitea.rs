@@ -0,0 +1,165 @@
+use anyhow::Result;
+use oauth2::AccessToken;
+use reqwest::{header, Client, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+
+const LAPDEV_USER_AGENT: &str = "lapdev";
+
+#[derive(Debug, Deserialize)]
+pub struct GiteaUser {
+    pub avatar_url: Option<String>,
+    pub email: Option<String>,
+    pub id: i32,
+    pub login: String,
+    pub full_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GiteaUserEmail {
+    pub email: String,
+    pub verified: bool,
+    pub primary: bool,
+}
+
+/// Forgejo is a fork of Gitea and keeps its API, so this works for both
+#[derive(Clone)]
+pub struct GiteaClient {
+    base_url: String,
+    client: Client,
+}
+
+impl GiteaClient {
+    /// Talks to the API at `base_url`, which differs between the public service and self-hosted instances
+    pub fn new(base_url: String) -> Self {
+        let client = reqwest::Client::new();
+        Self { base_url, client }
+    }
+
+    async fn request<T>(&self, url: &str, auth: &AccessToken) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let result = self.get(url, auth).await?.json().await?;
+        Ok(result)
+    }
+
+    async fn get(&self, url: &str, auth: &AccessToken) -> Result<reqwest::Response> {
+        let url = format!("{}{}", self.base_url, url);
+
+        let resp = self
+            .client
+            .get(&url)
+            .header(header::AUTHORIZATION, format!("Bearer {}", auth.secret()))
+            .header(header::USER_AGENT, LAPDEV_USER_AGENT)
+            .send()
+            .await?;
+        Ok(resp)
+    }
+
+    pub async fn current_user(&self, auth: &AccessToken) -> Result<GiteaUser> {
+        self.request("/user", auth).await
+    }
+
+    pub async fn user_email(&self, auth: &AccessToken) -> Result<Vec<GiteaUserEmail>> {
+        self.request("/user/emails", auth).await
+    }
+
+    /// If the user can read the repository at `path`, e.g. `owner/repo`
+    pub async fn repo_access(&self, auth: &AccessToken, path: &str) -> Result<bool> {
+        let resp = self.get(&format!("/repos/{path}"), auth).await?;
+        match resp.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(false),
+            _ => {
+                resp.error_for_status()?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::Path,
+        http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+        routing::get,
+        Json, Router,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    fn authorized(headers: &HeaderMap) -> bool {
+        headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()) == Some("Bearer the-token")
+    }
+
+    async fn mock_gitea() -> GiteaClient {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route(
+                "/api/v1/user",
+                get(|headers: HeaderMap| async move {
+                    if !authorized(&headers) {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+                    Ok(Json(json!({
+                        "id": 7,
+                        "login": "alice",
+                        "full_name": "",
+                        "email": "alice@noreply.gitea.example.com",
+                        "avatar_url": "https://gitea.example.com/avatars/7",
+                    })))
+                }),
+            )
+            .route(
+                "/api/v1/user/emails",
+                get(|| async {
+                    Json(json!([
+                        { "email": "old@example.com", "verified": true, "primary": false },
+                        { "email": "alice@example.com", "verified": true, "primary": true },
+                    ]))
+                }),
+            )
+            .route(
+                "/api/v1/repos/:owner/:repo",
+                get(
+                    |Path((owner, repo)): Path<(String, String)>, headers: HeaderMap| async move {
+                        if !authorized(&headers) {
+                            return StatusCode::UNAUTHORIZED;
+                        }
+                        match (owner.as_str(), repo.as_str()) {
+                            ("alice", "lapdev") => StatusCode::OK,
+                            ("alice", "broken") => StatusCode::INTERNAL_SERVER_ERROR,
+                            _ => StatusCode::NOT_FOUND,
+                        }
+                    },
+                ),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        GiteaClient::new(format!("http://{addr}/api/v1"))
+    }
+
+    #[tokio::test]
+    async fn test_gitea_client() {
+        let client = mock_gitea().await;
+        let token = AccessToken::new("the-token".to_string());
+
+        let user = client.current_user(&token).await.unwrap();
+        assert_eq!(user.id, 7);
+        assert_eq!(user.login, "alice");
+        assert_eq!(user.full_name.as_deref(), Some(""));
+
+        let emails = client.user_email(&token).await.unwrap();
+        assert_eq!(emails.len(), 2);
+        assert!(emails[1].primary && emails[1].verified);
+
+        assert!(client.repo_access(&token, "alice/lapdev").await.unwrap());
+        assert!(!client.repo_access(&token, "bob/private").await.unwrap());
+        assert!(client.repo_access(&token, "alice/broken").await.is_err());
+
+        let other = AccessToken::new("other-token".to_string());
+        assert!(client.current_user(&other).await.is_err());
+        assert!(!client.repo_access(&other, "alice/lapdev").await.unwrap());
+    }
+}
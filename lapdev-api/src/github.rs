@@ -1,15 +1,14 @@
 use anyhow::Result;
 use oauth2::AccessToken;
-use reqwest::{header, Client};
+use reqwest::{header, Client, StatusCode};
 use serde::{de::DeserializeOwned, Deserialize};
 
-const GITHUB_API_ENDPOINT: &str = "https://api.github.com";
-
 const LAPDEV_USER_AGENT: &str = "lapdev";
 
 #[derive(Debug, Deserialize)]
 pub struct GithubUser {
     pub avatar_url: Option<String>,
+    #[allow(dead_code)]
     pub email: Option<String>,
     pub id: i32,
     pub login: String,
@@ -19,8 +18,10 @@ pub struct GithubUser {
 #[derive(Debug, Deserialize)]
 pub struct GithubUserEmail {
     pub email: String,
+    #[allow(dead_code)]
     pub verified: bool,
     pub primary: bool,
+    #[allow(dead_code)]
     pub visibility: Option<String>,
 }
 
@@ -30,19 +31,11 @@ pub struct GithubClient {
     client: Client,
 }
 
-impl Default for GithubClient {
-    fn default() -> Self {
-        GithubClient::new()
-    }
-}
-
 impl GithubClient {
-    pub fn new() -> Self {
+    /// Talks to the API at `base_url`, which differs between the public service and self-hosted instances
+    pub fn new(base_url: String) -> Self {
         let client = reqwest::Client::new();
-        Self {
-            base_url: GITHUB_API_ENDPOINT.to_string(),
-            client,
-        }
+        Self { base_url, client }
     }
 
     /// Does all the nonsense for sending a GET to Github.
@@ -86,4 +79,23 @@ impl GithubClient {
     pub async fn user_email(&self, auth: &AccessToken) -> Result<Vec<GithubUserEmail>> {
         self.request("/user/emails", auth).await
     }
+
+    /// If the user can read the repository at `path`, e.g. `owner/repo`
+    pub async fn repo_access(&self, auth: &AccessToken, path: &str) -> Result<bool> {
+        let resp = self
+            .client()
+            .get(format!("{}/repos/{path}", self.base_url))
+            .header(header::ACCEPT, "application/vnd.github+json")
+            .header(header::AUTHORIZATION, format!("token {}", auth.secret()))
+            .header(header::USER_AGENT, LAPDEV_USER_AGENT)
+            .send()
+            .await?;
+        match resp.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(false),
+            _ => {
+                resp.error_for_status()?;
+                Ok(true)
+            }
+        }
+    }
 }
@@ -1,9 +1,8 @@
 use anyhow::Result;
 use oauth2::AccessToken;
-use reqwest::{header, Client};
+use reqwest::{header, Client, StatusCode};
 use serde::{de::DeserializeOwned, Deserialize};
 
-const GITLAB_API_ENDPOINT: &str = "https://gitlab.com/api/v4";
 const LAPDEV_USER_AGENT: &str = "lapdev";
 
 #[derive(Debug, Deserialize)]
@@ -21,40 +20,48 @@ pub struct GitlabClient {
     client: Client,
 }
 
-impl Default for GitlabClient {
-    fn default() -> Self {
-        GitlabClient::new()
-    }
-}
-
 impl GitlabClient {
-    pub fn new() -> Self {
+    /// Talks to the API at `base_url`, which differs between the public service and self-hosted instances
+    pub fn new(base_url: String) -> Self {
         let client = reqwest::Client::new();
-        Self {
-            base_url: GITLAB_API_ENDPOINT.to_string(),
-            client,
-        }
+        Self { base_url, client }
     }
 
     async fn request<T>(&self, url: &str, auth: &AccessToken) -> Result<T>
     where
         T: DeserializeOwned,
     {
+        let result = self.get(url, auth).await?.json().await?;
+        Ok(result)
+    }
+
+    async fn get(&self, url: &str, auth: &AccessToken) -> Result<reqwest::Response> {
         let url = format!("{}{}", self.base_url, url);
 
-        let result = self
+        let resp = self
             .client
             .get(&url)
             .header(header::AUTHORIZATION, format!("Bearer {}", auth.secret()))
             .header(header::USER_AGENT, LAPDEV_USER_AGENT)
             .send()
-            .await?
-            .json()
             .await?;
-        Ok(result)
+        Ok(resp)
     }
 
     pub async fn current_user(&self, auth: &AccessToken) -> Result<GitlabUser> {
         self.request("/user", auth).await
     }
+
+    /// If the user can read the project at `path`, e.g. `group/subgroup/project`
+    pub async fn repo_access(&self, auth: &AccessToken, path: &str) -> Result<bool> {
+        let path = path.replace('/', "%2F");
+        let resp = self.get(&format!("/projects/{path}"), auth).await?;
+        match resp.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(false),
+            _ => {
+                resp.error_for_status()?;
+                Ok(true)
+            }
+        }
+    }
 }
@@ -1,11 +1,18 @@
 mod account;
+mod acme;
 mod admin;
 mod auth;
+mod auto_start;
+mod bitbucket;
 mod cert;
+mod gitea;
 mod github;
 mod gitlab;
+mod local;
 mod machine_type;
+mod oidc;
 mod organization;
+mod port;
 mod prebuild;
 mod project;
 mod router;
@@ -0,0 +1,733 @@
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{Host, Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::{headers::Cookie, TypedHeader};
+use chrono::{Duration, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use hyper::StatusCode;
+use lapdev_common::{
+    AuditAction, AuditResourceKind, AuthProvider, ChangePassword, LocalBootstrap, LocalLogin,
+    LocalLoginResponse, LocalPasswordReset, LocalPasswordSetup, LocalSetupToken, NewLocalUser,
+    ProviderUser, TotpCode, TotpEnrollment,
+};
+use lapdev_db::entities;
+use lapdev_rpc::error::ApiError;
+use rand::RngCore;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter, TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    session::{check_users_limit, session_cookie},
+    state::{CoreState, RequestInfo},
+};
+
+const MIN_PASSWORD_LEN: usize = 10;
+/// How long the password setup tokens handed out by the admins are valid
+const SETUP_TOKEN_DAYS: i64 = 7;
+/// The seconds of a TOTP time step
+const TOTP_STEP: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// The window the failed logins are counted in
+const LOGIN_ATTEMPT_WINDOW: std::time::Duration = std::time::Duration::from_secs(900);
+/// A login is locked after this many failed attempts in the window, from any ip
+const MAX_LOGIN_FAILURES_PER_ACCOUNT: u32 = 10;
+/// An ip can't log in after this many failed attempts in the window, to any login
+const MAX_LOGIN_FAILURES_PER_IP: u32 = 30;
+
+struct LoginAttempts {
+    since: Instant,
+    failures: u32,
+}
+
+impl LoginAttempts {
+    fn count(&self, now: Instant) -> u32 {
+        if now.duration_since(self.since) > LOGIN_ATTEMPT_WINDOW {
+            0
+        } else {
+            self.failures
+        }
+    }
+}
+
+#[derive(Default)]
+struct LoginLimiterState {
+    logins: HashMap<String, LoginAttempts>,
+    ips: HashMap<String, LoginAttempts>,
+}
+
+/// Throttles the failed password and two-factor attempts of the local logins,
+/// per login and per ip. The counts are kept by each api server.
+#[derive(Default)]
+pub struct LoginLimiter {
+    state: Mutex<LoginLimiterState>,
+}
+
+impl LoginLimiter {
+    pub fn check(&self, login: &str, ip: Option<&str>, now: Instant) -> Result<(), ApiError> {
+        let state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("login limiter poisoned"))?;
+        let login_failures = state
+            .logins
+            .get(&login.to_lowercase())
+            .map(|a| a.count(now))
+            .unwrap_or(0);
+        let ip_failures = ip
+            .and_then(|ip| state.ips.get(ip))
+            .map(|a| a.count(now))
+            .unwrap_or(0);
+        if login_failures >= MAX_LOGIN_FAILURES_PER_ACCOUNT
+            || ip_failures >= MAX_LOGIN_FAILURES_PER_IP
+        {
+            return Err(ApiError::InvalidRequest(
+                "too many failed login attempts, please try again later".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn fail(&self, login: &str, ip: Option<&str>, now: Instant) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let state = &mut *state;
+        for attempts in [&mut state.logins, &mut state.ips] {
+            attempts.retain(|_, a| a.count(now) > 0);
+        }
+        let keys = [
+            (&mut state.logins, Some(login.to_lowercase())),
+            (&mut state.ips, ip.map(|ip| ip.to_string())),
+        ];
+        for (attempts, key) in keys {
+            if let Some(key) = key {
+                let attempts = attempts.entry(key).or_insert(LoginAttempts {
+                    since: now,
+                    failures: 0,
+                });
+                if attempts.count(now) == 0 {
+                    attempts.since = now;
+                    attempts.failures = 0;
+                }
+                attempts.failures += 1;
+            }
+        }
+    }
+
+    /// Clears the failures of the login, the ones of the ip are kept
+    pub fn succeed(&self, login: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.logins.remove(&login.to_lowercase());
+        }
+    }
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("hash password error: {e}"))?;
+    Ok(hash.to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Argon2 is slow on purpose, so it's kept off the async workers
+async fn hash_password_blocking(password: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || hash_password(&password)).await?
+}
+
+async fn verify_password_blocking(password: String, hash: Option<String>) -> bool {
+    tokio::task::spawn_blocking(move || match hash {
+        Some(hash) => verify_password(&password, &hash),
+        None => {
+            // hash anyway so that the response time doesn't tell if the account exists
+            let _ = hash_password(&password);
+            false
+        }
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// A random token for the password setup links, only its hash is stored
+fn new_setup_token() -> String {
+    let mut token = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut token);
+    base16ct::lower::encode_string(&token)
+}
+
+fn hash_setup_token(token: &str) -> String {
+    base16ct::lower::encode_string(&Sha256::digest(token.as_bytes()))
+}
+
+/// A new base32 encoded TOTP secret
+fn new_totp_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// The code of the time step, see RFC 6238
+fn totp_code(secret: &[u8], step: u64) -> Result<String> {
+    let mut mac = Hmac::<sha1::Sha1>::new_from_slice(secret)?;
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) % 10u32.pow(TOTP_DIGITS);
+    Ok(format!("{code:0width$}", width = TOTP_DIGITS as usize))
+}
+
+/// Checks the code against the current time step and the ones next to it for clock drift,
+/// returns the matched step, which must be after the last used one so that a code can't be replayed
+fn verify_totp(secret: &str, code: &str, now: u64, last_step: Option<i64>) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim().replace(' ', "");
+    let current = now / TOTP_STEP;
+    [current.saturating_sub(1), current, current + 1]
+        .into_iter()
+        .filter(|step| last_step.map(|last| *step as i64 > last).unwrap_or(true))
+        .find(|step| totp_code(&secret, *step).ok().as_deref() == Some(code.as_str()))
+        .map(|step| step as i64)
+}
+
+fn totp_url(login: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/Lapdev:{login}?secret={secret}&issuer=Lapdev&digits={TOTP_DIGITS}&period={TOTP_STEP}"
+    )
+}
+
+fn validate_login(login: &str) -> Result<String, ApiError> {
+    let login = login.trim();
+    if login.is_empty()
+        || login.len() > 64
+        || !login
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        return Err(ApiError::InvalidRequest(
+            "login can only have letters, numbers, -, _ and ., and at most 64 of them".to_string(),
+        ));
+    }
+    Ok(login.to_string())
+}
+
+fn validate_password(password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiError::InvalidRequest(format!(
+            "password needs at least {MIN_PASSWORD_LEN} characters"
+        )));
+    }
+    Ok(())
+}
+
+fn require_local(user: &entities::user::Model) -> Result<(), ApiError> {
+    if user.provider != AuthProvider::Local.to_string() {
+        return Err(ApiError::InvalidRequest(
+            "only local accounts have a password".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Verifies the code from the enabled or the pending secret of the user, and marks the code as used
+async fn check_totp(
+    state: &CoreState,
+    user: &entities::user::Model,
+    code: &str,
+) -> Result<(), ApiError> {
+    let invalid = || ApiError::InvalidRequest("the two-factor code is invalid".to_string());
+    let secret = user.totp_secret.as_ref().ok_or_else(invalid)?;
+    let secret = state.key_cipher.decrypt(secret)?;
+    let now = Utc::now().timestamp().max(0) as u64;
+    let step = verify_totp(&secret, code, now, user.totp_last_step).ok_or_else(invalid)?;
+    entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        totp_last_step: ActiveValue::Set(Some(step)),
+        ..Default::default()
+    }
+    .update(&state.db.conn)
+    .await?;
+    Ok(())
+}
+
+async fn new_local_user(
+    state: &CoreState,
+    txn: &sea_orm::DatabaseTransaction,
+    login: &str,
+    name: Option<String>,
+    email: Option<String>,
+) -> Result<entities::user::Model, ApiError> {
+    if state.db.get_local_user(login).await?.is_some() {
+        return Err(ApiError::InvalidRequest(format!(
+            "login {login} is already used"
+        )));
+    }
+    let user = state
+        .db
+        .create_new_user(
+            txn,
+            &AuthProvider::Local.to_string(),
+            ProviderUser {
+                id: 0,
+                // the login is unique among the local accounts through the subject
+                subject: Some(login.to_lowercase()),
+                login: login.to_string(),
+                name: name.or_else(|| Some(login.to_string())),
+                email,
+                avatar_url: None,
+            },
+            String::new(),
+        )
+        .await?;
+    Ok(user)
+}
+
+pub async fn local_login(
+    Host(hostname): Host,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(login): Json<LocalLogin>,
+) -> Result<Response, ApiError> {
+    let invalid = || ApiError::InvalidRequest("the login or password is invalid".to_string());
+    let name = login.login.trim();
+    let ip = info.ip.as_deref();
+    state.login_limiter.check(name, ip, Instant::now())?;
+
+    let user = state.db.get_local_user(name).await?;
+    let hash = user.as_ref().and_then(|u| u.password_hash.clone());
+    if !verify_password_blocking(login.password, hash).await {
+        state.login_limiter.fail(name, ip, Instant::now());
+        return Err(invalid());
+    }
+    let user = user.ok_or_else(invalid)?;
+
+    if user.totp_enabled {
+        let Some(code) = login.totp_code.filter(|c| !c.trim().is_empty()) else {
+            return Ok(Json(LocalLoginResponse {
+                totp_required: true,
+            })
+            .into_response());
+        };
+        if let Err(e) = check_totp(&state, &user, &code).await {
+            state.login_limiter.fail(name, ip, Instant::now());
+            return Err(e);
+        }
+    }
+    state.login_limiter.succeed(name);
+
+    let headers = session_cookie(&state, &hostname, &user)?;
+    Ok((
+        headers,
+        Json(LocalLoginResponse {
+            totp_required: false,
+        }),
+    )
+        .into_response())
+}
+
+/// Creates the first cluster admin as a local account, so that the cluster
+/// can be set up without registering an OAuth app first
+pub async fn local_bootstrap(
+    Host(hostname): Host,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(bootstrap): Json<LocalBootstrap>,
+) -> Result<Response, ApiError> {
+    let login = validate_login(&bootstrap.login)?;
+    validate_password(&bootstrap.password)?;
+    let password_hash = hash_password_blocking(bootstrap.password).await?;
+
+    let txn = state.db.conn.begin().await?;
+    if state.db.is_cluster_initiated(&txn).await {
+        return Err(ApiError::InvalidRequest(
+            "the cluster already has an admin".to_string(),
+        ));
+    }
+    let user = new_local_user(&state, &txn, &login, None, None).await?;
+    let user = entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        password_hash: ActiveValue::Set(Some(password_hash)),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            Utc::now().into(),
+            user.id,
+            user.current_organization,
+            AuditResourceKind::User.to_string(),
+            user.id,
+            user.name.clone().unwrap_or_default(),
+            AuditAction::UserCreate.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+    txn.commit().await?;
+
+    let headers = session_cookie(&state, &hostname, &user)?;
+    Ok((headers, StatusCode::NO_CONTENT).into_response())
+}
+
+pub async fn setup_local_password(
+    State(state): State<CoreState>,
+    Json(setup): Json<LocalPasswordSetup>,
+) -> Result<StatusCode, ApiError> {
+    validate_password(&setup.password)?;
+    let user = entities::user::Entity::find()
+        .filter(entities::user::Column::SetupToken.eq(hash_setup_token(setup.token.trim())))
+        .filter(entities::user::Column::SetupTokenExpiresAt.gt(Utc::now()))
+        .filter(entities::user::Column::DeletedAt.is_null())
+        .one(&state.db.conn)
+        .await?
+        .ok_or_else(|| {
+            ApiError::InvalidRequest("the setup link is invalid or expired".to_string())
+        })?;
+    let password_hash = hash_password_blocking(setup.password).await?;
+    entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        password_hash: ActiveValue::Set(Some(password_hash)),
+        setup_token: ActiveValue::Set(None),
+        setup_token_expires_at: ActiveValue::Set(None),
+        ..Default::default()
+    }
+    .update(&state.db.conn)
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn setup_token(user_id: Uuid) -> (LocalSetupToken, String) {
+    let token = new_setup_token();
+    let hash = hash_setup_token(&token);
+    let expires_at = Utc::now() + Duration::days(SETUP_TOKEN_DAYS);
+    (
+        LocalSetupToken {
+            user_id,
+            token,
+            expires_at: expires_at.into(),
+        },
+        hash,
+    )
+}
+
+/// Without a password in the request, the user is invited with a setup token instead
+pub async fn create_local_user(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(new_user): Json<NewLocalUser>,
+) -> Result<Json<Option<LocalSetupToken>>, ApiError> {
+    let admin = state.authenticate_cluster_admin(&cookie).await?;
+    let login = validate_login(&new_user.login)?;
+    let password_hash = match new_user.password {
+        Some(password) => {
+            validate_password(&password)?;
+            Some(hash_password_blocking(password).await?)
+        }
+        None => None,
+    };
+    check_users_limit(&state).await?;
+
+    let txn = state.db.conn.begin().await?;
+    let user = new_local_user(
+        &state,
+        &txn,
+        &login,
+        new_user.name.filter(|n| !n.trim().is_empty()),
+        new_user.email.filter(|e| !e.trim().is_empty()),
+    )
+    .await?;
+    let (token, token_hash) = setup_token(user.id);
+    let token = if password_hash.is_none() {
+        Some(token)
+    } else {
+        None
+    };
+    let user = entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        cluster_admin: ActiveValue::Set(new_user.cluster_admin),
+        setup_token: ActiveValue::Set(token.as_ref().map(|_| token_hash)),
+        setup_token_expires_at: ActiveValue::Set(token.as_ref().map(|t| t.expires_at)),
+        password_hash: ActiveValue::Set(password_hash),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            Utc::now().into(),
+            admin.id,
+            user.current_organization,
+            AuditResourceKind::User.to_string(),
+            user.id,
+            user.name.clone().unwrap_or_default(),
+            AuditAction::UserCreate.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+    txn.commit().await?;
+
+    Ok(Json(token))
+}
+
+/// Removes the password of a local user and hands out a new setup token for them
+pub async fn reset_local_password(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path(user_id): Path<Uuid>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(reset): Json<LocalPasswordReset>,
+) -> Result<Json<LocalSetupToken>, ApiError> {
+    let admin = state.authenticate_cluster_admin(&cookie).await?;
+    let user = state
+        .db
+        .get_user(user_id)
+        .await?
+        .ok_or_else(|| ApiError::InvalidRequest("user doesn't exist".to_string()))?;
+    require_local(&user)?;
+
+    let (token, token_hash) = setup_token(user.id);
+    let mut update = entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        password_hash: ActiveValue::Set(None),
+        setup_token: ActiveValue::Set(Some(token_hash)),
+        setup_token_expires_at: ActiveValue::Set(Some(token.expires_at)),
+        // log out the existing sessions
+        session_epoch: ActiveValue::Set(user.session_epoch + 1),
+        ..Default::default()
+    };
+    if reset.reset_totp {
+        update.totp_secret = ActiveValue::Set(None);
+        update.totp_enabled = ActiveValue::Set(false);
+    }
+    let txn = state.db.conn.begin().await?;
+    update.update(&txn).await?;
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            Utc::now().into(),
+            admin.id,
+            user.current_organization,
+            AuditResourceKind::User.to_string(),
+            user.id,
+            user.name.clone().unwrap_or_default(),
+            AuditAction::UserResetPassword.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+    txn.commit().await?;
+
+    Ok(Json(token))
+}
+
+/// Logs out the other sessions of the user, and hands out a new one for the current
+pub async fn change_password(
+    Host(hostname): Host,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+    Json(change): Json<ChangePassword>,
+) -> Result<Response, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    require_local(&user)?;
+    validate_password(&change.new)?;
+    if !verify_password_blocking(change.current, user.password_hash.clone()).await {
+        return Err(ApiError::InvalidRequest(
+            "the current password is invalid".to_string(),
+        ));
+    }
+    let password_hash = hash_password_blocking(change.new).await?;
+    let user = entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        password_hash: ActiveValue::Set(Some(password_hash)),
+        session_epoch: ActiveValue::Set(user.session_epoch + 1),
+        ..Default::default()
+    }
+    .update(&state.db.conn)
+    .await?;
+    let headers = session_cookie(&state, &hostname, &user)?;
+    Ok((headers, StatusCode::NO_CONTENT).into_response())
+}
+
+/// Generates a pending TOTP secret, which is enabled by [`enable_totp`] with a code from it
+pub async fn begin_totp(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+) -> Result<Json<TotpEnrollment>, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    require_local(&user)?;
+    if user.totp_enabled {
+        return Err(ApiError::InvalidRequest(
+            "two-factor authentication is already enabled".to_string(),
+        ));
+    }
+    let secret = new_totp_secret();
+    entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        totp_secret: ActiveValue::Set(Some(state.key_cipher.encrypt(&secret)?)),
+        ..Default::default()
+    }
+    .update(&state.db.conn)
+    .await?;
+    Ok(Json(TotpEnrollment {
+        url: totp_url(&user.provider_login, &secret),
+        secret,
+    }))
+}
+
+pub async fn enable_totp(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+    Json(code): Json<TotpCode>,
+) -> Result<StatusCode, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    require_local(&user)?;
+    check_totp(&state, &user, &code.code).await?;
+    entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        totp_enabled: ActiveValue::Set(true),
+        ..Default::default()
+    }
+    .update(&state.db.conn)
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn disable_totp(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    State(state): State<CoreState>,
+    Json(code): Json<TotpCode>,
+) -> Result<StatusCode, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    require_local(&user)?;
+    if !user.totp_enabled {
+        return Err(ApiError::InvalidRequest(
+            "two-factor authentication isn't enabled".to_string(),
+        ));
+    }
+    check_totp(&state, &user, &code.code).await?;
+    entities::user::ActiveModel {
+        id: ActiveValue::Set(user.id),
+        totp_secret: ActiveValue::Set(None),
+        totp_enabled: ActiveValue::Set(false),
+        ..Default::default()
+    }
+    .update(&state.db.conn)
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totp_code() {
+        // the sha1 test vectors of RFC 6238
+        let secret = b"12345678901234567890";
+        for (time, code) in [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+        ] {
+            assert_eq!(totp_code(secret, time / TOTP_STEP).unwrap(), code[2..]);
+        }
+    }
+
+    #[test]
+    fn test_verify_totp() {
+        let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+        let step = verify_totp(&secret, "287 082", 59, None).unwrap();
+        assert_eq!(step, 1);
+        // within the drift of one step
+        assert_eq!(verify_totp(&secret, "287082", 89, None), Some(1));
+        assert_eq!(verify_totp(&secret, "287082", 120, None), None);
+        // can't be used again
+        assert_eq!(verify_totp(&secret, "287082", 59, Some(step)), None);
+    }
+
+    #[test]
+    fn test_login_limiter() {
+        let limiter = LoginLimiter::default();
+        let now = Instant::now();
+        let ip = Some("10.0.0.1");
+        for _ in 0..MAX_LOGIN_FAILURES_PER_ACCOUNT {
+            assert!(limiter.check("admin", ip, now).is_ok());
+            limiter.fail("admin", ip, now);
+        }
+        // locked from any ip, and the login isn't case sensitive
+        assert!(limiter.check("admin", ip, now).is_err());
+        assert!(limiter.check("Admin", Some("10.0.0.2"), now).is_err());
+        assert!(limiter.check("other", ip, now).is_ok());
+        // unlocked after the window
+        let later = now + LOGIN_ATTEMPT_WINDOW + std::time::Duration::from_secs(1);
+        assert!(limiter.check("admin", ip, later).is_ok());
+
+        limiter.fail("admin", ip, later);
+        limiter.succeed("admin");
+        assert_eq!(
+            limiter
+                .state
+                .lock()
+                .unwrap()
+                .logins
+                .get("admin")
+                .map(|a| a.failures),
+            None
+        );
+
+        // the ip is blocked for every login
+        for i in 0..MAX_LOGIN_FAILURES_PER_IP {
+            limiter.fail(&format!("user{i}"), ip, later);
+        }
+        assert!(limiter.check("admin", ip, later).is_err());
+        assert!(limiter.check("admin", Some("10.0.0.2"), later).is_ok());
+        assert!(limiter.check("admin", None, later).is_ok());
+    }
+
+    #[test]
+    fn test_password() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use lapdev_common::{OidcSettings, ProviderUser};
+use openidconnect::{
+    core::{CoreAuthenticationFlow, CoreClient, CoreJwsSigningAlgorithm, CoreProviderMetadata},
+    reqwest::async_http_client,
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, OAuth2TokenResponse,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use serde_json::Value;
+
+/// The provider metadata is discovered again after this, so that rotated signing keys are picked up
+pub const OIDC_REDISCOVER_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// A client of a generic OpenID Connect provider, built from the discovered provider metadata
+pub struct OidcClient {
+    pub settings: OidcSettings,
+    pub discovered_at: Instant,
+    client: CoreClient,
+    /// The id token signing algorithms the provider supports
+    algs: Vec<CoreJwsSigningAlgorithm>,
+    userinfo_url: Option<String>,
+}
+
+/// The state of an authorization request that's needed again when the code is exchanged
+pub struct OidcAuthorization {
+    pub url: String,
+    pub csrf: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+}
+
+pub struct OidcUser {
+    pub user: ProviderUser,
+    pub groups: Vec<String>,
+    pub access_token: String,
+}
+
+impl OidcClient {
+    pub async fn discover(settings: OidcSettings) -> Result<Self> {
+        let issuer = IssuerUrl::new(settings.issuer_url.trim().to_string())?;
+        let metadata = CoreProviderMetadata::discover_async(issuer, async_http_client).await?;
+        let userinfo_url = metadata.userinfo_endpoint().map(|url| url.to_string());
+        let algs = metadata
+            .id_token_signing_alg_values_supported()
+            .iter()
+            .filter(|alg| **alg != CoreJwsSigningAlgorithm::None)
+            .cloned()
+            .collect();
+        let client = CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(settings.client_id.clone()),
+            Some(ClientSecret::new(settings.client_secret.clone())),
+        );
+        Ok(Self {
+            settings,
+            discovered_at: Instant::now(),
+            client,
+            algs,
+            userinfo_url,
+        })
+    }
+
+    pub fn authorize_url(&self, redirect_url: &str) -> Result<OidcAuthorization> {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let redirect_url = RedirectUrl::new(redirect_url.to_string())?;
+        let mut request = self
+            .client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .set_pkce_challenge(pkce_challenge)
+            .set_redirect_uri(Cow::Borrowed(&redirect_url));
+        for scope in &self.settings.scopes {
+            let scope = scope.trim();
+            if !scope.is_empty() && scope != "openid" {
+                request = request.add_scope(Scope::new(scope.to_string()));
+            }
+        }
+        let (url, csrf, nonce) = request.url();
+        Ok(OidcAuthorization {
+            url: url.to_string(),
+            csrf: csrf.secret().to_string(),
+            nonce: nonce.secret().to_string(),
+            pkce_verifier: pkce_verifier.secret().to_string(),
+        })
+    }
+
+    pub async fn exchange_code(
+        &self,
+        code: String,
+        redirect_url: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Result<OidcUser> {
+        let redirect_url = RedirectUrl::new(redirect_url)?;
+        let token = self
+            .client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .set_redirect_uri(Cow::Borrowed(&redirect_url))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| anyhow!("exchange oidc code error: {e:?}"))?;
+        let id_token = token
+            .id_token()
+            .ok_or_else(|| anyhow!("the provider didn't return an id token"))?;
+        let verifier = self
+            .client
+            .id_token_verifier()
+            .set_allowed_algs(self.algs.clone());
+        let verified = id_token.claims(&verifier, &Nonce::new(nonce))?;
+        let subject = verified.subject().to_string();
+
+        // the verified id token is decoded again for the claims that aren't standard, e.g. the groups
+        let mut claims = jwt_payload(&id_token.to_string())?;
+        let access_token = token.access_token().secret().to_string();
+        if let Some(url) = self.userinfo_url.as_ref() {
+            match userinfo(url, &access_token).await {
+                Ok(userinfo) => {
+                    if userinfo.get("sub").and_then(|s| s.as_str()) != Some(subject.as_str()) {
+                        return Err(anyhow!("the subject of the userinfo doesn't match"));
+                    }
+                    merge_claims(&mut claims, userinfo);
+                }
+                Err(e) => {
+                    tracing::warn!("get oidc userinfo error: {e:#}");
+                }
+            }
+        }
+
+        Ok(self.user_from_claims(subject, &claims, access_token))
+    }
+
+    fn user_from_claims(&self, subject: String, claims: &Value, access_token: String) -> OidcUser {
+        let settings = &self.settings;
+        let email = claim_string(claims, &settings.email_claim);
+        let login = claim_string(claims, &settings.login_claim)
+            .or_else(|| email.clone())
+            .unwrap_or_else(|| subject.clone());
+        let groups = settings
+            .groups_claim
+            .as_deref()
+            .map(|claim| claim_strings(claims, claim))
+            .unwrap_or_default();
+        OidcUser {
+            user: ProviderUser {
+                id: 0,
+                subject: Some(subject),
+                login,
+                name: claim_string(claims, &settings.name_claim),
+                email,
+                avatar_url: claim_string(claims, &settings.avatar_claim),
+            },
+            groups,
+            access_token,
+        }
+    }
+}
+
+async fn userinfo(url: &str, access_token: &str) -> Result<Value> {
+    let userinfo = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(access_token)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(userinfo)
+}
+
+fn jwt_payload(jwt: &str) -> Result<Value> {
+    let payload = jwt
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| anyhow!("invalid jwt"))?;
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('='))?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// The userinfo claims take precedence, because they're more up to date than the id token
+fn merge_claims(claims: &mut Value, userinfo: Value) {
+    if let (Some(claims), Value::Object(userinfo)) = (claims.as_object_mut(), userinfo) {
+        claims.extend(userinfo);
+    }
+}
+
+/// Look up a claim, nested claims are separated by dots, e.g. `realm_access.roles`
+fn claim<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim();
+    if path.is_empty() {
+        return None;
+    }
+    // claims with dots in their names take precedence over the nested ones
+    if let Some(value) = claims.get(path) {
+        return Some(value);
+    }
+    path.split('.')
+        .try_fold(claims, |value, segment| value.get(segment))
+}
+
+fn claim_string(claims: &Value, path: &str) -> Option<String> {
+    match claim(claims, path)? {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn claim_strings(claims: &Value, path: &str) -> Vec<String> {
+    match claim(claims, path) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|v| v.to_string())
+            .collect(),
+        Some(Value::String(s)) => s.split_whitespace().map(|s| s.to_string()).collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{extract::State, routing::get, routing::post, Form, Json, Router};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::Utc;
+    use lapdev_common::OidcSettings;
+    use openidconnect::{
+        core::{CoreHmacKey, CoreIdToken, CoreIdTokenClaims, CoreJwsSigningAlgorithm},
+        Audience, EmptyAdditionalClaims, EndUserUsername, IssuerUrl, Nonce, StandardClaims,
+        SubjectIdentifier,
+    };
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+
+    use super::{claim_strings, OidcClient};
+
+    const CLIENT_ID: &str = "lapdev";
+    const CLIENT_SECRET: &str = "a-client-secret-that-is-long-enough-for-hs256";
+
+    #[derive(Clone, Default)]
+    struct MockState {
+        issuer: String,
+        nonce: Arc<Mutex<String>>,
+        code_challenge: Arc<Mutex<String>>,
+    }
+
+    /// A minimal OpenID Connect provider, which signs the id tokens with the client secret
+    async fn mock_provider() -> MockState {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let state = MockState {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            ..Default::default()
+        };
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(|State(state): State<MockState>| async move {
+                    Json(json!({
+                        "issuer": state.issuer,
+                        "authorization_endpoint": format!("{}/authorize", state.issuer),
+                        "token_endpoint": format!("{}/token", state.issuer),
+                        "userinfo_endpoint": format!("{}/userinfo", state.issuer),
+                        "jwks_uri": format!("{}/jwks", state.issuer),
+                        "response_types_supported": ["code"],
+                        "subject_types_supported": ["public"],
+                        "id_token_signing_alg_values_supported": ["HS256", "none"],
+                    }))
+                }),
+            )
+            .route("/jwks", get(|| async { Json(json!({ "keys": [] })) }))
+            .route(
+                "/token",
+                post(
+                    |State(state): State<MockState>,
+                     Form(form): Form<std::collections::HashMap<String, String>>| async move {
+                        let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+                        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+                        if form.get("code").map(|c| c.as_str()) != Some("the-code")
+                            || challenge != *state.code_challenge.lock().unwrap()
+                        {
+                            return Err(axum::http::StatusCode::BAD_REQUEST);
+                        }
+                        let claims = CoreIdTokenClaims::new(
+                            IssuerUrl::new(state.issuer.clone()).unwrap(),
+                            vec![Audience::new(CLIENT_ID.to_string())],
+                            Utc::now() + chrono::Duration::seconds(300),
+                            Utc::now(),
+                            StandardClaims::new(SubjectIdentifier::new("user-1".to_string()))
+                                .set_preferred_username(Some(EndUserUsername::new(
+                                    "alice".to_string(),
+                                ))),
+                            EmptyAdditionalClaims {},
+                        )
+                        .set_nonce(Some(Nonce::new(state.nonce.lock().unwrap().clone())));
+                        let id_token = CoreIdToken::new(
+                            claims,
+                            &CoreHmacKey::new(CLIENT_SECRET.as_bytes().to_vec()),
+                            CoreJwsSigningAlgorithm::HmacSha256,
+                            None,
+                            None,
+                        )
+                        .unwrap();
+                        Ok(Json(json!({
+                            "access_token": "the-access-token",
+                            "token_type": "Bearer",
+                            "id_token": id_token.to_string(),
+                        })))
+                    },
+                ),
+            )
+            .route(
+                "/userinfo",
+                get(|| async {
+                    Json(json!({
+                        "sub": "user-1",
+                        "name": "Alice",
+                        "email": "alice@example.com",
+                        "realm_access": { "roles": ["developers", "admins"] },
+                    }))
+                }),
+            )
+            .with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        state
+    }
+
+    fn settings(issuer: &str) -> OidcSettings {
+        OidcSettings {
+            enabled: true,
+            issuer_url: issuer.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: CLIENT_SECRET.to_string(),
+            groups_claim: Some("realm_access.roles".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login() {
+        let provider = mock_provider().await;
+        let client = OidcClient::discover(settings(&provider.issuer))
+            .await
+            .unwrap();
+
+        let redirect_url = "https://lapdev.example.com/api/private/session/authorize";
+        let authorization = client.authorize_url(redirect_url).unwrap();
+        let url = reqwest::Url::parse(&authorization.url).unwrap();
+        let query: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["client_id"], CLIENT_ID);
+        assert_eq!(query["redirect_uri"], redirect_url);
+        assert_eq!(query["code_challenge_method"], "S256");
+        assert_eq!(query["state"], authorization.csrf);
+        assert_eq!(query["nonce"], authorization.nonce);
+        assert_eq!(query["scope"], "openid profile email");
+        *provider.nonce.lock().unwrap() = query["nonce"].clone();
+        *provider.code_challenge.lock().unwrap() = query["code_challenge"].clone();
+
+        let user = client
+            .exchange_code(
+                "the-code".to_string(),
+                redirect_url.to_string(),
+                authorization.pkce_verifier.clone(),
+                authorization.nonce.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(user.user.subject.as_deref(), Some("user-1"));
+        assert_eq!(user.user.login, "alice");
+        assert_eq!(user.user.name.as_deref(), Some("Alice"));
+        assert_eq!(user.user.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(user.user.avatar_url, None);
+        assert_eq!(user.groups, vec!["developers", "admins"]);
+        assert_eq!(user.access_token, "the-access-token");
+
+        // the nonce of another authorization is rejected
+        *provider.nonce.lock().unwrap() = "another-nonce".to_string();
+        assert!(client
+            .exchange_code(
+                "the-code".to_string(),
+                redirect_url.to_string(),
+                authorization.pkce_verifier.clone(),
+                authorization.nonce.clone(),
+            )
+            .await
+            .is_err());
+
+        // so is a wrong pkce verifier
+        *provider.nonce.lock().unwrap() = authorization.nonce.clone();
+        assert!(client
+            .exchange_code(
+                "the-code".to_string(),
+                redirect_url.to_string(),
+                "a-wrong-verifier-that-is-at-least-43-characters-long".to_string(),
+                authorization.nonce.clone(),
+            )
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_claim_strings() {
+        let claims: Value = json!({
+            "groups": ["a", "b"],
+            "scope": "read write",
+            "org.groups": ["c"],
+            "org": { "groups": ["d"] },
+        });
+        assert_eq!(claim_strings(&claims, "groups"), vec!["a", "b"]);
+        assert_eq!(claim_strings(&claims, "scope"), vec!["read", "write"]);
+        assert_eq!(claim_strings(&claims, "org.groups"), vec!["c"]);
+        assert!(claim_strings(&claims, "missing.groups").is_empty());
+    }
+}
@@ -11,8 +11,9 @@ use hyper::StatusCode;
 use lapdev_common::{
     console::{Organization, OrganizationMember},
     AuditAction, AuditLogRequest, AuditLogResult, AuditResourceKind, NewOrganization, OrgQuota,
-    UpdateOrgQuota, UpdateOrganizationAutoStartStop, UpdateOrganizationMember,
-    UpdateOrganizationName, UsageRequest, UsageResult, UserRole,
+    UpdateOrgQuota, UpdateOrganizationActivityPorts, UpdateOrganizationAutoStartStop,
+    UpdateOrganizationMember, UpdateOrganizationName, UpdateOrganizationSshAgentForwarding,
+    UpdateOrganizationSshSessionRecording, UsageRequest, UsageResult, UserRole,
 };
 use lapdev_db::entities;
 use lapdev_rpc::error::ApiError;
@@ -49,6 +50,9 @@ pub async fn create_organization(
         auto_stop: ActiveValue::Set(Some(3600)),
         allow_workspace_change_auto_stop: ActiveValue::Set(true),
         last_auto_stop_check: ActiveValue::Set(None),
+        ssh_session_recording: ActiveValue::Set(false),
+        allow_ssh_agent_forwarding: ActiveValue::Set(true),
+        activity_ports: ActiveValue::Set(None),
     }
     .insert(&txn)
     .await?;
@@ -90,6 +94,7 @@ pub async fn create_organization(
 
     txn.commit().await?;
 
+    let activity_ports = org_activity_ports(&org);
     Ok(Json(Organization {
         id: org.id,
         name: org.name,
@@ -98,6 +103,9 @@ pub async fn create_organization(
         auto_stop: org.auto_stop,
         allow_workspace_change_auto_start: org.allow_workspace_change_auto_start,
         allow_workspace_change_auto_stop: org.allow_workspace_change_auto_stop,
+        ssh_session_recording: org.ssh_session_recording,
+        allow_ssh_agent_forwarding: org.allow_ssh_agent_forwarding,
+        activity_ports,
     })
     .into_response())
 }
@@ -277,6 +285,178 @@ pub async fn update_org_auto_start_stop(
     Ok(StatusCode::NO_CONTENT.into_response())
 }
 
+pub async fn update_org_ssh_session_recording(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path(org_id): Path<Uuid>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(update_org): Json<UpdateOrganizationSshSessionRecording>,
+) -> Result<Response, ApiError> {
+    state.require_enterprise().await?;
+    let user = state.authenticate(&cookie).await?;
+    let member = state
+        .db
+        .get_organization_member(user.id, org_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    if member.role != UserRole::Owner.to_string() && member.role != UserRole::Admin.to_string() {
+        return Err(ApiError::Unauthorized);
+    }
+    let org = state.db.get_organization(member.organization_id).await?;
+
+    let now = Utc::now();
+    let txn = state.db.conn.begin().await?;
+
+    entities::organization::ActiveModel {
+        id: ActiveValue::Set(org_id),
+        ssh_session_recording: ActiveValue::Set(update_org.ssh_session_recording),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            now.into(),
+            user.id,
+            org.id,
+            AuditResourceKind::Organization.to_string(),
+            org.id,
+            org.name.clone(),
+            AuditAction::OrganizationUpdate.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn update_org_ssh_agent_forwarding(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path(org_id): Path<Uuid>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(update_org): Json<UpdateOrganizationSshAgentForwarding>,
+) -> Result<Response, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    let member = state
+        .db
+        .get_organization_member(user.id, org_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    if member.role != UserRole::Owner.to_string() && member.role != UserRole::Admin.to_string() {
+        return Err(ApiError::Unauthorized);
+    }
+    let org = state.db.get_organization(member.organization_id).await?;
+
+    let now = Utc::now();
+    let txn = state.db.conn.begin().await?;
+
+    entities::organization::ActiveModel {
+        id: ActiveValue::Set(org_id),
+        allow_ssh_agent_forwarding: ActiveValue::Set(update_org.allow_ssh_agent_forwarding),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            now.into(),
+            user.id,
+            org.id,
+            AuditResourceKind::Organization.to_string(),
+            org.id,
+            org.name.clone(),
+            AuditAction::OrganizationUpdate.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn update_org_activity_ports(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path(org_id): Path<Uuid>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(update_org): Json<UpdateOrganizationActivityPorts>,
+) -> Result<Response, ApiError> {
+    state.require_enterprise().await?;
+    let user = state.authenticate(&cookie).await?;
+    let member = state
+        .db
+        .get_organization_member(user.id, org_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    if member.role != UserRole::Owner.to_string() && member.role != UserRole::Admin.to_string() {
+        return Err(ApiError::Unauthorized);
+    }
+    let org = state.db.get_organization(member.organization_id).await?;
+
+    let activity_ports = update_org
+        .activity_ports
+        .map(|mut ports| {
+            ports.sort();
+            ports.dedup();
+            serde_json::to_string(&ports)
+        })
+        .transpose()?;
+
+    let now = Utc::now();
+    let txn = state.db.conn.begin().await?;
+
+    entities::organization::ActiveModel {
+        id: ActiveValue::Set(org_id),
+        activity_ports: ActiveValue::Set(activity_ports),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            now.into(),
+            user.id,
+            org.id,
+            AuditResourceKind::Organization.to_string(),
+            org.id,
+            org.name.clone(),
+            AuditAction::OrganizationUpdate.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// The ports whose http traffic counts as workspace activity besides the ide's,
+/// all ports count if it's None
+pub(crate) fn org_activity_ports(org: &entities::organization::Model) -> Option<Vec<u16>> {
+    org.activity_ports
+        .as_ref()
+        .and_then(|ports| serde_json::from_str(ports).ok())
+}
+
 pub async fn join_organization(
     TypedHeader(cookie): TypedHeader<Cookie>,
     Path(invitation_id): Path<Uuid>,
@@ -614,6 +794,8 @@ pub async fn get_organization_audit_log(
             org.id,
             audit_log_request.start,
             audit_log_request.end,
+            audit_log_request.action,
+            audit_log_request.search,
             audit_log_request.page_size,
             audit_log_request.page,
         )
@@ -622,6 +804,37 @@ pub async fn get_organization_audit_log(
     Ok(Json(result))
 }
 
+pub async fn get_ssh_session_recording(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, recording_id)): Path<(Uuid, Uuid)>,
+    State(state): State<CoreState>,
+) -> Result<Response, ApiError> {
+    state.require_enterprise().await?;
+    let user = state.authenticate(&cookie).await?;
+    let member = state
+        .db
+        .get_organization_member(user.id, org_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    if member.role != UserRole::Owner.to_string() && member.role != UserRole::Admin.to_string() {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let recording = entities::ssh_session_recording::Entity::find_by_id(recording_id)
+        .one(&state.db.conn)
+        .await?
+        .ok_or_else(|| ApiError::InvalidRequest("recording doesn't exist".to_string()))?;
+    if recording.organization_id != member.organization_id {
+        return Err(ApiError::Unauthorized);
+    }
+
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/x-asciicast")],
+        recording.content,
+    )
+        .into_response())
+}
+
 pub async fn get_organization_quota(
     TypedHeader(cookie): TypedHeader<Cookie>,
     Path(org_id): Path<Uuid>,
@@ -0,0 +1,678 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, State},
+    http::{header::SET_COOKIE, HeaderMap, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use axum_extra::{headers::Cookie, TypedHeader};
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use lapdev_common::{
+    utils::workspace_url, AuditAction, AuditResourceKind, NewWorkspacePort,
+    NewWorkspacePortShareLink, UpdateWorkspacePortLabel, UpdateWorkspacePortVisibility,
+    WorkspacePortInfo, WorkspacePortShareLink, WorkspacePortVisibility,
+};
+use lapdev_db::entities;
+use lapdev_proxy_http::activity::WorkspaceActivity;
+use lapdev_rpc::error::ApiError;
+use pasetors::{
+    claims::{Claims, ClaimsValidationRules},
+    keys::SymmetricKey,
+    token::UntrustedToken,
+    version4::V4,
+};
+use sea_orm::{ActiveModelTrait, ActiveValue, TransactionTrait};
+use uuid::Uuid;
+
+use crate::{
+    organization::org_activity_ports,
+    state::{CoreState, RequestInfo},
+    workspace::get_own_workspace,
+};
+
+/// The query parameter that carries the share link token in the link url
+pub const SHARE_TOKEN_QUERY: &str = "lapdev_share_token";
+/// The cookie the share link token is kept in on the port's hostname after the link is opened
+pub const SHARE_COOKIE_NAME: &str = "lapdev_share";
+const SHARE_LINK_ID: &str = "share_link_id";
+/// Bound into the share link tokens, so that they can't be used as session tokens, and vice versa
+const SHARE_TOKEN_ASSERTION: &[u8] = b"lapdev-port-share-link";
+const MAX_SHARE_LINK_DAYS: i64 = 30;
+
+pub async fn get_workspace_ports(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name)): Path<(Uuid, String)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+) -> Result<Json<Vec<WorkspacePortInfo>>, ApiError> {
+    let (_, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    let hostname = workspace_hostname(&state, &ws).await?;
+    let path_routing = *state.path_routing.read().await;
+    let mut ports = Vec::new();
+    for port in state.db.get_workspace_ports(ws.id).await? {
+        let visibility = WorkspacePortVisibility::from_str(&port.visibility).unwrap_or_default();
+        let users = if visibility == WorkspacePortVisibility::Users {
+            state
+                .db
+                .get_all_workspace_port_access(ws.id, port.port as u16)
+                .await?
+                .into_iter()
+                .map(|a| a.user_id)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        ports.push(WorkspacePortInfo {
+            port: port.port as u16,
+            url: workspace_url(
+                info.scheme,
+                &hostname,
+                &ws.name,
+                Some(port.port as u16),
+                path_routing,
+            ),
+            label: port.label,
+            visibility,
+            users,
+            auto_detected: port.auto_detected,
+        });
+    }
+    Ok(Json(ports))
+}
+
+pub async fn expose_workspace_port(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name)): Path<(Uuid, String)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(new_port): Json<NewWorkspacePort>,
+) -> Result<Response, ApiError> {
+    let (user, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    state
+        .conductor
+        .expose_workspace_port(&ws, new_port.port, user.id, info.ip, info.user_agent)
+        .await?;
+    // the port shows up in the workspace's ports once the workspace host has forwarded it
+    Ok(StatusCode::ACCEPTED.into_response())
+}
+
+pub async fn update_workspace_port_label(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name, port)): Path<(Uuid, String, u16)>,
+    State(state): State<CoreState>,
+    Json(update): Json<UpdateWorkspacePortLabel>,
+) -> Result<Response, ApiError> {
+    let (_, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    let ws_port = state
+        .db
+        .get_workspace_port(ws.id, port)
+        .await?
+        .ok_or_else(|| {
+            ApiError::InvalidRequest("The workspace doesn't have this port".to_string())
+        })?;
+    let label = update
+        .label
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty());
+    entities::workspace_port::ActiveModel {
+        id: ActiveValue::Set(ws_port.id),
+        label: ActiveValue::Set(label),
+        ..Default::default()
+    }
+    .update(&state.db.conn)
+    .await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn update_workspace_port_visibility(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name, port)): Path<(Uuid, String, u16)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(update): Json<UpdateWorkspacePortVisibility>,
+) -> Result<Response, ApiError> {
+    let (user, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    let ws_port = state
+        .db
+        .get_workspace_port(ws.id, port)
+        .await?
+        .ok_or_else(|| {
+            ApiError::InvalidRequest("The workspace doesn't have this port".to_string())
+        })?;
+    for user_id in &update.users {
+        state
+            .db
+            .get_organization_member(*user_id, ws.organization_id)
+            .await
+            .map_err(|_| {
+                ApiError::InvalidRequest("The user isn't a member of the organization".to_string())
+            })?;
+    }
+
+    let now = Utc::now();
+    let existing = state.db.get_all_workspace_port_access(ws.id, port).await?;
+    let txn = state.db.conn.begin().await?;
+    entities::workspace_port::ActiveModel {
+        id: ActiveValue::Set(ws_port.id),
+        visibility: ActiveValue::Set(update.visibility.to_string()),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+    for access in &existing {
+        if !update.users.contains(&access.user_id) {
+            entities::workspace_port_access::ActiveModel {
+                id: ActiveValue::Set(access.id),
+                deleted_at: ActiveValue::Set(Some(now.into())),
+                ..Default::default()
+            }
+            .update(&txn)
+            .await?;
+        }
+    }
+    for user_id in &update.users {
+        if existing.iter().any(|a| a.user_id == *user_id) {
+            continue;
+        }
+        entities::workspace_port_access::ActiveModel {
+            id: ActiveValue::Set(Uuid::new_v4()),
+            created_at: ActiveValue::Set(now.into()),
+            deleted_at: ActiveValue::Set(None),
+            workspace_id: ActiveValue::Set(ws.id),
+            port: ActiveValue::Set(port as i32),
+            user_id: ActiveValue::Set(*user_id),
+        }
+        .insert(&txn)
+        .await?;
+    }
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log_with_details(
+            &txn,
+            now.into(),
+            user.id,
+            ws.organization_id,
+            AuditResourceKind::Workspace.to_string(),
+            ws.id,
+            ws.name.clone(),
+            AuditAction::WorkspacePortUpdateVisibility.to_string(),
+            info.ip,
+            info.user_agent,
+            Some(format!("port {port}: {}", update.visibility)),
+        )
+        .await?;
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn get_workspace_port_share_links(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name)): Path<(Uuid, String)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+) -> Result<Json<Vec<WorkspacePortShareLink>>, ApiError> {
+    let (_, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    let hostname = workspace_hostname(&state, &ws).await?;
+    let path_routing = *state.path_routing.read().await;
+    let links = state
+        .db
+        .get_workspace_port_share_links(ws.id)
+        .await?
+        .into_iter()
+        .map(|link| share_link_info(&state, &ws, info.scheme, &hostname, path_routing, link))
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    Ok(Json(links))
+}
+
+pub async fn create_workspace_port_share_link(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name, port)): Path<(Uuid, String, u16)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(new_link): Json<NewWorkspacePortShareLink>,
+) -> Result<Json<WorkspacePortShareLink>, ApiError> {
+    let (user, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    state
+        .db
+        .get_workspace_port(ws.id, port)
+        .await?
+        .ok_or_else(|| {
+            ApiError::InvalidRequest("The workspace doesn't have this port".to_string())
+        })?;
+    let now = Utc::now();
+    if new_link.expires_at <= now {
+        return Err(ApiError::InvalidRequest(
+            "The expiry time needs to be in the future".to_string(),
+        ));
+    }
+    if new_link.expires_at > now + Duration::days(MAX_SHARE_LINK_DAYS) {
+        return Err(ApiError::InvalidRequest(format!(
+            "Share links can't be valid for more than {MAX_SHARE_LINK_DAYS} days"
+        )));
+    }
+
+    let txn = state.db.conn.begin().await?;
+    let link = entities::workspace_port_share_link::ActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        created_at: ActiveValue::Set(now.into()),
+        expires_at: ActiveValue::Set(new_link.expires_at),
+        revoked_at: ActiveValue::Set(None),
+        workspace_id: ActiveValue::Set(ws.id),
+        port: ActiveValue::Set(port as i32),
+        created_by: ActiveValue::Set(user.id),
+    }
+    .insert(&txn)
+    .await?;
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log_with_details(
+            &txn,
+            now.into(),
+            user.id,
+            ws.organization_id,
+            AuditResourceKind::Workspace.to_string(),
+            ws.id,
+            ws.name.clone(),
+            AuditAction::WorkspacePortShareLinkCreate.to_string(),
+            info.ip,
+            info.user_agent,
+            Some(format!("port {port}, expires at {}", new_link.expires_at)),
+        )
+        .await?;
+    txn.commit().await?;
+
+    let hostname = workspace_hostname(&state, &ws).await?;
+    let path_routing = *state.path_routing.read().await;
+    Ok(Json(share_link_info(
+        &state,
+        &ws,
+        info.scheme,
+        &hostname,
+        path_routing,
+        link,
+    )?))
+}
+
+pub async fn revoke_workspace_port_share_link(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name, link_id)): Path<(Uuid, String, Uuid)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+) -> Result<Response, ApiError> {
+    let (user, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    let link = state
+        .db
+        .get_workspace_port_share_link(link_id)
+        .await?
+        .filter(|link| link.workspace_id == ws.id)
+        .ok_or_else(|| ApiError::InvalidRequest("The share link doesn't exist".to_string()))?;
+    if link.revoked_at.is_some() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    let now = Utc::now();
+    let txn = state.db.conn.begin().await?;
+    entities::workspace_port_share_link::ActiveModel {
+        id: ActiveValue::Set(link.id),
+        revoked_at: ActiveValue::Set(Some(now.into())),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log_with_details(
+            &txn,
+            now.into(),
+            user.id,
+            ws.organization_id,
+            AuditResourceKind::Workspace.to_string(),
+            ws.id,
+            ws.name.clone(),
+            AuditAction::WorkspacePortShareLinkRevoke.to_string(),
+            info.ip,
+            info.user_agent,
+            Some(format!("port {}", link.port)),
+        )
+        .await?;
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Check if the request can reach the workspace port over http.
+/// Without a port, it's the workspace's IDE, which is only for the owner.
+pub async fn is_http_forward_allowed(
+    cookie: &Cookie,
+    state: &CoreState,
+    ws: &entities::workspace::Model,
+    port: Option<&entities::workspace_port::Model>,
+) -> Result<(), ApiError> {
+    let visibility = port
+        .map(|p| WorkspacePortVisibility::from_str(&p.visibility).unwrap_or_default())
+        .unwrap_or_default();
+    if let Some(port) = port {
+        if visibility == WorkspacePortVisibility::Public {
+            return Ok(());
+        }
+        if let Some(token) = cookie.get(SHARE_COOKIE_NAME) {
+            if verify_share_token(state, token, ws, port.port)
+                .await
+                .is_some()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    let user = state.authenticate(cookie).await?;
+    if ws.user_id == user.id {
+        return Ok(());
+    }
+    let Some(port) = port else {
+        return Err(ApiError::Unauthorized);
+    };
+    if visibility == WorkspacePortVisibility::Private {
+        return Err(ApiError::Unauthorized);
+    }
+    state
+        .db
+        .get_organization_member(user.id, ws.organization_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    if visibility == WorkspacePortVisibility::Users
+        && state
+            .db
+            .get_workspace_port_access(ws.id, port.port as u16, user.id)
+            .await?
+            .is_none()
+    {
+        return Err(ApiError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// Open a share link: the token in the query string is moved into a cookie
+/// of the port's hostname, and the browser is redirected to the url without it.
+/// `cookie_path` scopes the cookie to the port's path when path based routing is used.
+/// Returns None if the request doesn't carry a share link token.
+pub async fn open_share_link(
+    state: &CoreState,
+    ws: &entities::workspace::Model,
+    port: &entities::workspace_port::Model,
+    uri: &Uri,
+    cookie_path: &str,
+    info: &RequestInfo,
+) -> Result<Option<Response>, ApiError> {
+    let Some(query) = uri.query() else {
+        return Ok(None);
+    };
+    let mut token = None;
+    let mut rest = Vec::new();
+    for param in query.split('&') {
+        match param.split_once('=') {
+            Some((SHARE_TOKEN_QUERY, value)) => token = Some(value),
+            _ => rest.push(param),
+        }
+    }
+    let Some(token) = token else {
+        return Ok(None);
+    };
+
+    let link = verify_share_token(state, token, ws, port.port)
+        .await
+        .ok_or_else(|| {
+            ApiError::InvalidRequest("The share link is invalid or has expired".to_string())
+        })?;
+
+    let now = Utc::now();
+    let txn = state.db.conn.begin().await?;
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log_with_details(
+            &txn,
+            now.into(),
+            link.created_by,
+            ws.organization_id,
+            AuditResourceKind::Workspace.to_string(),
+            ws.id,
+            ws.name.clone(),
+            AuditAction::WorkspacePortShareLinkUse.to_string(),
+            info.ip.clone(),
+            info.user_agent.clone(),
+            Some(format!("port {}", link.port)),
+        )
+        .await?;
+    txn.commit().await?;
+
+    let max_age = (link.expires_at.with_timezone(&Utc) - now)
+        .num_seconds()
+        .max(0);
+    let cookie = format!(
+        "{SHARE_COOKIE_NAME}={token}; Path={cookie_path}; Max-Age={max_age}; HttpOnly; SameSite=Lax"
+    );
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, cookie.parse()?);
+    let location = if rest.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), rest.join("&"))
+    };
+    Ok(Some(
+        (headers, Redirect::temporary(&location)).into_response(),
+    ))
+}
+
+async fn verify_share_token(
+    state: &CoreState,
+    token: &str,
+    ws: &entities::workspace::Model,
+    port: i32,
+) -> Option<entities::workspace_port_share_link::Model> {
+    let link_id = open_share_token(&state.auth_token_key, token)?;
+    let link = state
+        .db
+        .get_workspace_port_share_link(link_id)
+        .await
+        .ok()??;
+    if !is_share_link_valid(&link, ws.id, port, Utc::now()) {
+        return None;
+    }
+    Some(link)
+}
+
+fn seal_share_token(
+    key: &SymmetricKey<V4>,
+    link_id: Uuid,
+    valid_for: std::time::Duration,
+) -> Result<String, ApiError> {
+    let mut claims = Claims::new_expires_in(&valid_for)?;
+    claims.add_additional(SHARE_LINK_ID, link_id.to_string())?;
+    let token = pasetors::local::encrypt(key, &claims, None, Some(SHARE_TOKEN_ASSERTION))?;
+    Ok(token)
+}
+
+/// The share link id in the token, if the token is one of ours and hasn't expired
+fn open_share_token(key: &SymmetricKey<V4>, token: &str) -> Option<Uuid> {
+    let untrusted_token = UntrustedToken::try_from(token).ok()?;
+    let token = pasetors::local::decrypt(
+        key,
+        &untrusted_token,
+        &ClaimsValidationRules::new(),
+        None,
+        Some(SHARE_TOKEN_ASSERTION),
+    )
+    .ok()?;
+    token
+        .payload_claims()
+        .and_then(|c| c.get_claim(SHARE_LINK_ID))
+        .and_then(|v| serde_json::from_value(v.to_owned()).ok())
+}
+
+/// The token only says which link it is, the link itself
+/// can be revoked, and is only for its workspace port
+fn is_share_link_valid(
+    link: &entities::workspace_port_share_link::Model,
+    ws_id: Uuid,
+    port: i32,
+    now: DateTime<Utc>,
+) -> bool {
+    link.workspace_id == ws_id
+        && link.port == port
+        && link.revoked_at.is_none()
+        && link.expires_at > now
+}
+
+fn share_link_info(
+    state: &CoreState,
+    ws: &entities::workspace::Model,
+    scheme: &str,
+    hostname: &str,
+    path_routing: bool,
+    link: entities::workspace_port_share_link::Model,
+) -> Result<WorkspacePortShareLink, ApiError> {
+    let now = Utc::now();
+    let url = if link.revoked_at.is_none() && link.expires_at > now {
+        // the token is sealed with the server key, so it can be issued again whenever it's listed
+        let valid_for = (link.expires_at.with_timezone(&Utc) - now).to_std()?;
+        let token = seal_share_token(&state.auth_token_key, link.id, valid_for)?;
+        let url = workspace_url(
+            scheme,
+            hostname,
+            &ws.name,
+            Some(link.port as u16),
+            path_routing,
+        );
+        Some(format!("{url}?{SHARE_TOKEN_QUERY}={token}"))
+    } else {
+        None
+    };
+    Ok(WorkspacePortShareLink {
+        id: link.id,
+        port: link.port as u16,
+        url,
+        created_by: link.created_by,
+        created_at: link.created_at,
+        expires_at: link.expires_at,
+        revoked_at: link.revoked_at,
+    })
+}
+
+/// The activity the proxy records for the request, the ide always counts as activity
+/// and the other ports count if the organization hasn't limited the activity to some ports
+pub async fn workspace_activity(
+    state: &CoreState,
+    ws: &entities::workspace::Model,
+    port: Option<u16>,
+) -> Option<WorkspaceActivity> {
+    if let Some(port) = port {
+        let org = state.db.get_organization(ws.organization_id).await.ok()?;
+        if let Some(ports) = org_activity_ports(&org) {
+            if !ports.contains(&port) {
+                return None;
+            }
+        }
+    }
+    Some(WorkspaceActivity {
+        recorder: state.activity.clone(),
+        ws_id: ws.id,
+    })
+}
+
+pub(crate) async fn workspace_hostname(
+    state: &CoreState,
+    ws: &entities::workspace::Model,
+) -> Result<String, ApiError> {
+    let host = state.db.get_workspace_host(ws.host_id).await?;
+    let region = host.map(|host| host.region).unwrap_or_default();
+    let hostname = state
+        .conductor
+        .hostnames
+        .read()
+        .await
+        .get(region.trim())
+        .cloned()
+        .unwrap_or_default();
+    Ok(hostname)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use lapdev_db::entities;
+    use pasetors::{claims::Claims, keys::Generate, keys::SymmetricKey, version4::V4};
+    use uuid::Uuid;
+
+    use super::{is_share_link_valid, open_share_token, seal_share_token, SHARE_LINK_ID};
+
+    #[test]
+    fn test_share_token() {
+        let key = SymmetricKey::<V4>::generate().unwrap();
+        let link_id = Uuid::new_v4();
+        let token = seal_share_token(&key, link_id, std::time::Duration::from_secs(3600)).unwrap();
+        assert_eq!(open_share_token(&key, &token), Some(link_id));
+
+        // sealed with another key
+        let other_key = SymmetricKey::<V4>::generate().unwrap();
+        assert_eq!(open_share_token(&other_key, &token), None);
+        assert_eq!(open_share_token(&key, "not a token"), None);
+
+        // a session token can't be used as a share token
+        let mut claims = Claims::new().unwrap();
+        claims
+            .add_additional(SHARE_LINK_ID, link_id.to_string())
+            .unwrap();
+        let session_token = pasetors::local::encrypt(&key, &claims, None, None).unwrap();
+        assert_eq!(open_share_token(&key, &session_token), None);
+
+        // the token has expired
+        let mut claims = Claims::new().unwrap();
+        claims.issued_at("2024-01-01T00:00:00+00:00").unwrap();
+        claims.not_before("2024-01-01T00:00:00+00:00").unwrap();
+        claims.expiration("2024-01-02T00:00:00+00:00").unwrap();
+        claims
+            .add_additional(SHARE_LINK_ID, link_id.to_string())
+            .unwrap();
+        let expired_token =
+            pasetors::local::encrypt(&key, &claims, None, Some(super::SHARE_TOKEN_ASSERTION))
+                .unwrap();
+        assert_eq!(open_share_token(&key, &expired_token), None);
+    }
+
+    #[test]
+    fn test_share_link_valid() {
+        let now = Utc::now();
+        let ws_id = Uuid::new_v4();
+        let link = entities::workspace_port_share_link::Model {
+            id: Uuid::new_v4(),
+            created_at: now.into(),
+            expires_at: (now + Duration::hours(1)).into(),
+            revoked_at: None,
+            workspace_id: ws_id,
+            port: 8080,
+            created_by: Uuid::new_v4(),
+        };
+        assert!(is_share_link_valid(&link, ws_id, 8080, now));
+        assert!(!is_share_link_valid(&link, ws_id, 8081, now));
+        assert!(!is_share_link_valid(&link, Uuid::new_v4(), 8080, now));
+        assert!(!is_share_link_valid(
+            &link,
+            ws_id,
+            8080,
+            now + Duration::hours(2)
+        ));
+
+        let revoked = entities::workspace_port_share_link::Model {
+            revoked_at: Some(now.into()),
+            ..link
+        };
+        assert!(!is_share_link_valid(&revoked, ws_id, 8080, now));
+    }
+}
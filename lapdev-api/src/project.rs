@@ -9,15 +9,23 @@ use axum_extra::{headers::Cookie, TypedHeader};
 use chrono::Utc;
 use hyper::StatusCode;
 use lapdev_common::{
-    AuditAction, AuditResourceKind, NewProject, NewProjectPrebuild, PrebuildStatus, ProjectInfo,
-    ProjectPrebuild, UserRole,
+    AuditAction, AuditResourceKind, AuthProvider, DeployKey, NewProject, NewProjectPrebuild,
+    PrebuildStatus, ProjectGitCredential, ProjectGitCredentialInfo, ProjectInfo, ProjectPrebuild,
+    UserRole,
 };
 use lapdev_db::entities;
 use lapdev_rpc::error::ApiError;
+use oauth2::AccessToken;
 use sea_orm::{ActiveModelTrait, ActiveValue, TransactionTrait};
 use uuid::Uuid;
 
-use crate::state::{CoreState, RequestInfo};
+use crate::{
+    bitbucket::BitbucketClient,
+    gitea::GiteaClient,
+    github::GithubClient,
+    gitlab::GitlabClient,
+    state::{CoreState, RequestInfo},
+};
 
 pub async fn create_project(
     TypedHeader(cookie): TypedHeader<Cookie>,
@@ -32,6 +40,11 @@ pub async fn create_project(
         .get_organization_member(user.id, org_id)
         .await
         .map_err(|_| ApiError::Unauthorized)?;
+    let repo_url = state.conductor.format_repo_url(&project.repo);
+    // ssh remotes are fetched with the deploy key instead of the user's token
+    if !repo_url.starts_with("ssh://") {
+        check_repo_access(&state, &user, &repo_url).await?;
+    }
     let result = state
         .conductor
         .create_project(user, org_id, project, info.ip, info.user_agent)
@@ -43,6 +56,46 @@ pub async fn create_project(
     Ok(result.into_response())
 }
 
+/// Asks the api of the instance the user signed in with if they can read the repository,
+/// so that they get a clear error instead of a failed git fetch
+async fn check_repo_access(
+    state: &CoreState,
+    user: &entities::user::Model,
+    repo_url: &str,
+) -> Result<(), ApiError> {
+    let Ok(Some(provider)) = state.db.get_oauth_provider(&user.provider).await else {
+        return Ok(());
+    };
+    let Some(path) = provider.repo_path(repo_url) else {
+        return Ok(());
+    };
+    let token = AccessToken::new(state.conductor.user_access_token(user, &provider).await);
+    let api_url = provider.api_url();
+    let access = match provider.kind {
+        AuthProvider::Github => GithubClient::new(api_url).repo_access(&token, path).await,
+        AuthProvider::Gitlab => GitlabClient::new(api_url).repo_access(&token, path).await,
+        AuthProvider::Gitea => GiteaClient::new(api_url).repo_access(&token, path).await,
+        AuthProvider::Bitbucket => {
+            BitbucketClient::new(api_url)
+                .repo_access(&token, path)
+                .await
+        }
+        AuthProvider::Oidc | AuthProvider::Local => return Ok(()),
+    };
+    match access {
+        Ok(false) => Err(ApiError::RepositoryInvalid(format!(
+            "you don't have access to the repository {repo_url} on {}",
+            provider.name
+        ))),
+        Ok(true) => Ok(()),
+        Err(e) => {
+            // the git fetch afterwards still tells if the repository is there
+            tracing::warn!("check repo access of {repo_url} error: {e:#}");
+            Ok(())
+        }
+    }
+}
+
 pub async fn all_projects(
     State(state): State<CoreState>,
     Path(org_id): Path<Uuid>,
@@ -115,6 +168,72 @@ pub async fn delete_project(
     Ok(StatusCode::NO_CONTENT.into_response())
 }
 
+fn deploy_key_info(key: entities::project_deploy_key::Model) -> DeployKey {
+    DeployKey {
+        id: key.id,
+        public_key: key.public_key,
+        host_key: key.host_key,
+        created_at: key.created_at,
+    }
+}
+
+/// Generates a deploy key for a project that's going to be created,
+/// so that the key can be added to the git host before that
+pub async fn create_deploy_key(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path(org_id): Path<Uuid>,
+    State(state): State<CoreState>,
+) -> Result<Json<DeployKey>, ApiError> {
+    let user = state.authenticate(&cookie).await?;
+    state
+        .db
+        .get_organization_member(user.id, org_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    let key = state
+        .conductor
+        .create_deploy_key(org_id, user.id, None)
+        .await?;
+    Ok(Json(deploy_key_info(key)))
+}
+
+pub async fn get_project_deploy_key(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, project_id)): Path<(Uuid, Uuid)>,
+    State(state): State<CoreState>,
+) -> Result<Json<Option<DeployKey>>, ApiError> {
+    let (_, project) = state.get_project(&cookie, org_id, project_id).await?;
+    let key = state.db.get_project_deploy_key(project.id).await?;
+    Ok(Json(key.map(deploy_key_info)))
+}
+
+/// Replaces the deploy key of the project with a new one
+pub async fn regenerate_project_deploy_key(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, project_id)): Path<(Uuid, Uuid)>,
+    State(state): State<CoreState>,
+) -> Result<Json<DeployKey>, ApiError> {
+    let (user, project) = state.get_project(&cookie, org_id, project_id).await?;
+    let member = state
+        .db
+        .get_organization_member(user.id, org_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    if user.id != project.created_by
+        && member.role != UserRole::Owner.to_string()
+        && member.role != UserRole::Admin.to_string()
+    {
+        return Err(ApiError::InvalidRequest(
+            "Only project owner or orgnization admin can change the deploy key".to_string(),
+        ));
+    }
+    let key = state
+        .conductor
+        .create_deploy_key(org_id, user.id, Some(project.id))
+        .await?;
+    Ok(Json(deploy_key_info(key)))
+}
+
 pub async fn get_project_branches(
     TypedHeader(cookie): TypedHeader<Cookie>,
     Path((org_id, project_id)): Path<(Uuid, Uuid)>,
@@ -122,11 +241,7 @@ pub async fn get_project_branches(
     info: RequestInfo,
 ) -> Result<Response, ApiError> {
     let (user, project) = state.get_project(&cookie, org_id, project_id).await?;
-    let auth = if let Ok(Some(user)) = state.db.get_user(project.created_by).await {
-        (user.provider_login, user.access_token)
-    } else {
-        (user.provider_login.clone(), user.access_token.clone())
-    };
+    let auth = state.conductor.project_auth(&user, &project).await;
     let branches = state
         .conductor
         .project_branches(user.id, &project, auth, info.ip, info.user_agent)
@@ -347,3 +462,105 @@ pub async fn update_project_env(
 
     Ok(StatusCode::NO_CONTENT.into_response())
 }
+
+pub async fn get_project_git_credential(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, project_id)): Path<(Uuid, Uuid)>,
+    State(state): State<CoreState>,
+) -> Result<Json<Option<ProjectGitCredentialInfo>>, ApiError> {
+    let (_, project) = state.get_project(&cookie, org_id, project_id).await?;
+    let credential = project
+        .git_credential_token
+        .as_ref()
+        .and(project.git_credential_username)
+        .map(|username| ProjectGitCredentialInfo { username });
+    Ok(Json(credential))
+}
+
+pub async fn update_project_git_credential(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, project_id)): Path<(Uuid, Uuid)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(credential): Json<ProjectGitCredential>,
+) -> Result<Response, ApiError> {
+    if credential.username.trim().is_empty() || credential.token.trim().is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "username and token can't be empty".to_string(),
+        ));
+    }
+    let token = state.key_cipher.encrypt(credential.token.trim())?;
+    set_project_git_credential(
+        &state,
+        &cookie,
+        org_id,
+        project_id,
+        info,
+        Some((credential.username.trim().to_string(), token)),
+    )
+    .await
+}
+
+pub async fn delete_project_git_credential(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, project_id)): Path<(Uuid, Uuid)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+) -> Result<Response, ApiError> {
+    set_project_git_credential(&state, &cookie, org_id, project_id, info, None).await
+}
+
+/// Sets or removes the token that the workspaces of the project get for git
+async fn set_project_git_credential(
+    state: &CoreState,
+    cookie: &Cookie,
+    org_id: Uuid,
+    project_id: Uuid,
+    info: RequestInfo,
+    credential: Option<(String, String)>,
+) -> Result<Response, ApiError> {
+    let (user, project) = state.get_project(cookie, org_id, project_id).await?;
+    let member = state
+        .db
+        .get_organization_member(user.id, org_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    if user.id != project.created_by
+        && member.role != UserRole::Owner.to_string()
+        && member.role != UserRole::Admin.to_string()
+    {
+        return Err(ApiError::InvalidRequest(
+            "Only project owner or orgnization admin can change the git credential".to_string(),
+        ));
+    }
+
+    let (username, token) = credential.unzip();
+    let txn = state.db.conn.begin().await?;
+    entities::project::ActiveModel {
+        id: ActiveValue::Set(project.id),
+        git_credential_username: ActiveValue::Set(username),
+        git_credential_token: ActiveValue::Set(token),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            Utc::now().into(),
+            user.id,
+            org_id,
+            AuditResourceKind::Project.to_string(),
+            project.id,
+            project.name.clone(),
+            AuditAction::ProjectUpdateGitCredential.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
@@ -1,21 +1,24 @@
 use axum::{
     body::Body,
     extract::{Host, State, WebSocketUpgrade},
-    http::Request,
-    response::{IntoResponse, Response},
+    http::{HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
     routing::{any, delete, get, post, put},
     Router,
 };
 use axum_client_ip::SecureClientIpSource;
 use axum_extra::{headers, TypedHeader};
-use lapdev_db::entities;
-use lapdev_proxy_http::forward::ProxyForward;
+use lapdev_common::{
+    utils::is_path_routing_hostname, PATH_ROUTING_SUBDOMAIN, WORKSPACE_PATH_PREFIX,
+};
+use lapdev_proxy_http::proxy::WorkspacePath;
 use lapdev_rpc::error::ApiError;
 
 use crate::{
-    account, admin, machine_type, organization, project,
+    account, acme, admin, auto_start, local, machine_type, organization, port, project,
     session::{logout, new_session, session_authorize},
-    state::CoreState,
+    state::{CoreState, RequestInfo, TOKEN_COOKIE_NAME},
     websocket::handle_websocket,
     workspace,
 };
@@ -27,6 +30,9 @@ fn private_routes() -> Router<CoreState> {
         .route("/session", get(new_session))
         .route("/session", delete(logout))
         .route("/session/authorize", get(session_authorize))
+        .route("/session/local", post(local::local_login))
+        .route("/session/local/bootstrap", post(local::local_bootstrap))
+        .route("/session/local/setup", post(local::setup_local_password))
         .route("/me", get(account::me))
         .route(
             "/me/organization/:org_id",
@@ -54,6 +60,18 @@ fn v1_api_routes() -> Router<CoreState> {
             "/organizations/:org_id/auto_start_stop",
             put(organization::update_org_auto_start_stop),
         )
+        .route(
+            "/organizations/:org_id/ssh_session_recording",
+            put(organization::update_org_ssh_session_recording),
+        )
+        .route(
+            "/organizations/:org_id/ssh_agent_forwarding",
+            put(organization::update_org_ssh_agent_forwarding),
+        )
+        .route(
+            "/organizations/:org_id/activity_ports",
+            put(organization::update_org_activity_ports),
+        )
         .route(
             "/organizations/:org_id/members",
             get(organization::get_organization_members),
@@ -78,6 +96,10 @@ fn v1_api_routes() -> Router<CoreState> {
             "/organizations/:org_id/audit_logs",
             get(organization::get_organization_audit_log),
         )
+        .route(
+            "/organizations/:org_id/ssh_recordings/:recording_id",
+            get(organization::get_ssh_session_recording),
+        )
         .route(
             "/organizations/:org_id/quota",
             get(organization::get_organization_quota),
@@ -102,6 +124,30 @@ fn v1_api_routes() -> Router<CoreState> {
             "/organizations/:org_id/projects/:project_id",
             delete(project::delete_project),
         )
+        .route(
+            "/organizations/:org_id/deploy_keys",
+            post(project::create_deploy_key),
+        )
+        .route(
+            "/organizations/:org_id/projects/:project_id/deploy_key",
+            get(project::get_project_deploy_key),
+        )
+        .route(
+            "/organizations/:org_id/projects/:project_id/deploy_key",
+            post(project::regenerate_project_deploy_key),
+        )
+        .route(
+            "/organizations/:org_id/projects/:project_id/git_credential",
+            get(project::get_project_git_credential),
+        )
+        .route(
+            "/organizations/:org_id/projects/:project_id/git_credential",
+            put(project::update_project_git_credential),
+        )
+        .route(
+            "/organizations/:org_id/projects/:project_id/git_credential",
+            delete(project::delete_project_git_credential),
+        )
         .route(
             "/organizations/:org_id/projects/:project_id/branches",
             get(project::get_project_branches),
@@ -154,9 +200,59 @@ fn v1_api_routes() -> Router<CoreState> {
             "/organizations/:org_id/workspaces/:workspace_name/stop",
             post(workspace::stop_workspace),
         )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/ssh_access",
+            get(workspace::get_workspace_ssh_access),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/ssh_access",
+            put(workspace::grant_workspace_ssh_access),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/ssh_access/:user_id",
+            delete(workspace::revoke_workspace_ssh_access),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/ports",
+            get(port::get_workspace_ports),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/ports",
+            post(port::expose_workspace_port),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/ports/:port/label",
+            put(port::update_workspace_port_label),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/ports/:port/visibility",
+            put(port::update_workspace_port_visibility),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/ports/:port/share_links",
+            post(port::create_workspace_port_share_link),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/share_links",
+            get(port::get_workspace_port_share_links),
+        )
+        .route(
+            "/organizations/:org_id/workspaces/:workspace_name/share_links/:link_id",
+            delete(port::revoke_workspace_port_share_link),
+        )
         .route("/account/ssh_keys", post(account::create_ssh_key))
         .route("/account/ssh_keys", get(account::all_ssh_keys))
         .route("/account/ssh_keys/:key_id", delete(account::delete_ssh_key))
+        .route("/account/password", put(local::change_password))
+        .route("/account/totp", post(local::begin_totp))
+        .route("/account/totp", put(local::enable_totp))
+        .route("/account/totp", delete(local::disable_totp))
+        .route("/account/git_tokens", post(account::create_git_token))
+        .route("/account/git_tokens", get(account::all_git_tokens))
+        .route(
+            "/account/git_tokens/:token_id",
+            delete(account::delete_git_token),
+        )
         .route("/admin/workspace_hosts", get(admin::get_workspace_hosts))
         .route("/admin/workspace_hosts", post(admin::create_workspace_host))
         .route(
@@ -172,14 +268,23 @@ fn v1_api_routes() -> Router<CoreState> {
         .route("/admin/new_license", post(admin::new_license))
         .route("/admin/oauth", get(admin::get_oauth))
         .route("/admin/oauth", put(admin::update_oauth))
+        .route("/admin/oidc", get(admin::get_oidc))
+        .route("/admin/oidc", put(admin::update_oidc))
         .route("/admin/certs", get(admin::get_certs))
-        .route("/admin/certs", put(admin::update_certs))
+        .route("/admin/certs", post(admin::create_cert))
+        .route("/admin/certs/:fingerprint", delete(admin::delete_cert))
+        .route("/admin/acme", get(admin::get_acme))
+        .route("/admin/acme", put(admin::update_acme))
         .route("/admin/hostnames", put(admin::update_hostnames))
         .route("/admin/cpu_overcommit", get(admin::get_cpu_overcommit))
         .route(
             "/admin/cpu_overcommit/:value",
             put(admin::update_cpu_overcommit),
         )
+        .route(
+            "/admin/path_routing/:enabled",
+            put(admin::update_path_routing),
+        )
         .route(
             "/admin/machine_types",
             post(machine_type::create_machine_type),
@@ -194,6 +299,11 @@ fn v1_api_routes() -> Router<CoreState> {
         )
         .route("/admin/users", get(admin::get_cluster_users))
         .route("/admin/users/:user_id", put(admin::update_cluster_user))
+        .route("/admin/local_users", post(local::create_local_user))
+        .route(
+            "/admin/users/:user_id/password_reset",
+            post(local::reset_local_password),
+        )
 }
 
 fn main_routes() -> Router<CoreState> {
@@ -206,60 +316,153 @@ pub async fn build_router(state: CoreState) -> Router<()> {
     Router::new()
         .route("/", any(handle_catch_all))
         .route("/*0", any(handle_catch_all))
-        .nest("/api", main_routes())
+        .nest(
+            "/api",
+            main_routes().route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                reject_path_routing_hostname,
+            )),
+        )
         .with_state(state)
         .layer(SecureClientIpSource::ConnectInfo.into_extension())
 }
 
+/// The api isn't served on the hostname of the path routed workspaces,
+/// so that the scripts of the workspaces can't call it with the visitor's session
+async fn reject_path_routing_hostname(
+    State(state): State<CoreState>,
+    Host(hostname): Host,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if *state.path_routing.read().await && is_path_routing_hostname(&hostname) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    next.run(req).await
+}
+
 async fn handle_catch_all(
     Host(hostname): Host,
     websocket: Option<WebSocketUpgrade>,
     State(state): State<CoreState>,
     TypedHeader(cookie): TypedHeader<headers::Cookie>,
-    req: Request<Body>,
+    info: RequestInfo,
+    mut req: Request<Body>,
 ) -> Result<Response, ApiError> {
     let path = req.uri().path();
+    if let Some(token) = path.strip_prefix(acme::HTTP_CHALLENGE_PATH) {
+        if let Some(key_authorization) = state.acme.http_challenge_response(token).await {
+            return Ok(key_authorization.into_response());
+        }
+    }
     let path_query = req
         .uri()
         .path_and_query()
         .map(|v| v.as_str())
         .unwrap_or(path);
-    if let Some((ws, workspace_host, port)) =
-        lapdev_proxy_http::proxy::forward_workspace(hostname, &state.db).await
-    {
-        is_http_foward_allowed(&cookie, &state, &ws, port.as_ref()).await?;
+    let forward = if *state.path_routing.read().await {
+        if !is_path_routing_hostname(&hostname) {
+            // the workspaces are only served from their own hostname,
+            // so that they don't share the origin of the dashboard
+            if path.starts_with(WORKSPACE_PATH_PREFIX) {
+                let location = format!(
+                    "{}://{PATH_ROUTING_SUBDOMAIN}.{hostname}{path_query}",
+                    info.scheme
+                );
+                return Ok(Redirect::temporary(&location).into_response());
+            }
+            None
+        } else {
+            // and the workspace hostname doesn't serve the dashboard
+            let Some(forward) =
+                lapdev_proxy_http::proxy::forward_workspace_path(path_query, &state.db).await
+            else {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            };
+            let (ws, host, port, path) = forward;
+            Some((ws, host, port, Some(path)))
+        }
+    } else {
+        lapdev_proxy_http::proxy::forward_workspace(hostname.clone(), &state.db)
+            .await
+            .map(|(ws, host, port)| (ws, host, port, None))
+    };
+    if let Some((ws, workspace_host, port, ws_path)) = forward {
+        if let Some(ws_path) = ws_path.as_ref() {
+            // the relative urls of the workspace only resolve under the prefix with the trailing slash
+            if !ws_path.path_query.starts_with('/') {
+                let location = format!("{}/{}", ws_path.prefix, ws_path.path_query);
+                return Ok(Redirect::permanent(&location).into_response());
+            }
+        }
+        if let Some(port) = port.as_ref() {
+            let cookie_path = ws_path
+                .as_ref()
+                .map(|p| format!("{}/", p.prefix))
+                .unwrap_or_else(|| "/".to_string());
+            if let Some(resp) =
+                port::open_share_link(&state, &ws, port, req.uri(), &cookie_path, &info).await?
+            {
+                return Ok(resp);
+            }
+        }
+        port::is_http_forward_allowed(&cookie, &state, &ws, port.as_ref()).await?;
+        let activity =
+            port::workspace_activity(&state, &ws, port.as_ref().map(|p| p.port as u16)).await;
         let port = port
             .map(|p| p.host_port as u16)
             .or_else(|| ws.ide_port.map(|p| p as u16));
-        if let Some(forward) =
-            lapdev_proxy_http::forward::handler(&workspace_host.host, path_query, websocket, port)
-                .await
+        let forward_path_query = ws_path
+            .as_ref()
+            .map(|p| p.path_query.clone())
+            .unwrap_or_else(|| path_query.to_string());
+        if let Some(resp) = auto_start::auto_start_workspace(
+            &state,
+            &cookie,
+            &ws,
+            req.method(),
+            req.headers(),
+            &info,
+        )
+        .await?
         {
-            match forward {
-                ProxyForward::Resp(resp) => return Ok(resp),
-                ProxyForward::Proxy(uri) => {
-                    // *req.uri_mut() = uri;
-                    let headers = req.headers().clone();
-                    let mut new_req = Request::builder()
-                        .method(req.method())
-                        .uri(uri)
-                        .body(req.into_body())
-                        .unwrap();
-                    *new_req.headers_mut() = headers;
-                    let resp = state.hyper_client.request(new_req).await?;
-                    return Ok(resp.into_response());
-                }
-            }
-        } else {
+            return Ok(resp);
+        }
+        let Some(port) = port else {
             return Err(ApiError::InternalError("can't foward http".to_string()));
+        };
+        workspace_request_headers(req.headers_mut(), ws_path.as_ref());
+        let mut resp = state
+            .forwarder
+            .forward(
+                req,
+                &workspace_host.host,
+                port,
+                &forward_path_query,
+                websocket,
+                activity,
+            )
+            .await;
+        if let Some(ws_path) = ws_path.as_ref() {
+            lapdev_proxy_http::forward::rewrite_response_headers(
+                resp.headers_mut(),
+                &hostname,
+                &ws_path.prefix,
+            );
         }
+        return Ok(resp);
     }
 
     if let Some(websocket) = websocket {
         let uri = req.uri();
         let path = uri.path();
         let query = uri.query();
-        let resp = handle_websocket(path, query, websocket, cookie, state).await?;
+        let origin = req
+            .headers()
+            .get(axum::http::header::ORIGIN)
+            .and_then(|h| h.to_str().ok());
+        let resp =
+            handle_websocket(path, query, origin, &hostname, websocket, cookie, state).await?;
         return Ok(resp);
     }
 
@@ -319,27 +522,48 @@ async fn handle_catch_all(
     Err(ApiError::InvalidRequest("Invalid Request".to_string()))
 }
 
-async fn is_http_foward_allowed(
-    cookie: &headers::Cookie,
-    state: &CoreState,
-    ws: &entities::workspace::Model,
-    port: Option<&entities::workspace_port::Model>,
-) -> Result<(), ApiError> {
-    let user = state.authenticate(cookie).await?;
-    if ws.user_id == user.id {
-        return Ok(());
+/// The headers of a request that's forwarded to a workspace. The browser sends the visitor's
+/// lapdev session and share link cookies to the workspace hostnames as well, since the session
+/// cookie is for the subdomains, so they're removed before the workspace owner's server sees them.
+fn workspace_request_headers(headers: &mut HeaderMap, ws_path: Option<&WorkspacePath>) {
+    lapdev_proxy_http::forward::remove_cookies(
+        headers,
+        &[TOKEN_COOKIE_NAME, port::SHARE_COOKIE_NAME],
+    );
+    if let Some(prefix) = ws_path.and_then(|p| p.prefix.parse().ok()) {
+        headers.insert("x-forwarded-prefix", prefix);
     }
-    if let Some(port) = port {
-        if port.shared
-            && state
-                .db
-                .get_organization_member(user.id, ws.organization_id)
-                .await
-                .is_ok()
-        {
-            return Ok(());
-        }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap, HeaderValue};
+    use lapdev_proxy_http::proxy::WorkspacePath;
+
+    use super::workspace_request_headers;
+
+    fn headers(cookie: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static(cookie));
+        headers
     }
 
-    Err(ApiError::Unauthorized)
+    #[test]
+    fn test_workspace_request_headers() {
+        // subdomain routing
+        let mut h = headers("token=session; lapdev_share=link; app=1");
+        workspace_request_headers(&mut h, None);
+        assert_eq!(h[header::COOKIE], "app=1");
+        assert!(h.get("x-forwarded-prefix").is_none());
+
+        // path routing
+        let mut h = headers("lapdev_share=link; token=session");
+        let ws_path = WorkspacePath {
+            prefix: "/ws/myws/8080".to_string(),
+            path_query: "/".to_string(),
+        };
+        workspace_request_headers(&mut h, Some(&ws_path));
+        assert!(h.get(header::COOKIE).is_none());
+        assert_eq!(h["x-forwarded-prefix"], "/ws/myws/8080");
+    }
 }
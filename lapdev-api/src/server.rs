@@ -1,20 +1,26 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Context, Result};
-use axum::extract::Request;
+use axum::extract::{ConnectInfo, Request};
 use clap::Parser;
 use futures_util::pin_mut;
 use hyper::body::Incoming;
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use lapdev_conductor::Conductor;
 use lapdev_db::api::DbApi;
+use lapdev_proxy_http::forward::{ForwardConfig, TlsConnection};
+use metrics_exporter_prometheus::PrometheusBuilder;
 use serde::Deserialize;
 use tokio::net::TcpListener;
 use tokio_rustls::TlsAcceptor;
 use tower::Service;
 use tracing::error;
 
-use crate::{cert::tls_config, router, state::CoreState};
+use crate::{
+    cert::{load_key_cipher, tls_config},
+    router,
+    state::CoreState,
+};
 
 pub const LAPDEV_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -26,6 +32,18 @@ struct LapdevConfig {
     http_port: Option<u16>,
     https_port: Option<u16>,
     ssh_proxy_port: Option<u16>,
+    /// Seconds to wait for the connection to a workspace port
+    proxy_connect_timeout: Option<u64>,
+    /// Seconds an idle connection to a workspace port is kept open
+    proxy_idle_timeout: Option<u64>,
+    /// The file with the key that encrypts the private keys stored in the db.
+    /// It's generated on the first start, after that every api server needs a copy of it,
+    /// and it must be backed up with the db.
+    secret_key_file: Option<PathBuf>,
+    /// Warn about the certificates that expire within this many days
+    cert_expiry_warning_days: Option<i64>,
+    /// Serve the prometheus metrics at `/metrics` on this port, it's off if not set
+    metrics_port: Option<u16>,
 }
 
 #[derive(Parser)]
@@ -51,8 +69,21 @@ pub async fn run() -> Result<()> {
         .db
         .ok_or_else(|| anyhow!("can't find database url in your config file"))?;
 
+    if let Some(port) = config.metrics_port {
+        let bind = format!(
+            "{}:{port}",
+            config.bind.clone().unwrap_or_else(|| "0.0.0.0".to_string())
+        );
+        serve_metrics(&bind).await?;
+    }
+
     let db = DbApi::new(&db_url).await?;
-    let conductor = Conductor::new(LAPDEV_VERSION, db.clone()).await?;
+    let secret_key_file = config
+        .secret_key_file
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("/var/lib/lapdev/secret.key"));
+    let key_cipher = load_key_cipher(&db, &secret_key_file).await?;
+    let conductor = Conductor::new(LAPDEV_VERSION, db.clone(), key_cipher).await?;
 
     let ssh_proxy_port = config.ssh_proxy_port.unwrap_or(2222);
     {
@@ -71,7 +102,21 @@ pub async fn run() -> Result<()> {
         });
     }
 
-    let state = CoreState::new(conductor, ssh_proxy_port).await;
+    let mut forward_config = ForwardConfig::default();
+    if let Some(timeout) = config.proxy_connect_timeout {
+        forward_config.connect_timeout = Duration::from_secs(timeout);
+    }
+    if let Some(timeout) = config.proxy_idle_timeout {
+        forward_config.idle_timeout = Duration::from_secs(timeout);
+    }
+
+    let state = CoreState::new(
+        conductor,
+        ssh_proxy_port,
+        forward_config,
+        config.cert_expiry_warning_days.unwrap_or(14),
+    )
+    .await;
     let app = router::build_router(state.clone()).await;
     let certs = state.certs.clone();
 
@@ -87,7 +132,12 @@ pub async fn run() -> Result<()> {
             .with_context(|| format!("bind to {bind}"))?;
         let app = app.clone();
         tokio::spawn(async move {
-            if let Err(err) = axum::serve(tcp_listener, app.into_make_service()).await {
+            if let Err(err) = axum::serve(
+                tcp_listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            {
                 tracing::error!("http server stopped error: {err}");
             }
         });
@@ -127,13 +177,17 @@ pub async fn run() -> Result<()> {
             // Hyper also has its own `Service` trait and doesn't use tower. We can use
             // `hyper::service::service_fn` to create a hyper `Service` that calls our app through
             // `tower::Service::call`.
-            let hyper_service = hyper::service::service_fn(move |request: Request<Incoming>| {
-                // We have to clone `tower_service` because hyper's `Service` uses `&self` whereas
-                // tower's `Service` requires `&mut self`.
-                //
-                // We don't need to call `poll_ready` since `Router` is always ready.
-                tower_service.clone().call(request)
-            });
+            let hyper_service =
+                hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                    // the client address and the scheme for the forwarded headers to the workspaces
+                    request.extensions_mut().insert(ConnectInfo(addr));
+                    request.extensions_mut().insert(TlsConnection);
+                    // We have to clone `tower_service` because hyper's `Service` uses `&self` whereas
+                    // tower's `Service` requires `&mut self`.
+                    //
+                    // We don't need to call `poll_ready` since `Router` is always ready.
+                    tower_service.clone().call(request)
+                });
 
             let ret = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
                 .serve_connection_with_upgrades(stream, hyper_service)
@@ -145,3 +199,23 @@ pub async fn run() -> Result<()> {
         });
     }
 }
+
+/// Install the prometheus recorder for the `metrics` macros, and serve what it collects
+async fn serve_metrics(bind: &str) -> Result<()> {
+    let handle = PrometheusBuilder::new()
+        .install_recorder()
+        .map_err(|e| anyhow!("install metrics recorder: {e}"))?;
+    let tcp_listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("bind to {bind}"))?;
+    let app = axum::Router::new().route(
+        "/metrics",
+        axum::routing::get(move || std::future::ready(handle.render())),
+    );
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(tcp_listener, app).await {
+            tracing::error!("metrics server stopped error: {err}");
+        }
+    });
+    Ok(())
+}
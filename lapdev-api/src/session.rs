@@ -1,4 +1,4 @@
-use std::{collections::HashMap, net::IpAddr, str::FromStr};
+use std::{collections::HashMap, net::IpAddr};
 
 use axum::{
     extract::{Host, Query, State},
@@ -11,26 +11,39 @@ use chrono::Utc;
 use hyper::StatusCode;
 use lapdev_common::{
     console::NewSessionResponse, AuditAction, AuditResourceKind, AuthProvider, ProviderUser,
+    UserRole,
 };
+use lapdev_conductor::oauth::OauthToken;
 use lapdev_db::entities;
 use lapdev_rpc::error::ApiError;
+use oauth2::AccessToken;
 use pasetors::claims::Claims;
 use sea_orm::{
     ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter,
     TransactionTrait,
 };
 use serde::Deserialize;
+use uuid::Uuid;
 
-use crate::state::{CoreState, RequestInfo, TOKEN_COOKIE_NAME};
+use crate::{
+    bitbucket::BitbucketClient,
+    gitea::GiteaClient,
+    github::GithubClient,
+    gitlab::GitlabClient,
+    state::{CoreState, RequestInfo, SESSION_EPOCH, TOKEN_COOKIE_NAME},
+};
 
 const OAUTH_STATE: &str = "oauth_state";
 const REDIRECT_URL: &str = "redirect_url";
+const OIDC_NONCE: &str = "oidc_nonce";
+const PKCE_VERIFIER: &str = "pkce_verifier";
 
 #[derive(Debug, Deserialize)]
 pub struct AuthRequest {
     code: String,
     state: String,
-    provider: AuthProvider,
+    /// The id of the provider instance, see [`lapdev_common::LoginProvider`]
+    provider: String,
     next: Option<String>,
 }
 
@@ -48,31 +61,32 @@ pub(crate) async fn new_session(
     let provider = query
         .get("provider")
         .ok_or_else(|| ApiError::InvalidRequest("no provider in query string".to_string()))?;
-    let provider = AuthProvider::from_str(provider)
-        .map_err(|_| ApiError::InvalidRequest(format!("provider {provider} is invalid")))?;
 
     let redirect_url =
         format!("{host}/api/private/session/authorize?provider={provider}&next={next}");
-    let oauth_no_read_repo = state.db.oauth_no_read_repo().await.unwrap_or(false);
-    let (url, csrf) = state
-        .auth
-        .authorize_url(provider, &redirect_url, oauth_no_read_repo)
-        .await?;
-
     let mut claims = Claims::new()?;
+    let (url, csrf) = if *provider == AuthProvider::Oidc.to_string() {
+        let authorization = state
+            .auth
+            .oidc_client()
+            .await?
+            .authorize_url(&redirect_url)?;
+        claims.add_additional(OIDC_NONCE, authorization.nonce)?;
+        claims.add_additional(PKCE_VERIFIER, authorization.pkce_verifier)?;
+        (authorization.url, authorization.csrf)
+    } else {
+        let oauth_no_read_repo = state.db.oauth_no_read_repo().await.unwrap_or(false);
+        state
+            .auth
+            .authorize_url(provider, &redirect_url, oauth_no_read_repo)
+            .await
+            .map_err(|_| ApiError::InvalidRequest(format!("provider {provider} is invalid")))?
+    };
+
     claims.add_additional(OAUTH_STATE, csrf.clone())?;
     claims.add_additional(REDIRECT_URL, redirect_url.clone())?;
     let token = pasetors::local::encrypt(&state.auth_token_key, &claims, None, None)?;
-    let cookie = format!("{TOKEN_COOKIE_NAME}={token}; Path=/");
-    let cookie = if let Some(hostname) = hostname.split(':').next() {
-        if hostname.parse::<IpAddr>().is_err() {
-            format!("{cookie}; Domain=.{hostname}")
-        } else {
-            cookie
-        }
-    } else {
-        cookie
-    };
+    let cookie = token_cookie(&token, &hostname, "");
     let mut headers = HeaderMap::new();
     headers.insert(SET_COOKIE, cookie.parse()?);
 
@@ -105,16 +119,26 @@ pub(crate) async fn session_authorize(
         ));
     }
 
-    let token = state
-        .auth
-        .exchange_code(&query.provider, query.code, redirect_url)
-        .await?;
+    let (kind, api_url) = if query.provider == AuthProvider::Oidc.to_string() {
+        (AuthProvider::Oidc, String::new())
+    } else {
+        let provider = state.auth.provider(&query.provider).await.ok_or_else(|| {
+            ApiError::InvalidRequest(format!("provider {} is invalid", query.provider))
+        })?;
+        (provider.kind.clone(), provider.api_url())
+    };
 
-    let provider_user = match &query.provider {
+    let mut groups = None;
+    let (provider_user, token) = match kind {
         AuthProvider::Github => {
-            let ghuser = state.github_client.current_user(&token).await?;
+            let token = state
+                .auth
+                .exchange_code(&query.provider, query.code, redirect_url)
+                .await?;
+            let github_client = GithubClient::new(api_url);
+            let ghuser = github_client.current_user(&token.access_token).await?;
             let mut email = None;
-            if let Ok(emails) = state.github_client.user_email(&token).await {
+            if let Ok(emails) = github_client.user_email(&token.access_token).await {
                 for e in &emails {
                     if e.primary {
                         email = Some(e.email.clone());
@@ -125,38 +149,139 @@ pub(crate) async fn session_authorize(
                     email = emails.first().map(|e| e.email.clone());
                 }
             }
-            ProviderUser {
+            let provider_user = ProviderUser {
                 id: ghuser.id,
+                subject: None,
                 login: ghuser.login,
                 name: ghuser.name,
                 email,
                 avatar_url: ghuser.avatar_url,
-            }
+            };
+            (provider_user, token)
         }
         AuthProvider::Gitlab => {
-            let user = state.auth.gitlab_client.current_user(&token).await?;
-            ProviderUser {
+            let token = state
+                .auth
+                .exchange_code(&query.provider, query.code, redirect_url)
+                .await?;
+            let user = GitlabClient::new(api_url)
+                .current_user(&token.access_token)
+                .await?;
+            let provider_user = ProviderUser {
                 id: user.id,
+                subject: None,
                 login: user.username,
                 email: user.email,
                 name: user.name,
                 avatar_url: user.avatar_url,
+            };
+            (provider_user, token)
+        }
+        AuthProvider::Gitea => {
+            let token = state
+                .auth
+                .exchange_code(&query.provider, query.code, redirect_url)
+                .await?;
+            let gitea_client = GiteaClient::new(api_url);
+            let user = gitea_client.current_user(&token.access_token).await?;
+            let mut email = user.email.filter(|e| !e.is_empty());
+            if let Ok(emails) = gitea_client.user_email(&token.access_token).await {
+                if let Some(e) = emails
+                    .iter()
+                    .find(|e| e.primary && e.verified)
+                    .or_else(|| emails.iter().find(|e| e.verified))
+                {
+                    email = Some(e.email.clone());
+                }
+            }
+            let provider_user = ProviderUser {
+                id: user.id,
+                subject: None,
+                login: user.login,
+                email,
+                name: user.full_name.filter(|n| !n.is_empty()),
+                avatar_url: user.avatar_url,
+            };
+            (provider_user, token)
+        }
+        AuthProvider::Bitbucket => {
+            let token = state
+                .auth
+                .exchange_code(&query.provider, query.code, redirect_url)
+                .await?;
+            let bitbucket_client = BitbucketClient::new(api_url);
+            let user = bitbucket_client.current_user(&token.access_token).await?;
+            let mut email = None;
+            if let Ok(emails) = bitbucket_client.user_email(&token.access_token).await {
+                email = emails
+                    .iter()
+                    .find(|e| e.is_primary && e.is_confirmed)
+                    .or_else(|| emails.iter().find(|e| e.is_confirmed))
+                    .map(|e| e.email.clone());
             }
+            let provider_user = ProviderUser {
+                // identified by the uuid, since there's no numeric id
+                id: 0,
+                subject: Some(user.uuid),
+                login: user.nickname,
+                email,
+                name: user.display_name,
+                avatar_url: user.links.avatar.map(|a| a.href),
+            };
+            (provider_user, token)
+        }
+        AuthProvider::Local => {
+            return Err(ApiError::InvalidRequest(
+                "local accounts sign in with their password".to_string(),
+            ));
+        }
+        AuthProvider::Oidc => {
+            let nonce = claims
+                .get_claim(OIDC_NONCE)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| ApiError::InvalidRequest("doesn't have oidc_nonce".to_string()))?;
+            let pkce_verifier = claims
+                .get_claim(PKCE_VERIFIER)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| {
+                    ApiError::InvalidRequest("doesn't have pkce_verifier".to_string())
+                })?;
+            let oidc_user = state
+                .auth
+                .oidc_client()
+                .await?
+                .exchange_code(
+                    query.code,
+                    redirect_url,
+                    pkce_verifier.to_string(),
+                    nonce.to_string(),
+                )
+                .await?;
+            groups = Some(oidc_user.groups);
+            let token = OauthToken {
+                access_token: AccessToken::new(oidc_user.access_token),
+                refresh_token: None,
+                expires_at: None,
+            };
+            (oidc_user.user, token)
         }
     };
 
-    let user = match entities::user::Entity::find()
-        .filter(entities::user::Column::Provider.eq(query.provider.to_string()))
+    let mut existing = entities::user::Entity::find()
+        .filter(entities::user::Column::Provider.eq(query.provider.clone()))
         .filter(entities::user::Column::ProviderId.eq(provider_user.id))
-        .filter(entities::user::Column::DeletedAt.is_null())
-        .one(&state.db.conn)
-        .await?
-    {
+        .filter(entities::user::Column::DeletedAt.is_null());
+    if let Some(subject) = provider_user.subject.as_ref() {
+        existing = existing.filter(entities::user::Column::ProviderSubject.eq(subject.clone()));
+    }
+    let user = match existing.one(&state.db.conn).await? {
         Some(user) => {
             entities::user::ActiveModel {
                 id: ActiveValue::Set(user.id),
                 provider_login: ActiveValue::Set(provider_user.login),
-                access_token: ActiveValue::Set(token.secret().to_string()),
+                access_token: ActiveValue::Set(token.access_token.secret().to_string()),
+                oauth_refresh_token: ActiveValue::Set(token.refresh_token),
+                access_token_expires_at: ActiveValue::Set(token.expires_at.map(|t| t.into())),
                 avatar_url: ActiveValue::Set(provider_user.avatar_url),
                 email: ActiveValue::Set(provider_user.email),
                 name: ActiveValue::Set(provider_user.name),
@@ -166,27 +291,7 @@ pub(crate) async fn session_authorize(
             .await?
         }
         None => {
-            if let Some(enterprise) = state
-                .conductor
-                .enterprise
-                .license
-                .license
-                .read()
-                .await
-                .as_ref()
-            {
-                if enterprise.users > 0 {
-                    let count = entities::user::Entity::find()
-                        .filter(entities::user::Column::DeletedAt.is_null())
-                        .count(&state.db.conn)
-                        .await?;
-                    if count >= enterprise.users as u64 {
-                        return Err(ApiError::InvalidRequest(
-                            "You've reached the users limit in your enterprise license".to_string(),
-                        ));
-                    }
-                }
-            }
+            check_users_limit(&state).await?;
             let now = Utc::now();
             let txn = state.db.conn.begin().await?;
             let user = state
@@ -195,9 +300,17 @@ pub(crate) async fn session_authorize(
                     &txn,
                     &query.provider,
                     provider_user,
-                    token.secret().to_string(),
+                    token.access_token.secret().to_string(),
                 )
                 .await?;
+            let user = entities::user::ActiveModel {
+                id: ActiveValue::Set(user.id),
+                oauth_refresh_token: ActiveValue::Set(token.refresh_token),
+                access_token_expires_at: ActiveValue::Set(token.expires_at.map(|t| t.into())),
+                ..Default::default()
+            }
+            .update(&txn)
+            .await?;
 
             state
                 .conductor
@@ -209,10 +322,10 @@ pub(crate) async fn session_authorize(
                     user.current_organization,
                     AuditResourceKind::User.to_string(),
                     user.id,
-                    user.name.clone().unwrap_or_else(|| "".to_string()),
+                    user.name.clone().unwrap_or_default(),
                     AuditAction::UserCreate.to_string(),
-                    info.ip,
-                    info.user_agent,
+                    info.ip.clone(),
+                    info.user_agent.clone(),
                 )
                 .await?;
 
@@ -221,22 +334,11 @@ pub(crate) async fn session_authorize(
         }
     };
 
-    let mut claims = Claims::new_expires_in(&core::time::Duration::from_secs(86400 * 30))?;
-    claims.add_additional("user_id", user.id.to_string())?;
-    let token = pasetors::local::encrypt(&state.auth_token_key, &claims, None, None)?;
-    let cookie = format!("{TOKEN_COOKIE_NAME}={token}; Path=/");
-    let cookie = if let Some(hostname) = hostname.split(':').next() {
-        if hostname.parse::<IpAddr>().is_err() {
-            format!("{cookie}; Domain=.{hostname}")
-        } else {
-            cookie
-        }
-    } else {
-        cookie
-    };
-    let mut headers = HeaderMap::new();
-    headers.insert(SET_COOKIE, cookie.parse()?);
+    if let Some(groups) = groups {
+        apply_group_mappings(&state, &user, &groups, &info).await?;
+    }
 
+    let headers = session_cookie(&state, &hostname, &user)?;
     Ok((
         headers,
         Redirect::temporary(query.next.as_deref().unwrap_or("/")),
@@ -244,18 +346,161 @@ pub(crate) async fn session_authorize(
         .into_response())
 }
 
-pub(crate) async fn logout(Host(hostname): Host) -> Result<Response, ApiError> {
-    let cookie =
-        format!("{TOKEN_COOKIE_NAME}=deleted; Path=/; expires=Thu, 01 Jan 1970 00:00:00 GMT");
-    let cookie = if let Some(hostname) = hostname.split(':').next() {
-        if hostname.parse::<IpAddr>().is_err() {
+/// The session token cookie, which the dashboard's scripts don't need to read.
+/// It's for the hostname and its subdomains, where the workspaces are.
+fn token_cookie(value: &str, hostname: &str, attrs: &str) -> String {
+    let cookie = format!("{TOKEN_COOKIE_NAME}={value}; Path=/; HttpOnly; SameSite=Lax{attrs}");
+    match hostname.split(':').next() {
+        Some(hostname) if hostname.parse::<IpAddr>().is_err() => {
             format!("{cookie}; Domain=.{hostname}")
-        } else {
-            cookie
         }
-    } else {
-        cookie
-    };
+        _ => cookie,
+    }
+}
+
+/// The cookie of the new session of the user, which is shared by the subdomains of the hostname.
+/// The session is only valid in the current session epoch of the user.
+pub(crate) fn session_cookie(
+    state: &CoreState,
+    hostname: &str,
+    user: &entities::user::Model,
+) -> Result<HeaderMap, ApiError> {
+    let mut claims = Claims::new_expires_in(&core::time::Duration::from_secs(86400 * 30))?;
+    claims.add_additional("user_id", user.id.to_string())?;
+    claims.add_additional(SESSION_EPOCH, user.session_epoch)?;
+    let token = pasetors::local::encrypt(&state.auth_token_key, &claims, None, None)?;
+    let cookie = token_cookie(&token, hostname, "");
+    let mut headers = HeaderMap::new();
+    headers.insert(SET_COOKIE, cookie.parse()?);
+    Ok(headers)
+}
+
+/// Refuses new users when the enterprise license has a users limit that's reached
+pub(crate) async fn check_users_limit(state: &CoreState) -> Result<(), ApiError> {
+    if let Some(enterprise) = state
+        .conductor
+        .enterprise
+        .license
+        .license
+        .read()
+        .await
+        .as_ref()
+    {
+        if enterprise.users > 0 {
+            let count = entities::user::Entity::find()
+                .filter(entities::user::Column::DeletedAt.is_null())
+                .count(&state.db.conn)
+                .await?;
+            if count >= enterprise.users as u64 {
+                return Err(ApiError::InvalidRequest(
+                    "You've reached the users limit in your enterprise license".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add the user to the organizations that the OIDC groups are mapped to, or update the role in them.
+/// Organization owners are left as they are, and the user isn't removed from any organization.
+async fn apply_group_mappings(
+    state: &CoreState,
+    user: &entities::user::Model,
+    groups: &[String],
+    info: &RequestInfo,
+) -> Result<(), ApiError> {
+    let mappings = state
+        .auth
+        .oidc_settings
+        .read()
+        .await
+        .as_ref()
+        .map(|s| s.group_mappings.clone())
+        .unwrap_or_default();
+    let mut roles: HashMap<Uuid, UserRole> = HashMap::new();
+    for mapping in mappings.into_iter().filter(|m| groups.contains(&m.group)) {
+        let role = roles
+            .entry(mapping.organization)
+            .or_insert(UserRole::Member);
+        if mapping.role == UserRole::Admin {
+            *role = UserRole::Admin;
+        }
+    }
+
+    for (org_id, role) in roles {
+        let org = match state.db.get_organization(org_id).await {
+            Ok(org) => org,
+            Err(_) => {
+                tracing::warn!("oidc group mapping organization {org_id} doesn't exist");
+                continue;
+            }
+        };
+        let member = entities::organization_member::Entity::find()
+            .filter(entities::organization_member::Column::UserId.eq(user.id))
+            .filter(entities::organization_member::Column::OrganizationId.eq(org.id))
+            .filter(entities::organization_member::Column::DeletedAt.is_null())
+            .one(&state.db.conn)
+            .await?;
+        if let Some(member) = member.as_ref() {
+            if member.role == UserRole::Owner.to_string() || member.role == role.to_string() {
+                continue;
+            }
+        }
+
+        let now = Utc::now();
+        let txn = state.db.conn.begin().await?;
+        let action = match member {
+            Some(member) => {
+                entities::organization_member::ActiveModel {
+                    id: ActiveValue::Set(member.id),
+                    role: ActiveValue::Set(role.to_string()),
+                    ..Default::default()
+                }
+                .update(&txn)
+                .await?;
+                AuditAction::OrganizationUpdateMember
+            }
+            None => {
+                entities::organization_member::ActiveModel {
+                    created_at: ActiveValue::Set(now.into()),
+                    user_id: ActiveValue::Set(user.id),
+                    organization_id: ActiveValue::Set(org.id),
+                    role: ActiveValue::Set(role.to_string()),
+                    ..Default::default()
+                }
+                .insert(&txn)
+                .await?;
+                AuditAction::OrganizationJoin
+            }
+        };
+        state
+            .conductor
+            .enterprise
+            .insert_audit_log(
+                &txn,
+                now.into(),
+                user.id,
+                org.id,
+                AuditResourceKind::User.to_string(),
+                user.id,
+                user.name.clone().unwrap_or_default(),
+                action.to_string(),
+                info.ip.clone(),
+                info.user_agent.clone(),
+            )
+            .await?;
+        txn.commit().await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn logout(Host(hostname): Host) -> Result<Response, ApiError> {
+    let cookie = token_cookie(
+        "deleted",
+        &hostname,
+        "; expires=Thu, 01 Jan 1970 00:00:00 GMT",
+    );
     let mut headers = HeaderMap::new();
     headers.insert(SET_COOKIE, cookie.parse()?);
     Ok((headers, StatusCode::OK).into_response())
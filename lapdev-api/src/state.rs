@@ -1,19 +1,24 @@
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::{anyhow, Context, Result};
-use axum::{
-    async_trait, body::Body, extract::FromRequestParts, http::request::Parts, RequestPartsExt,
-};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, RequestPartsExt};
 use axum_client_ip::InsecureClientIp;
 use axum_extra::{
     headers::{self, UserAgent},
     TypedHeader,
 };
-use hyper_util::{client::legacy::connect::HttpConnector, rt::TokioExecutor};
-use lapdev_common::LAPDEV_BASE_HOSTNAME;
-use lapdev_conductor::{scheduler::LAPDEV_CPU_OVERCOMMIT, Conductor};
+use chrono::Utc;
+use lapdev_common::{
+    CertificateInfo, LAPDEV_ACME, LAPDEV_BASE_HOSTNAME, LAPDEV_OAUTH_PROVIDERS, LAPDEV_OIDC,
+    LAPDEV_PATH_ROUTING,
+};
+use lapdev_conductor::{cipher::KeyCipher, scheduler::LAPDEV_CPU_OVERCOMMIT, Conductor};
 use lapdev_db::{api::DbApi, entities};
 use lapdev_enterprise::license::LAPDEV_ENTERPRISE_LICENSE;
+use lapdev_proxy_http::{
+    activity::ActivityRecorder,
+    forward::{ForwardConfig, Forwarder, TlsConnection},
+};
 use lapdev_rpc::error::ApiError;
 use pasetors::{
     claims::ClaimsValidationRules,
@@ -24,23 +29,29 @@ use pasetors::{
 use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use serde::Deserialize;
 use sqlx::postgres::PgNotification;
+use tokio::sync::{Mutex, RwLock};
 use tokio_rustls::rustls::sign::CertifiedKey;
 use uuid::Uuid;
 
 use crate::{
-    auth::{Auth, AuthConfig},
-    cert::{load_cert, CertStore},
-    github::GithubClient,
+    account::encrypt_stored_git_tokens,
+    acme::{Acme, LAPDEV_ACME_CERTS, LAPDEV_ACME_HTTP_CHALLENGES},
+    auth::Auth,
+    cert::{cert_info, encrypt_stored_keys, load_cert, stored_certs, CertStore},
+    local::LoginLimiter,
 };
 
 pub const TOKEN_COOKIE_NAME: &str = "token";
+/// The claim of the session tokens with the session epoch of the user
+pub const SESSION_EPOCH: &str = "session_epoch";
 pub const LAPDEV_CERTS: &str = "lapdev-certs";
-
-pub type HyperClient = hyper_util::client::legacy::Client<HttpConnector, Body>;
+const CERT_EXPIRY_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 3600);
 
 pub struct RequestInfo {
     pub ip: Option<String>,
     pub user_agent: Option<String>,
+    /// `https` if the request came over tls, to lapdev or to a proxy in front of it
+    pub scheme: &'static str,
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,44 +70,102 @@ impl<S: Send + Sync> FromRequestParts<S> for RequestInfo {
             .map(|ip| ip.0.to_string());
         let user_agent = parts.extract::<TypedHeader<UserAgent>>().await.ok();
         let user_agent = user_agent.map(|u| u.to_string());
-        Ok(Self { user_agent, ip })
+        let https = parts.extensions.get::<TlsConnection>().is_some()
+            || parts
+                .headers
+                .get("x-forwarded-proto")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().eq_ignore_ascii_case("https"))
+                .unwrap_or(false);
+        let scheme = if https { "https" } else { "http" };
+        Ok(Self {
+            user_agent,
+            ip,
+            scheme,
+        })
     }
 }
 
 #[derive(Clone)]
 pub struct CoreState {
     pub conductor: Conductor,
-    pub github_client: GithubClient,
-    pub hyper_client: Arc<HyperClient>,
+    pub forwarder: Arc<Forwarder>,
     pub db: DbApi,
     pub auth: Arc<Auth>,
     pub auth_token_key: Arc<SymmetricKey<V4>>,
     pub certs: CertStore,
+    pub key_cipher: KeyCipher,
+    /// Warn about the certificates that expire within this many days
+    pub cert_expiry_warning_days: i64,
     pub ssh_proxy_port: u16,
+    /// Serve the workspaces under `/ws/<workspace>/` of the main hostname
+    /// instead of their own subdomains
+    pub path_routing: Arc<RwLock<bool>>,
+    /// Held while checking and auto starting a stopped workspace from http,
+    /// so that concurrent page loads don't start it twice
+    pub auto_start_lock: Arc<Mutex<()>>,
+    pub activity: Arc<ActivityRecorder>,
+    pub acme: Acme,
+    pub login_limiter: Arc<LoginLimiter>,
 }
 
 impl CoreState {
-    pub async fn new(conductor: Conductor, ssh_proxy_port: u16) -> Self {
-        let github_client = GithubClient::new();
+    pub async fn new(
+        conductor: Conductor,
+        ssh_proxy_port: u16,
+        forward_config: ForwardConfig,
+        cert_expiry_warning_days: i64,
+    ) -> Self {
+        let key_cipher = conductor.key_cipher.clone();
         let key = conductor.db.load_api_auth_token_key().await;
         let auth = Auth::new(&conductor.db).await;
-        let certs = load_certs(&conductor.db).await.unwrap_or_default();
-
-        let hyper_client: HyperClient =
-            hyper_util::client::legacy::Client::<(), ()>::builder(TokioExecutor::new())
-                .build(HttpConnector::new());
+        for name in [LAPDEV_CERTS, LAPDEV_ACME_CERTS] {
+            if let Err(e) = encrypt_stored_keys(&conductor.db, &key_cipher, name).await {
+                tracing::error!("encrypt stored keys of {name} error: {e:#}");
+            }
+        }
+        if let Err(e) = encrypt_stored_git_tokens(&conductor.db, &key_cipher).await {
+            tracing::error!("encrypt stored git tokens error: {e:#}");
+        }
+        if let Err(e) = conductor.encrypt_stored_deploy_keys().await {
+            tracing::error!("encrypt stored deploy keys error: {e:#}");
+        }
+        let certs = load_certs(&conductor.db, &key_cipher)
+            .await
+            .unwrap_or_default();
+        let path_routing = conductor
+            .db
+            .is_path_routing_enabled()
+            .await
+            .unwrap_or(false);
+        let path_routing = Arc::new(RwLock::new(path_routing));
+        let activity = Arc::new(ActivityRecorder::new(conductor.db.clone()));
+        let acme = Acme::new(conductor.db.clone(), key_cipher.clone());
+        tokio::spawn(
+            acme.clone()
+                .run(conductor.hostnames.clone(), path_routing.clone()),
+        );
 
         let state = Self {
             db: conductor.db.clone(),
             conductor,
-            github_client,
             auth: Arc::new(auth),
             auth_token_key: Arc::new(key),
             certs: Arc::new(std::sync::RwLock::new(Arc::new(certs))),
+            key_cipher,
+            cert_expiry_warning_days,
             ssh_proxy_port,
-            hyper_client: Arc::new(hyper_client),
+            path_routing,
+            auto_start_lock: Arc::new(Mutex::new(())),
+            activity,
+            acme,
+            login_limiter: Arc::new(LoginLimiter::default()),
+            forwarder: Arc::new(Forwarder::new(forward_config)),
         };
 
+        tokio::spawn(state.activity.clone().run());
+        tokio::spawn(state.clone().monitor_cert_expiry());
+
         {
             let state = state.clone();
             tokio::spawn(async move {
@@ -128,11 +197,7 @@ impl CoreState {
     async fn handle_config_update_notification(&self, notification: PgNotification) -> Result<()> {
         let payload: ConfigUpdatePayload = serde_json::from_str(notification.payload())
             .with_context(|| format!("trying to deserialize payload {}", notification.payload()))?;
-        if payload.name == AuthConfig::GITHUB.client_id
-            || payload.name == AuthConfig::GITHUB.client_secret
-            || payload.name == AuthConfig::GITLAB.client_id
-            || payload.name == AuthConfig::GITLAB.client_secret
-        {
+        if payload.name == LAPDEV_OAUTH_PROVIDERS || payload.name == LAPDEV_OIDC {
             self.auth.resync(&self.db).await;
         } else if payload.name == LAPDEV_ENTERPRISE_LICENSE {
             self.conductor.enterprise.license.resync_license().await;
@@ -147,8 +212,17 @@ impl CoreState {
                 .get_hostnames()
                 .await
                 .unwrap_or_default();
-        } else if payload.name == LAPDEV_CERTS {
-            if let Ok(certs) = load_certs(&self.db).await {
+            self.acme.trigger();
+        } else if payload.name == LAPDEV_ACME {
+            self.acme.trigger();
+        } else if payload.name == LAPDEV_ACME_HTTP_CHALLENGES {
+            self.acme.update_http_challenges(&payload.value);
+        } else if payload.name == LAPDEV_PATH_ROUTING {
+            *self.path_routing.write().await = payload.value == "yes";
+            // the certificate needs to cover the workspace hostname
+            self.acme.trigger();
+        } else if payload.name == LAPDEV_CERTS || payload.name == LAPDEV_ACME_CERTS {
+            if let Ok(certs) = load_certs(&self.db, &self.key_cipher).await {
                 if let Ok(mut current) = self.certs.write() {
                     *current = Arc::new(certs);
                 }
@@ -157,6 +231,71 @@ impl CoreState {
         Ok(())
     }
 
+    /// The metadata of the uploaded and the acme certificates
+    pub async fn cert_infos(&self) -> Result<Vec<CertificateInfo>> {
+        let mut infos = vec![];
+        for (name, acme) in [(LAPDEV_CERTS, false), (LAPDEV_ACME_CERTS, true)] {
+            for (cert, _) in stored_certs(&self.db, name).await? {
+                match cert_info(&cert, acme, self.cert_expiry_warning_days) {
+                    Ok(info) => infos.push(info),
+                    Err(e) => tracing::warn!("parse certificate in {name} error: {e:#}"),
+                }
+            }
+        }
+        Ok(infos)
+    }
+
+    async fn monitor_cert_expiry(self) {
+        let mut interval = tokio::time::interval(CERT_EXPIRY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let certs = match self.cert_infos().await {
+                Ok(certs) => certs,
+                Err(e) => {
+                    tracing::error!("check certificate expiry error: {e:#}");
+                    continue;
+                }
+            };
+            let now = Utc::now();
+            let mut expiring = 0;
+            let mut expired = 0;
+            for cert in certs.iter().filter(|c| c.expiring) {
+                if cert.not_after <= now {
+                    expired += 1;
+                    tracing::error!(
+                        "certificate {} for {:?} expired at {}",
+                        cert.fingerprint,
+                        cert.dns_names,
+                        cert.not_after
+                    );
+                } else {
+                    expiring += 1;
+                    tracing::warn!(
+                        "certificate {} for {:?} expires at {}",
+                        cert.fingerprint,
+                        cert.dns_names,
+                        cert.not_after
+                    );
+                }
+            }
+            tracing::info!(
+                "certificates: total={} expiring={expiring} expired={expired}",
+                certs.len()
+            );
+            metrics::gauge!("lapdev_certificates").set(certs.len() as f64);
+            metrics::gauge!("lapdev_certificates_expiring").set(expiring as f64);
+            metrics::gauge!("lapdev_certificates_expired").set(expired as f64);
+            for cert in &certs {
+                metrics::gauge!(
+                    "lapdev_certificate_expiry_timestamp_seconds",
+                    "fingerprint" => cert.fingerprint.clone(),
+                    "dns_name" => cert.dns_names.first().cloned().unwrap_or_default()
+                )
+                .set(cert.not_after.timestamp() as f64);
+            }
+        }
+    }
+
     pub fn token(&self, cookie: &headers::Cookie) -> Result<TrustedToken, ApiError> {
         let token = cookie.get(TOKEN_COOKIE_NAME).ok_or(ApiError::NoAuthToken)?;
         let untrusted_token =
@@ -189,11 +328,21 @@ impl CoreState {
             .and_then(|c| c.get_claim("user_id"))
             .and_then(|v| serde_json::from_value(v.to_owned()).ok())
             .ok_or(ApiError::Unauthenticated)?;
+        // the sessions issued before the epoch claim was added are in epoch 0
+        let session_epoch: i32 = token
+            .payload_claims()
+            .and_then(|c| c.get_claim(SESSION_EPOCH))
+            .map(|v| serde_json::from_value(v.to_owned()).map_err(|_| ApiError::InvalidAuthToken))
+            .transpose()?
+            .unwrap_or(0);
         let user = entities::user::Entity::find_by_id(user_id)
             .filter(entities::user::Column::DeletedAt.is_null())
             .one(&self.db.conn)
             .await?
             .ok_or(ApiError::Unauthenticated)?;
+        if user.session_epoch != session_epoch {
+            return Err(ApiError::Unauthenticated);
+        }
         Ok(user)
     }
 
@@ -231,12 +380,23 @@ impl CoreState {
     }
 }
 
-async fn load_certs(db: &DbApi) -> Result<HashMap<String, Arc<CertifiedKey>>> {
-    let certs = db.get_config(LAPDEV_CERTS).await?;
-    let certs: Vec<(String, String)> = serde_json::from_str(&certs)?;
+async fn load_certs(db: &DbApi, cipher: &KeyCipher) -> Result<HashMap<String, Arc<CertifiedKey>>> {
+    let mut certs: Vec<(String, String)> = vec![];
+    // the uploaded certificates come last, so they take precedence over the acme ones
+    for name in [LAPDEV_ACME_CERTS, LAPDEV_CERTS] {
+        certs.extend(stored_certs(db, name).await?);
+    }
 
     let mut final_certs = HashMap::new();
     for (cert, key) in certs {
+        let key = match cipher.decrypt(&key) {
+            Ok(key) => key,
+            Err(e) => {
+                tracing::error!("decrypt certificate key error: {e:#}");
+                metrics::counter!("lapdev_certificate_key_decrypt_errors_total").increment(1);
+                continue;
+            }
+        };
         if let Ok((dns_names, cert)) = load_cert(&cert, &key) {
             for dns_name in dns_names {
                 final_certs.insert(dns_name, Arc::new(cert.clone()));
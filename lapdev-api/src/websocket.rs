@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use axum::{
     extract::{
         ws::{Message, WebSocket},
@@ -6,17 +8,29 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use axum_extra::headers;
+use chrono::Utc;
 use futures_util::StreamExt;
 use hyper::StatusCode;
+use lapdev_common::{
+    AuditAction, AuditResourceKind, TerminalClientMessage, WorkspacePort, WorkspacePortVisibility,
+    WorkspaceStatus, WorkspaceUpdateEvent,
+};
 use lapdev_db::entities;
+use lapdev_proxy_ssh::{terminal::WorkspaceTerminal, HostKeyMismatch};
 use lapdev_rpc::error::ApiError;
+use sea_orm::TransactionTrait;
 use uuid::Uuid;
 
-use crate::state::CoreState;
+use crate::{
+    auto_start::{main_workspace, WORKSPACE_UPDATES_PATH},
+    state::CoreState,
+};
 
 pub async fn handle_websocket(
     path: &str,
     query: Option<&str>,
+    origin: Option<&str>,
+    hostname: &str,
     websocket: WebSocketUpgrade,
     cookies: headers::Cookie,
     state: CoreState,
@@ -25,7 +39,7 @@ pub async fn handle_websocket(
         let ws_name = query
             .ok_or(ApiError::InvalidRequest("no workspace name".to_string()))?
             .split('=')
-            .last()
+            .next_back()
             .ok_or_else(|| ApiError::InvalidRequest("no workspace name".to_string()))?;
 
         let user = state.authenticate(&cookies).await?;
@@ -38,31 +52,172 @@ pub async fn handle_websocket(
             return Err(ApiError::Unauthorized);
         }
 
+        return Ok(workspace_updates_response(ws.id, true, websocket, state));
+    } else if path == WORKSPACE_UPDATES_PATH {
+        let ws_name = query
+            .and_then(|q| query_param(q, "name"))
+            .ok_or_else(|| ApiError::InvalidRequest("no workspace name".to_string()))?;
+        let ws = state
+            .db
+            .get_workspace_by_name(ws_name)
+            .await
+            .map_err(|_| ApiError::InvalidRequest("invalid workspace name".to_string()))?;
+        let main_ws = main_workspace(&state, &ws).await?;
+        // the progress page is also shown to the visitors of public ports and share links,
+        // who only get the status, the build output is for the organization
+        let full = match state.authenticate(&cookies).await {
+            Ok(user) => {
+                user.id == main_ws.user_id
+                    || state
+                        .db
+                        .get_organization_member(user.id, main_ws.organization_id)
+                        .await
+                        .is_ok()
+            }
+            Err(_) => false,
+        };
+        return Ok(workspace_updates_response(
+            main_ws.id, full, websocket, state,
+        ));
+    } else if path == "/all_workspaces_ws" {
+        let user = state.authenticate(&cookies).await?;
         let resp = websocket
             .on_upgrade(move |mut socket| async move {
-                handle_workspace_updates(ws.id, &mut socket, &state).await;
-                state.conductor.cleanup_workspace_updates(ws.id).await;
+                handle_all_workspaces_update(&user, &mut socket, &state).await;
+                state.conductor.cleanup_all_workspace_updates(user.id).await;
             })
             .into_response();
         println!("websocket finished");
         return Ok(resp);
-    } else if path == "/all_workspaces_ws" {
+    } else if path == "/terminal" {
+        // the cookies are sent with the upgrade from any site,
+        // so another page mustn't be able to open a shell with them
+        if !is_dashboard_origin(origin, hostname) {
+            return Err(ApiError::Unauthorized);
+        }
+        let query = query.unwrap_or("");
+        let ws_name = query_param(query, "name")
+            .ok_or_else(|| ApiError::InvalidRequest("no workspace name".to_string()))?;
+        let cols = query_param(query, "cols")
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(80);
+        let rows = query_param(query, "rows")
+            .and_then(|r| r.parse().ok())
+            .unwrap_or(24);
+
         let user = state.authenticate(&cookies).await?;
+        let ws = state
+            .db
+            .get_workspace_by_name(ws_name)
+            .await
+            .map_err(|_| ApiError::InvalidRequest("invalid workspace name".to_string()))?;
+        if ws.user_id != user.id {
+            return Err(ApiError::Unauthorized);
+        }
+        if ws.status != WorkspaceStatus::Running.to_string() {
+            return Err(ApiError::InvalidRequest(
+                "workspace isn't running".to_string(),
+            ));
+        }
+        let workspace_host = state
+            .db
+            .get_workspace_host(ws.host_id)
+            .await?
+            .ok_or_else(|| ApiError::InternalError("can't find workspace host".to_string()))?;
+
         let resp = websocket
             .on_upgrade(move |mut socket| async move {
-                handle_all_workspaces_update(&user, &mut socket, &state).await;
-                state.conductor.cleanup_all_workspace_updates(user.id).await;
+                match WorkspaceTerminal::open(&ws, &workspace_host.host, cols, rows).await {
+                    Ok(terminal) => handle_terminal(terminal, &mut socket).await,
+                    Err(e) => {
+                        tracing::error!("open terminal for workspace {} error: {e:#}", ws.name);
+                        if e.is::<HostKeyMismatch>() {
+                            log_host_key_mismatch(&state, &ws, user.id).await;
+                        }
+                        let _ = socket
+                            .send(Message::Binary(
+                                format!("failed to open terminal: {e}\r\n").into_bytes(),
+                            ))
+                            .await;
+                    }
+                }
+                let _ = socket.close().await;
             })
             .into_response();
-        println!("websocket finished");
         return Ok(resp);
     }
 
     Ok(StatusCode::NOT_FOUND.into_response())
 }
 
-async fn handle_workspace_updates(ws_id: Uuid, socket: &mut WebSocket, state: &CoreState) {
+/// Upgrade to a websocket that streams the updates of the workspace,
+/// only the status changes unless `full` is set
+fn workspace_updates_response(
+    ws_id: Uuid,
+    full: bool,
+    websocket: WebSocketUpgrade,
+    state: CoreState,
+) -> Response {
+    websocket
+        .on_upgrade(move |mut socket| async move {
+            handle_workspace_updates(ws_id, full, &mut socket, &state).await;
+            state.conductor.cleanup_workspace_updates(ws_id).await;
+        })
+        .into_response()
+}
+
+async fn log_host_key_mismatch(state: &CoreState, ws: &entities::workspace::Model, user_id: Uuid) {
+    let result: anyhow::Result<()> = async {
+        let txn = state.db.conn.begin().await?;
+        state
+            .conductor
+            .enterprise
+            .insert_audit_log(
+                &txn,
+                Utc::now().into(),
+                user_id,
+                ws.organization_id,
+                AuditResourceKind::Workspace.to_string(),
+                ws.id,
+                ws.name.clone(),
+                AuditAction::WorkspaceSshHostKeyMismatch.to_string(),
+                None,
+                None,
+            )
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::error!("insert host key mismatch audit log error: {e:#}");
+    }
+}
+
+async fn handle_workspace_updates(
+    ws_id: Uuid,
+    full: bool,
+    socket: &mut WebSocket,
+    state: &CoreState,
+) {
     let mut rx = state.conductor.workspace_updates(ws_id).await;
+
+    // port events aren't replayed by the conductor, so we send the current ports first
+    if let (true, Ok(ports)) = (full, state.db.get_workspace_ports(ws_id).await) {
+        let ports = ports
+            .into_iter()
+            .map(|p| WorkspacePort {
+                port: p.port as u16,
+                visibility: WorkspacePortVisibility::from_str(&p.visibility).unwrap_or_default(),
+                auto_detected: p.auto_detected,
+                label: p.label,
+            })
+            .collect();
+        if let Ok(c) = serde_json::to_string(&WorkspaceUpdateEvent::Ports(ports)) {
+            let _ = socket.send(Message::Text(c)).await;
+        }
+    }
+
     loop {
         tokio::select! {
             incoming = socket.recv() => {
@@ -73,6 +228,9 @@ async fn handle_workspace_updates(ws_id: Uuid, socket: &mut WebSocket, state: &C
             }
             msg = rx.next() => {
                 if let Some(msg) = msg {
+                    if !full && !matches!(msg, WorkspaceUpdateEvent::Status(_)) {
+                        continue;
+                    }
                     if let Ok(c) = serde_json::to_string(&msg) {
                         let _ = socket.send(Message::Text(c)).await;
                     }
@@ -85,6 +243,68 @@ async fn handle_workspace_updates(ws_id: Uuid, socket: &mut WebSocket, state: &C
     }
 }
 
+/// Browsers always send the Origin of the page that opens a websocket,
+/// which is the workspace's own hostname for the workspace pages.
+fn is_dashboard_origin(origin: Option<&str>, hostname: &str) -> bool {
+    let Some(origin) = origin else {
+        // not from a browser, so it's not a cross site request
+        return true;
+    };
+    origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        == Some(hostname)
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+async fn handle_terminal(mut terminal: WorkspaceTerminal, socket: &mut WebSocket) {
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                let msg = match incoming {
+                    Some(Ok(msg)) => msg,
+                    _ => break,
+                };
+                let result = match msg {
+                    Message::Text(s) => match serde_json::from_str::<TerminalClientMessage>(&s) {
+                        Ok(TerminalClientMessage::Input { data }) => {
+                            terminal.write(data.as_bytes()).await
+                        }
+                        Ok(TerminalClientMessage::Resize { cols, rows }) => {
+                            terminal.resize(cols, rows).await
+                        }
+                        Err(_) => Ok(()),
+                    },
+                    Message::Binary(data) => terminal.write(&data).await,
+                    Message::Close(_) => break,
+                    _ => Ok(()),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+            output = terminal.read() => {
+                match output {
+                    Some(data) => {
+                        if socket.send(Message::Binary(data)).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+    terminal.close().await;
+}
+
 async fn handle_all_workspaces_update(
     user: &entities::user::Model,
     socket: &mut WebSocket,
@@ -112,3 +332,34 @@ async fn handle_all_workspaces_update(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_dashboard_origin() {
+        assert!(is_dashboard_origin(
+            Some("https://lapdev.example.com"),
+            "lapdev.example.com"
+        ));
+        assert!(is_dashboard_origin(
+            Some("http://localhost:3000"),
+            "localhost:3000"
+        ));
+        assert!(is_dashboard_origin(None, "lapdev.example.com"));
+        assert!(!is_dashboard_origin(
+            Some("https://evil.example.com"),
+            "lapdev.example.com"
+        ));
+        assert!(!is_dashboard_origin(
+            Some("https://lapdev.example.com.evil.com"),
+            "lapdev.example.com"
+        ));
+        assert!(!is_dashboard_origin(
+            Some("https://lapdev.example.com:8443"),
+            "lapdev.example.com"
+        ));
+        assert!(!is_dashboard_origin(Some("null"), "lapdev.example.com"));
+    }
+}
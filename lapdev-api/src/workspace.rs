@@ -2,16 +2,23 @@ use std::{collections::HashMap, str::FromStr};
 
 use anyhow::Result;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::{IntoResponse, Response},
     Json,
 };
 use axum_extra::{headers::Cookie, TypedHeader};
+use chrono::Utc;
 use hyper::StatusCode;
-use lapdev_common::{NewWorkspace, WorkspaceInfo, WorkspaceService, WorkspaceStatus};
+use lapdev_common::{
+    AuditAction, AuditResourceKind, NewWorkspace, NewWorkspaceSshAccess, WorkspaceInfo,
+    WorkspaceListRequest, WorkspaceService, WorkspaceSshAccess, WorkspaceSshAccessGrant,
+    WorkspaceStatus,
+};
 use lapdev_db::entities;
 use lapdev_rpc::error::ApiError;
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter, TransactionTrait,
+};
 use tracing::error;
 use uuid::Uuid;
 
@@ -45,6 +52,7 @@ pub async fn create_workspace(
 pub async fn all_workspaces(
     State(state): State<CoreState>,
     Path(org_id): Path<Uuid>,
+    Query(list_request): Query<WorkspaceListRequest>,
     TypedHeader(cookie): TypedHeader<Cookie>,
 ) -> Result<Json<Vec<WorkspaceInfo>>, ApiError> {
     let user = state.authenticate(&cookie).await?;
@@ -56,6 +64,11 @@ pub async fn all_workspaces(
     let workspaces = state.db.get_all_workspaces(user.id, org_id).await?;
 
     let mut services: HashMap<Uuid, Vec<WorkspaceService>> = HashMap::new();
+    let parent_names: HashMap<Uuid, String> = workspaces
+        .iter()
+        .filter(|(ws, _)| ws.is_compose && ws.compose_parent.is_none())
+        .map(|(ws, _)| (ws.id, ws.name.clone()))
+        .collect();
     for (ws, _) in &workspaces {
         if ws.is_compose {
             if let Some(parent) = ws.compose_parent {
@@ -73,13 +86,17 @@ pub async fn all_workspaces(
     let workspaces: Vec<WorkspaceInfo> = workspaces
         .into_iter()
         .filter_map(|(w, host)| {
-            let services = if w.is_compose {
-                if w.compose_parent.is_some() {
-                    return None;
+            let (services, parent) = if w.is_compose {
+                if let Some(parent) = w.compose_parent {
+                    if !list_request.services {
+                        return None;
+                    }
+                    (Vec::new(), Some(parent_names.get(&parent)?.clone()))
+                } else {
+                    (services.get(&w.id).cloned().unwrap_or_default(), None)
                 }
-                services.get(&w.id).cloned().unwrap_or_default()
             } else {
-                Vec::new()
+                (Vec::new(), None)
             };
             let region = host.map(|host| host.region).unwrap_or_default();
             let hostname = hostnames.get(region.trim()).cloned().unwrap_or_default();
@@ -95,6 +112,8 @@ pub async fn all_workspaces(
                 services,
                 created_at: w.created_at,
                 hostname,
+                service: parent.as_ref().and(w.service),
+                parent,
             })
         })
         .collect();
@@ -164,6 +183,11 @@ pub async fn get_workspace(
         Vec::new()
     };
 
+    let parent = match ws.compose_parent {
+        Some(parent) if ws.is_compose => Some(state.db.get_workspace(parent).await?.name),
+        _ => None,
+    };
+
     let host = state.db.get_workspace_host(ws.host_id).await?;
     let region = host.map(|host| host.region).unwrap_or_default();
     let hostname = state
@@ -186,6 +210,8 @@ pub async fn get_workspace(
         services,
         created_at: ws.created_at,
         hostname,
+        service: parent.as_ref().and(ws.service),
+        parent,
     };
     Ok(Json(info))
 }
@@ -257,3 +283,169 @@ pub async fn stop_workspace(
         .await?;
     Ok(StatusCode::NO_CONTENT.into_response())
 }
+
+pub(crate) async fn get_own_workspace(
+    state: &CoreState,
+    cookie: &Cookie,
+    org_id: Uuid,
+    workspace_name: &str,
+) -> Result<(entities::user::Model, entities::workspace::Model), ApiError> {
+    let user = state.authenticate(cookie).await?;
+    state
+        .db
+        .get_organization_member(user.id, org_id)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    let ws = state
+        .db
+        .get_workspace_by_name(workspace_name)
+        .await
+        .map_err(|_| ApiError::InvalidRequest("workspace name doesn't exist".to_string()))?;
+    if ws.user_id != user.id {
+        return Err(ApiError::Unauthorized);
+    }
+    Ok((user, ws))
+}
+
+pub async fn get_workspace_ssh_access(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name)): Path<(Uuid, String)>,
+    State(state): State<CoreState>,
+) -> Result<Json<Vec<WorkspaceSshAccessGrant>>, ApiError> {
+    let (_, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    let grants = state
+        .db
+        .get_all_workspace_ssh_access(ws.id)
+        .await?
+        .into_iter()
+        .filter_map(|(grant, user)| {
+            Some(WorkspaceSshAccessGrant {
+                user_id: grant.user_id,
+                name: user.as_ref().and_then(|u| u.name.clone()),
+                avatar_url: user.and_then(|u| u.avatar_url),
+                access: WorkspaceSshAccess::from_str(&grant.access).ok()?,
+                created_at: grant.created_at,
+            })
+        })
+        .collect();
+    Ok(Json(grants))
+}
+
+pub async fn grant_workspace_ssh_access(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name)): Path<(Uuid, String)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+    Json(grant): Json<NewWorkspaceSshAccess>,
+) -> Result<Response, ApiError> {
+    let (user, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    if ws.compose_parent.is_some() {
+        return Err(ApiError::InvalidRequest(
+            "You can only grant access on the main workspace, which covers all its services"
+                .to_string(),
+        ));
+    }
+    if grant.user_id == user.id {
+        return Err(ApiError::InvalidRequest(
+            "You already have access to your own workspace".to_string(),
+        ));
+    }
+    state
+        .db
+        .get_organization_member(grant.user_id, ws.organization_id)
+        .await
+        .map_err(|_| {
+            ApiError::InvalidRequest("The user isn't a member of the organization".to_string())
+        })?;
+
+    let now = Utc::now();
+    let txn = state.db.conn.begin().await?;
+    if let Some(existing) = state
+        .db
+        .get_workspace_ssh_access(ws.id, grant.user_id)
+        .await?
+    {
+        entities::workspace_ssh_access::ActiveModel {
+            id: ActiveValue::Set(existing.id),
+            access: ActiveValue::Set(grant.access.to_string()),
+            granted_by: ActiveValue::Set(user.id),
+            ..Default::default()
+        }
+        .update(&txn)
+        .await?;
+    } else {
+        entities::workspace_ssh_access::ActiveModel {
+            id: ActiveValue::Set(Uuid::new_v4()),
+            created_at: ActiveValue::Set(now.into()),
+            deleted_at: ActiveValue::Set(None),
+            workspace_id: ActiveValue::Set(ws.id),
+            user_id: ActiveValue::Set(grant.user_id),
+            granted_by: ActiveValue::Set(user.id),
+            access: ActiveValue::Set(grant.access.to_string()),
+        }
+        .insert(&txn)
+        .await?;
+    }
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            now.into(),
+            user.id,
+            ws.organization_id,
+            AuditResourceKind::Workspace.to_string(),
+            ws.id,
+            ws.name.clone(),
+            AuditAction::WorkspaceSshAccessGrant.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn revoke_workspace_ssh_access(
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Path((org_id, workspace_name, user_id)): Path<(Uuid, String, Uuid)>,
+    State(state): State<CoreState>,
+    info: RequestInfo,
+) -> Result<Response, ApiError> {
+    let (user, ws) = get_own_workspace(&state, &cookie, org_id, &workspace_name).await?;
+    let grant = state
+        .db
+        .get_workspace_ssh_access(ws.id, user_id)
+        .await?
+        .ok_or_else(|| ApiError::InvalidRequest("The user doesn't have access".to_string()))?;
+
+    let now = Utc::now();
+    let txn = state.db.conn.begin().await?;
+    entities::workspace_ssh_access::ActiveModel {
+        id: ActiveValue::Set(grant.id),
+        deleted_at: ActiveValue::Set(Some(now.into())),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+    state
+        .conductor
+        .enterprise
+        .insert_audit_log(
+            &txn,
+            now.into(),
+            user.id,
+            ws.organization_id,
+            AuditResourceKind::Workspace.to_string(),
+            ws.id,
+            ws.name.clone(),
+            AuditAction::WorkspaceSshAccessRevoke.to_string(),
+            info.ip,
+            info.user_agent,
+        )
+        .await?;
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
@@ -19,6 +19,11 @@ pub struct Organization {
     pub auto_stop: Option<i32>,
     pub allow_workspace_change_auto_start: bool,
     pub allow_workspace_change_auto_stop: bool,
+    pub ssh_session_recording: bool,
+    pub allow_ssh_agent_forwarding: bool,
+    /// The ports whose http traffic counts as workspace activity besides the ide's,
+    /// all ports count if it's None
+    pub activity_ports: Option<Vec<u16>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -30,6 +35,11 @@ pub struct MeUser {
     pub cluster_admin: bool,
     pub organization: Organization,
     pub all_organizations: Vec<Organization>,
+    /// If it's a local account, which has its own password and second factor
+    #[serde(default)]
+    pub local: bool,
+    #[serde(default)]
+    pub totp_enabled: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
@@ -1,6 +1,8 @@
 use rand::{distributions::Alphanumeric, Rng};
 use sha2::{Digest, Sha256};
 
+use crate::{PATH_ROUTING_SUBDOMAIN, WORKSPACE_PATH_PREFIX};
+
 pub fn rand_string(n: usize) -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
@@ -14,3 +16,34 @@ pub fn sha256(input: &str) -> String {
     let hash = Sha256::digest(input.as_bytes());
     base16ct::lower::encode_string(&hash)
 }
+
+/// The url of the workspace's ide, or of the port of the workspace if it's given.
+/// The workspace is either in the hostname prefix, or in the path on the
+/// [`PATH_ROUTING_SUBDOMAIN`] of the hostname if path routing is enabled.
+/// `scheme` is the one lapdev is reached with, i.e. `http` or `https`.
+pub fn workspace_url(
+    scheme: &str,
+    hostname: &str,
+    ws_name: &str,
+    port: Option<u16>,
+    path_routing: bool,
+) -> String {
+    match (port, path_routing) {
+        (Some(port), true) => format!(
+            "{scheme}://{PATH_ROUTING_SUBDOMAIN}.{hostname}{WORKSPACE_PATH_PREFIX}{ws_name}/{port}/"
+        ),
+        (None, true) => format!(
+            "{scheme}://{PATH_ROUTING_SUBDOMAIN}.{hostname}{WORKSPACE_PATH_PREFIX}{ws_name}/"
+        ),
+        (Some(port), false) => format!("{scheme}://{port}-{ws_name}.{hostname}/"),
+        (None, false) => format!("{scheme}://{ws_name}.{hostname}/"),
+    }
+}
+
+/// If the hostname, which can have a port, is the one the path routed workspaces are served from
+pub fn is_path_routing_hostname(hostname: &str) -> bool {
+    hostname
+        .split_once('.')
+        .map(|(subdomain, rest)| subdomain == PATH_ROUTING_SUBDOMAIN && !rest.is_empty())
+        .unwrap_or(false)
+}
@@ -0,0 +1,139 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use pasetors::{
+    keys::{Generate, SymmetricKey},
+    token::UntrustedToken,
+    version4::{LocalToken, V4},
+    Local,
+};
+use tokio::io::AsyncWriteExt;
+
+/// Bound to the encrypted private keys, so that the tokens can't be used for anything else
+const KEY_IMPLICIT_ASSERTION: &[u8] = b"lapdev-private-key";
+/// The encrypted values start with it
+pub const ENCRYPTED_PREFIX: &str = "v4.local.";
+
+/// Encrypts the private keys and the tokens before they're stored in the db,
+/// with a key that's kept in a file on the api server instead of the db.
+///
+/// All the api servers must have the same key file, and it must be backed up
+/// together with the db: the stored keys can't be decrypted without it.
+#[derive(Clone)]
+pub struct KeyCipher {
+    key: Arc<SymmetricKey<V4>>,
+}
+
+impl KeyCipher {
+    /// Load the key from the file. If the file doesn't exist yet,
+    /// the key is generated when `generate` is set.
+    pub async fn load(path: &Path, generate: bool) -> Result<Self> {
+        let key = match tokio::fs::read_to_string(path).await {
+            Ok(content) => {
+                let key = STANDARD.decode(content.trim())?;
+                SymmetricKey::from(&key)
+                    .map_err(|e| anyhow!("invalid key in {}: {e}", path.to_string_lossy()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if !generate {
+                    bail!(
+                        "the secret key file {} doesn't exist, but the db has keys encrypted with it, \
+                        copy it from another api server or restore it from the backup",
+                        path.to_string_lossy()
+                    );
+                }
+                let key =
+                    SymmetricKey::<V4>::generate().map_err(|e| anyhow!("generate key: {e}"))?;
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await.with_context(|| {
+                        format!(
+                            "create the directory of the secret key file {}, \
+                            set secret-key-file in the config to a writable path",
+                            path.to_string_lossy()
+                        )
+                    })?;
+                }
+                let mut file = tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(path)
+                    .await
+                    .with_context(|| format!("create {}", path.to_string_lossy()))?;
+                file.write_all(STANDARD.encode(key.as_bytes()).as_bytes())
+                    .await?;
+                tracing::info!("generated secret key in {}", path.to_string_lossy());
+                key
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("read {}", path.to_string_lossy()));
+            }
+        };
+        Ok(Self { key: Arc::new(key) })
+    }
+
+    pub fn encrypt(&self, plain: &str) -> Result<String> {
+        LocalToken::encrypt(
+            &self.key,
+            plain.as_bytes(),
+            None,
+            Some(KEY_IMPLICIT_ASSERTION),
+        )
+        .map_err(|e| anyhow!("encrypt key: {e}"))
+    }
+
+    /// Keys stored before they were encrypted are returned as they are
+    pub fn decrypt(&self, stored: &str) -> Result<String> {
+        if !is_encrypted(stored) {
+            return Ok(stored.to_string());
+        }
+        let token = UntrustedToken::<Local, V4>::try_from(stored)
+            .map_err(|e| anyhow!("invalid encrypted key: {e}"))?;
+        let token = LocalToken::decrypt(&self.key, &token, None, Some(KEY_IMPLICIT_ASSERTION))
+            .map_err(|e| anyhow!("decrypt key: {e}"))?;
+        Ok(token.payload().to_string())
+    }
+}
+
+/// Whether the value is encrypted by [`KeyCipher`], and not stored in plain text from before
+pub fn is_encrypted(stored: &str) -> bool {
+    stored.starts_with(ENCRYPTED_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_key_cipher() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lapdev").join("secret.key");
+
+        assert!(KeyCipher::load(&path, false).await.is_err());
+        assert!(!path.exists());
+
+        let cipher = KeyCipher::load(&path, true).await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let encrypted = cipher.encrypt("private key").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("private key"));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), "private key");
+
+        // the same key is loaded from the file
+        let loaded = KeyCipher::load(&path, false).await.unwrap();
+        assert_eq!(loaded.decrypt(&encrypted).unwrap(), "private key");
+
+        let other = KeyCipher::load(&dir.path().join("other.key"), true)
+            .await
+            .unwrap();
+        assert!(other.decrypt(&encrypted).is_err());
+
+        // the keys stored before the encryption are returned as they are
+        assert_eq!(cipher.decrypt("plain key").unwrap(), "plain key");
+    }
+}
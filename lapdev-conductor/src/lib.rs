@@ -1,3 +1,5 @@
+pub mod cipher;
+pub mod oauth;
 mod rpc;
 pub mod scheduler;
 pub mod server;
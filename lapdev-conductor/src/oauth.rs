@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use lapdev_common::OauthProvider;
+use oauth2::{
+    basic::{BasicClient, BasicTokenResponse},
+    reqwest::async_http_client,
+    AccessToken, AuthUrl, ClientId, ClientSecret, RefreshToken, TokenResponse, TokenUrl,
+};
+
+/// The access tokens are refreshed when they expire within this many seconds,
+/// so that they don't expire in the middle of a git fetch
+const REFRESH_BEFORE_SECS: i64 = 300;
+
+/// The OAuth client of the app on the instance
+pub fn oauth_client(provider: &OauthProvider) -> Result<BasicClient> {
+    if provider.client_id.trim().is_empty() || provider.client_secret.trim().is_empty() {
+        return Err(anyhow!("client id or secret is empty"));
+    }
+    let (auth_url, token_url) = provider
+        .oauth_urls()
+        .ok_or_else(|| anyhow!("{} isn't an OAuth provider", provider.kind))?;
+
+    let client = BasicClient::new(
+        ClientId::new(provider.client_id.clone()),
+        Some(ClientSecret::new(provider.client_secret.clone())),
+        AuthUrl::new(auth_url)?,
+        Some(TokenUrl::new(token_url)?),
+    );
+    Ok(client)
+}
+
+/// The tokens the OAuth provider issued for a user
+pub struct OauthToken {
+    pub access_token: AccessToken,
+    /// Only given with the access tokens that expire, e.g. by bitbucket and gitlab
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl OauthToken {
+    pub fn new(token: &BasicTokenResponse, now: DateTime<Utc>) -> Self {
+        Self {
+            access_token: token.access_token().to_owned(),
+            refresh_token: token.refresh_token().map(|t| t.secret().to_string()),
+            expires_at: token
+                .expires_in()
+                .and_then(|expires_in| Duration::from_std(expires_in).ok())
+                .map(|expires_in| now + expires_in),
+        }
+    }
+}
+
+/// If the access token expires soon, the ones without an expiry don't
+pub fn needs_refresh(expires_at: Option<DateTime<FixedOffset>>, now: DateTime<Utc>) -> bool {
+    expires_at
+        .map(|expires_at| {
+            expires_at.with_timezone(&Utc) - now < Duration::seconds(REFRESH_BEFORE_SECS)
+        })
+        .unwrap_or(false)
+}
+
+/// Get a new access token with the refresh token. The providers that rotate
+/// the refresh tokens return a new one, otherwise the current one is kept.
+pub async fn refresh_token(provider: &OauthProvider, refresh_token: String) -> Result<OauthToken> {
+    let token = oauth_client(provider)?
+        .exchange_refresh_token(&RefreshToken::new(refresh_token.clone()))
+        .request_async(async_http_client)
+        .await?;
+    let mut token = OauthToken::new(&token, Utc::now());
+    if token.refresh_token.is_none() {
+        token.refresh_token = Some(refresh_token);
+    }
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Form, Json, Router};
+    use lapdev_common::AuthProvider;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_needs_refresh() {
+        let now = Utc::now();
+        assert!(!needs_refresh(None, now));
+        assert!(!needs_refresh(Some((now + Duration::hours(1)).into()), now));
+        assert!(needs_refresh(
+            Some((now + Duration::minutes(1)).into()),
+            now
+        ));
+        assert!(needs_refresh(Some((now - Duration::hours(1)).into()), now));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/site/oauth2/access_token",
+            post(
+                |Form(form): Form<std::collections::HashMap<String, String>>| async move {
+                    if form.get("grant_type").map(|t| t.as_str()) != Some("refresh_token") {
+                        return Err(axum::http::StatusCode::BAD_REQUEST);
+                    }
+                    match form.get("refresh_token").map(|t| t.as_str()) {
+                        // bitbucket keeps the refresh token
+                        Some("kept") => Ok(Json(json!({
+                            "access_token": "new-access-token",
+                            "token_type": "bearer",
+                            "expires_in": 7200,
+                        }))),
+                        Some("rotated") => Ok(Json(json!({
+                            "access_token": "new-access-token",
+                            "token_type": "bearer",
+                            "refresh_token": "new-refresh-token",
+                        }))),
+                        _ => Err(axum::http::StatusCode::BAD_REQUEST),
+                    }
+                },
+            ),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let provider = OauthProvider {
+            base_url: format!("http://{addr}"),
+            client_id: "client-id".to_string(),
+            client_secret: "client-secret".to_string(),
+            ..OauthProvider::new(AuthProvider::Bitbucket)
+        };
+
+        let before = Utc::now();
+        let token = refresh_token(&provider, "kept".to_string()).await.unwrap();
+        assert_eq!(token.access_token.secret(), "new-access-token");
+        assert_eq!(token.refresh_token.as_deref(), Some("kept"));
+        let expires_at = token.expires_at.unwrap();
+        assert!(expires_at >= before + Duration::seconds(7200));
+        assert!(expires_at <= Utc::now() + Duration::seconds(7200));
+
+        let token = refresh_token(&provider, "rotated".to_string())
+            .await
+            .unwrap();
+        assert_eq!(token.refresh_token.as_deref(), Some("new-refresh-token"));
+        assert!(token.expires_at.is_none());
+
+        assert!(refresh_token(&provider, "revoked".to_string())
+            .await
+            .is_err());
+
+        let provider = OauthProvider {
+            client_secret: String::new(),
+            ..provider
+        };
+        assert!(refresh_token(&provider, "kept".to_string()).await.is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use anyhow::Result;
-use lapdev_common::{BuildTarget, PrebuildUpdateEvent, RunningWorkspace, WorkspaceUpdateEvent};
+use lapdev_common::{
+    BuildTarget, GitCredential, GitCredentialRequest, PrebuildUpdateEvent, RunningWorkspace,
+    WorkspaceUpdateEvent,
+};
 use lapdev_db::entities;
 use lapdev_rpc::{error::ApiError, ConductorService, WorkspaceServiceClient};
 use sea_orm::{ActiveModelTrait, ActiveValue};
@@ -12,6 +15,7 @@ use crate::Conductor;
 pub struct ConductorRpc {
     pub ws_host_id: Uuid,
     pub conductor: Conductor,
+    #[allow(dead_code)]
     pub ws_client: WorkspaceServiceClient,
 }
 
@@ -84,6 +88,8 @@ impl ConductorService for ConductorRpc {
             .into_iter()
             .map(|ws| RunningWorkspace {
                 id: ws.id,
+                name: ws.name,
+                osuser: ws.osuser,
                 ssh_port: ws.ssh_port,
                 ide_port: ws.ide_port,
                 last_inactivity: ws.last_inactivity,
@@ -91,4 +97,26 @@ impl ConductorService for ConductorRpc {
             .collect();
         Ok(workspaces)
     }
+
+    async fn update_workspace_ports(
+        self,
+        _context: tarpc::context::Context,
+        workspace_id: Uuid,
+        ports: Vec<(u16, u16)>,
+    ) -> Result<(), ApiError> {
+        self.conductor
+            .update_workspace_ports(workspace_id, ports)
+            .await
+    }
+
+    async fn git_credential(
+        self,
+        _context: tarpc::context::Context,
+        workspace_id: Uuid,
+        req: GitCredentialRequest,
+    ) -> Result<GitCredential, ApiError> {
+        self.conductor
+            .workspace_git_credential(self.ws_host_id, workspace_id, req)
+            .await
+    }
 }
@@ -219,7 +219,7 @@ async fn get_existing_resource(
     for (workspace, machine_type) in models {
         if let Some(machine_type) = machine_type {
             let cores: Vec<usize> = serde_json::from_str(&workspace.cores)?;
-            let cores: HashSet<usize> = HashSet::from_iter(cores.into_iter());
+            let cores: HashSet<usize> = HashSet::from_iter(cores);
             existing.cores.push((cores, machine_type.shared));
             existing.memory += machine_type.memory.max(0) as usize;
             existing.disk += machine_type.disk.max(0) as usize;
@@ -237,7 +237,7 @@ async fn get_existing_resource(
     for (prebuild, machine_type) in result {
         if let Some(machine_type) = machine_type {
             let cores: Vec<usize> = serde_json::from_str(&prebuild.cores)?;
-            let cores: HashSet<usize> = HashSet::from_iter(cores.into_iter());
+            let cores: HashSet<usize> = HashSet::from_iter(cores);
             existing.cores.push((cores, machine_type.shared));
             existing.memory += machine_type.memory.max(0) as usize;
             existing.disk += machine_type.disk.max(0) as usize;
@@ -409,6 +409,7 @@ mod tests {
             osuser: ActiveValue::Set("".to_string()),
             ssh_private_key: ActiveValue::Set("".to_string()),
             ssh_public_key: ActiveValue::Set("".to_string()),
+            ssh_host_public_key: ActiveValue::Set(None),
             cores: ActiveValue::Set(serde_json::to_string(&[1, 2]).unwrap()),
             ssh_port: ActiveValue::Set(None),
             ide_port: ActiveValue::Set(None),
@@ -1,4 +1,5 @@
 use std::{
+    cell::RefCell,
     collections::HashMap,
     path::{Path, PathBuf},
     str::FromStr,
@@ -8,18 +9,22 @@ use std::{
 
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
-use data_encoding::BASE64_MIME;
+use data_encoding::{BASE64_MIME, BASE64_NOPAD};
 use futures::{channel::mpsc::UnboundedReceiver, stream::AbortHandle, SinkExt, StreamExt};
-use git2::{Cred, FetchOptions, FetchPrune, RemoteCallbacks, Repository};
+use git2::{
+    CertificateCheckStatus, Cred, CredentialType, FetchOptions, FetchPrune, RemoteCallbacks,
+    Repository,
+};
+use lapdev_common::{url_host, PrebuildReplicaStatus, WorkspaceHostStatus};
 use lapdev_common::{
     utils::rand_string, AuditAction, AuditResourceKind, BuildTarget, CreateWorkspaceRequest,
-    DeleteWorkspaceRequest, GitBranch, NewProject, NewProjectResponse, NewWorkspace,
-    NewWorkspaceResponse, PrebuildInfo, PrebuildStatus, PrebuildUpdateEvent, RepoBuildInfo,
-    RepoBuildOutput, RepoContent, RepoContentPosition, RepoSource, StartWorkspaceRequest,
-    StopWorkspaceRequest, UsageResourceKind, WorkspaceStatus, WorkspaceUpdateEvent,
-    LAPDEV_DEFAULT_OSUSER,
+    DeleteWorkspaceRequest, GitBranch, GitCredential, GitCredentialRequest, NewProject,
+    NewProjectResponse, NewWorkspace, NewWorkspaceResponse, OauthProvider, PrebuildInfo,
+    PrebuildStatus, PrebuildUpdateEvent, RepoBuildInfo, RepoBuildOutput, RepoContent,
+    RepoContentPosition, RepoSource, StartWorkspaceRequest, StopWorkspaceRequest,
+    UsageResourceKind, WorkspacePort, WorkspacePortVisibility, WorkspaceStatus,
+    WorkspaceUpdateEvent, LAPDEV_DEFAULT_OSUSER,
 };
-use lapdev_common::{PrebuildReplicaStatus, WorkspaceHostStatus};
 use lapdev_db::{api::DbApi, entities};
 use lapdev_enterprise::enterprise::Enterprise;
 use lapdev_rpc::{
@@ -47,6 +52,8 @@ use tokio::{
 use uuid::Uuid;
 
 use crate::{
+    cipher::{KeyCipher, ENCRYPTED_PREFIX},
+    oauth::{self, needs_refresh},
     rpc::ConductorRpc,
     scheduler::{self, LAPDEV_CPU_OVERCOMMIT},
 };
@@ -90,6 +97,25 @@ struct WorkspaceHostInfo {
     latency: Option<u128>,
 }
 
+/// The credentials to fetch a repository with
+#[derive(Debug, Clone, Default)]
+pub enum RepoAuth {
+    #[default]
+    None,
+    /// The username and token for https remotes
+    Token { username: String, token: String },
+    /// The deploy key of the project for ssh remotes, with the fingerprint
+    /// of the host key that was seen the first time the key was used
+    DeployKey {
+        id: Uuid,
+        private_key: String,
+        host_key: Option<String>,
+    },
+}
+
+/// The branches before and after a pull, and the ssh host key seen while pulling
+type PulledBranches = (Vec<GitBranch>, Vec<GitBranch>, Option<String>);
+
 #[derive(Debug, Clone)]
 pub struct RepoDetails {
     pub url: String,
@@ -97,7 +123,7 @@ pub struct RepoDetails {
     pub branch: String,
     pub commit: String,
     pub project: Option<entities::project::Model>,
-    pub auth: (String, String),
+    pub auth: RepoAuth,
     // head branch name
     pub head: String,
     // all branches and their commit id
@@ -126,10 +152,15 @@ pub struct Conductor {
     >,
     pub enterprise: Arc<Enterprise>,
     pub db: DbApi,
+    /// Encrypts the secrets stored in the db
+    pub key_cipher: KeyCipher,
+    /// Held while refreshing an oauth access token,
+    /// since the rotated refresh tokens can only be used once
+    token_refresh_lock: Arc<Mutex<()>>,
 }
 
 impl Conductor {
-    pub async fn new(version: &str, db: DbApi) -> Result<Self> {
+    pub async fn new(version: &str, db: DbApi, key_cipher: KeyCipher) -> Result<Self> {
         tokio::fs::create_dir_all("/var/lib/lapdev/projects/")
             .await
             .with_context(|| "trying to create /var/lib/lapdev/projects/")?;
@@ -157,6 +188,8 @@ impl Conductor {
             all_workspace_updates: Default::default(),
             enterprise,
             db,
+            key_cipher,
+            token_refresh_lock: Default::default(),
         };
 
         {
@@ -524,9 +557,11 @@ impl Conductor {
         }
     }
 
-    fn format_repo_url(&self, repo: &str) -> String {
+    pub fn format_repo_url(&self, repo: &str) -> String {
         let repo = repo.trim();
-        let repo = if !repo.starts_with("http://")
+        let repo = if let Some(url) = scp_like_to_ssh_url(repo) {
+            url
+        } else if !repo.starts_with("http://")
             && !repo.starts_with("https://")
             && !repo.starts_with("ssh://")
         {
@@ -539,13 +574,203 @@ impl Conductor {
             .unwrap_or(repo)
     }
 
+    /// The credentials to fetch the repository with, the token of the user
+    /// is only sent to the instance they signed in with
+    pub async fn repo_auth(&self, user: &entities::user::Model, repo_url: &str) -> RepoAuth {
+        match self.db.get_oauth_provider(&user.provider).await {
+            Ok(Some(provider)) if provider.hosts_repo(repo_url) => {
+                return RepoAuth::Token {
+                    username: provider.git_username(&user.provider_login),
+                    token: self.user_access_token(user, &provider).await,
+                }
+            }
+            _ => {}
+        }
+        // the git hosts the user didn't sign in with use the tokens the user added themselves
+        if let Some(host) = url_host(repo_url) {
+            if let Ok(Some(token)) = self.db.get_user_git_token(user.id, host).await {
+                match self.key_cipher.decrypt(&token.token) {
+                    Ok(secret) => {
+                        return RepoAuth::Token {
+                            username: token.username,
+                            token: secret,
+                        }
+                    }
+                    Err(e) => tracing::error!("decrypt git token {} error: {e:#}", token.id),
+                }
+            }
+        }
+        RepoAuth::None
+    }
+
+    /// The access token of the user for the instance they signed in with,
+    /// which is refreshed first if it's about to expire
+    pub async fn user_access_token(
+        &self,
+        user: &entities::user::Model,
+        provider: &OauthProvider,
+    ) -> String {
+        if !needs_refresh(user.access_token_expires_at, Utc::now()) {
+            return user.access_token.clone();
+        }
+        match self.refresh_access_token(user.id, provider).await {
+            Ok(token) => token,
+            Err(e) => {
+                tracing::warn!("refresh access token of user {} error: {e:#}", user.id);
+                user.access_token.clone()
+            }
+        }
+    }
+
+    async fn refresh_access_token(
+        &self,
+        user_id: Uuid,
+        provider: &OauthProvider,
+    ) -> Result<String> {
+        let _lock = self.token_refresh_lock.lock().await;
+        // another call could have refreshed it while we were waiting for the lock
+        let user = self
+            .db
+            .get_user(user_id)
+            .await?
+            .ok_or_else(|| anyhow!("user doesn't exist"))?;
+        if !needs_refresh(user.access_token_expires_at, Utc::now()) {
+            return Ok(user.access_token);
+        }
+        let current = user
+            .oauth_refresh_token
+            .ok_or_else(|| anyhow!("the access token expired and there's no refresh token"))?;
+        let token = oauth::refresh_token(provider, current).await?;
+        let access_token = token.access_token.secret().to_string();
+        self.db
+            .update_user_oauth_token(
+                user.id,
+                access_token.clone(),
+                token.refresh_token,
+                token.expires_at.map(|t| t.into()),
+            )
+            .await?;
+        Ok(access_token)
+    }
+
+    /// The credentials to fetch the repository of the project with, which is its deploy key
+    /// for ssh remotes, otherwise the credentials of the user who created the project
+    pub async fn project_auth(
+        &self,
+        user: &entities::user::Model,
+        project: &entities::project::Model,
+    ) -> RepoAuth {
+        if project.repo_url.starts_with("ssh://") {
+            if let Ok(Some(key)) = self.db.get_project_deploy_key(project.id).await {
+                match self.key_cipher.decrypt(&key.private_key) {
+                    Ok(private_key) => {
+                        return RepoAuth::DeployKey {
+                            id: key.id,
+                            private_key,
+                            host_key: key.host_key,
+                        }
+                    }
+                    Err(e) => tracing::error!("decrypt deploy key {} error: {e:#}", key.id),
+                }
+            }
+        }
+        if let Ok(Some(creator)) = self.db.get_user(project.created_by).await {
+            self.repo_auth(&creator, &project.repo_url).await
+        } else {
+            self.repo_auth(user, &project.repo_url).await
+        }
+    }
+
+    /// Generates a deploy key, which is either for an existing project and replaces
+    /// its current key, or for a project that's going to be created with it
+    pub async fn create_deploy_key(
+        &self,
+        org_id: Uuid,
+        user_id: Uuid,
+        project_id: Option<Uuid>,
+    ) -> Result<entities::project_deploy_key::Model, ApiError> {
+        let (private_key, public_key) = self.generate_key_pair()?;
+        let now = Utc::now();
+        let txn = self.db.conn.begin().await?;
+        if let Some(project_id) = project_id {
+            if let Some(key) = self.db.get_project_deploy_key(project_id).await? {
+                entities::project_deploy_key::ActiveModel {
+                    id: ActiveValue::Set(key.id),
+                    deleted_at: ActiveValue::Set(Some(now.into())),
+                    ..Default::default()
+                }
+                .update(&txn)
+                .await?;
+            }
+        }
+        let key = entities::project_deploy_key::ActiveModel {
+            id: ActiveValue::Set(Uuid::new_v4()),
+            created_at: ActiveValue::Set(now.into()),
+            deleted_at: ActiveValue::Set(None),
+            organization_id: ActiveValue::Set(org_id),
+            created_by: ActiveValue::Set(user_id),
+            project_id: ActiveValue::Set(project_id),
+            private_key: ActiveValue::Set(self.key_cipher.encrypt(&private_key)?),
+            public_key: ActiveValue::Set(public_key),
+            host_key: ActiveValue::Set(None),
+        }
+        .insert(&txn)
+        .await?;
+        txn.commit().await?;
+        Ok(key)
+    }
+
+    /// Encrypt the deploy keys that were stored in plain text before the keys were encrypted
+    pub async fn encrypt_stored_deploy_keys(&self) -> Result<()> {
+        let keys = entities::project_deploy_key::Entity::find()
+            .filter(
+                entities::project_deploy_key::Column::PrivateKey
+                    .not_like(format!("{ENCRYPTED_PREFIX}%")),
+            )
+            .all(&self.db.conn)
+            .await?;
+        for key in &keys {
+            entities::project_deploy_key::ActiveModel {
+                id: ActiveValue::Set(key.id),
+                private_key: ActiveValue::Set(self.key_cipher.encrypt(&key.private_key)?),
+                ..Default::default()
+            }
+            .update(&self.db.conn)
+            .await?;
+        }
+        if !keys.is_empty() {
+            tracing::info!("encrypted {} stored deploy keys", keys.len());
+        }
+        Ok(())
+    }
+
+    /// Stores the host key that was trusted the first time the deploy key was used
+    async fn pin_host_key(&self, auth: &RepoAuth, seen_host_key: Option<String>) -> Result<()> {
+        if let (
+            RepoAuth::DeployKey {
+                id, host_key: None, ..
+            },
+            Some(seen_host_key),
+        ) = (auth, seen_host_key)
+        {
+            entities::project_deploy_key::ActiveModel {
+                id: ActiveValue::Set(*id),
+                host_key: ActiveValue::Set(Some(seen_host_key)),
+                ..Default::default()
+            }
+            .update(&self.db.conn)
+            .await?;
+        }
+        Ok(())
+    }
+
     async fn get_raw_repo_details(
         &self,
         repo_url: &str,
         branch: Option<&str>,
-        auth: (String, String),
+        mut auth: RepoAuth,
     ) -> Result<RepoDetails, ApiError> {
-        let (head, branches) = {
+        let (head, branches, seen_host_key) = {
             let local_repo_url = repo_url.to_string();
             let repo_url = repo_url.to_string();
             let auth = auth.clone();
@@ -553,8 +778,8 @@ impl Conductor {
                 let repo = git2::Repository::init("/var/lib/lapdev")?;
                 let mut remote = repo.remote_anonymous(&repo_url)?;
 
-                let mut cbs = RemoteCallbacks::new();
-                cbs.credentials(move |_, _, _| Cred::userpass_plaintext(&auth.0, &auth.1));
+                let seen_host_key = RefCell::new(None);
+                let cbs = repo_callbacks(&auth, &seen_host_key);
                 let connection = remote.connect_auth(git2::Direction::Fetch, Some(cbs), None)?;
 
                 let mut head = None;
@@ -574,7 +799,8 @@ impl Conductor {
                         .find(|(_, c)| c == &head)
                         .map(|(b, _)| b.to_string())
                 });
-                Ok::<(Option<String>, Vec<(String, String)>), ApiError>((head, branches))
+                drop(connection);
+                Ok::<_, ApiError>((head, branches, seen_host_key.into_inner()))
             })
             .await?
             .map_err(|e| {
@@ -592,6 +818,11 @@ impl Conductor {
         let head = head.ok_or_else(|| {
             ApiError::RepositoryInvalid("repo doesn't have default branch".to_string())
         })?;
+        if let RepoAuth::DeployKey { host_key, .. } = &mut auth {
+            if host_key.is_none() {
+                *host_key = seen_host_key;
+            }
+        }
 
         let branch = branch
             .map(|b| b.to_string())
@@ -608,7 +839,7 @@ impl Conductor {
 
         let path = repo_url
             .split('/')
-            .last()
+            .next_back()
             .ok_or_else(|| ApiError::RepositoryInvalid("invalid repo path".to_string()))?;
         let repo_name = path.strip_suffix(".git").unwrap_or(path).to_string();
 
@@ -633,13 +864,32 @@ impl Conductor {
         user_agent: Option<String>,
     ) -> Result<NewProjectResponse, ApiError> {
         let repo = self.format_repo_url(&project.repo);
-        let repo = self
-            .get_raw_repo_details(
-                &repo,
-                None,
-                (user.provider_login.clone(), user.access_token.clone()),
-            )
-            .await?;
+        let deploy_key = if let Some(id) = project.deploy_key {
+            let key =
+                self.db.get_deploy_key(id).await.map_err(|_| {
+                    ApiError::InvalidRequest("deploy key doesn't exist".to_string())
+                })?;
+            // the repository access isn't checked for the deploy keys,
+            // so only the creator of the key can bind it to a project
+            if key.organization_id != org_id
+                || key.project_id.is_some()
+                || key.created_by != user.id
+            {
+                return Err(ApiError::Unauthorized);
+            }
+            Some(key)
+        } else {
+            None
+        };
+        let auth = match &deploy_key {
+            Some(key) if repo.starts_with("ssh://") => RepoAuth::DeployKey {
+                id: key.id,
+                private_key: self.key_cipher.decrypt(&key.private_key)?,
+                host_key: None,
+            },
+            _ => self.repo_auth(&user, &repo).await,
+        };
+        let repo = self.get_raw_repo_details(&repo, None, auth).await?;
 
         let txn = self.db.conn.begin().await?;
         if let Some(quota) = self
@@ -665,6 +915,21 @@ impl Conductor {
         };
         let project = project.insert(&txn).await?;
 
+        if let Some(key) = deploy_key {
+            let host_key = match &repo.auth {
+                RepoAuth::DeployKey { host_key, .. } => host_key.clone(),
+                _ => None,
+            };
+            entities::project_deploy_key::ActiveModel {
+                id: ActiveValue::Set(key.id),
+                project_id: ActiveValue::Set(Some(project.id)),
+                host_key: ActiveValue::Set(host_key),
+                ..Default::default()
+            }
+            .update(&txn)
+            .await?;
+        }
+
         self.enterprise
             .insert_audit_log(
                 &txn,
@@ -696,13 +961,14 @@ impl Conductor {
         &self,
         user_id: Uuid,
         project: &entities::project::Model,
-        auth: (String, String),
+        auth: RepoAuth,
         ip: Option<String>,
         user_agent: Option<String>,
     ) -> Result<Vec<GitBranch>, ApiError> {
         let project_id = project.id;
-        let (previous_branches, branches) = tokio::task::spawn_blocking(
-            move || -> Result<(Vec<GitBranch>, Vec<GitBranch>), ApiError> {
+        let pull_auth = auth.clone();
+        let (previous_branches, branches, seen_host_key) =
+            tokio::task::spawn_blocking(move || -> Result<PulledBranches, ApiError> {
                 let path = PathBuf::from(format!("/var/lib/lapdev/projects/{project_id}"));
                 let repo = git2::Repository::open(path)?;
 
@@ -711,22 +977,28 @@ impl Conductor {
                 let head = repo.head()?;
                 let head_branch = head.shorthand().ok_or_else(|| anyhow!("no head branch"))?;
 
-                if let Err(e) = repo_pull(&repo, auth) {
-                    let err = if let ApiError::InternalError(e) = e {
-                        e.to_string()
-                    } else {
-                        e.to_string()
-                    };
-                    tracing::debug!("repo pull error: {err}");
-                }
+                let seen_host_key = match repo_pull(&repo, pull_auth) {
+                    Ok(seen_host_key) => seen_host_key,
+                    Err(e) => {
+                        let err = if let ApiError::InternalError(e) = e {
+                            e.to_string()
+                        } else {
+                            e.to_string()
+                        };
+                        tracing::debug!("repo pull error: {err}");
+                        None
+                    }
+                };
 
                 let mut branches = repo_branches(&repo)?;
                 branches.sort_by_key(|b| (b.name.as_str() == head_branch, b.time));
                 branches.reverse();
-                Ok((previous_branches, branches))
-            },
-        )
-        .await??;
+                Ok((previous_branches, branches, seen_host_key))
+            })
+            .await??;
+        if let Err(e) = self.pin_host_key(&auth, seen_host_key).await {
+            tracing::error!("can't store the ssh host key of project {project_id}: {e:#}");
+        }
 
         // find deleted branches
         {
@@ -1149,6 +1421,7 @@ impl Conductor {
             osuser: ActiveValue::Set(osuser),
             ssh_private_key: ActiveValue::Set(id_rsa),
             ssh_public_key: ActiveValue::Set(public_key),
+            ssh_host_public_key: ActiveValue::Set(None),
             cores: ActiveValue::Set(serde_json::to_string(&cores)?),
             ssh_port: ActiveValue::Set(None),
             ide_port: ActiveValue::Set(None),
@@ -1496,11 +1769,7 @@ impl Conductor {
         ip: Option<String>,
         user_agent: Option<String>,
     ) -> Result<RepoDetails, ApiError> {
-        let auth = if let Ok(Some(user)) = self.db.get_user(project.created_by).await {
-            (user.provider_login, user.access_token)
-        } else {
-            (user.provider_login.clone(), user.access_token.clone())
-        };
+        let auth = self.project_auth(user, project).await;
         let branches = self
             .project_branches(user.id, project, auth.clone(), ip, user_agent)
             .await?;
@@ -1551,13 +1820,8 @@ impl Conductor {
                 if let Some(project) = project {
                     project
                 } else {
-                    return self
-                        .get_raw_repo_details(
-                            &repo,
-                            branch,
-                            (user.provider_login.clone(), user.access_token.clone()),
-                        )
-                        .await;
+                    let auth = self.repo_auth(user, &repo).await;
+                    return self.get_raw_repo_details(&repo, branch, auth).await;
                 }
             }
             RepoSource::Project(project_id) => {
@@ -1838,11 +2102,14 @@ impl Conductor {
             } else {
                 ws.name.clone()
             };
+            let service_ws_id = if i == 0 { ws.id } else { Uuid::new_v4() };
             let (id_rsa, ssh_public_key) = if i == 0 {
                 (ws.ssh_private_key.clone(), ws.ssh_public_key.clone())
             } else {
                 self.generate_key_pair()?
             };
+            // the proxy checks the workspace sshd against this host key when connecting
+            let (ssh_host_key, ssh_host_public_key) = self.generate_key_pair()?;
             let mut env = env.clone();
             env.extend_from_slice(&image_env);
             ws_client
@@ -1858,6 +2125,7 @@ impl Conductor {
                         osuser: ws.osuser.clone(),
                         image: tag,
                         ssh_public_key: ssh_public_key.clone(),
+                        ssh_host_key,
                         repo_name: ws.repo_name.clone(),
                         env: env.clone(),
                         cpus: cores.clone(),
@@ -1870,6 +2138,7 @@ impl Conductor {
                 .start_workspace(
                     context::current(),
                     StartWorkspaceRequest {
+                        id: service_ws_id,
                         osuser: ws.osuser.clone(),
                         workspace_name: workspace_name.clone(),
                     },
@@ -1892,7 +2161,7 @@ impl Conductor {
             for (port, port_bindings) in &info.host_config.port_bindings {
                 if let Some(port) = port.strip_suffix("/tcp") {
                     if let Ok(port) = port.parse::<u16>() {
-                        if port != 22 && port != 30000 {
+                        if port != 22 && port != 30000 && port != 30001 {
                             for binding in port_bindings {
                                 if let Ok(host_port) = binding.host_port.parse::<u16>() {
                                     exposed_ports.push((port, host_port));
@@ -1923,6 +2192,7 @@ impl Conductor {
                     id: ActiveValue::Set(ws.id),
                     ssh_port: ActiveValue::Set(ssh_port.map(|port| port as i32)),
                     ide_port: ActiveValue::Set(ide_port.map(|port| port as i32)),
+                    ssh_host_public_key: ActiveValue::Set(Some(ssh_host_public_key)),
                     service: ActiveValue::Set(service),
                     status: ActiveValue::Set(WorkspaceStatus::Running.to_string()),
                     prebuild_id: ActiveValue::Set(prebuild_id),
@@ -1937,7 +2207,7 @@ impl Conductor {
                 txn.commit().await?;
             } else {
                 entities::workspace::ActiveModel {
-                    id: ActiveValue::Set(Uuid::new_v4()),
+                    id: ActiveValue::Set(service_ws_id),
                     name: ActiveValue::Set(workspace_name),
                     created_at: ActiveValue::Set(Utc::now().into()),
                     deleted_at: ActiveValue::Set(None),
@@ -1954,6 +2224,7 @@ impl Conductor {
                     osuser: ActiveValue::Set(ws.osuser.clone()),
                     ssh_private_key: ActiveValue::Set(id_rsa),
                     ssh_public_key: ActiveValue::Set(ssh_public_key),
+                    ssh_host_public_key: ActiveValue::Set(Some(ssh_host_public_key)),
                     ssh_port: ActiveValue::Set(ssh_port.map(|port| port as i32)),
                     ide_port: ActiveValue::Set(ide_port.map(|port| port as i32)),
                     service: ActiveValue::Set(service),
@@ -1979,7 +2250,9 @@ impl Conductor {
                         workspace_id: ActiveValue::Set(ws.id),
                         port: ActiveValue::Set(port as i32),
                         host_port: ActiveValue::Set(host_port as i32),
-                        shared: ActiveValue::Set(false),
+                        visibility: ActiveValue::Set(WorkspacePortVisibility::Private.to_string()),
+                        label: ActiveValue::Set(None),
+                        auto_detected: ActiveValue::Set(false),
                     }
                     .insert(&self.db.conn)
                     .await?;
@@ -2114,7 +2387,10 @@ impl Conductor {
             let subscribers = {
                 let mut ws_updates = self.ws_updates.lock().await;
                 let update = ws_updates.entry(workspace_id).or_default();
-                if !matches!(event, WorkspaceUpdateEvent::Status(_)) {
+                if !matches!(
+                    event,
+                    WorkspaceUpdateEvent::Status(_) | WorkspaceUpdateEvent::Ports(_)
+                ) {
                     update.seen.push(event.clone());
                 }
                 update.subscribers.clone()
@@ -2144,6 +2420,214 @@ impl Conductor {
         }
     }
 
+    pub async fn expose_workspace_port(
+        &self,
+        ws: &entities::workspace::Model,
+        port: u16,
+        user_id: Uuid,
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<(), ApiError> {
+        if ws.status != WorkspaceStatus::Running.to_string() {
+            return Err(ApiError::InvalidRequest(
+                "You can only expose ports of a running workspace".to_string(),
+            ));
+        }
+        if self.db.get_workspace_port(ws.id, port).await?.is_some() {
+            return Err(ApiError::InvalidRequest(format!(
+                "Port {port} is already exposed"
+            )));
+        }
+
+        let ws_client = { self.rpcs.lock().await.get(&ws.host_id).cloned() };
+        let ws_client =
+            ws_client.ok_or_else(|| anyhow!("can't connect to the workspace servic client"))?;
+        ws_client
+            .expose_workspace_port(context::current(), ws.id, port)
+            .await??;
+
+        let txn = self.db.conn.begin().await?;
+        self.enterprise
+            .insert_audit_log_with_details(
+                &txn,
+                Utc::now().into(),
+                user_id,
+                ws.organization_id,
+                AuditResourceKind::Workspace.to_string(),
+                ws.id,
+                ws.name.clone(),
+                AuditAction::WorkspacePortExpose.to_string(),
+                ip,
+                user_agent,
+                Some(format!("port {port}")),
+            )
+            .await?;
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn update_workspace_ports(
+        &self,
+        workspace_id: Uuid,
+        ports: Vec<(u16, u16)>,
+    ) -> Result<(), ApiError> {
+        let ws = self.db.get_workspace(workspace_id).await?;
+        let existing = self.db.get_workspace_ports(ws.id).await?;
+
+        let mut changed = false;
+        for port in &existing {
+            if !port.auto_detected {
+                continue;
+            }
+            let still_open = ports.iter().any(|(p, host_port)| {
+                port.port == *p as i32 && port.host_port == *host_port as i32
+            });
+            if !still_open {
+                entities::workspace_port::Entity::delete_by_id(port.id)
+                    .exec(&self.db.conn)
+                    .await?;
+                changed = true;
+            }
+        }
+
+        for (port, host_port) in ports {
+            if existing.iter().any(|p| {
+                p.port == port as i32 && (!p.auto_detected || p.host_port == host_port as i32)
+            }) {
+                // the port is already published by podman, or we've seen it before
+                continue;
+            }
+            entities::workspace_port::ActiveModel {
+                id: ActiveValue::Set(Uuid::new_v4()),
+                workspace_id: ActiveValue::Set(ws.id),
+                port: ActiveValue::Set(port as i32),
+                host_port: ActiveValue::Set(host_port as i32),
+                visibility: ActiveValue::Set(WorkspacePortVisibility::Private.to_string()),
+                label: ActiveValue::Set(None),
+                auto_detected: ActiveValue::Set(true),
+            }
+            .insert(&self.db.conn)
+            .await?;
+            changed = true;
+        }
+
+        if changed {
+            let ports = self
+                .db
+                .get_workspace_ports(ws.id)
+                .await?
+                .into_iter()
+                .map(|p| WorkspacePort {
+                    port: p.port as u16,
+                    visibility: WorkspacePortVisibility::from_str(&p.visibility)
+                        .unwrap_or_default(),
+                    auto_detected: p.auto_detected,
+                    label: p.label,
+                })
+                .collect();
+            self.add_workspace_update_event(
+                Some(ws.user_id),
+                ws.id,
+                WorkspaceUpdateEvent::Ports(ports),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    /// The credential for the git credential helper inside the workspace, which is the token
+    /// of the workspace owner, and is only given for the repository of the workspace.
+    /// The token the project admins scoped to the repository is used if the owner doesn't have one.
+    pub async fn workspace_git_credential(
+        &self,
+        ws_host_id: Uuid,
+        workspace_id: Uuid,
+        req: GitCredentialRequest,
+    ) -> Result<GitCredential, ApiError> {
+        let ws = self.db.get_workspace(workspace_id).await?;
+        if ws.host_id != ws_host_id {
+            return Err(ApiError::Unauthorized);
+        }
+        if ws.status != WorkspaceStatus::Running.to_string() {
+            return Err(ApiError::InvalidRequest(
+                "The workspace isn't running".to_string(),
+            ));
+        }
+        if !git_credential_matches_repo(&ws.repo_url, &req) {
+            return Err(ApiError::InvalidRequest(format!(
+                "The workspace only has credentials for {}",
+                ws.repo_url
+            )));
+        }
+        let user = self
+            .db
+            .get_user(ws.user_id)
+            .await?
+            .ok_or_else(|| anyhow!("can't find workspace owner"))?;
+        let (username, token) = match self.repo_auth(&user, &ws.repo_url).await {
+            RepoAuth::Token { username, token } => (username, token),
+            _ => self
+                .project_git_credential(ws.project_id)
+                .await?
+                .ok_or_else(|| {
+                    ApiError::InvalidRequest(format!(
+                        "The workspace owner doesn't have a token for {}",
+                        req.host
+                    ))
+                })?,
+        };
+
+        let txn = self.db.conn.begin().await?;
+        self.enterprise
+            .insert_audit_log_with_details(
+                &txn,
+                Utc::now().into(),
+                ws.user_id,
+                ws.organization_id,
+                AuditResourceKind::Workspace.to_string(),
+                ws.id,
+                ws.name.clone(),
+                AuditAction::WorkspaceGitCredential.to_string(),
+                None,
+                None,
+                Some(format!(
+                    "{}://{}/{}",
+                    req.protocol,
+                    req.host,
+                    req.path.as_deref().unwrap_or_default()
+                )),
+            )
+            .await?;
+        txn.commit().await?;
+
+        Ok(GitCredential {
+            username,
+            password: token,
+            password_expiry_utc: (Utc::now() + chrono::Duration::minutes(GIT_CREDENTIAL_MINUTES))
+                .timestamp(),
+        })
+    }
+
+    /// The username and token the project admins scoped to the repository of the project
+    async fn project_git_credential(
+        &self,
+        project_id: Option<Uuid>,
+    ) -> Result<Option<(String, String)>, ApiError> {
+        let Some(project_id) = project_id else {
+            return Ok(None);
+        };
+        let project = self.db.get_project(project_id).await?;
+        let (Some(username), Some(token)) = (
+            project.git_credential_username,
+            project.git_credential_token,
+        ) else {
+            return Ok(None);
+        };
+        Ok(Some((username, self.key_cipher.decrypt(&token)?)))
+    }
+
     pub async fn update_workspace_status(
         &self,
         ws: &entities::workspace::Model,
@@ -2565,6 +3049,7 @@ impl Conductor {
             .start_workspace(
                 long_running_context(),
                 StartWorkspaceRequest {
+                    id: ws.id,
                     osuser: ws.osuser.clone(),
                     workspace_name: ws.name.clone(),
                 },
@@ -3003,7 +3488,7 @@ impl Conductor {
                 .project_branches(
                     user_id,
                     &project,
-                    ("".to_string(), "".to_string()),
+                    RepoAuth::None,
                     ip.clone(),
                     user_agent.clone(),
                 )
@@ -3127,17 +3612,134 @@ fn repo_branches(repo: &Repository) -> Result<Vec<GitBranch>> {
     Ok(branches)
 }
 
-async fn clone_repo(
-    repo_url: String,
-    path: PathBuf,
-    auth: (String, String),
-) -> Result<(), ApiError> {
+/// Fails right away when there are no credentials for the repository,
+/// instead of git asking for them again and again
+fn repo_credentials(
+    auth: &RepoAuth,
+    url_username: Option<&str>,
+    allowed: CredentialType,
+) -> Result<Cred, git2::Error> {
+    match auth {
+        RepoAuth::Token { username, token }
+            if !token.is_empty() && allowed.contains(CredentialType::USER_PASS_PLAINTEXT) =>
+        {
+            Cred::userpass_plaintext(username, token)
+        }
+        RepoAuth::DeployKey { private_key, .. } if allowed.contains(CredentialType::SSH_KEY) => {
+            Cred::ssh_key_from_memory(url_username.unwrap_or("git"), None, private_key, None)
+        }
+        RepoAuth::DeployKey { .. } if allowed.contains(CredentialType::USERNAME) => {
+            Cred::username(url_username.unwrap_or("git"))
+        }
+        _ if allowed.contains(CredentialType::SSH_KEY) => Err(git2::Error::from_str(
+            "the ssh remote needs a deploy key of the project",
+        )),
+        _ => Err(git2::Error::from_str(
+            "the repository needs credentials that the user doesn't have",
+        )),
+    }
+}
+
+/// The callbacks to authenticate to the remote with. Git asks for the credentials again
+/// when they're rejected, so they're only offered once. The ssh host key is pinned to
+/// the one seen the first time the deploy key was used, which is put in `seen_host_key`
+fn repo_callbacks<'a>(
+    auth: &'a RepoAuth,
+    seen_host_key: &'a RefCell<Option<String>>,
+) -> RemoteCallbacks<'a> {
+    let mut offered = false;
+    let mut cbs = RemoteCallbacks::new();
+    cbs.credentials(move |_, url_username, allowed| {
+        if offered && !allowed.contains(CredentialType::USERNAME) {
+            return Err(git2::Error::from_str(
+                "the credentials for the repository were rejected",
+            ));
+        }
+        let cred = repo_credentials(auth, url_username, allowed)?;
+        if !allowed.contains(CredentialType::USERNAME) {
+            offered = true;
+        }
+        Ok(cred)
+    });
+    cbs.certificate_check(move |cert, host| {
+        let (RepoAuth::DeployKey { host_key, .. }, Some(hostkey)) = (auth, cert.as_hostkey())
+        else {
+            return Ok(CertificateCheckStatus::CertificatePassthrough);
+        };
+        let fingerprint = hostkey
+            .hash_sha256()
+            .map(|hash| format!("SHA256:{}", BASE64_NOPAD.encode(hash)))
+            .ok_or_else(|| {
+                git2::Error::from_str(&format!("can't get the ssh host key of {host}"))
+            })?;
+        match host_key {
+            Some(pinned) if pinned != &fingerprint => Err(git2::Error::from_str(&format!(
+                "the ssh host key of {host} changed from {pinned} to {fingerprint}"
+            ))),
+            Some(_) => Ok(CertificateCheckStatus::CertificateOk),
+            None => {
+                *seen_host_key.borrow_mut() = Some(fingerprint);
+                Ok(CertificateCheckStatus::CertificateOk)
+            }
+        }
+    });
+    cbs
+}
+
+/// How long git and the caching helpers in the workspace may keep a credential,
+/// the helper asks again after it expires. It doesn't limit the token on the git host.
+const GIT_CREDENTIAL_MINUTES: i64 = 5;
+
+/// If git asks for the credential of the repository at `repo_url`,
+/// git only sends the path when `credential.useHttpPath` is set
+fn git_credential_matches_repo(repo_url: &str, req: &GitCredentialRequest) -> bool {
+    let Some((scheme, rest)) = repo_url.split_once("://") else {
+        return false;
+    };
+    if scheme != "https" && scheme != "http" {
+        return false;
+    }
+    if !req.protocol.eq_ignore_ascii_case(scheme) {
+        return false;
+    }
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let authority = authority.rsplit('@').next().unwrap_or(authority);
+    if !req.host.eq_ignore_ascii_case(authority) {
+        return false;
+    }
+    let normalize = |path: &str| {
+        let path = path.trim_matches('/');
+        path.strip_suffix(".git").unwrap_or(path).to_lowercase()
+    };
+    req.path
+        .as_deref()
+        .map(|p| normalize(p) == normalize(path))
+        .unwrap_or(false)
+}
+
+/// Turns the scp-like syntax of git, e.g. `git@github.com:org/repo.git`,
+/// into an ssh url, while `host:port/path` is left as is
+fn scp_like_to_ssh_url(repo: &str) -> Option<String> {
+    if repo.contains("://") {
+        return None;
+    }
+    let (host, path) = repo.split_once(':')?;
+    if host.is_empty() || host.contains('/') || path.is_empty() {
+        return None;
+    }
+    let port = path.split('/').next().unwrap_or(path);
+    if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("ssh://{host}/{}", path.trim_start_matches('/')))
+}
+
+async fn clone_repo(repo_url: String, path: PathBuf, auth: RepoAuth) -> Result<(), ApiError> {
     tokio::task::spawn_blocking(move || -> Result<()> {
         println!("clone repo {repo_url}");
+        let seen_host_key = RefCell::new(None);
         let mut opt = git2::FetchOptions::new();
-        let mut cbs = RemoteCallbacks::new();
-        cbs.credentials(|_, _, _| Cred::userpass_plaintext(&auth.0, &auth.1));
-        opt.remote_callbacks(cbs);
+        opt.remote_callbacks(repo_callbacks(&auth, &seen_host_key));
         git2::build::RepoBuilder::new()
             .fetch_options(opt)
             .clone(&repo_url, &path)?;
@@ -3147,16 +3749,18 @@ async fn clone_repo(
     Ok(())
 }
 
-fn repo_pull(repo: &Repository, auth: (String, String)) -> Result<(), ApiError> {
+/// Returns the ssh host key that was trusted for the first time
+fn repo_pull(repo: &Repository, auth: RepoAuth) -> Result<Option<String>, ApiError> {
     let mut remote = repo
         .find_remote("origin")
         .or_else(|_| repo.remote_anonymous("origin"))?;
+    let seen_host_key = RefCell::new(None);
     let mut fetch_options = FetchOptions::new();
-    let mut cbs = RemoteCallbacks::new();
-    cbs.credentials(|_, _, _| Cred::userpass_plaintext(&auth.0, &auth.1));
     fetch_options.prune(FetchPrune::On);
-    fetch_options.remote_callbacks(cbs);
+    fetch_options.remote_callbacks(repo_callbacks(&auth, &seen_host_key));
     remote.fetch(&[] as &[&str], Some(&mut fetch_options), None)?;
+    drop(fetch_options);
+    let seen_host_key = seen_host_key.into_inner();
 
     let head = repo.head()?;
     let head_branch = head.shorthand().ok_or_else(|| anyhow!("no head branch"))?;
@@ -3207,5 +3811,70 @@ fn repo_pull(repo: &Repository, auth: (String, String)) -> Result<(), ApiError>
             }
         };
     }
-    Ok(())
+    Ok(seen_host_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use lapdev_common::GitCredentialRequest;
+
+    use super::{git_credential_matches_repo, scp_like_to_ssh_url};
+
+    #[test]
+    fn test_scp_like_url() {
+        assert_eq!(
+            scp_like_to_ssh_url("git@github.com:org/repo.git").as_deref(),
+            Some("ssh://git@github.com/org/repo.git")
+        );
+        assert_eq!(
+            scp_like_to_ssh_url("gitlab.example.com:group/sub/repo").as_deref(),
+            Some("ssh://gitlab.example.com/group/sub/repo")
+        );
+        assert_eq!(scp_like_to_ssh_url("github.com/org/repo"), None);
+        assert_eq!(scp_like_to_ssh_url("localhost:3000/org/repo"), None);
+        assert_eq!(scp_like_to_ssh_url("ssh://git@github.com/org/repo"), None);
+        assert_eq!(scp_like_to_ssh_url("https://github.com/org/repo"), None);
+    }
+
+    #[test]
+    fn test_git_credential_matches_repo() {
+        let req = |protocol: &str, host: &str, path: Option<&str>| GitCredentialRequest {
+            protocol: protocol.to_string(),
+            host: host.to_string(),
+            path: path.map(|p| p.to_string()),
+        };
+        let repo = "https://github.com/org/repo";
+        assert!(git_credential_matches_repo(
+            repo,
+            &req("https", "github.com", Some("org/repo.git"))
+        ));
+        assert!(git_credential_matches_repo(
+            repo,
+            &req("https", "GitHub.com", Some("/Org/Repo"))
+        ));
+        assert!(!git_credential_matches_repo(
+            repo,
+            &req("https", "github.com", Some("org/other.git"))
+        ));
+        assert!(!git_credential_matches_repo(
+            repo,
+            &req("https", "github.com", None)
+        ));
+        assert!(!git_credential_matches_repo(
+            repo,
+            &req("http", "github.com", Some("org/repo"))
+        ));
+        assert!(!git_credential_matches_repo(
+            repo,
+            &req("https", "gitlab.com", Some("org/repo"))
+        ));
+        assert!(git_credential_matches_repo(
+            "https://git.example.com:8443/group/repo.git",
+            &req("https", "git.example.com:8443", Some("group/repo.git"))
+        ));
+        assert!(!git_credential_matches_repo(
+            "ssh://git@github.com/org/repo",
+            &req("ssh", "github.com", Some("org/repo"))
+        ));
+    }
 }
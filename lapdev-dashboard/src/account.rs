@@ -2,22 +2,27 @@ use anyhow::Result;
 use gloo_net::http::Request;
 use lapdev_common::{
     console::{MeUser, NewSessionResponse},
-    AuthProvider, ClusterInfo,
+    ChangePassword, ClusterInfo, LocalBootstrap, LocalLogin, LocalLoginResponse,
+    LocalPasswordSetup, LoginProvider, TotpCode, TotpEnrollment,
 };
 use leptos::{
-    component, create_action, create_local_resource, expect_context, use_context, view, window,
-    IntoView, Resource, RwSignal, Signal, SignalGet, SignalGetUntracked, SignalSet, SignalWith,
+    component, create_action, create_local_resource, create_rw_signal, event_target_value,
+    expect_context, use_context, view, window, IntoView, Resource, RwSignal, Signal, SignalGet,
+    SignalGetUntracked, SignalSet, SignalUpdate, SignalWith,
 };
 use leptos_router::{use_location, use_params_map};
 
-use crate::{cluster::OauthSettings, modal::ErrorResponse};
+use crate::{
+    cluster::{auth_provider_icon, OauthSettings, OidcSettings},
+    modal::{CreationInput, ErrorResponse, SettingView},
+};
 
 pub async fn get_login() -> Result<MeUser> {
     let resp: MeUser = Request::get("/api/private/me").send().await?.json().await?;
     Ok(resp)
 }
 
-async fn now_login(provider: AuthProvider) -> Result<()> {
+async fn now_login(provider: String) -> Result<()> {
     let location = use_location();
     let next = format!(
         "{}{}",
@@ -27,7 +32,7 @@ async fn now_login(provider: AuthProvider) -> Result<()> {
     let location = window().window().location();
     let resp = Request::get("/api/private/session")
         .query([
-            ("provider", &provider.to_string()),
+            ("provider", &provider),
             ("next", &next),
             ("host", &location.origin().unwrap_or_default()),
         ])
@@ -60,10 +65,10 @@ pub fn Login() -> impl IntoView {
                     Lapdev
                 </a>
                 {
-                    move || if let Some(auth_providers) = cluster_info.with(|i| i.as_ref().map(|i| i.auth_providers.clone())) {
-                        if !auth_providers.is_empty() {
+                    move || if let Some((auth_providers, local_login)) = cluster_info.with(|i| i.as_ref().map(|i| (i.auth_providers.clone(), i.local_login))) {
+                        if !auth_providers.is_empty() || local_login {
                             view! {
-                                <LoginWithView auth_providers />
+                                <LoginWithView auth_providers local_login />
                             }.into_view()
                         } else {
                             view! {
@@ -83,8 +88,117 @@ pub fn Login() -> impl IntoView {
     }
 }
 
+async fn error_response(resp: gloo_net::http::Response) -> ErrorResponse {
+    resp.json::<ErrorResponse>()
+        .await
+        .unwrap_or_else(|_| ErrorResponse {
+            error: "Internal Server Error".to_string(),
+        })
+}
+
+async fn local_login(
+    login: RwSignal<String>,
+    password: RwSignal<String>,
+    totp_code: RwSignal<String>,
+    totp_required: RwSignal<bool>,
+    login_counter: RwSignal<i32>,
+) -> Result<(), ErrorResponse> {
+    let code = totp_code.get_untracked();
+    let resp = Request::post("/api/private/session/local")
+        .json(&LocalLogin {
+            login: login.get_untracked(),
+            password: password.get_untracked(),
+            totp_code: if code.is_empty() { None } else { Some(code) },
+        })?
+        .send()
+        .await?;
+    if resp.status() != 200 {
+        return Err(error_response(resp).await);
+    }
+    let resp: LocalLoginResponse = resp.json().await?;
+    if resp.totp_required {
+        totp_required.set(true);
+        return Ok(());
+    }
+    login_counter.update(|c| *c += 1);
+    Ok(())
+}
+
 #[component]
-pub fn LoginWithView(auth_providers: Vec<AuthProvider>) -> impl IntoView {
+fn PasswordInput(label: String, value: RwSignal<String>) -> impl IntoView {
+    view! {
+        <div>
+            <label class="block mb-2 text-sm font-medium text-gray-900 dark:text-white">{ label }</label>
+            <input
+                type="password"
+                prop:value={move || value.get()}
+                on:input=move |ev| { value.set(event_target_value(&ev)); }
+                class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+            />
+        </div>
+    }
+}
+
+#[component]
+fn ErrorView(error: RwSignal<Option<String>>) -> impl IntoView {
+    move || {
+        if let Some(error) = error.get() {
+            view! {
+                <div class="my-2 p-4 rounded-lg bg-red-50 dark:bg-gray-800 ">
+                    <span class="text-sm font-medium text-red-800 dark:text-red-400">{ error }</span>
+                </div>
+            }
+            .into_view()
+        } else {
+            view! {}.into_view()
+        }
+    }
+}
+
+#[component]
+fn LocalLoginView() -> impl IntoView {
+    let login = create_rw_signal(String::new());
+    let password = create_rw_signal(String::new());
+    let totp_code = create_rw_signal(String::new());
+    let totp_required = create_rw_signal(false);
+    let login_counter = expect_context::<RwSignal<i32>>();
+    let error = create_rw_signal(None);
+    let action = create_action(move |_| async move {
+        error.set(None);
+        if let Err(e) = local_login(login, password, totp_code, totp_required, login_counter).await
+        {
+            error.set(Some(e.error));
+        }
+    });
+    let pending = action.pending();
+
+    view! {
+        <form
+            class="space-y-4"
+            on:submit=move |e| {
+                e.prevent_default();
+                action.dispatch(());
+            }
+        >
+            <ErrorView error />
+            <CreationInput label="Login".to_string() value=login placeholder="".to_string() />
+            <PasswordInput label="Password".to_string() value=password />
+            <div class:hidden=move || !totp_required.get()>
+                <CreationInput label="Two-factor code".to_string() value=totp_code placeholder="123456".to_string() />
+            </div>
+            <button
+                type="submit"
+                class="w-full text-white bg-blue-700 hover:bg-blue-800 focus:ring-4 focus:outline-none focus:ring-blue-300 font-medium rounded-lg text-sm px-5 py-2.5 text-center dark:bg-blue-600 dark:hover:bg-blue-700 dark:focus:ring-blue-800"
+                disabled=move || pending.get()
+            >
+                Sign in
+            </button>
+        </form>
+    }
+}
+
+#[component]
+pub fn LoginWithView(auth_providers: Vec<LoginProvider>, local_login: bool) -> impl IntoView {
     let login = use_context::<Resource<i32, Option<MeUser>>>().unwrap();
     view! {
         <div
@@ -95,34 +209,138 @@ pub fn LoginWithView(auth_providers: Vec<AuthProvider>) -> impl IntoView {
                 <h1 class="mb-4 text-xl font-bold leading-tight tracking-tight text-gray-900 md:text-2xl dark:text-white">
                     Sign in to your account
                 </h1>
-                <button type="button"
-                    class="mb-2 w-full text-gray-900 bg-white hover:bg-gray-100 border border-gray-200 focus:ring-4 focus:outline-none focus:ring-gray-100 font-medium rounded-lg text-sm px-5 py-2.5 text-center inline-flex items-center dark:focus:ring-gray-600 dark:bg-gray-800 dark:border-gray-700 dark:text-white dark:hover:bg-gray-700"
-                    class:hidden={ let auth_providers = auth_providers.clone(); move || !auth_providers.contains(&AuthProvider::Github) }
-                    on:click=move |_| { create_action(move |_| {now_login(AuthProvider::Github)}).dispatch(()) }
-                >
-                    <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="currentColor" viewBox="0 0 20 20">
-                    <path fill-rule="evenodd" d="M10 .333A9.911 9.911 0 0 0 6.866 19.65c.5.092.678-.215.678-.477 0-.237-.01-1.017-.014-1.845-2.757.6-3.338-1.169-3.338-1.169a2.627 2.627 0 0 0-1.1-1.451c-.9-.615.07-.6.07-.6a2.084 2.084 0 0 1 1.518 1.021 2.11 2.11 0 0 0 2.884.823c.044-.503.268-.973.63-1.325-2.2-.25-4.516-1.1-4.516-4.9A3.832 3.832 0 0 1 4.7 7.068a3.56 3.56 0 0 1 .095-2.623s.832-.266 2.726 1.016a9.409 9.409 0 0 1 4.962 0c1.89-1.282 2.717-1.016 2.717-1.016.366.83.402 1.768.1 2.623a3.827 3.827 0 0 1 1.02 2.659c0 3.807-2.319 4.644-4.525 4.889a2.366 2.366 0 0 1 .673 1.834c0 1.326-.012 2.394-.012 2.72 0 .263.18.572.681.475A9.911 9.911 0 0 0 10 .333Z" clip-rule="evenodd"/>
-                    </svg>
-                    Sign in with GitHub
-                </button>
-
-                <button type="button"
-                    class="mb-2 w-full text-gray-900 bg-white hover:bg-gray-100 border border-gray-200 focus:ring-4 focus:outline-none focus:ring-gray-100 font-medium rounded-lg text-sm px-5 py-2.5 text-center inline-flex items-center dark:focus:ring-gray-600 dark:bg-gray-800 dark:border-gray-700 dark:text-white dark:hover:bg-gray-700"
-                    class:hidden=move || !auth_providers.contains(&AuthProvider::Gitlab)
-                    on:click=move |_| { create_action(move |_| {now_login(AuthProvider::Gitlab)}).dispatch(()) }
-                >
-                    <svg class="w-4 h-4 me-2" viewBox="0 0 25 24" xmlns="http://www.w3.org/2000/svg"><path d="M24.507 9.5l-.034-.09L21.082.562a.896.896 0 00-1.694.091l-2.29 7.01H7.825L5.535.653a.898.898 0 00-1.694-.09L.451 9.411.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 2.56 1.935 1.554 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#E24329"/><path d="M24.507 9.5l-.034-.09a11.44 11.44 0 00-4.56 2.051l-7.447 5.632 4.742 3.584 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#FC6D26"/><path d="M7.707 20.677l2.56 1.935 1.555 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935-4.743-3.584-4.755 3.584z" fill="#FCA326"/><path d="M5.01 11.461a11.43 11.43 0 00-4.56-2.05L.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 4.745-3.584-7.444-5.632z" fill="#FC6D26"/></svg>
-                    Sign in with GitLab
-                </button>
+                {
+                    auth_providers.into_iter().map(|provider| {
+                        let id = provider.id.clone();
+                        view! {
+                            <button type="button"
+                                class="mb-2 w-full text-gray-900 bg-white hover:bg-gray-100 border border-gray-200 focus:ring-4 focus:outline-none focus:ring-gray-100 font-medium rounded-lg text-sm px-5 py-2.5 text-center inline-flex items-center dark:focus:ring-gray-600 dark:bg-gray-800 dark:border-gray-700 dark:text-white dark:hover:bg-gray-700"
+                                on:click=move |_| { let id = id.clone(); create_action(move |_| {now_login(id.clone())}).dispatch(()) }
+                            >
+                                {auth_provider_icon(&provider.kind)}
+                                {format!("Sign in with {}", provider.name)}
+                            </button>
+                        }
+                    }).collect::<Vec<_>>()
+                }
+                {
+                    if local_login {
+                        view! {
+                            <div class="mt-2">
+                                <LocalLoginView />
+                            </div>
+                        }.into_view()
+                    } else {
+                        view! {}.into_view()
+                    }
+                }
             </div>
         </div>
     }
 }
 
+async fn local_bootstrap(
+    login: RwSignal<String>,
+    password: RwSignal<String>,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::post("/api/private/session/local/bootstrap")
+        .json(&LocalBootstrap {
+            login: login.get_untracked(),
+            password: password.get_untracked(),
+        })?
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        return Err(error_response(resp).await);
+    }
+    let _ = window().location().reload();
+    Ok(())
+}
+
+/// Creates the first cluster admin with a password, as an alternative to setting up an OAuth app
+#[component]
+fn LocalBootstrapView() -> impl IntoView {
+    let login = create_rw_signal(String::new());
+    let password = create_rw_signal(String::new());
+    let update_counter = create_rw_signal(0);
+    let action = create_action(move |_| local_bootstrap(login, password));
+    let body = view! {
+        <p class="mb-4 text-sm text-gray-700 dark:text-gray-400">
+            {"Or create the cluster admin with a username and password."}
+        </p>
+        <div class="space-y-4">
+            <CreationInput label="Login".to_string() value=login placeholder="".to_string() />
+            <PasswordInput label="Password".to_string() value=password />
+        </div>
+    };
+    view! {
+        <SettingView title="Local Admin Account".to_string() action body update_counter extra=None />
+    }
+}
+
 #[component]
 pub fn InitAuthProvidersView() -> impl IntoView {
     view! {
         <OauthSettings reload=true />
+        <div class="mt-4">
+            <OidcSettings reload=true />
+        </div>
+        <div class="mt-4">
+            <LocalBootstrapView />
+        </div>
+    }
+}
+
+async fn setup_password(
+    token: String,
+    password: RwSignal<String>,
+    confirm: RwSignal<String>,
+) -> Result<(), ErrorResponse> {
+    let password = password.get_untracked();
+    if password != confirm.get_untracked() {
+        return Err(ErrorResponse {
+            error: "the passwords don't match".to_string(),
+        });
+    }
+    let resp = Request::post("/api/private/session/local/setup")
+        .json(&LocalPasswordSetup { token, password })?
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        return Err(error_response(resp).await);
+    }
+    let _ = window().location().set_href("/");
+    Ok(())
+}
+
+/// Where a local user sets their password with the link from the cluster admin
+#[component]
+pub fn SetupPasswordView() -> impl IntoView {
+    let params = use_params_map();
+    let token = Signal::derive(move || {
+        params
+            .with(|params| params.get("token").cloned())
+            .unwrap_or_default()
+    });
+    let password = create_rw_signal(String::new());
+    let confirm = create_rw_signal(String::new());
+    let update_counter = create_rw_signal(0);
+    let action = create_action(move |_| setup_password(token.get_untracked(), password, confirm));
+    let body = view! {
+        <div class="space-y-4">
+            <PasswordInput label="New password".to_string() value=password />
+            <PasswordInput label="Confirm password".to_string() value=confirm />
+        </div>
+    };
+
+    view! {
+        <section class="bg-gray-50 dark:bg-gray-900">
+            <div class="flex flex-col items-center justify-center px-6 py-8 mx-auto md:h-screen lg:py-0">
+                <div class="w-96 bg-white rounded-lg shadow p-6">
+                    <SettingView title="Set your password".to_string() action body update_counter extra=None />
+                </div>
+            </div>
+        </section>
     }
 }
 
@@ -179,6 +397,10 @@ pub fn NavUserControl(user_control_hidden: RwSignal<bool>) -> impl IntoView {
 
 #[component]
 pub fn AccountSettings() -> impl IntoView {
+    let login = use_context::<Resource<i32, Option<MeUser>>>().unwrap();
+    let local = Signal::derive(move || {
+        login.with(|l| l.as_ref().and_then(|l| l.as_ref()).map(|l| l.local))
+    });
     view! {
         <div class="border-b pb-4">
             <h5 class="mr-3 text-2xl font-semibold dark:text-white">
@@ -186,6 +408,164 @@ pub fn AccountSettings() -> impl IntoView {
             </h5>
             <p class="text-gray-700 dark:text-gray-400">{"Manage your account settings"}</p>
         </div>
+        {
+            move || if local.get() == Some(true) {
+                view! {
+                    <div class="mt-8">
+                        <PasswordSettings />
+                    </div>
+                    <div class="mt-8">
+                        <TotpSettings />
+                    </div>
+                }.into_view()
+            } else {
+                view! {}.into_view()
+            }
+        }
+    }
+}
+
+async fn change_password(
+    current: RwSignal<String>,
+    new: RwSignal<String>,
+    confirm: RwSignal<String>,
+) -> Result<(), ErrorResponse> {
+    if new.get_untracked() != confirm.get_untracked() {
+        return Err(ErrorResponse {
+            error: "the new passwords don't match".to_string(),
+        });
+    }
+    let resp = Request::put("/api/v1/account/password")
+        .json(&ChangePassword {
+            current: current.get_untracked(),
+            new: new.get_untracked(),
+        })?
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        return Err(error_response(resp).await);
+    }
+    current.set(String::new());
+    new.set(String::new());
+    confirm.set(String::new());
+    Ok(())
+}
+
+#[component]
+fn PasswordSettings() -> impl IntoView {
+    let current = create_rw_signal(String::new());
+    let new = create_rw_signal(String::new());
+    let confirm = create_rw_signal(String::new());
+    let update_counter = create_rw_signal(0);
+    let action = create_action(move |_| change_password(current, new, confirm));
+    let body = view! {
+        <div class="w-96 space-y-4">
+            <PasswordInput label="Current password".to_string() value=current />
+            <PasswordInput label="New password".to_string() value=new />
+            <PasswordInput label="Confirm new password".to_string() value=confirm />
+        </div>
+    };
+    view! {
+        <SettingView title="Password".to_string() action body update_counter extra=None />
+    }
+}
+
+async fn begin_totp(enrollment: RwSignal<Option<TotpEnrollment>>) -> Result<(), ErrorResponse> {
+    let resp = Request::post("/api/v1/account/totp").send().await?;
+    if resp.status() != 200 {
+        return Err(error_response(resp).await);
+    }
+    enrollment.set(Some(resp.json().await?));
+    Ok(())
+}
+
+async fn update_totp(enable: bool, code: RwSignal<String>) -> Result<(), ErrorResponse> {
+    let req = if enable {
+        Request::put("/api/v1/account/totp")
+    } else {
+        Request::delete("/api/v1/account/totp")
+    };
+    let resp = req
+        .json(&TotpCode {
+            code: code.get_untracked(),
+        })?
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        return Err(error_response(resp).await);
+    }
+    let _ = window().location().reload();
+    Ok(())
+}
+
+#[component]
+fn TotpSettings() -> impl IntoView {
+    let login = use_context::<Resource<i32, Option<MeUser>>>().unwrap();
+    let enabled = Signal::derive(move || {
+        login.with(|l| {
+            l.as_ref()
+                .and_then(|l| l.as_ref())
+                .map(|l| l.totp_enabled)
+                .unwrap_or(false)
+        })
+    });
+    let enrollment = create_rw_signal(None);
+    let code = create_rw_signal(String::new());
+    let error = create_rw_signal(None);
+    let begin_action = create_action(move |_| async move {
+        error.set(None);
+        if let Err(e) = begin_totp(enrollment).await {
+            error.set(Some(e.error));
+        }
+    });
+    let update_counter = create_rw_signal(0);
+    let update_action = create_action(move |_| update_totp(!enabled.get_untracked(), code));
+
+    view! {
+        <h5 class="text-lg font-semibold dark:text-white">Two-factor Authentication</h5>
+        <ErrorView error />
+        {
+            move || if enabled.get() {
+                let body = view! {
+                    <p class="mb-4 text-sm text-gray-700 dark:text-gray-400">
+                        {"Two-factor authentication is enabled. Enter a code from your authenticator app to turn it off."}
+                    </p>
+                    <div class="w-96">
+                        <CreationInput label="Code".to_string() value=code placeholder="123456".to_string() />
+                    </div>
+                };
+                view! {
+                    <SettingView title=String::new() action=update_action body update_counter extra=None />
+                }.into_view()
+            } else if let Some(enrollment) = enrollment.get() {
+                let body = view! {
+                    <p class="mb-2 text-sm text-gray-700 dark:text-gray-400">
+                        {"Add this secret to your authenticator app, then enter the code it shows to turn on two-factor authentication."}
+                    </p>
+                    <p class="mb-2 text-sm font-mono break-all text-gray-900 dark:text-white">{enrollment.secret}</p>
+                    <p class="mb-4 text-sm font-mono break-all text-gray-500 dark:text-gray-400">{enrollment.url}</p>
+                    <div class="w-96">
+                        <CreationInput label="Code".to_string() value=code placeholder="123456".to_string() />
+                    </div>
+                };
+                view! {
+                    <SettingView title=String::new() action=update_action body update_counter extra=None />
+                }.into_view()
+            } else {
+                view! {
+                    <p class="mb-4 text-sm text-gray-700 dark:text-gray-400">
+                        {"Require a code from an authenticator app in addition to your password when signing in."}
+                    </p>
+                    <button
+                        type="button"
+                        class="text-white bg-blue-700 hover:bg-blue-800 focus:ring-4 focus:outline-none focus:ring-blue-300 font-medium rounded-lg text-sm px-5 py-2.5 text-center dark:bg-blue-600 dark:hover:bg-blue-700 dark:focus:ring-blue-800"
+                        on:click=move |_| begin_action.dispatch(())
+                    >
+                        Set up two-factor authentication
+                    </button>
+                }.into_view()
+            }
+        }
     }
 }
 
@@ -8,15 +8,17 @@ use leptos::{
 use leptos_router::{Route, Router, Routes};
 
 use crate::{
-    account::{get_login, AccountSettings, JoinView, Login},
-    audit_log::AuditLogView,
+    account::{get_login, AccountSettings, JoinView, Login, SetupPasswordView},
+    audit_log::{AuditLogView, SshRecordingView},
     cluster::{ClusterSettings, ClusterUsersView, MachineTypeView, WorkspaceHostView},
+    git_token::GitTokens,
     license::{LicenseView, SignLicenseView},
     nav::{AdminSideNav, NavExpanded, SideNav, TopNav},
     organization::{NewOrgModal, OrgMembers, OrgSettings},
     project::{ProjectDetails, Projects},
     quota::QuotaView,
     ssh_key::SshKeys,
+    terminal::WorkspaceTerminalView,
     usage::UsageView,
     workspace::{WorkspaceDetails, Workspaces},
 };
@@ -77,14 +79,18 @@ pub fn App() -> impl IntoView {
                 <Route path="/projects/:id" view=move || view! { <WrappedView element=ProjectDetails /> } />
                 <Route path="/workspaces" view=move || view! { <WrappedView element=Workspaces /> } />
                 <Route path="/workspaces/:name" view=move || view! { <WrappedView element=WorkspaceDetails /> } />
+                <Route path="/workspaces/:name/terminal" view=move || view! { <WrappedView element=WorkspaceTerminalView /> } />
                 <Route path="/organization/usage" view=move || view! { <WrappedView element=UsageView /> } />
                 <Route path="/organization/members" view=move || view! { <WrappedView element=OrgMembers /> } />
                 <Route path="/organization/quota" view=move || view! { <WrappedView element=QuotaView /> } />
                 <Route path="/organization/audit_log" view=move || view! { <WrappedView element=AuditLogView /> } />
+                <Route path="/organization/audit_log/recordings/:id" view=move || view! { <WrappedView element=SshRecordingView /> } />
                 <Route path="/organization/settings" view=move || view! { <WrappedView element=OrgSettings /> } />
                 <Route path="/account" view=move || view! { <WrappedView element=AccountSettings /> } />
                 <Route path="/join/:id" view=move || view! { <WrappedView element=JoinView /> } />
                 <Route path="/account/ssh-keys" view=move || view! { <WrappedView element=SshKeys /> } />
+                <Route path="/account/git-tokens" view=move || view! { <WrappedView element=GitTokens /> } />
+                <Route path="/setup_password/:token" view=SetupPasswordView />
                 <Route path="/admin" view=move || view! { <AdminWrappedView element=WorkspaceHostView /> } />
                 <Route path="/admin/workspace_hosts" view=move || view! { <AdminWrappedView element=WorkspaceHostView /> } />
                 <Route path="/admin/machine_types" view=move || view! { <AdminWrappedView element=MachineTypeView /> } />
@@ -1,12 +1,17 @@
+use std::rc::Rc;
+
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeZone};
 use gloo_net::http::Request;
-use lapdev_common::{console::Organization, AuditLogRecord, AuditLogResult};
+use lapdev_common::{console::Organization, AuditAction, AuditLogRecord, AuditLogResult};
 use leptos::{
-    component, create_action, create_rw_signal, event_target_value, use_context, view, For,
-    IntoView, Signal, SignalGet, SignalGetUntracked, SignalSet, SignalUpdate, SignalWith,
-    SignalWithUntracked,
+    component, create_action, create_node_ref, create_rw_signal, event_target_value, html,
+    on_cleanup, use_context, view, For, IntoView, Signal, SignalGet, SignalGetUntracked, SignalSet,
+    SignalUpdate, SignalWith, SignalWithUntracked,
 };
+use leptos_router::use_params_map;
+use strum::IntoEnumIterator;
+use wasm_bindgen::JsValue;
 
 use crate::{
     datepicker::Datepicker,
@@ -16,6 +21,8 @@ use crate::{
 async fn get_audit_logs(
     start: Option<NaiveDate>,
     end: Option<NaiveDate>,
+    action: String,
+    search: String,
     page_size: String,
     page: u64,
 ) -> Result<AuditLogResult, ErrorResponse> {
@@ -52,13 +59,22 @@ async fn get_audit_logs(
 
     let page_size = page_size.parse::<u64>().unwrap_or(10);
 
+    let mut query = vec![
+        ("start", start.to_rfc3339()),
+        ("end", end.to_rfc3339()),
+        ("page", page.to_string()),
+        ("page_size", page_size.to_string()),
+    ];
+    if !action.is_empty() {
+        query.push(("action", action));
+    }
+    let search = search.trim().to_string();
+    if !search.is_empty() {
+        query.push(("search", search));
+    }
+
     let resp = Request::get(&format!("/api/v1/organizations/{}/audit_logs", org.id))
-        .query([
-            ("start", &start.to_rfc3339()),
-            ("end", &end.to_rfc3339()),
-            ("page", &page.to_string()),
-            ("page_size", &page_size.to_string()),
-        ])
+        .query(query)
         .send()
         .await?;
 
@@ -81,6 +97,8 @@ async fn get_audit_logs(
 pub fn AuditLogView() -> impl IntoView {
     let from_date = create_rw_signal(Some(Local::now().date_naive()));
     let to_date = create_rw_signal(Some(Local::now().date_naive()));
+    let action = create_rw_signal(String::new());
+    let search = create_rw_signal(String::new());
     let page_size = create_rw_signal(String::new());
     let page = create_rw_signal(0);
 
@@ -91,6 +109,8 @@ pub fn AuditLogView() -> impl IntoView {
         let result = get_audit_logs(
             from_date.get_untracked(),
             to_date.get_untracked(),
+            action.get_untracked(),
+            search.get_untracked(),
             page_size.get_untracked(),
             page.get_untracked(),
         )
@@ -154,10 +174,26 @@ pub fn AuditLogView() -> impl IntoView {
             <Datepicker value=from_date />
             <span class="mx-2">to</span>
             <Datepicker value=to_date />
+            <select
+                class="ml-4 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block p-2.5 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500"
+                on:change=move |e| action.set(event_target_value(&e))
+            >
+                <option value="" selected>{"All actions"}</option>
+                { AuditAction::iter().map(|a| view! { <option>{ a.to_string() }</option> }).collect::<Vec<_>>() }
+            </select>
+            <input
+                class="ml-4 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block p-2.5 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500"
+                placeholder="Search resources and details"
+                prop:value=move || search.get()
+                on:input=move |e| search.set(event_target_value(&e))
+            />
             <button
                 type="button"
                 class="ml-4 px-4 py-2 text-sm font-medium text-white rounded-lg bg-blue-700 hover:bg-blue-800 focus:ring-4 focus:ring-blue-300 dark:bg-blue-600 dark:hover:bg-blue-700 focus:outline-none dark:focus:ring-blue-800"
-                on:click=move |_| get_action.dispatch(())
+                on:click=move |_| {
+                    page.set(0);
+                    get_action.dispatch(());
+                }
             >
                 Search
             </button>
@@ -260,11 +296,85 @@ fn RecordItemView(i: usize, record: AuditLogRecord) -> impl IntoView {
             </div>
             <div class="w-1/4 flex flex-col">
                 <p>{record.action}</p>
+                <p class="text-gray-500 truncate">{record.details}</p>
+                { record.recording.map(|id| view! {
+                    <a href={ format!("/organization/audit_log/recordings/{id}") } class="text-blue-700 dark:text-blue-500 hover:underline">
+                        Replay
+                    </a>
+                }) }
             </div>
             <div class="w-1/4 flex flex-col">
                 <p>{record.resource_kind}</p>
                 <p class="text-gray-500">{record.resource_name}</p>
+                <p class="text-gray-500">{record.ip}</p>
             </div>
         </div>
     }
 }
+
+// binding for asciinema player, which is loaded in index.html
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    type Player;
+
+    #[wasm_bindgen(js_namespace = AsciinemaPlayer, js_name = create)]
+    fn create_player(
+        src: &str,
+        element: &web_sys::HtmlElement,
+        options: &JsValue,
+    ) -> Player;
+
+    #[wasm_bindgen(method)]
+    fn dispose(this: &Player);
+}
+
+#[component]
+pub fn SshRecordingView() -> impl IntoView {
+    let params = use_params_map();
+    let id = params.with_untracked(|params| params.get("id").cloned().unwrap_or_default());
+    let container = create_node_ref::<html::Div>();
+    let error = create_rw_signal(None);
+    let player = create_rw_signal(None::<Rc<Player>>);
+    let org = use_context::<Signal<Option<Organization>>>().and_then(|org| org.get_untracked());
+
+    container.on_load(move |div| {
+        let Some(org) = org else {
+            error.set(Some("can't get org".to_string()));
+            return;
+        };
+        let src = format!("/api/v1/organizations/{}/ssh_recordings/{id}", org.id);
+        player.set(Some(Rc::new(create_player(
+            &src,
+            &div,
+            &JsValue::UNDEFINED,
+        ))));
+    });
+
+    on_cleanup(move || {
+        if let Some(player) = player.get_untracked() {
+            player.dispose();
+        }
+    });
+
+    view! {
+        <a href="/organization/audit_log" class="text-sm inline-flex items-center text-gray-700 dark:text-gray-400">
+            <svg class="w-2 h-2 mr-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 14 10">
+            <path stroke="currentColor" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M13 5H1m0 0 4 4M1 5l4-4"/>
+            </svg>
+            Back to Audit Log
+        </a>
+        <h5 class="text-semibold text-2xl dark:text-white mt-4">
+            SSH Session Recording
+        </h5>
+        { move || if let Some(error) = error.get() {
+            view! {
+                <div class="my-4 p-4 rounded-lg bg-red-50 dark:bg-gray-800 ">
+                    <span class="text-sm font-medium text-red-800 dark:text-red-400">{ error }</span>
+                </div>
+            }.into_view()
+        } else {
+            view!{}.into_view()
+        }}
+        <div class="mt-4 w-full" node_ref=container></div>
+    }
+}
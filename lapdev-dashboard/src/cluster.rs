@@ -1,17 +1,19 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, str::FromStr, time::Duration};
 
 use anyhow::Result;
 use gloo_net::http::Request;
 use lapdev_common::{
-    AuthProvider, ClusterInfo, ClusterUser, ClusterUserResult, CreateMachineType, MachineType,
-    NewWorkspaceHost, OauthSettings, UpdateClusterUser, UpdateMachineType, UpdateWorkspaceHost,
+    AcmeDnsProvider, AcmeSettings, AuthProvider, CertificateInfo, ClusterInfo, ClusterUser,
+    ClusterUserResult, CreateMachineType, LocalPasswordReset, LocalSetupToken, MachineType,
+    NewCertificate, NewLocalUser, NewWorkspaceHost, OauthProvider, OauthSettings, OidcGroupMapping,
+    OidcSettings, UpdateClusterUser, UpdateMachineType, UpdateWorkspaceHost, UserRole,
     WorkspaceHost,
 };
 use leptos::{
     component, create_action, create_effect, create_local_resource, create_rw_signal, document,
     event_target_checked, event_target_value, expect_context, leptos_dom::helpers::location,
     set_timeout, view, Action, For, IntoView, RwSignal, Signal, SignalGet, SignalGetUntracked,
-    SignalSet, SignalUpdate, SignalWith, SignalWithUntracked,
+    SignalSet, SignalUpdate, SignalWith, SignalWithUntracked, View,
 };
 use uuid::Uuid;
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
@@ -476,6 +478,7 @@ pub fn ClusterSettings() -> impl IntoView {
             </h5>
             <p class="text-gray-700 dark:text-gray-400">{"Manage your cluster settings"}</p>
         </div>
+        <CertExpiryWarning />
         <div class="mb-8">
             <div class="w-full p-8 border rounded-xl">
                 <ClusterHostnameSetting />
@@ -483,15 +486,24 @@ pub fn ClusterSettings() -> impl IntoView {
             <div class="mt-4 w-full p-8 border rounded-xl">
                 <ClusterCertsSetting />
             </div>
+            <div class="mt-4 w-full p-8 border rounded-xl">
+                <AcmeSetting />
+            </div>
             <div
                 class="mt-4 w-full p-8 border rounded-xl"
                 class:hidden=move || !cluster_info.with(|i| i.as_ref().map(|i| i.has_enterprise)).unwrap_or(false)
             >
                 <CpuOvercommitSetting />
             </div>
+            <div class="mt-4 w-full p-8 border rounded-xl">
+                <PathRoutingSetting />
+            </div>
             <div class="mt-4 w-full p-8 border rounded-xl">
                 <OauthSettings reload=false />
             </div>
+            <div class="mt-4 w-full p-8 border rounded-xl">
+                <OidcSettings reload=false />
+            </div>
         </div>
     }
 }
@@ -565,6 +577,56 @@ fn CpuOvercommitSetting() -> impl IntoView {
     }
 }
 
+async fn update_path_routing(enabled: bool) -> Result<(), ErrorResponse> {
+    let resp = Request::put(&format!("/api/v1/admin/path_routing/{enabled}"))
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    // the workspace urls in the cluster info need to be reloaded
+    let _ = location().reload();
+    Ok(())
+}
+
+#[component]
+fn PathRoutingSetting() -> impl IntoView {
+    let cluster_info = expect_context::<Signal<Option<ClusterInfo>>>();
+    let update_counter = create_rw_signal(0);
+    let enabled = create_rw_signal(false);
+    create_effect(move |_| {
+        if let Some(v) = cluster_info.with(|i| i.as_ref().map(|i| i.path_routing)) {
+            enabled.set(v);
+        }
+    });
+    let body = view! {
+        <p class="mt-2 text-sm text-gray-500 dark:text-gray-400">
+            {"Serve the workspaces under /ws/<workspace>/<port>/ of the ws. subdomain of the main hostname, e.g. ws.lapdev.example.com, instead of their own subdomains, for networks without wildcard DNS or wildcard certificates. The ws. hostname needs a DNS record and to be covered by the certificate. The workspaces share its origin with each other, but not with the dashboard."}
+        </p>
+        <div class="mt-2">
+            <label class="inline-flex items-center cursor-pointer">
+                <input type="checkbox" value="" class="sr-only peer"
+                    prop:checked=move || enabled.get()
+                    on:change=move |e| enabled.set(event_target_checked(&e))
+                />
+                <div class="relative w-11 h-6 bg-gray-200 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-blue-300 dark:peer-focus:ring-blue-800 rounded-full peer dark:bg-gray-700 peer-checked:after:translate-x-full rtl:peer-checked:after:-translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:start-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all dark:border-gray-600 peer-checked:bg-blue-600"></div>
+                <span class="ms-3 text-sm font-medium text-gray-900 dark:text-gray-300">Path Based Routing</span>
+            </label>
+        </div>
+    };
+    let save_action =
+        create_action(move |_| async move { update_path_routing(enabled.get_untracked()).await });
+    view! {
+        <SettingView title="Workspace Routing".to_string() action=save_action body update_counter extra=None />
+    }
+}
+
 async fn update_oauth2(
     update_oauth: OauthSettings,
     update_counter: RwSignal<i32>,
@@ -603,10 +665,13 @@ async fn get_oauth2() -> Result<OauthSettings> {
 #[component]
 pub fn OauthSettings(reload: bool) -> impl IntoView {
     let update_counter = create_rw_signal(0);
-    let github_client_id = create_rw_signal(String::new());
-    let github_client_secret = create_rw_signal(String::new());
-    let gitlab_client_id = create_rw_signal(String::new());
-    let gitlab_client_secret = create_rw_signal(String::new());
+    let next_key = create_rw_signal(0usize);
+    let providers: RwSignal<Vec<(usize, RwSignal<OauthProvider>)>> = create_rw_signal(vec![]);
+    let add_provider = move |provider: OauthProvider| {
+        let key = next_key.get_untracked();
+        next_key.set(key + 1);
+        providers.update(|p| p.push((key, create_rw_signal(provider))));
+    };
 
     let oauth = create_local_resource(
         move || update_counter.get(),
@@ -616,78 +681,364 @@ pub fn OauthSettings(reload: bool) -> impl IntoView {
     create_effect(move |_| {
         let oauth = oauth.with(|oauth| oauth.as_ref().and_then(|o| o.as_ref().ok().cloned()));
         if let Some(oauth) = oauth {
-            github_client_id.set(oauth.github_client_id);
-            github_client_secret.set(oauth.github_client_secret);
-            gitlab_client_id.set(oauth.gitlab_client_id);
-            gitlab_client_secret.set(oauth.gitlab_client_secret);
+            providers.set(vec![]);
+            for provider in oauth.providers {
+                add_provider(provider);
+            }
         }
     });
 
+    let new_instance = move |kind: AuthProvider| {
+        let mut provider = OauthProvider::new(kind);
+        // the id of a new instance is generated when it's saved
+        provider.id = String::new();
+        provider.base_url = String::new();
+        add_provider(provider);
+    };
+
     let body = view! {
-        <div class="mt-2">
-            <div class="flex flex-row items-center mb-2 text-sm font-medium text-gray-900 dark:text-white">
-                <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="currentColor" viewBox="0 0 20 20">
+        <For
+            each=move || providers.get()
+            key=|(key, _)| *key
+            children=move |(key, provider)| {
+                let remove = move || providers.update(|p| p.retain(|(k, _)| *k != key));
+                view! { <OauthProviderItem provider remove /> }
+            }
+        />
+        <div class="mt-4 flex flex-row items-center">
+            <button
+                type="button"
+                class="flex flex-row items-center text-gray-900 bg-white border border-gray-300 focus:outline-none hover:bg-gray-100 focus:ring-4 focus:ring-gray-100 font-medium rounded-lg text-sm px-3 py-2 me-2 dark:bg-gray-800 dark:text-white dark:border-gray-600 dark:hover:bg-gray-700 dark:hover:border-gray-600 dark:focus:ring-gray-700"
+                on:click=move |_| new_instance(AuthProvider::Github)
+            >
+                {auth_provider_icon(&AuthProvider::Github)}
+                Add GitHub instance
+            </button>
+            <button
+                type="button"
+                class="flex flex-row items-center text-gray-900 bg-white border border-gray-300 focus:outline-none hover:bg-gray-100 focus:ring-4 focus:ring-gray-100 font-medium rounded-lg text-sm px-3 py-2 me-2 dark:bg-gray-800 dark:text-white dark:border-gray-600 dark:hover:bg-gray-700 dark:hover:border-gray-600 dark:focus:ring-gray-700"
+                on:click=move |_| new_instance(AuthProvider::Gitlab)
+            >
+                {auth_provider_icon(&AuthProvider::Gitlab)}
+                Add GitLab instance
+            </button>
+            <button
+                type="button"
+                class="flex flex-row items-center text-gray-900 bg-white border border-gray-300 focus:outline-none hover:bg-gray-100 focus:ring-4 focus:ring-gray-100 font-medium rounded-lg text-sm px-3 py-2 me-2 dark:bg-gray-800 dark:text-white dark:border-gray-600 dark:hover:bg-gray-700 dark:hover:border-gray-600 dark:focus:ring-gray-700"
+                on:click=move |_| new_instance(AuthProvider::Gitea)
+            >
+                {auth_provider_icon(&AuthProvider::Gitea)}
+                Add Gitea / Forgejo instance
+            </button>
+            <button
+                type="button"
+                class="flex flex-row items-center text-gray-900 bg-white border border-gray-300 focus:outline-none hover:bg-gray-100 focus:ring-4 focus:ring-gray-100 font-medium rounded-lg text-sm px-3 py-2 dark:bg-gray-800 dark:text-white dark:border-gray-600 dark:hover:bg-gray-700 dark:hover:border-gray-600 dark:focus:ring-gray-700"
+                on:click=move |_| {
+                    // there's only bitbucket cloud
+                    let mut provider = OauthProvider::new(AuthProvider::Bitbucket);
+                    provider.id = String::new();
+                    add_provider(provider);
+                }
+            >
+                {auth_provider_icon(&AuthProvider::Bitbucket)}
+                Add Bitbucket
+            </button>
+        </div>
+    };
+    let save_action = create_action(move |_| async move {
+        update_oauth2(
+            OauthSettings {
+                providers: providers
+                    .get_untracked()
+                    .into_iter()
+                    .map(|(_, p)| p.get_untracked())
+                    .collect(),
+            },
+            update_counter,
+            reload,
+        )
+        .await
+    });
+    view! {
+        <SettingView title="OAuth Provider Settings".to_string() action=save_action body update_counter extra=None />
+    }
+}
+
+#[component]
+fn OauthProviderItem<F>(provider: RwSignal<OauthProvider>, remove: F) -> impl IntoView
+where
+    F: Fn() + 'static,
+{
+    let kind = provider.with_untracked(|p| p.kind.clone());
+    let input = move |label: &'static str,
+                      placeholder: &'static str,
+                      get: fn(&OauthProvider) -> String,
+                      set: fn(&mut OauthProvider, String)| {
+        view! {
+            <div class="mt-2">
+                <span class="block mb-2 text-sm font-medium text-gray-900 dark:text-white">{label}</span>
+                <input
+                    prop:value=move || provider.with(get)
+                    on:input=move |ev| provider.update(|p| set(p, event_target_value(&ev)))
+                    placeholder=placeholder
+                    class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                />
+            </div>
+        }
+    };
+    let base_url_placeholder = match kind {
+        AuthProvider::Gitlab => "https://gitlab.example.com",
+        AuthProvider::Gitea => "https://gitea.example.com",
+        AuthProvider::Bitbucket => OauthProvider::BITBUCKET_URL,
+        _ => "https://github.example.com",
+    };
+    view! {
+        <div class="mt-4 p-4 border rounded-lg dark:border-gray-700">
+            <div class="flex flex-row items-center justify-between text-sm font-medium text-gray-900 dark:text-white">
+                <div class="flex flex-row items-center">
+                    {auth_provider_icon(&kind)}
+                    <span>{move || provider.with(|p| if p.id.is_empty() { "New instance".to_string() } else { p.id.clone() })}</span>
+                </div>
+                <button
+                    type="button"
+                    class="text-red-700 hover:text-white border border-red-700 hover:bg-red-800 focus:ring-4 focus:outline-none focus:ring-red-300 font-medium rounded-lg text-sm px-3 py-1.5 dark:border-red-500 dark:text-red-500 dark:hover:text-white dark:hover:bg-red-600 dark:focus:ring-red-900"
+                    on:click=move |_| remove()
+                >
+                    Remove
+                </button>
+            </div>
+            {input("Name", "Shown on the login page", |p| p.name.clone(), |p, v| p.name = v)}
+            {input("Base URL", base_url_placeholder, |p| p.base_url.clone(), |p, v| p.base_url = v)}
+            {input("Client Id", "", |p| p.client_id.clone(), |p, v| p.client_id = v)}
+            {input("Client Secret", "", |p| p.client_secret.clone(), |p, v| p.client_secret = v)}
+        </div>
+    }
+}
+
+pub fn auth_provider_icon(kind: &AuthProvider) -> View {
+    match kind {
+        AuthProvider::Github => view! {
+            <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="currentColor" viewBox="0 0 20 20">
                     <path fill-rule="evenodd" d="M10 .333A9.911 9.911 0 0 0 6.866 19.65c.5.092.678-.215.678-.477 0-.237-.01-1.017-.014-1.845-2.757.6-3.338-1.169-3.338-1.169a2.627 2.627 0 0 0-1.1-1.451c-.9-.615.07-.6.07-.6a2.084 2.084 0 0 1 1.518 1.021 2.11 2.11 0 0 0 2.884.823c.044-.503.268-.973.63-1.325-2.2-.25-4.516-1.1-4.516-4.9A3.832 3.832 0 0 1 4.7 7.068a3.56 3.56 0 0 1 .095-2.623s.832-.266 2.726 1.016a9.409 9.409 0 0 1 4.962 0c1.89-1.282 2.717-1.016 2.717-1.016.366.83.402 1.768.1 2.623a3.827 3.827 0 0 1 1.02 2.659c0 3.807-2.319 4.644-4.525 4.889a2.366 2.366 0 0 1 .673 1.834c0 1.326-.012 2.394-.012 2.72 0 .263.18.572.681.475A9.911 9.911 0 0 0 10 .333Z" clip-rule="evenodd"/>
                 </svg>
-                <span>GitHub Client Id</span>
+        }
+        .into_view(),
+        AuthProvider::Gitlab => view! {
+            <svg class="w-4 h-4 me-2" viewBox="0 0 25 24" xmlns="http://www.w3.org/2000/svg"><path d="M24.507 9.5l-.034-.09L21.082.562a.896.896 0 00-1.694.091l-2.29 7.01H7.825L5.535.653a.898.898 0 00-1.694-.09L.451 9.411.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 2.56 1.935 1.554 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#E24329"/><path d="M24.507 9.5l-.034-.09a11.44 11.44 0 00-4.56 2.051l-7.447 5.632 4.742 3.584 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#FC6D26"/><path d="M7.707 20.677l2.56 1.935 1.555 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935-4.743-3.584-4.755 3.584z" fill="#FCA326"/><path d="M5.01 11.461a11.43 11.43 0 00-4.56-2.05L.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 4.745-3.584-7.444-5.632z" fill="#FC6D26"/></svg>
+        }
+        .into_view(),
+        AuthProvider::Gitea => view! {
+            <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 24 24">
+                <path stroke="#609926" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M4 7h13v6a5 5 0 0 1-5 5H9a5 5 0 0 1-5-5V7Zm13 2h1.5a2.5 2.5 0 0 1 0 5H16M10 10v4m0 0 2-2m-2 2-2-2"/>
+            </svg>
+        }
+        .into_view(),
+        AuthProvider::Bitbucket => view! {
+            <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24">
+                <path fill="#2684FF" d="M2.65 3a.65.65 0 0 0-.64.75l2.72 16.5c.07.42.43.73.86.74h13.05c.32 0 .59-.23.64-.54l2.71-16.7a.65.65 0 0 0-.64-.75H2.65Zm11.46 11.93H9.94l-1.13-5.9h6.31l-1.01 5.9Z"/>
+            </svg>
+        }
+        .into_view(),
+        AuthProvider::Oidc => view! {
+            <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 24 24">
+                <path stroke="currentColor" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M15 7a2 2 0 1 1 4 0 2 2 0 0 1-4 0Zm-2 0a4 4 0 1 0 8 0 4 4 0 0 0-8 0Zm0 0-9 9v3h3v-2h2v-2h2l2-2"/>
+            </svg>
+        }
+        .into_view(),
+        AuthProvider::Local => view! {
+            <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 24 24">
+                <path stroke="currentColor" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M12 14v3m-3-6V7a3 3 0 1 1 6 0v4m-8 0h10a1 1 0 0 1 1 1v7a1 1 0 0 1-1 1H7a1 1 0 0 1-1-1v-7a1 1 0 0 1 1-1Z"/>
+            </svg>
+        }
+        .into_view(),
+    }
+}
+
+async fn get_oidc() -> Result<OidcSettings> {
+    let resp = Request::get("/api/v1/admin/oidc").send().await?;
+    let settings: OidcSettings = resp.json().await?;
+    Ok(settings)
+}
+
+async fn update_oidc(
+    settings: OidcSettings,
+    update_counter: RwSignal<i32>,
+    reload: bool,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::put("/api/v1/admin/oidc")
+        .json(&settings)?
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    if reload {
+        let _ = location().reload();
+    } else {
+        update_counter.update(|c| *c += 1);
+    }
+    Ok(())
+}
+
+/// One mapping per line, in the form of `group organization-id role`
+fn parse_group_mappings(mappings: &str) -> Result<Vec<OidcGroupMapping>, ErrorResponse> {
+    mappings
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let invalid = || ErrorResponse {
+                error: format!("invalid group mapping \"{line}\""),
+            };
+            let mut parts = line.split_whitespace();
+            let group = parts.next().ok_or_else(invalid)?;
+            let organization = parts
+                .next()
+                .and_then(|id| Uuid::from_str(id).ok())
+                .ok_or_else(invalid)?;
+            let role = parts
+                .next()
+                .map(UserRole::from_str)
+                .unwrap_or(Ok(UserRole::Member))
+                .map_err(|_| invalid())?;
+            if parts.next().is_some() {
+                return Err(invalid());
+            }
+            Ok(OidcGroupMapping {
+                group: group.to_string(),
+                organization,
+                role,
+            })
+        })
+        .collect()
+}
+
+#[component]
+pub fn OidcSettings(reload: bool) -> impl IntoView {
+    let update_counter = create_rw_signal(0);
+    let settings = create_rw_signal(OidcSettings::default());
+    let scopes = create_rw_signal(String::new());
+    let groups_claim = create_rw_signal(String::new());
+    let group_mappings = create_rw_signal(String::new());
+
+    let current = create_local_resource(
+        move || update_counter.get(),
+        move |_| async move { get_oidc().await },
+    );
+    create_effect(move |_| {
+        let current = current.with(|s| s.as_ref().and_then(|s| s.as_ref().ok().cloned()));
+        if let Some(current) = current {
+            scopes.set(current.scopes.join(" "));
+            groups_claim.set(current.groups_claim.clone().unwrap_or_default());
+            group_mappings.set(
+                current
+                    .group_mappings
+                    .iter()
+                    .map(|m| format!("{} {} {}", m.group, m.organization, m.role))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+            settings.set(current);
+        }
+    });
+
+    let input = move |label: &'static str,
+                      get: fn(&OidcSettings) -> String,
+                      set: fn(&mut OidcSettings, String)| {
+        view! {
+            <div class="mt-2">
+                <div class="mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                    <span>{label}</span>
+                </div>
+                <input
+                    prop:value=move || settings.with(get)
+                    on:input=move |ev| settings.update(|s| set(s, event_target_value(&ev)))
+                    class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                />
             </div>
-            <input
-                prop:value={move || github_client_id.get()}
-                on:input=move |ev| { github_client_id.set(event_target_value(&ev)) }
-                class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
-            />
+        }
+    };
+
+    let body = view! {
+        <p class="mt-2 text-sm text-gray-500 dark:text-gray-400">
+            {"Sign in through an OpenID Connect provider, e.g. Keycloak, Okta or Authentik. The redirect url to register at the provider is "}
+            <code>{move || format!("{}/api/private/session/authorize", location().origin().unwrap_or_default())}</code>
+        </p>
+        <div class="mt-2">
+            <label class="inline-flex items-center cursor-pointer">
+                <input type="checkbox" value="" class="sr-only peer"
+                    prop:checked=move || settings.with(|s| s.enabled)
+                    on:change=move |e| settings.update(|s| s.enabled = event_target_checked(&e))
+                />
+                <div class="relative w-11 h-6 bg-gray-200 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-blue-300 dark:peer-focus:ring-blue-800 rounded-full peer dark:bg-gray-700 peer-checked:after:translate-x-full rtl:peer-checked:after:-translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:start-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all dark:border-gray-600 peer-checked:bg-blue-600"></div>
+                <span class="ms-3 text-sm font-medium text-gray-900 dark:text-gray-300">OpenID Connect Login</span>
+            </label>
         </div>
+        {input("Display Name", |s| s.display_name.clone(), |s, v| s.display_name = v)}
+        {input("Issuer Url", |s| s.issuer_url.clone(), |s, v| s.issuer_url = v)}
+        {input("Client Id", |s| s.client_id.clone(), |s, v| s.client_id = v)}
+        {input("Client Secret", |s| s.client_secret.clone(), |s, v| s.client_secret = v)}
         <div class="mt-2">
-            <div class="flex flex-row items-center mb-2 text-sm font-medium text-gray-900 dark:text-white">
-                <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="currentColor" viewBox="0 0 20 20">
-                    <path fill-rule="evenodd" d="M10 .333A9.911 9.911 0 0 0 6.866 19.65c.5.092.678-.215.678-.477 0-.237-.01-1.017-.014-1.845-2.757.6-3.338-1.169-3.338-1.169a2.627 2.627 0 0 0-1.1-1.451c-.9-.615.07-.6.07-.6a2.084 2.084 0 0 1 1.518 1.021 2.11 2.11 0 0 0 2.884.823c.044-.503.268-.973.63-1.325-2.2-.25-4.516-1.1-4.516-4.9A3.832 3.832 0 0 1 4.7 7.068a3.56 3.56 0 0 1 .095-2.623s.832-.266 2.726 1.016a9.409 9.409 0 0 1 4.962 0c1.89-1.282 2.717-1.016 2.717-1.016.366.83.402 1.768.1 2.623a3.827 3.827 0 0 1 1.02 2.659c0 3.807-2.319 4.644-4.525 4.889a2.366 2.366 0 0 1 .673 1.834c0 1.326-.012 2.394-.012 2.72 0 .263.18.572.681.475A9.911 9.911 0 0 0 10 .333Z" clip-rule="evenodd"/>
-                </svg>
-                <span>GitHub Client Secret</span>
+            <div class="mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                <span>Scopes</span>
             </div>
             <input
-                prop:value={move || github_client_secret.get()}
-                on:input=move |ev| { github_client_secret.set(event_target_value(&ev)) }
+                prop:value=move || scopes.get()
+                on:input=move |ev| scopes.set(event_target_value(&ev))
                 class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                placeholder="profile email"
             />
         </div>
+        {input("Login Claim", |s| s.login_claim.clone(), |s, v| s.login_claim = v)}
+        {input("Name Claim", |s| s.name_claim.clone(), |s, v| s.name_claim = v)}
+        {input("Email Claim", |s| s.email_claim.clone(), |s, v| s.email_claim = v)}
+        {input("Avatar Claim", |s| s.avatar_claim.clone(), |s, v| s.avatar_claim = v)}
         <div class="mt-2">
-            <div class="flex flex-row items-center mb-2 text-sm font-medium text-gray-900 dark:text-white">
-                <svg class="w-4 h-4 me-2" viewBox="0 0 25 24" xmlns="http://www.w3.org/2000/svg"><path d="M24.507 9.5l-.034-.09L21.082.562a.896.896 0 00-1.694.091l-2.29 7.01H7.825L5.535.653a.898.898 0 00-1.694-.09L.451 9.411.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 2.56 1.935 1.554 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#E24329"/><path d="M24.507 9.5l-.034-.09a11.44 11.44 0 00-4.56 2.051l-7.447 5.632 4.742 3.584 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#FC6D26"/><path d="M7.707 20.677l2.56 1.935 1.555 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935-4.743-3.584-4.755 3.584z" fill="#FCA326"/><path d="M5.01 11.461a11.43 11.43 0 00-4.56-2.05L.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 4.745-3.584-7.444-5.632z" fill="#FC6D26"/></svg>
-                <span>GitLab Client Id</span>
+            <div class="mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                <span>Groups Claim</span>
             </div>
             <input
-                prop:value={move || gitlab_client_id.get()}
-                on:input=move |ev| { gitlab_client_id.set(event_target_value(&ev)) }
+                prop:value=move || groups_claim.get()
+                on:input=move |ev| groups_claim.set(event_target_value(&ev))
                 class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                placeholder="groups"
             />
         </div>
         <div class="mt-2">
-            <div class="flex flex-row items-center mb-2 text-sm font-medium text-gray-900 dark:text-white">
-                <svg class="w-4 h-4 me-2" viewBox="0 0 25 24" xmlns="http://www.w3.org/2000/svg"><path d="M24.507 9.5l-.034-.09L21.082.562a.896.896 0 00-1.694.091l-2.29 7.01H7.825L5.535.653a.898.898 0 00-1.694-.09L.451 9.411.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 2.56 1.935 1.554 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#E24329"/><path d="M24.507 9.5l-.034-.09a11.44 11.44 0 00-4.56 2.051l-7.447 5.632 4.742 3.584 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#FC6D26"/><path d="M7.707 20.677l2.56 1.935 1.555 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935-4.743-3.584-4.755 3.584z" fill="#FCA326"/><path d="M5.01 11.461a11.43 11.43 0 00-4.56-2.05L.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 4.745-3.584-7.444-5.632z" fill="#FC6D26"/></svg>
-                <span>GitLab Client Secret</span>
+            <div class="mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                <span>Group Mappings</span>
             </div>
-            <input
-                prop:value={move || gitlab_client_secret.get()}
-                on:input=move |ev| { gitlab_client_secret.set(event_target_value(&ev)) }
+            <p class="mb-2 text-sm text-gray-500 dark:text-gray-400">
+                {"One mapping per line in the form of \"group organization-id role\", the role is Admin or Member. The members of the group join the organization on login."}
+            </p>
+            <textarea
+                rows=4
+                prop:value=move || group_mappings.get()
+                on:input=move |ev| group_mappings.set(event_target_value(&ev))
                 class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
             />
         </div>
     };
     let save_action = create_action(move |_| async move {
-        update_oauth2(
-            OauthSettings {
-                github_client_id: github_client_id.get_untracked(),
-                github_client_secret: github_client_secret.get_untracked(),
-                gitlab_client_id: gitlab_client_id.get_untracked(),
-                gitlab_client_secret: gitlab_client_secret.get_untracked(),
-            },
-            update_counter,
-            reload,
-        )
-        .await
+        let mut new_settings = settings.get_untracked();
+        new_settings.scopes = scopes
+            .get_untracked()
+            .split_whitespace()
+            .map(|s| s.to_string())
+            .collect();
+        new_settings.groups_claim =
+            Some(groups_claim.get_untracked().trim().to_string()).filter(|c| !c.is_empty());
+        new_settings.group_mappings = parse_group_mappings(&group_mappings.get_untracked())?;
+        update_oidc(new_settings, update_counter, reload).await
     });
     view! {
-        <SettingView title="OAuth Provider Settings".to_string() action=save_action body update_counter extra=None />
+        <SettingView title="OpenID Connect Settings".to_string() action=save_action body update_counter extra=None />
     }
 }
 
@@ -798,7 +1149,7 @@ pub fn ClusterHostnameSetting() -> impl IntoView {
     }
 }
 
-async fn get_certs() -> Result<Vec<(String, String)>, ErrorResponse> {
+async fn get_certs() -> Result<Vec<CertificateInfo>, ErrorResponse> {
     let resp = Request::get("/api/v1/admin/certs").send().await?;
     if resp.status() != 200 {
         let error = resp
@@ -810,13 +1161,22 @@ async fn get_certs() -> Result<Vec<(String, String)>, ErrorResponse> {
         return Err(error);
     }
 
-    let envs: Vec<(String, String)> = resp.json().await?;
-    Ok(envs)
+    let certs: Vec<CertificateInfo> = resp.json().await?;
+    Ok(certs)
 }
 
-async fn update_certs(certs: Vec<(String, String)>) -> Result<(), ErrorResponse> {
-    let resp = Request::put("/api/v1/admin/certs")
-        .json(&certs)?
+async fn create_cert(cert: RwSignal<String>, key: RwSignal<String>) -> Result<(), ErrorResponse> {
+    let new_cert = NewCertificate {
+        cert: cert.get_untracked().trim().to_string(),
+        key: key.get_untracked().trim().to_string(),
+    };
+    if new_cert.cert.is_empty() || new_cert.key.is_empty() {
+        return Err(ErrorResponse {
+            error: "Certificate chain and private key can't be empty".to_string(),
+        });
+    }
+    let resp = Request::post("/api/v1/admin/certs")
+        .json(&new_cert)?
         .send()
         .await?;
     if resp.status() != 204 {
@@ -828,113 +1188,306 @@ async fn update_certs(certs: Vec<(String, String)>) -> Result<(), ErrorResponse>
             });
         return Err(error);
     }
+    cert.set(String::new());
+    key.set(String::new());
+    Ok(())
+}
 
+async fn delete_cert(
+    fingerprint: String,
+    update_counter: RwSignal<i32>,
+    delete_modal_hidden: RwSignal<bool>,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::delete(&format!("/api/v1/admin/certs/{fingerprint}"))
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    update_counter.update(|c| *c += 1);
+    delete_modal_hidden.set(true);
     Ok(())
 }
 
 #[component]
-pub fn ClusterCertsSetting() -> impl IntoView {
-    let certs = create_rw_signal(vec![]);
-    let new_cert = move |_| {
-        certs.update(|certs| {
-            certs.push((
-                create_rw_signal(String::new()),
-                create_rw_signal(String::new()),
-            ));
-        });
-    };
-    let delete_cert = move |i: usize| {
-        certs.update(|certs| {
-            certs.remove(i);
+fn CertItem(cert: CertificateInfo, update_counter: RwSignal<i32>) -> impl IntoView {
+    let delete_modal_hidden = create_rw_signal(true);
+    let delete_action = {
+        let fingerprint = cert.fingerprint.clone();
+        create_action(move |_| {
+            let fingerprint = fingerprint.clone();
+            async move { delete_cert(fingerprint, update_counter, delete_modal_hidden).await }
         })
     };
+    let names = cert.dns_names.join(", ");
+    view! {
+        <div
+            class="mt-2 border rounded-xl p-4 flex flex-row items-center"
+            class=("border-red-300", cert.expiring)
+            class=("bg-red-50", cert.expiring)
+        >
+            <div class="w-1/2 flex flex-col">
+                <p class="font-medium">{names.clone()}</p>
+                <p class="text-sm text-gray-500">{cert.issuer.clone()}</p>
+                <p class="text-xs text-gray-500 truncate">{format!("SHA-256 {}", cert.fingerprint)}</p>
+            </div>
+            <div class="w-1/3 flex flex-col text-sm">
+                <p><span class="text-gray-500 mr-1">{"Valid from:"}</span>{cert.not_before.format("%Y-%m-%d").to_string()}</p>
+                <p><span class="text-gray-500 mr-1">{"Expires:"}</span>{cert.not_after.format("%Y-%m-%d").to_string()}</p>
+                <p class="text-red-700" class:hidden=!cert.expiring>{"Expiring soon"}</p>
+            </div>
+            <div class="w-1/6 flex flex-row justify-end">
+                {
+                    if cert.acme {
+                        view! { <span class="text-sm text-gray-500">{"ACME"}</span> }.into_view()
+                    } else {
+                        view! {
+                            <button
+                                type="button"
+                                class="py-2 px-4 text-sm font-medium text-red-700 focus:outline-none bg-white rounded-lg border border-gray-200 hover:bg-gray-100"
+                                on:click=move |_| delete_modal_hidden.set(false)
+                            >
+                                Delete
+                            </button>
+                        }.into_view()
+                    }
+                }
+            </div>
+        </div>
+        <DeletionModal resource=names modal_hidden=delete_modal_hidden delete_action />
+    }
+}
 
+#[component]
+pub fn ClusterCertsSetting() -> impl IntoView {
     let update_counter = create_rw_signal(0);
-    let current_certs = create_local_resource(
+    let new_cert = create_rw_signal(String::new());
+    let new_key = create_rw_signal(String::new());
+    let certs = create_local_resource(
         move || update_counter.get(),
         move |_| async move { get_certs().await.unwrap_or_default() },
     );
-    create_effect(move |_| {
-        let current_certs = current_certs.with(|certs| certs.clone().unwrap_or_default());
-        certs.update(|certs| {
-            *certs = current_certs
-                .into_iter()
-                .map(|(name, value)| (create_rw_signal(name), create_rw_signal(value)))
-                .collect();
-        });
-    });
 
-    let save_action = create_action(move |_| async move {
-        let certs = certs.get_untracked();
-        let certs: Vec<(String, String)> = certs
-            .into_iter()
-            .filter_map(|(cert, key)| {
-                let cert = cert.get_untracked();
-                let key = key.get_untracked();
-                if cert.trim().is_empty() || key.trim().is_empty() {
-                    return None;
-                }
-                Some((cert.trim().to_string(), key.trim().to_string()))
-            })
-            .collect();
-        update_certs(certs).await
-    });
+    let save_action = create_action(move |_| async move { create_cert(new_cert, new_key).await });
 
     let body = view! {
         <div class="mt-2">
-        <For
-            each=move || certs.get().into_iter().enumerate()
-            key=|cert| cert.to_owned()
-            children=move |(i, (cert, key))| {
-                view! {
-                    <div
-                        class="flex flex-row items-center"
-                        class=("mt-2", move || i > 0)
-                    >
-                            <div class="w-96">
-                            <textarea
-                                rows=8
-                                prop:value={move || cert.get()}
-                                on:input=move |ev| { cert.set(event_target_value(&ev)); }
-                                class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
-                                placeholder="Certificate Chain"
-                            />
-                        </div>
-                        <div class="ml-2 w-96">
-                            <textarea
-                                rows=8
-                                prop:value={move || key.get()}
-                                on:input=move |ev| { key.set(event_target_value(&ev)); }
-                                class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
-                                placeholder="Private Key"
-                            />
-                        </div>
-                        <div class="ml-2 hover:bg-gray-200 rounded-lg p-2 cursor-pointer"
-                            on:click=move |_| delete_cert(i)
-                        >
-                            <svg class="h-4 text-gray-800 dark:text-white" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 24 24">
-                                <path stroke="currentColor" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M6 18 18 6m0 12L6 6"/>
-                            </svg>
-                        </div>
-                    </div>
+            <For
+                each=move || certs.get().unwrap_or_default()
+                key=|cert| cert.fingerprint.clone()
+                children=move |cert| {
+                    view! { <CertItem cert update_counter /> }
                 }
-            }
-        />
+            />
+        </div>
+        <div class="mt-4 flex flex-row items-center">
+            <div class="w-96">
+                <textarea
+                    rows=8
+                    prop:value={move || new_cert.get()}
+                    on:input=move |ev| { new_cert.set(event_target_value(&ev)); }
+                    class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                    placeholder="Certificate Chain"
+                />
+            </div>
+            <div class="ml-2 w-96">
+                <textarea
+                    rows=8
+                    prop:value={move || new_key.get()}
+                    on:input=move |ev| { new_key.set(event_target_value(&ev)); }
+                    class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                    placeholder="Private Key"
+                />
+            </div>
         </div>
     };
 
-    let extra = view! {
-        <button
-            type="button"
-            class="ml-2 py-2.5 px-5 text-sm font-medium text-gray-900 focus:outline-none bg-white rounded-lg border border-gray-200 hover:bg-gray-100 hover:text-blue-700 focus:z-10 focus:ring-4 focus:ring-gray-100 dark:focus:ring-gray-700 dark:bg-gray-800 dark:text-gray-400 dark:border-gray-600 dark:hover:text-white dark:hover:bg-gray-700"
-            on:click=new_cert
+    view! {
+        <SettingView title="Certificate Settings".to_string() action=save_action body update_counter extra=None />
+    }
+}
+
+/// Shown on top of the cluster settings when a certificate expires soon
+#[component]
+fn CertExpiryWarning() -> impl IntoView {
+    let certs = create_local_resource(
+        || (),
+        |_| async move { get_certs().await.unwrap_or_default() },
+    );
+    let expiring = move || {
+        certs
+            .get()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|c| c.expiring)
+            .collect::<Vec<_>>()
+    };
+    view! {
+        <div
+            class="mb-4 p-4 rounded-lg bg-red-50 dark:bg-gray-800"
+            class:hidden=move || expiring().is_empty()
         >
-            New Certificate
-        </button>
-    }.into_view();
+            <p class="text-sm font-medium text-red-800 dark:text-red-400">
+                {"Certificates expiring soon:"}
+            </p>
+            <For
+                each=expiring
+                key=|cert| cert.fingerprint.clone()
+                children=move |cert| {
+                    view! {
+                        <p class="text-sm text-red-800 dark:text-red-400">
+                            {format!("{} expires at {}", cert.dns_names.join(", "), cert.not_after.format("%Y-%m-%d %H:%M UTC"))}
+                        </p>
+                    }
+                }
+            />
+        </div>
+    }
+}
+
+async fn get_acme() -> Result<AcmeSettings> {
+    let resp = Request::get("/api/v1/admin/acme").send().await?;
+    let settings: AcmeSettings = resp.json().await?;
+    Ok(settings)
+}
+
+async fn update_acme(
+    settings: AcmeSettings,
+    update_counter: RwSignal<i32>,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::put("/api/v1/admin/acme")
+        .json(&settings)?
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    update_counter.update(|c| *c += 1);
+    Ok(())
+}
 
+#[component]
+fn AcmeSetting() -> impl IntoView {
+    let update_counter = create_rw_signal(0);
+    let enabled = create_rw_signal(false);
+    let directory_url = create_rw_signal(String::new());
+    let email = create_rw_signal(String::new());
+    let dns_provider = create_rw_signal(String::new());
+    let dns_value = create_rw_signal(String::new());
+
+    let settings = create_local_resource(
+        move || update_counter.get(),
+        move |_| async move { get_acme().await },
+    );
+    create_effect(move |_| {
+        let settings = settings.with(|s| s.as_ref().and_then(|s| s.as_ref().ok().cloned()));
+        if let Some(settings) = settings {
+            enabled.set(settings.enabled);
+            directory_url.set(settings.directory_url);
+            email.set(settings.email);
+            let (provider, value) = match settings.dns_provider {
+                Some(AcmeDnsProvider::Cloudflare { api_token }) => ("cloudflare", api_token),
+                Some(AcmeDnsProvider::Webhook { url }) => ("webhook", url),
+                None => ("", String::new()),
+            };
+            dns_provider.set(provider.to_string());
+            dns_value.set(value);
+        }
+    });
+
+    let body = view! {
+        <p class="mt-2 text-sm text-gray-500 dark:text-gray-400">
+            {"Issue and renew the certificates of the hostnames automatically. The hostnames are validated over http on port 80, and the wildcard certificates for the workspaces need a DNS provider. Uploaded certificates take precedence over the issued ones."}
+        </p>
+        <div class="mt-2">
+            <label class="inline-flex items-center cursor-pointer">
+                <input type="checkbox" value="" class="sr-only peer"
+                    prop:checked=move || enabled.get()
+                    on:change=move |e| enabled.set(event_target_checked(&e))
+                />
+                <div class="relative w-11 h-6 bg-gray-200 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-blue-300 dark:peer-focus:ring-blue-800 rounded-full peer dark:bg-gray-700 peer-checked:after:translate-x-full rtl:peer-checked:after:-translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:start-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all dark:border-gray-600 peer-checked:bg-blue-600"></div>
+                <span class="ms-3 text-sm font-medium text-gray-900 dark:text-gray-300">Automatic Certificates</span>
+            </label>
+        </div>
+        <div class="mt-2">
+            <div class="mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                <span>ACME Directory Url</span>
+            </div>
+            <input
+                prop:value={move || directory_url.get()}
+                on:input=move |ev| { directory_url.set(event_target_value(&ev)) }
+                class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                placeholder="Let's Encrypt"
+            />
+        </div>
+        <div class="mt-2">
+            <div class="mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                <span>Contact Email</span>
+            </div>
+            <input
+                prop:value={move || email.get()}
+                on:input=move |ev| { email.set(event_target_value(&ev)) }
+                class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+            />
+        </div>
+        <div class="mt-2">
+            <div class="mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                <span>DNS Provider</span>
+            </div>
+            <select
+                class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500"
+                prop:value=move || dns_provider.get()
+                on:change=move |e| dns_provider.set(event_target_value(&e))
+            >
+                <option value="">{"None"}</option>
+                <option value="cloudflare">{"Cloudflare"}</option>
+                <option value="webhook">{"Webhook"}</option>
+            </select>
+        </div>
+        <div class="mt-2" class:hidden=move || dns_provider.with(|p| p.is_empty())>
+            <div class="mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                <span>{move || if dns_provider.get() == "cloudflare" { "Cloudflare API Token" } else { "Webhook Url" }}</span>
+            </div>
+            <input
+                prop:value={move || dns_value.get()}
+                on:input=move |ev| { dns_value.set(event_target_value(&ev)) }
+                class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                placeholder=move || if dns_provider.get() == "cloudflare" { "Leave empty to keep the current token" } else { "" }
+            />
+        </div>
+    };
+    let save_action = create_action(move |_| async move {
+        let value = dns_value.get_untracked().trim().to_string();
+        let dns_provider = match dns_provider.get_untracked().as_str() {
+            "cloudflare" => Some(AcmeDnsProvider::Cloudflare { api_token: value }),
+            "webhook" => Some(AcmeDnsProvider::Webhook { url: value }),
+            _ => None,
+        };
+        update_acme(
+            AcmeSettings {
+                enabled: enabled.get_untracked(),
+                directory_url: directory_url.get_untracked().trim().to_string(),
+                email: email.get_untracked().trim().to_string(),
+                dns_provider,
+            },
+            update_counter,
+        )
+        .await
+    });
     view! {
-        <SettingView title="Certificate Settings".to_string() action=save_action body update_counter extra=Some(extra) />
+        <SettingView title="Automatic Certificates".to_string() action=save_action body update_counter extra=None />
     }
 }
 
@@ -1409,6 +1962,160 @@ async fn update_cluster_user(
     Ok(())
 }
 
+async fn create_local_user(
+    new_user: NewLocalUser,
+    setup_token: RwSignal<Option<LocalSetupToken>>,
+    search_action: Action<(), Result<ClusterUserResult, ErrorResponse>>,
+    modal_hidden: RwSignal<bool>,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::post("/api/v1/admin/local_users")
+        .json(&new_user)?
+        .send()
+        .await?;
+    if resp.status() != 200 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    let token: Option<LocalSetupToken> = resp.json().await?;
+    setup_token.set(token);
+    search_action.dispatch(());
+    modal_hidden.set(true);
+    Ok(())
+}
+
+async fn reset_local_password(
+    id: Uuid,
+    reset_totp: bool,
+    setup_token: RwSignal<Option<LocalSetupToken>>,
+    modal_hidden: RwSignal<bool>,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::post(&format!("/api/v1/admin/users/{id}/password_reset"))
+        .json(&LocalPasswordReset { reset_totp })?
+        .send()
+        .await?;
+    if resp.status() != 200 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    let token: LocalSetupToken = resp.json().await?;
+    setup_token.set(Some(token));
+    modal_hidden.set(true);
+    Ok(())
+}
+
+/// The link for a local user to set their password, which the admin passes on to them
+#[component]
+fn SetupLinkView(setup_token: RwSignal<Option<LocalSetupToken>>) -> impl IntoView {
+    move || {
+        if let Some(token) = setup_token.get() {
+            let link = format!(
+                "{}/setup_password/{}",
+                location().origin().unwrap_or_default(),
+                token.token
+            );
+            view! {
+                <div class="my-4 p-4 rounded-lg bg-green-50 dark:bg-gray-800">
+                    <p class="text-sm font-medium text-green-800 dark:text-green-400">
+                        {format!("Send this link to the user to set their password. It expires at {}.", token.expires_at.to_rfc2822())}
+                    </p>
+                    <p class="mt-2 text-sm font-mono break-all text-gray-900 dark:text-white">{link}</p>
+                    <button
+                        type="button"
+                        class="mt-2 text-sm text-gray-500 hover:underline"
+                        on:click=move |_| setup_token.set(None)
+                    >Dismiss</button>
+                </div>
+            }
+            .into_view()
+        } else {
+            view! {}.into_view()
+        }
+    }
+}
+
+#[component]
+fn NewLocalUserView(
+    setup_token: RwSignal<Option<LocalSetupToken>>,
+    search_action: Action<(), Result<ClusterUserResult, ErrorResponse>>,
+) -> impl IntoView {
+    let modal_hidden = create_rw_signal(true);
+    let login = create_rw_signal(String::new());
+    let name = create_rw_signal(String::new());
+    let email = create_rw_signal(String::new());
+    let password = create_rw_signal(String::new());
+    let cluster_admin = create_rw_signal(false);
+
+    let action = create_action(move |_| async move {
+        let password = password.get_untracked();
+        create_local_user(
+            NewLocalUser {
+                login: login.get_untracked(),
+                name: Some(name.get_untracked()),
+                email: Some(email.get_untracked()),
+                cluster_admin: cluster_admin.get_untracked(),
+                password: if password.is_empty() {
+                    None
+                } else {
+                    Some(password)
+                },
+            },
+            setup_token,
+            search_action,
+            modal_hidden,
+        )
+        .await
+    });
+
+    let body = view! {
+        <CreationInput label="Login".to_string() value=login placeholder="".to_string() />
+        <CreationInput label="Name".to_string() value=name placeholder="".to_string() />
+        <CreationInput label="Email".to_string() value=email placeholder="".to_string() />
+        <div>
+            <label class="block mb-2 text-sm font-medium text-gray-900 dark:text-white">Password</label>
+            <input
+                type="password"
+                prop:value={move || password.get()}
+                on:input=move |ev| { password.set(event_target_value(&ev)); }
+                class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                placeholder="leave empty to send the user a setup link instead"
+            />
+        </div>
+        <div class="flex items-center">
+            <input
+                id="new-local-user-cluster-admin"
+                type="checkbox"
+                class="w-4 h-4 border border-gray-300 rounded bg-gray-50 focus:ring-3 focus:ring-blue-300 dark:bg-gray-700 dark:border-gray-600 dark:focus:ring-blue-600 dark:ring-offset-gray-800"
+                prop:checked=move || cluster_admin.get()
+                on:change=move |e| cluster_admin.set(event_target_checked(&e))
+            />
+            <label for="new-local-user-cluster-admin" class="ml-2 text-sm font-medium text-gray-900 dark:text-gray-300">
+                Is Cluster Admin
+            </label>
+        </div>
+    };
+
+    view! {
+        <button
+            type="button"
+            class="px-4 py-2 text-sm font-medium text-white rounded-lg bg-green-700 hover:bg-green-800 focus:ring-4 focus:ring-green-300 dark:bg-green-600 dark:hover:bg-green-700 focus:outline-none dark:focus:ring-green-800"
+            on:click=move |_| modal_hidden.set(false)
+        >
+            New Local User
+        </button>
+        <CreationModal title="New Local User".to_string() modal_hidden action body is_update=false create_button_hidden=false />
+    }
+}
+
 #[component]
 pub fn ClusterUsersView() -> impl IntoView {
     let page_size = create_rw_signal(String::new());
@@ -1467,6 +2174,8 @@ pub fn ClusterUsersView() -> impl IntoView {
         search_action.dispatch(());
     };
 
+    let setup_token = create_rw_signal(None);
+
     search_action.dispatch(());
     view! {
         <div class="pb-4">
@@ -1501,9 +2210,12 @@ pub fn ClusterUsersView() -> impl IntoView {
                 >
                     Search User
                 </button>
+                <NewLocalUserView setup_token search_action />
             </div>
         </div>
 
+        <SetupLinkView setup_token />
+
         { move || if let Some(error) = error.get() {
             view! {
                 <div class="my-4 p-4 rounded-lg bg-red-50 dark:bg-gray-800 ">
@@ -1573,7 +2285,7 @@ pub fn ClusterUsersView() -> impl IntoView {
             key=|(_, u)| (u.id, u.cluster_admin)
             children=move |(i, user)| {
                 view! {
-                    <ClusterUserItemView i user search_action />
+                    <ClusterUserItemView i user search_action setup_token />
                 }
             }
         />
@@ -1585,10 +2297,45 @@ fn ClusterUserItemView(
     i: usize,
     user: ClusterUser,
     search_action: Action<(), Result<ClusterUserResult, ErrorResponse>>,
+    setup_token: RwSignal<Option<LocalSetupToken>>,
 ) -> impl IntoView {
     let user_id = user.id;
     let update_modal_hidden = create_rw_signal(true);
     let is_cluster_admin = create_rw_signal(user.cluster_admin);
+    let is_local = user.auth_provider == AuthProvider::Local;
+    let reset_modal_hidden = create_rw_signal(true);
+    let reset_totp = create_rw_signal(false);
+
+    let reset_modal_body = view! {
+        <p class="text-sm text-gray-700 dark:text-gray-400">
+            {"The current password of the user stops working, and you'll get a link for the user to set a new one."}
+        </p>
+        <div class="flex items-center">
+            <input
+                id={format!("{user_id}-reset-totp")}
+                type="checkbox"
+                class="w-4 h-4 border border-gray-300 rounded bg-gray-50 focus:ring-3 focus:ring-blue-300 dark:bg-gray-700 dark:border-gray-600 dark:focus:ring-blue-600 dark:ring-offset-gray-800"
+                prop:checked=move || reset_totp.get()
+                on:change=move |e| reset_totp.set(event_target_checked(&e))
+            />
+            <label
+                for={format!("{user_id}-reset-totp")}
+                class="ml-2 text-sm font-medium text-gray-900 dark:text-gray-300"
+            >
+                Also turn off two-factor authentication
+            </label>
+        </div>
+    };
+
+    let reset_action = create_action(move |()| async move {
+        reset_local_password(
+            user_id,
+            reset_totp.get_untracked(),
+            setup_token,
+            reset_modal_hidden,
+        )
+        .await
+    });
 
     let update_modal_body = view! {
         <div
@@ -1623,16 +2370,7 @@ fn ClusterUserItemView(
         .await
     });
 
-    let icon = match user.auth_provider {
-        AuthProvider::Github => view! {
-            <svg class="w-4 h-4 me-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="currentColor" viewBox="0 0 20 20">
-                <path fill-rule="evenodd" d="M10 .333A9.911 9.911 0 0 0 6.866 19.65c.5.092.678-.215.678-.477 0-.237-.01-1.017-.014-1.845-2.757.6-3.338-1.169-3.338-1.169a2.627 2.627 0 0 0-1.1-1.451c-.9-.615.07-.6.07-.6a2.084 2.084 0 0 1 1.518 1.021 2.11 2.11 0 0 0 2.884.823c.044-.503.268-.973.63-1.325-2.2-.25-4.516-1.1-4.516-4.9A3.832 3.832 0 0 1 4.7 7.068a3.56 3.56 0 0 1 .095-2.623s.832-.266 2.726 1.016a9.409 9.409 0 0 1 4.962 0c1.89-1.282 2.717-1.016 2.717-1.016.366.83.402 1.768.1 2.623a3.827 3.827 0 0 1 1.02 2.659c0 3.807-2.319 4.644-4.525 4.889a2.366 2.366 0 0 1 .673 1.834c0 1.326-.012 2.394-.012 2.72 0 .263.18.572.681.475A9.911 9.911 0 0 0 10 .333Z" clip-rule="evenodd"/>
-            </svg>
-        },
-        AuthProvider::Gitlab => view! {
-            <svg class="w-4 h-4 me-2" viewBox="0 0 25 24" xmlns="http://www.w3.org/2000/svg"><path d="M24.507 9.5l-.034-.09L21.082.562a.896.896 0 00-1.694.091l-2.29 7.01H7.825L5.535.653a.898.898 0 00-1.694-.09L.451 9.411.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 2.56 1.935 1.554 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#E24329"/><path d="M24.507 9.5l-.034-.09a11.44 11.44 0 00-4.56 2.051l-7.447 5.632 4.742 3.584 5.197-3.89.014-.01A6.297 6.297 0 0024.507 9.5z" fill="#FC6D26"/><path d="M7.707 20.677l2.56 1.935 1.555 1.176a1.051 1.051 0 001.268 0l1.555-1.176 2.56-1.935-4.743-3.584-4.755 3.584z" fill="#FCA326"/><path d="M5.01 11.461a11.43 11.43 0 00-4.56-2.05L.416 9.5a6.297 6.297 0 002.09 7.278l.012.01.03.022 5.16 3.867 4.745-3.584-7.444-5.632z" fill="#FC6D26"/></svg>
-        },
-    };
+    let icon = auth_provider_icon(&user.auth_provider);
     view! {
         <div
             class="flex items-center w-full px-4 py-2"
@@ -1660,14 +2398,24 @@ fn ClusterUserItemView(
             </div>
             <div class="w-1/4 flex flex-row items-center justify-between">
                 <p>{user.cluster_admin}</p>
-                <button
-                    class="px-4 py-2 text-sm font-medium text-white rounded-lg bg-green-700 hover:bg-green-800 focus:ring-4 focus:ring-green-300 dark:bg-green-600 dark:hover:bg-green-700 focus:outline-none dark:focus:ring-green-800"
-                    on:click=move |_| update_modal_hidden.set(false)
-                >
-                    Update
-                </button>
+                <div class="flex flex-row items-center">
+                    <button
+                        class="mr-2 px-4 py-2 text-sm font-medium text-gray-900 bg-white border border-gray-200 rounded-lg hover:bg-gray-100 focus:ring-4 focus:ring-gray-100 dark:bg-gray-800 dark:text-white dark:border-gray-600 dark:hover:bg-gray-700 focus:outline-none"
+                        class:hidden=move || !is_local
+                        on:click=move |_| reset_modal_hidden.set(false)
+                    >
+                        Reset Password
+                    </button>
+                    <button
+                        class="px-4 py-2 text-sm font-medium text-white rounded-lg bg-green-700 hover:bg-green-800 focus:ring-4 focus:ring-green-300 dark:bg-green-600 dark:hover:bg-green-700 focus:outline-none dark:focus:ring-green-800"
+                        on:click=move |_| update_modal_hidden.set(false)
+                    >
+                        Update
+                    </button>
+                </div>
             </div>
         </div>
         <CreationModal title=format!("Update Cluster User") modal_hidden=update_modal_hidden action=update_action body=update_modal_body is_update=true create_button_hidden=false />
+        <CreationModal title=format!("Reset Password") modal_hidden=reset_modal_hidden action=reset_action body=reset_modal_body is_update=true create_button_hidden=false />
     }
 }
@@ -0,0 +1,170 @@
+use anyhow::Result;
+use gloo_net::http::Request;
+use lapdev_common::{GitToken, NewGitToken};
+use leptos::{
+    component, create_action, create_local_resource, create_rw_signal, event_target_value, view,
+    For, IntoView, RwSignal, SignalGet, SignalGetUntracked, SignalSet, SignalUpdate,
+};
+
+use crate::modal::{CreationInput, CreationModal, DeletionModal, ErrorResponse};
+
+async fn delete_git_token(
+    id: String,
+    delete_modal_hidden: RwSignal<bool>,
+    update_counter: RwSignal<i32>,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::delete(&format!("/api/v1/account/git_tokens/{id}"))
+        .send()
+        .await?;
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    delete_modal_hidden.set(true);
+    update_counter.update(|c| *c += 1);
+    Ok(())
+}
+
+#[component]
+pub fn GitTokenItem(token: GitToken, update_counter: RwSignal<i32>) -> impl IntoView {
+    let id = token.id;
+    let host = token.host.clone();
+    let delete_modal_hidden = create_rw_signal(true);
+    let delete_action = create_action(move |_| {
+        delete_git_token(id.to_string(), delete_modal_hidden, update_counter)
+    });
+
+    view! {
+        <div class="flex flex-row items-center border rounded-xl px-4 py-2">
+            <span class="w-2/6 truncate">{token.host}</span>
+            <span class="w-2/6 truncate p-2">{token.username}</span>
+            <span class="w-1/6 truncate p-2">{token.created_at.to_rfc2822()}</span>
+            <div class="w-1/6 flex justify-end items-center">
+                <button class="px-4 py-2 text-sm text-white rounded-lg bg-red-700 hover:bg-red-800 focus:ring-4 focus:ring-red-300 dark:bg-red-600 dark:hover:bg-red-700 focus:outline-none dark:focus:ring-red-800"
+                    on:click=move |_| delete_modal_hidden.set(false)
+                >Delete</button>
+            </div>
+            <DeletionModal resource=host modal_hidden=delete_modal_hidden delete_action=delete_action />
+        </div>
+    }
+}
+
+async fn all_git_tokens() -> Result<Vec<GitToken>> {
+    let resp = Request::get("/api/v1/account/git_tokens").send().await?;
+    let tokens: Vec<GitToken> = resp.json().await?;
+    Ok(tokens)
+}
+
+#[component]
+pub fn GitTokens() -> impl IntoView {
+    let update_counter = create_rw_signal(0);
+    let tokens = create_local_resource(
+        move || update_counter.get(),
+        |_| async move { all_git_tokens().await.unwrap_or_default() },
+    );
+
+    view! {
+        <section class="w-full h-full flex flex-col">
+            <div class="border-b pb-4">
+                <div class="flex items-end justify-between">
+                    <div class="min-w-0 mr-4">
+                        <h5 class="mr-3 text-2xl font-semibold dark:text-white">
+                            Git Tokens
+                        </h5>
+                        <p class="text-gray-700 dark:text-gray-400">{"Manage your access tokens for git hosts. The tokens are used to clone private repositories from the hosts you didn't sign in with."}</p>
+                    </div>
+                    <NewGitToken update_counter />
+                </div>
+            </div>
+            <div class="relative w-full basis-0 grow">
+                <div class="absolute w-full h-full flex flex-col py-4 space-y-4 overflow-y-auto">
+                    <For
+                        each=move || tokens.get().unwrap_or_default()
+                        key=|token| token.id
+                        children=move |token| {
+                            view! {
+                                <GitTokenItem token update_counter />
+                            }
+                        }
+                    />
+                </div>
+            </div>
+        </section>
+    }
+}
+
+async fn create_git_token(
+    host: RwSignal<String>,
+    username: RwSignal<String>,
+    token: RwSignal<String>,
+    modal_hidden: RwSignal<bool>,
+    update_counter: RwSignal<i32>,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::post("/api/v1/account/git_tokens")
+        .json(&NewGitToken {
+            host: host.get_untracked(),
+            username: username.get_untracked(),
+            token: token.get_untracked(),
+        })?
+        .send()
+        .await?;
+    if resp.status() != 200 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+
+    modal_hidden.set(true);
+    update_counter.update(|c| *c += 1);
+    host.set(String::new());
+    username.set(String::new());
+    token.set(String::new());
+
+    Ok(())
+}
+
+#[component]
+pub fn NewGitToken(update_counter: RwSignal<i32>) -> impl IntoView {
+    let host = create_rw_signal(String::new());
+    let username = create_rw_signal(String::new());
+    let token = create_rw_signal(String::new());
+
+    let modal_hidden = create_rw_signal(true);
+    let action = create_action(move |_| {
+        create_git_token(host, username, token, modal_hidden, update_counter)
+    });
+
+    let body = view! {
+        <CreationInput label="Host".to_string() value=host placeholder="git.example.com".to_string() />
+        <CreationInput label="Username".to_string() value=username placeholder="".to_string() />
+        <div>
+            <label class="block mb-2 text-sm font-medium text-gray-900 dark:text-white">Token</label>
+            <input
+                type="password"
+                prop:value={move || token.get()}
+                on:input=move |ev| { token.set(event_target_value(&ev)); }
+                class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+            />
+        </div>
+    };
+
+    view! {
+        <button
+            type="button"
+            class="flex items-center justify-center whitespace-nowrap px-4 py-2 text-sm font-medium text-white rounded-lg bg-blue-700 hover:bg-blue-800 focus:ring-4 focus:ring-blue-300 dark:bg-blue-600 dark:hover:bg-blue-700 focus:outline-none dark:focus:ring-blue-800"
+            on:click=move |_| modal_hidden.set(false)
+        >
+            New Git Token
+        </button>
+        <CreationModal title="New Git Token".to_string() modal_hidden action body is_update=false create_button_hidden=false />
+    }
+}
@@ -3,13 +3,16 @@ mod app;
 mod audit_log;
 mod cluster;
 mod datepicker;
+mod git_token;
 mod license;
 mod modal;
 mod nav;
 mod organization;
 mod project;
 mod quota;
+mod ssh_access;
 mod ssh_key;
+mod terminal;
 mod usage;
 mod workspace;
 pub use app::App;
@@ -200,6 +200,14 @@ pub fn SideNav(new_org_modal_hidden: RwSignal<bool>) -> impl IntoView {
                         </a>
                     </li>
 
+                    <li
+                        class:hidden=move || !nav_expanded.account.get()
+                    >
+                        <a href="/account/git-tokens" class="flex items-center p-2 text-base font-normal text-gray-900 rounded-lg transition duration-75 hover:bg-gray-100 dark:hover:bg-gray-700 dark:text-white group">
+                            <span class="ml-8">Git Tokens</span>
+                        </a>
+                    </li>
+
                     <li
                         class:hidden=move || {
                             let role = role.get();
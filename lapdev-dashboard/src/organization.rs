@@ -4,8 +4,9 @@ use anyhow::{anyhow, Result};
 use gloo_net::http::Request;
 use lapdev_common::{
     console::{MeUser, Organization, OrganizationMember},
-    ClusterInfo, NewOrganization, UpdateOrganizationAutoStartStop, UpdateOrganizationMember,
-    UpdateOrganizationName, UserRole,
+    ClusterInfo, NewOrganization, UpdateOrganizationActivityPorts, UpdateOrganizationAutoStartStop,
+    UpdateOrganizationMember, UpdateOrganizationName, UpdateOrganizationSshAgentForwarding,
+    UpdateOrganizationSshSessionRecording, UserRole,
 };
 use leptos::{
     component, create_action, create_effect, create_local_resource, create_rw_signal, document,
@@ -264,12 +265,27 @@ pub fn OrgSettings() -> impl IntoView {
             <div class="w-full p-8 border rounded-xl">
                 <UpdateNameView />
             </div>
+            <div class="mt-4 w-full p-8 border rounded-xl">
+                <SshAgentForwardingView />
+            </div>
             <div
                 class="mt-4 w-full p-8 border rounded-xl"
                 class:hidden=move || !cluster_info.with(|i| i.as_ref().map(|i| i.has_enterprise)).unwrap_or(false)
             >
                 <AutoStartStopView />
             </div>
+            <div
+                class="mt-4 w-full p-8 border rounded-xl"
+                class:hidden=move || !cluster_info.with(|i| i.as_ref().map(|i| i.has_enterprise)).unwrap_or(false)
+            >
+                <ActivityPortsView />
+            </div>
+            <div
+                class="mt-4 w-full p-8 border rounded-xl"
+                class:hidden=move || !cluster_info.with(|i| i.as_ref().map(|i| i.has_enterprise)).unwrap_or(false)
+            >
+                <SshSessionRecordingView />
+            </div>
 
             <div
                 class="mt-4 w-full p-8 border rounded-xl"
@@ -583,6 +599,240 @@ fn AutoStartStopView() -> impl IntoView {
     }
 }
 
+async fn update_ssh_session_recording(
+    id: Uuid,
+    ssh_session_recording: bool,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::put(&format!("/api/v1/organizations/{id}/ssh_session_recording"))
+        .json(&UpdateOrganizationSshSessionRecording {
+            ssh_session_recording,
+        })?
+        .send()
+        .await?;
+
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[component]
+fn SshSessionRecordingView() -> impl IntoView {
+    let org = use_context::<Signal<Option<Organization>>>().unwrap();
+    let login_counter = expect_context::<RwSignal<i32>>();
+
+    let recording_enabled = create_rw_signal(false);
+    create_effect(move |_| {
+        if let Some(enabled) = org.with(|o| o.as_ref().map(|o| o.ssh_session_recording)) {
+            recording_enabled.set(enabled);
+        }
+    });
+
+    let save_action = create_action(move |_| async move {
+        if let Some(id) = org.with(|o| o.as_ref().map(|o| o.id)) {
+            update_ssh_session_recording(id, recording_enabled.get_untracked()).await
+        } else {
+            Err(ErrorResponse {
+                error: "Organization not loaded yet".to_string(),
+            })
+        }
+    });
+
+    let body = view! {
+        <p class="mt-2 text-sm text-gray-500 dark:text-gray-400">
+            {"Interactive SSH sessions will be recorded, and can be replayed from the audit log."}
+        </p>
+        <div class="mt-2">
+            <label class="inline-flex items-center cursor-pointer">
+                <input type="checkbox" value="" class="sr-only peer"
+                    prop:checked=move || recording_enabled.get()
+                    on:change=move |e| recording_enabled.set(event_target_checked(&e))
+                />
+                <div class="relative w-11 h-6 bg-gray-200 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-blue-300 dark:peer-focus:ring-blue-800 rounded-full peer dark:bg-gray-700 peer-checked:after:translate-x-full rtl:peer-checked:after:-translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:start-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all dark:border-gray-600 peer-checked:bg-blue-600"></div>
+                <span class="ms-3 text-sm font-medium text-gray-900 dark:text-gray-300">Record SSH Sessions</span>
+            </label>
+        </div>
+    };
+
+    view! {
+        <SettingView title="SSH Session Recording".to_string() action=save_action body update_counter=login_counter extra=None />
+    }
+}
+
+async fn update_ssh_agent_forwarding(
+    id: Uuid,
+    allow_ssh_agent_forwarding: bool,
+) -> Result<(), ErrorResponse> {
+    let resp = Request::put(&format!("/api/v1/organizations/{id}/ssh_agent_forwarding"))
+        .json(&UpdateOrganizationSshAgentForwarding {
+            allow_ssh_agent_forwarding,
+        })?
+        .send()
+        .await?;
+
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[component]
+fn SshAgentForwardingView() -> impl IntoView {
+    let org = use_context::<Signal<Option<Organization>>>().unwrap();
+    let login_counter = expect_context::<RwSignal<i32>>();
+
+    let forwarding_enabled = create_rw_signal(true);
+    create_effect(move |_| {
+        if let Some(enabled) = org.with(|o| o.as_ref().map(|o| o.allow_ssh_agent_forwarding)) {
+            forwarding_enabled.set(enabled);
+        }
+    });
+
+    let save_action = create_action(move |_| async move {
+        if let Some(id) = org.with(|o| o.as_ref().map(|o| o.id)) {
+            update_ssh_agent_forwarding(id, forwarding_enabled.get_untracked()).await
+        } else {
+            Err(ErrorResponse {
+                error: "Organization not loaded yet".to_string(),
+            })
+        }
+    });
+
+    let body = view! {
+        <p class="mt-2 text-sm text-gray-500 dark:text-gray-400">
+            {"Members can use their local SSH agent inside workspaces with ssh -A."}
+        </p>
+        <div class="mt-2">
+            <label class="inline-flex items-center cursor-pointer">
+                <input type="checkbox" value="" class="sr-only peer"
+                    prop:checked=move || forwarding_enabled.get()
+                    on:change=move |e| forwarding_enabled.set(event_target_checked(&e))
+                />
+                <div class="relative w-11 h-6 bg-gray-200 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-blue-300 dark:peer-focus:ring-blue-800 rounded-full peer dark:bg-gray-700 peer-checked:after:translate-x-full rtl:peer-checked:after:-translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:start-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all dark:border-gray-600 peer-checked:bg-blue-600"></div>
+                <span class="ms-3 text-sm font-medium text-gray-900 dark:text-gray-300">Allow SSH Agent Forwarding</span>
+            </label>
+        </div>
+    };
+
+    view! {
+        <SettingView title="SSH Agent Forwarding".to_string() action=save_action body update_counter=login_counter extra=None />
+    }
+}
+
+async fn update_activity_ports(
+    id: Uuid,
+    all_ports: bool,
+    ports: String,
+) -> Result<(), ErrorResponse> {
+    let activity_ports = if all_ports {
+        None
+    } else {
+        let ports = ports
+            .split(',')
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                p.parse::<u16>().map_err(|_| ErrorResponse {
+                    error: format!("{p} isn't a valid port"),
+                })
+            })
+            .collect::<Result<Vec<u16>, ErrorResponse>>()?;
+        Some(ports)
+    };
+    let resp = Request::put(&format!("/api/v1/organizations/{id}/activity_ports"))
+        .json(&UpdateOrganizationActivityPorts { activity_ports })?
+        .send()
+        .await?;
+
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[component]
+fn ActivityPortsView() -> impl IntoView {
+    let org = use_context::<Signal<Option<Organization>>>().unwrap();
+    let login_counter = expect_context::<RwSignal<i32>>();
+
+    let all_ports = create_rw_signal(true);
+    let ports = create_rw_signal(String::new());
+    create_effect(move |_| {
+        if let Some(activity_ports) = org.with(|o| o.as_ref().map(|o| o.activity_ports.clone())) {
+            all_ports.set(activity_ports.is_none());
+            ports.set(
+                activity_ports
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        }
+    });
+
+    let save_action = create_action(move |_| async move {
+        if let Some(id) = org.with(|o| o.as_ref().map(|o| o.id)) {
+            update_activity_ports(id, all_ports.get_untracked(), ports.get_untracked()).await
+        } else {
+            Err(ErrorResponse {
+                error: "Organization not loaded yet".to_string(),
+            })
+        }
+    });
+
+    let body = view! {
+        <p class="mt-2 text-sm text-gray-500 dark:text-gray-400">
+            {"Browser traffic to the workspace IDE always keeps the workspace from being auto stopped. Choose which forwarded ports count as activity as well."}
+        </p>
+        <div class="mt-2">
+            <label class="inline-flex items-center cursor-pointer">
+                <input type="checkbox" value="" class="sr-only peer"
+                    prop:checked=move || all_ports.get()
+                    on:change=move |e| all_ports.set(event_target_checked(&e))
+                />
+                <div class="relative w-11 h-6 bg-gray-200 peer-focus:outline-none peer-focus:ring-4 peer-focus:ring-blue-300 dark:peer-focus:ring-blue-800 rounded-full peer dark:bg-gray-700 peer-checked:after:translate-x-full rtl:peer-checked:after:-translate-x-full peer-checked:after:border-white after:content-[''] after:absolute after:top-[2px] after:start-[2px] after:bg-white after:border-gray-300 after:border after:rounded-full after:h-5 after:w-5 after:transition-all dark:border-gray-600 peer-checked:bg-blue-600"></div>
+                <span class="ms-3 text-sm font-medium text-gray-900 dark:text-gray-300">All Ports Count as Activity</span>
+            </label>
+        </div>
+        <div class="mt-2" class:hidden=move || all_ports.get()>
+            <input
+                prop:value={move || ports.get()}
+                on:input=move |ev| {
+                    ports.set(event_target_value(&ev));
+                }
+                placeholder="Ports separated by commas, e.g. 3000, 8080"
+                class="max-w-96 bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+            />
+        </div>
+    };
+
+    view! {
+        <SettingView title="Workspace Activity".to_string() action=save_action body update_counter=login_counter extra=None />
+    }
+}
+
 #[component]
 fn UpdateNameView() -> impl IntoView {
     let org = use_context::<Signal<Option<Organization>>>().unwrap();
@@ -623,7 +873,7 @@ fn UpdateNameView() -> impl IntoView {
     }
 }
 
-async fn get_org_members() -> Result<Vec<OrganizationMember>> {
+pub async fn get_org_members() -> Result<Vec<OrganizationMember>> {
     let org =
         use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
     let org = org
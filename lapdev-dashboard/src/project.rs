@@ -3,8 +3,9 @@ use std::{collections::HashMap, str::FromStr, time::Duration};
 use anyhow::{anyhow, Result};
 use gloo_net::http::Request;
 use lapdev_common::{
-    console::Organization, ClusterInfo, GitBranch, NewProject, NewProjectPrebuild,
-    NewProjectResponse, PrebuildStatus, ProjectInfo, ProjectPrebuild,
+    console::Organization, ClusterInfo, DeployKey, GitBranch, NewProject, NewProjectPrebuild,
+    NewProjectResponse, PrebuildStatus, ProjectGitCredential, ProjectGitCredentialInfo,
+    ProjectInfo, ProjectPrebuild,
 };
 use leptos::{
     component, create_action, create_effect, create_local_resource, create_rw_signal, document,
@@ -24,7 +25,11 @@ use crate::{
     workspace::{repo_img, CreateWorkspaceProjectInfo, NewWorkspaceModal},
 };
 
-async fn create_project(repo: String, machine_type: Option<Uuid>) -> Result<(), ErrorResponse> {
+async fn create_project(
+    repo: String,
+    machine_type: Option<Uuid>,
+    deploy_key: Option<Uuid>,
+) -> Result<(), ErrorResponse> {
     let current_org =
         use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
     let org = current_org
@@ -42,6 +47,7 @@ async fn create_project(repo: String, machine_type: Option<Uuid>) -> Result<(),
         .json(&NewProject {
             repo,
             machine_type_id,
+            deploy_key,
         })?
         .send()
         .await?;
@@ -61,15 +67,106 @@ async fn create_project(repo: String, machine_type: Option<Uuid>) -> Result<(),
     Ok(())
 }
 
+async fn new_deploy_key(deploy_key: RwSignal<Option<DeployKey>>) -> Result<(), ErrorResponse> {
+    let current_org =
+        use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
+    let org = current_org
+        .get_untracked()
+        .ok_or_else(|| anyhow!("can't get org"))?;
+    let resp = Request::post(&format!("/api/v1/organizations/{}/deploy_keys", org.id))
+        .send()
+        .await?;
+    if resp.status() != 200 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    deploy_key.set(Some(resp.json().await?));
+    Ok(())
+}
+
+/// If the repo is an ssh url or in the `git@host:path` form
+fn is_ssh_repo(repo: &str) -> bool {
+    let repo = repo.trim();
+    repo.starts_with("ssh://")
+        || (!repo.contains("://") && repo.contains('@') && repo.contains(':'))
+}
+
+#[component]
+fn DeployKeyInfoView(deploy_key: DeployKey) -> impl IntoView {
+    view! {
+        <p class="mb-2 text-sm text-gray-700 dark:text-gray-400">
+            {"Add this public key as a read-only deploy key of the repository on your git host."}
+        </p>
+        <p class="p-2 text-sm font-mono break-all rounded-lg bg-gray-50 border border-gray-300 text-gray-900 dark:bg-gray-600 dark:border-gray-500 dark:text-white">{deploy_key.public_key}</p>
+        {
+            deploy_key.host_key.map(|host_key| view! {
+                <p class="mt-2 text-sm text-gray-700 dark:text-gray-400">
+                    {format!("Host key: {host_key}")}
+                </p>
+            })
+        }
+    }
+}
+
 #[component]
 pub fn NewProjectView(new_project_modal_hidden: RwSignal<bool>) -> impl IntoView {
     let repo = create_rw_signal(String::new());
     let current_machine_type = create_rw_signal(None);
     let preferred_machine_type = Signal::derive(|| None);
-    let action =
-        create_action(move |_| create_project(repo.get_untracked(), current_machine_type.get()));
+    let deploy_key = create_rw_signal(None);
+    let deploy_key_error = create_rw_signal(None);
+    let deploy_key_action = create_action(move |_| async move {
+        deploy_key_error.set(None);
+        if let Err(e) = new_deploy_key(deploy_key).await {
+            deploy_key_error.set(Some(e.error));
+        }
+    });
+    let action = create_action(move |_| {
+        create_project(
+            repo.get_untracked(),
+            current_machine_type.get(),
+            deploy_key.with_untracked(|k: &Option<DeployKey>| k.as_ref().map(|k| k.id)),
+        )
+    });
     let body = view! {
         <CreationInput label="Your repository url".to_string() value=repo placeholder="https://github.com/owner/repo".to_string() />
+        <div class:hidden=move || !is_ssh_repo(&repo.get())>
+            {
+                move || if let Some(error) = deploy_key_error.get() {
+                    view! {
+                        <div class="mb-2 p-4 rounded-lg bg-red-50 dark:bg-gray-800 ">
+                            <span class="text-sm font-medium text-red-800 dark:text-red-400">{ error }</span>
+                        </div>
+                    }.into_view()
+                } else {
+                    view! {}.into_view()
+                }
+            }
+            {
+                move || if let Some(deploy_key) = deploy_key.get() {
+                    view! { <DeployKeyInfoView deploy_key /> }.into_view()
+                } else {
+                    view! {
+                        <p class="mb-2 text-sm text-gray-700 dark:text-gray-400">
+                            {"SSH repositories are fetched with a deploy key of the project."}
+                        </p>
+                        <button
+                            type="button"
+                            class="px-4 py-2 text-sm font-medium text-gray-900 bg-white border border-gray-200 rounded-lg hover:bg-gray-100 focus:ring-4 focus:ring-gray-100 dark:bg-gray-800 dark:text-white dark:border-gray-600 dark:hover:bg-gray-700 focus:outline-none"
+                            disabled=move || deploy_key_action.pending().get()
+                            on:click=move |_| deploy_key_action.dispatch(())
+                        >
+                            Generate Deploy Key
+                        </button>
+                    }.into_view()
+                }
+            }
+        </div>
         <MachineTypeView current_machine_type preferred_machine_type />
     };
     view! {
@@ -1171,10 +1268,259 @@ fn ProjectSettingsView(
             class:hidden=move || tab_kind.get() != TabKind::Setting
         >
             <SaveMachineTypeView project_id project_update_counter />
+            <div class="mt-8 w-1/2">
+                <ProjectDeployKeyView project_id />
+            </div>
+            <div class="mt-8 w-1/2">
+                <ProjectGitCredentialView project_id />
+            </div>
         </div>
     }
 }
 
+async fn get_project_deploy_key(project_id: Uuid) -> Result<Option<DeployKey>> {
+    let current_org =
+        use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
+    let org = current_org
+        .get_untracked()
+        .ok_or_else(|| anyhow!("can't get org"))?;
+    let resp = Request::get(&format!(
+        "/api/v1/organizations/{}/projects/{project_id}/deploy_key",
+        org.id
+    ))
+    .send()
+    .await?;
+    let key: Option<DeployKey> = resp.json().await?;
+    Ok(key)
+}
+
+async fn regenerate_project_deploy_key(project_id: Uuid) -> Result<(), ErrorResponse> {
+    let current_org =
+        use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
+    let org = current_org
+        .get_untracked()
+        .ok_or_else(|| anyhow!("can't get org"))?;
+    let resp = Request::post(&format!(
+        "/api/v1/organizations/{}/projects/{project_id}/deploy_key",
+        org.id
+    ))
+    .send()
+    .await?;
+    if resp.status() != 200 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[component]
+fn ProjectDeployKeyView(project_id: Uuid) -> impl IntoView {
+    let update_counter = create_rw_signal(0);
+    let deploy_key = create_local_resource(
+        move || update_counter.get(),
+        move |_| async move { get_project_deploy_key(project_id).await.ok().flatten() },
+    );
+    let error = create_rw_signal(None);
+    let action = create_action(move |_| async move {
+        error.set(None);
+        match regenerate_project_deploy_key(project_id).await {
+            Ok(_) => update_counter.update(|c| *c += 1),
+            Err(e) => error.set(Some(e.error)),
+        }
+    });
+    view! {
+        <h5 class="text-lg font-semibold dark:text-white">Deploy Key</h5>
+        {
+            move || if let Some(error) = error.get() {
+                view! {
+                    <div class="my-2 p-4 rounded-lg bg-red-50 dark:bg-gray-800 ">
+                        <span class="text-sm font-medium text-red-800 dark:text-red-400">{ error }</span>
+                    </div>
+                }.into_view()
+            } else {
+                view! {}.into_view()
+            }
+        }
+        <div class="mt-2">
+        {
+            move || if let Some(deploy_key) = deploy_key.get().flatten() {
+                view! { <DeployKeyInfoView deploy_key /> }.into_view()
+            } else {
+                view! {
+                    <p class="text-sm text-gray-700 dark:text-gray-400">
+                        {"The project doesn't have a deploy key. Generate one to fetch the repository over SSH."}
+                    </p>
+                }.into_view()
+            }
+        }
+        </div>
+        <button
+            type="button"
+            class="mt-4 text-white bg-blue-700 hover:bg-blue-800 focus:ring-4 focus:outline-none focus:ring-blue-300 font-medium rounded-lg text-sm px-5 py-2.5 text-center dark:bg-blue-600 dark:hover:bg-blue-700 dark:focus:ring-blue-800"
+            disabled=move || action.pending().get()
+            on:click=move |_| action.dispatch(())
+        >
+            Generate New Key
+        </button>
+    }
+}
+
+async fn get_project_git_credential(project_id: Uuid) -> Result<Option<ProjectGitCredentialInfo>> {
+    let current_org =
+        use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
+    let org = current_org
+        .get_untracked()
+        .ok_or_else(|| anyhow!("can't get org"))?;
+    let resp = Request::get(&format!(
+        "/api/v1/organizations/{}/projects/{project_id}/git_credential",
+        org.id
+    ))
+    .send()
+    .await?;
+    let credential: Option<ProjectGitCredentialInfo> = resp.json().await?;
+    Ok(credential)
+}
+
+/// Saves the credential, or removes it when there's none
+async fn update_project_git_credential(
+    project_id: Uuid,
+    credential: Option<ProjectGitCredential>,
+) -> Result<(), ErrorResponse> {
+    let current_org =
+        use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
+    let org = current_org
+        .get_untracked()
+        .ok_or_else(|| anyhow!("can't get org"))?;
+    let url = format!(
+        "/api/v1/organizations/{}/projects/{project_id}/git_credential",
+        org.id
+    );
+    let resp = if let Some(credential) = credential {
+        Request::put(&url).json(&credential)?.send().await?
+    } else {
+        Request::delete(&url).send().await?
+    };
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[component]
+fn ProjectGitCredentialView(project_id: Uuid) -> impl IntoView {
+    let update_counter = create_rw_signal(0);
+    let credential = create_local_resource(
+        move || update_counter.get(),
+        move |_| async move { get_project_git_credential(project_id).await.ok().flatten() },
+    );
+    let username = create_rw_signal(String::new());
+    let token = create_rw_signal(String::new());
+    let error = create_rw_signal(None);
+    let action = create_action(move |remove: &bool| {
+        let credential = if *remove {
+            None
+        } else {
+            Some(ProjectGitCredential {
+                username: username.get_untracked(),
+                token: token.get_untracked(),
+            })
+        };
+        async move {
+            error.set(None);
+            match update_project_git_credential(project_id, credential).await {
+                Ok(_) => {
+                    username.set(String::new());
+                    token.set(String::new());
+                    update_counter.update(|c| *c += 1);
+                }
+                Err(e) => error.set(Some(e.error)),
+            }
+        }
+    });
+    view! {
+        <h5 class="text-lg font-semibold dark:text-white">Git Credential for Workspaces</h5>
+        <p class="mt-2 text-sm text-gray-700 dark:text-gray-400">
+            {"The git credential helper in the workspaces hands out the token of the workspace owner for the repository of the project. This token is handed out instead when the owner doesn't have one. Use a token that only has access to this repository and expires, e.g. a GitHub fine-grained token, a GitLab project access token or a Bitbucket repository access token."}
+        </p>
+        {
+            move || if let Some(error) = error.get() {
+                view! {
+                    <div class="my-2 p-4 rounded-lg bg-red-50 dark:bg-gray-800 ">
+                        <span class="text-sm font-medium text-red-800 dark:text-red-400">{ error }</span>
+                    </div>
+                }.into_view()
+            } else {
+                view! {}.into_view()
+            }
+        }
+        <div class="mt-2">
+        {
+            move || if let Some(credential) = credential.get().flatten() {
+                view! {
+                    <div class="flex flex-row items-center">
+                        <p class="text-sm text-gray-700 dark:text-gray-400">
+                            {format!("The workspaces get the token of {}.", credential.username)}
+                        </p>
+                        <button
+                            type="button"
+                            class="ml-4 px-4 py-2 text-sm text-white rounded-lg bg-red-700 hover:bg-red-800 focus:ring-4 focus:ring-red-300 dark:bg-red-600 dark:hover:bg-red-700 focus:outline-none dark:focus:ring-red-800"
+                            disabled=move || action.pending().get()
+                            on:click=move |_| action.dispatch(true)
+                        >
+                            Remove
+                        </button>
+                    </div>
+                }.into_view()
+            } else {
+                view! {
+                    <p class="text-sm text-gray-700 dark:text-gray-400">
+                        {"The project doesn't have a git credential, so git in the workspaces asks for one when the workspace owner doesn't have a token."}
+                    </p>
+                }.into_view()
+            }
+        }
+        </div>
+        <div class="mt-4 flex flex-row items-center">
+            <div class="w-48">
+                <input
+                    placeholder="Username"
+                    prop:value={move || username.get()}
+                    on:input=move |ev| { username.set(event_target_value(&ev)); }
+                    class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                />
+            </div>
+            <div class="ml-2 w-96">
+                <input
+                    type="password"
+                    placeholder="Token"
+                    prop:value={move || token.get()}
+                    on:input=move |ev| { token.set(event_target_value(&ev)); }
+                    class="bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-600 dark:border-gray-500 dark:placeholder-gray-400 dark:text-white"
+                />
+            </div>
+        </div>
+        <button
+            type="button"
+            class="mt-4 text-white bg-blue-700 hover:bg-blue-800 focus:ring-4 focus:outline-none focus:ring-blue-300 font-medium rounded-lg text-sm px-5 py-2.5 text-center dark:bg-blue-600 dark:hover:bg-blue-700 dark:focus:ring-blue-800"
+            disabled=move || action.pending().get()
+            on:click=move |_| action.dispatch(false)
+        >
+            Save Token
+        </button>
+    }
+}
+
 #[component]
 fn SaveMachineTypeView(project_id: Uuid, project_update_counter: RwSignal<i32>) -> impl IntoView {
     let current_machine_type = create_rw_signal(None);
@@ -151,11 +151,9 @@ fn QuotaItemView(
     value: OrgQuotaValue,
     get_action: Action<(), Result<OrgQuota, ErrorResponse>>,
 ) -> impl IntoView {
-    let percentage = if value.org_quota == 0 {
-        0
-    } else {
-        value.existing * 100 / value.org_quota
-    };
+    let percentage = (value.existing * 100)
+        .checked_div(value.org_quota)
+        .unwrap_or(0);
     let color = if percentage >= 100 {
         "bg-red-600"
     } else if percentage >= 75 {
@@ -0,0 +1,260 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use gloo_net::http::Request;
+use lapdev_common::{
+    console::{Organization, OrganizationMember},
+    NewWorkspaceSshAccess, WorkspaceSshAccess, WorkspaceSshAccessGrant,
+};
+use leptos::{
+    component, create_action, create_local_resource, create_rw_signal, event_target_value,
+    use_context, view, For, IntoView, RwSignal, Signal, SignalGet, SignalGetUntracked, SignalSet,
+    SignalUpdate, SignalWith,
+};
+use uuid::Uuid;
+
+use crate::{
+    modal::{CreationModal, DatetimeModal, ErrorResponse},
+    organization::get_org_members,
+};
+
+fn current_org() -> Result<Organization> {
+    let org =
+        use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
+    org.get_untracked().ok_or_else(|| anyhow!("can't get org"))
+}
+
+async fn get_ssh_access(workspace_name: &str) -> Result<Vec<WorkspaceSshAccessGrant>> {
+    let org = current_org()?;
+    let resp = Request::get(&format!(
+        "/api/v1/organizations/{}/workspaces/{workspace_name}/ssh_access",
+        org.id
+    ))
+    .send()
+    .await?;
+    let grants: Vec<WorkspaceSshAccessGrant> = resp.json().await?;
+    Ok(grants)
+}
+
+async fn grant_ssh_access(
+    workspace_name: String,
+    user_id: Option<Uuid>,
+    access: WorkspaceSshAccess,
+    modal_hidden: RwSignal<bool>,
+    update_counter: RwSignal<i32>,
+) -> Result<(), ErrorResponse> {
+    let user_id = user_id.ok_or_else(|| anyhow!("Please choose a member"))?;
+    let org = current_org()?;
+    let resp = Request::put(&format!(
+        "/api/v1/organizations/{}/workspaces/{workspace_name}/ssh_access",
+        org.id
+    ))
+    .json(&NewWorkspaceSshAccess { user_id, access })?
+    .send()
+    .await?;
+    if resp.status() != 204 {
+        let error = resp
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: "Internal Server Error".to_string(),
+            });
+        return Err(error);
+    }
+    modal_hidden.set(true);
+    update_counter.update(|c| *c += 1);
+    Ok(())
+}
+
+async fn revoke_ssh_access(
+    workspace_name: String,
+    user_id: Uuid,
+    error: RwSignal<Option<String>>,
+    update_counter: RwSignal<i32>,
+) {
+    let result: Result<(), ErrorResponse> = async {
+        let org = current_org()?;
+        let resp = Request::delete(&format!(
+            "/api/v1/organizations/{}/workspaces/{workspace_name}/ssh_access/{user_id}",
+            org.id
+        ))
+        .send()
+        .await?;
+        if resp.status() != 204 {
+            let error = resp
+                .json::<ErrorResponse>()
+                .await
+                .unwrap_or_else(|_| ErrorResponse {
+                    error: "Internal Server Error".to_string(),
+                });
+            return Err(error);
+        }
+        Ok(())
+    }
+    .await;
+    match result {
+        Ok(_) => {
+            error.set(None);
+            update_counter.update(|c| *c += 1);
+        }
+        Err(e) => error.set(Some(e.error)),
+    }
+}
+
+fn access_label(access: WorkspaceSshAccess) -> &'static str {
+    match access {
+        WorkspaceSshAccess::ReadOnly => "Read only (port forwarding)",
+        WorkspaceSshAccess::Full => "Full",
+    }
+}
+
+#[component]
+pub fn WorkspaceSshAccessView(workspace_name: String) -> impl IntoView {
+    let update_counter = create_rw_signal(0);
+    let error = create_rw_signal(None);
+    let grants = {
+        let workspace_name = workspace_name.clone();
+        create_local_resource(
+            move || update_counter.get(),
+            move |_| {
+                let workspace_name = workspace_name.clone();
+                async move { get_ssh_access(&workspace_name).await.unwrap_or_default() }
+            },
+        )
+    };
+    let grant_modal_hidden = create_rw_signal(true);
+
+    view! {
+        <div class="border-t mt-8 pt-4">
+            <div class="flex flex-row items-center">
+                <span class="text-sm font-medium dark:text-white">{"SSH Access"}</span>
+                <button
+                    class="ml-4 px-3 py-1 text-sm font-medium text-white bg-blue-700 rounded-lg hover:bg-blue-800 focus:ring-4 focus:outline-none focus:ring-blue-300 dark:bg-blue-600 dark:hover:bg-blue-700 dark:focus:ring-blue-800"
+                    on:click=move |_| grant_modal_hidden.set(false)
+                >
+                    Grant Access
+                </button>
+            </div>
+            <p class="mt-1 text-sm text-gray-500 dark:text-gray-400">
+                {"Organization members you grant access can connect to this workspace with their own SSH keys."}
+            </p>
+            { move || if let Some(error) = error.get() {
+                view! {
+                    <div class="w-full my-2 p-4 rounded-lg bg-red-50 dark:bg-gray-800 ">
+                        <span class="text-sm font-medium text-red-800 dark:text-red-400">{ error }</span>
+                    </div>
+                }.into_view()
+            } else {
+                view!{}.into_view()
+            }}
+            <For
+                each=move || grants.get().unwrap_or_default()
+                key=|g| (g.user_id, g.access)
+                children={
+                    let workspace_name = workspace_name.clone();
+                    move |grant| {
+                        let workspace_name = workspace_name.clone();
+                        let revoke_action = create_action(move |_| {
+                            revoke_ssh_access(workspace_name.clone(), grant.user_id, error, update_counter)
+                        });
+                        view! {
+                            <div class="mt-2 text-sm flex flex-row items-center">
+                                <img
+                                    class="w-6 h-6 rounded-full mr-2"
+                                    src={ grant.avatar_url.clone().unwrap_or_default() }
+                                />
+                                <span class="mr-4 w-48 truncate">{ grant.name.clone().unwrap_or_default() }</span>
+                                <span class="mr-4 w-48 text-gray-500 dark:text-gray-400">{ access_label(grant.access) }</span>
+                                <span class="mr-4 text-gray-500 dark:text-gray-400"><DatetimeModal time=grant.created_at /></span>
+                                <button
+                                    class="text-red-600 dark:text-red-500 hover:underline"
+                                    on:click=move |_| revoke_action.dispatch(())
+                                >
+                                    Revoke
+                                </button>
+                            </div>
+                        }
+                    }
+                }
+            />
+            <GrantSshAccessModal workspace_name modal_hidden=grant_modal_hidden update_counter />
+        </div>
+    }
+}
+
+#[component]
+fn GrantSshAccessModal(
+    workspace_name: String,
+    modal_hidden: RwSignal<bool>,
+    update_counter: RwSignal<i32>,
+) -> impl IntoView {
+    let members = create_local_resource(
+        move || modal_hidden.get(),
+        move |_| async move { get_org_members().await.unwrap_or_default() },
+    );
+    let user_id = create_rw_signal(None);
+    let access = create_rw_signal(WorkspaceSshAccess::Full);
+    let action = create_action(move |_| {
+        grant_ssh_access(
+            workspace_name.clone(),
+            user_id.get_untracked(),
+            access.get_untracked(),
+            modal_hidden,
+            update_counter,
+        )
+    });
+
+    let select_class = "bg-gray-50 border border-gray-300 text-gray-900 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full p-2.5 dark:bg-gray-700 dark:border-gray-600 dark:placeholder-gray-400 dark:text-white dark:focus:ring-blue-500 dark:focus:border-blue-500";
+    let body = view! {
+        <div>
+            <label class="block mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                Member
+            </label>
+            <select
+                class=select_class
+                on:change=move |ev| user_id.set(Uuid::from_str(&event_target_value(&ev)).ok())
+            >
+                <option selected=move || user_id.with(|id| id.is_none())>{"Choose a member"}</option>
+                <For
+                    each=move || members.get().unwrap_or_default()
+                    key=|m: &OrganizationMember| m.user_id
+                    children=move |m| {
+                        view! {
+                            <option
+                                value={ m.user_id.to_string() }
+                                selected=move || user_id.get() == Some(m.user_id)
+                            >{ m.name.clone().unwrap_or_default() }</option>
+                        }
+                    }
+                />
+            </select>
+            <label class="block mt-4 mb-2 text-sm font-medium text-gray-900 dark:text-white">
+                Access
+            </label>
+            <select
+                class=select_class
+                on:change=move |ev| {
+                    if let Ok(a) = WorkspaceSshAccess::from_str(&event_target_value(&ev)) {
+                        access.set(a);
+                    }
+                }
+            >
+                <For
+                    each=move || vec![WorkspaceSshAccess::Full, WorkspaceSshAccess::ReadOnly]
+                    key=|a| *a
+                    children=move |a| {
+                        view! {
+                            <option
+                                value={ a.to_string() }
+                                selected=move || access.get() == a
+                            >{ access_label(a) }</option>
+                        }
+                    }
+                />
+            </select>
+        </div>
+    };
+    view! {
+        <CreationModal title="Grant SSH Access".to_string() modal_hidden body action is_update=false create_button_hidden=false />
+    }
+}
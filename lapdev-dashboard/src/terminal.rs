@@ -0,0 +1,301 @@
+use std::{cell::Cell, rc::Rc};
+
+use anyhow::{anyhow, Result};
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use gloo_net::websocket::{futures::WebSocket, Message};
+use lapdev_common::TerminalClientMessage;
+use leptos::{
+    component, create_effect, create_node_ref, create_rw_signal, ev, html, on_cleanup,
+    request_animation_frame, spawn_local, view, window, window_event_listener, For, IntoView,
+    Signal, SignalGet, SignalGetUntracked, SignalSet, SignalUpdate, SignalWithUntracked,
+};
+use leptos_router::use_params_map;
+use wasm_bindgen::{closure::Closure, JsValue};
+
+// bindings for xterm.js and its fit addon, which are loaded in index.html
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    type Terminal;
+
+    #[wasm_bindgen(constructor)]
+    fn new(options: &JsValue) -> Terminal;
+
+    #[wasm_bindgen(method)]
+    fn open(this: &Terminal, parent: &web_sys::HtmlElement);
+
+    #[wasm_bindgen(method)]
+    fn write(this: &Terminal, data: &[u8]);
+
+    #[wasm_bindgen(method)]
+    fn focus(this: &Terminal);
+
+    #[wasm_bindgen(method)]
+    fn dispose(this: &Terminal);
+
+    #[wasm_bindgen(method, getter)]
+    fn cols(this: &Terminal) -> u32;
+
+    #[wasm_bindgen(method, getter)]
+    fn rows(this: &Terminal) -> u32;
+
+    #[wasm_bindgen(method, js_name = loadAddon)]
+    fn load_addon(this: &Terminal, addon: &TerminalFitAddon);
+
+    #[wasm_bindgen(method, js_name = onData)]
+    fn on_data(this: &Terminal, f: &Closure<dyn FnMut(String)>);
+
+    #[wasm_bindgen(method, js_name = onResize)]
+    fn on_resize(this: &Terminal, f: &Closure<dyn FnMut(JsValue)>);
+
+    // the fit addon bundle exports its class as `FitAddon.FitAddon`
+    #[wasm_bindgen(js_namespace = FitAddon, js_name = FitAddon)]
+    type TerminalFitAddon;
+
+    #[wasm_bindgen(constructor, js_namespace = FitAddon, js_class = FitAddon)]
+    fn new() -> TerminalFitAddon;
+
+    #[wasm_bindgen(method, js_class = FitAddon)]
+    fn fit(this: &TerminalFitAddon);
+}
+
+#[component]
+pub fn WorkspaceTerminalView() -> impl IntoView {
+    let params = use_params_map();
+    let name = params.with_untracked(|params| params.get("name").cloned().unwrap());
+
+    let tabs = create_rw_signal(vec![1]);
+    let active_tab = create_rw_signal(1);
+    let next_tab = create_rw_signal(2);
+
+    let new_tab = move |_| {
+        let tab = next_tab.get_untracked();
+        next_tab.set(tab + 1);
+        tabs.update(|tabs| tabs.push(tab));
+        active_tab.set(tab);
+    };
+    let close_tab = move |tab: usize| {
+        tabs.update(|tabs| tabs.retain(|t| *t != tab));
+        if active_tab.get_untracked() == tab {
+            if let Some(last) = tabs.get_untracked().last() {
+                active_tab.set(*last);
+            }
+        }
+    };
+
+    view! {
+        <section class="w-full h-full flex flex-col">
+            <a href={ format!("/workspaces/{name}") } class="text-sm inline-flex items-center text-gray-700 dark:text-gray-400">
+                <svg class="w-2 h-2 mr-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 14 10">
+                <path stroke="currentColor" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M13 5H1m0 0 4 4M1 5l4-4"/>
+                </svg>
+                Back to Workspace
+            </a>
+            <h5 class="text-semibold text-2xl dark:text-white mt-4">
+                { name.clone() }
+            </h5>
+            <div class="mt-4 flex flex-row items-center border-b text-sm">
+                <For
+                    each=move || tabs.get()
+                    key=|tab| *tab
+                    children=move |tab| {
+                        view! {
+                            <div
+                                class="flex flex-row items-center px-4 py-2 cursor-pointer border-b-2"
+                                class=("border-blue-600", move || active_tab.get() == tab)
+                                class=("border-transparent", move || active_tab.get() != tab)
+                                on:click=move |_| active_tab.set(tab)
+                            >
+                                <span class="dark:text-white">{ format!("Terminal {tab}") }</span>
+                                <button
+                                    class="ml-2 text-gray-400 hover:text-gray-900 dark:hover:text-white"
+                                    on:click=move |e| {
+                                        e.stop_propagation();
+                                        close_tab(tab);
+                                    }
+                                >
+                                    <svg class="w-2 h-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 14 14">
+                                        <path stroke="currentColor" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="m1 1 6 6m0 0 6 6M7 7l6-6M7 7l-6 6"/>
+                                    </svg>
+                                </button>
+                            </div>
+                        }
+                    }
+                />
+                <button
+                    class="px-4 py-2 text-gray-500 hover:text-gray-900 dark:hover:text-white"
+                    on:click=new_tab
+                >
+                    {"+"}
+                </button>
+            </div>
+            <div class="mt-2 w-full h-[32rem]">
+                <For
+                    each=move || tabs.get()
+                    key=|tab| *tab
+                    children={
+                        let name = name.clone();
+                        move |tab| {
+                            view! {
+                                <TerminalTab name=name.clone() hidden=Signal::derive(move || active_tab.get() != tab) />
+                            }
+                        }
+                    }
+                />
+            </div>
+        </section>
+    }
+}
+
+#[component]
+fn TerminalTab(name: String, hidden: Signal<bool>) -> impl IntoView {
+    let container = create_node_ref::<html::Div>();
+    let error = create_rw_signal(None);
+
+    let term = Rc::new(Terminal::new(&terminal_options()));
+    let fit = Rc::new(TerminalFitAddon::new());
+    term.load_addon(&fit);
+    let opened = Rc::new(Cell::new(false));
+    let disposed = Rc::new(Cell::new(false));
+
+    // the messages to the server are queued here, and the queue ends
+    // when the closures holding the sender are dropped on cleanup
+    let (tx, rx) = mpsc::unbounded();
+    let on_data = {
+        let tx = tx.clone();
+        Closure::<dyn FnMut(String)>::new(move |data| {
+            let _ = tx.unbounded_send(TerminalClientMessage::Input { data });
+        })
+    };
+    term.on_data(&on_data);
+    let on_resize = Closure::<dyn FnMut(JsValue)>::new(move |size: JsValue| {
+        let get = |key: &str| {
+            web_sys::js_sys::Reflect::get(&size, &JsValue::from_str(key))
+                .ok()
+                .and_then(|v| v.as_f64())
+                .map(|v| v as u32)
+        };
+        if let (Some(cols), Some(rows)) = (get("cols"), get("rows")) {
+            let _ = tx.unbounded_send(TerminalClientMessage::Resize { cols, rows });
+        }
+    });
+    term.on_resize(&on_resize);
+
+    let resize_listener = {
+        let fit = fit.clone();
+        let opened = opened.clone();
+        window_event_listener(ev::resize, move |_| {
+            if opened.get() {
+                fit.fit();
+            }
+        })
+    };
+
+    {
+        let term = term.clone();
+        let fit = fit.clone();
+        let opened = opened.clone();
+        create_effect(move |_| {
+            if !hidden.get() && opened.get() {
+                fit.fit();
+                term.focus();
+            }
+        });
+    }
+
+    {
+        let term = term.clone();
+        let fit = fit.clone();
+        let opened = opened.clone();
+        let disposed = disposed.clone();
+        container.on_load(move |div| {
+            // the element needs to be in the document so that xterm can measure it
+            request_animation_frame(move || {
+                term.open(&div);
+                fit.fit();
+                opened.set(true);
+                if !hidden.get_untracked() {
+                    term.focus();
+                }
+                spawn_local(async move {
+                    if let Err(e) = run_terminal(&name, &term, rx, &disposed).await {
+                        error.set(Some(e.to_string()));
+                    }
+                });
+            });
+        });
+    }
+
+    on_cleanup(move || {
+        resize_listener.remove();
+        drop(on_data);
+        drop(on_resize);
+        disposed.set(true);
+        term.dispose();
+    });
+
+    view! {
+        <div class="w-full h-full" class:hidden=move || hidden.get()>
+            { move || if let Some(error) = error.get() {
+                view! {
+                    <div class="w-full mb-2 p-4 rounded-lg bg-red-50 dark:bg-gray-800 ">
+                        <span class="text-sm font-medium text-red-800 dark:text-red-400">{ error }</span>
+                    </div>
+                }.into_view()
+            } else {
+                view!{}.into_view()
+            }}
+            <div class="w-full h-full p-2 bg-black rounded-lg" node_ref=container></div>
+        </div>
+    }
+}
+
+fn terminal_options() -> JsValue {
+    web_sys::js_sys::JSON::parse(r#"{"cursorBlink":true,"fontSize":14}"#)
+        .unwrap_or(JsValue::UNDEFINED)
+}
+
+async fn run_terminal(
+    name: &str,
+    term: &Terminal,
+    mut rx: mpsc::UnboundedReceiver<TerminalClientMessage>,
+    disposed: &Cell<bool>,
+) -> Result<()> {
+    let location = window().location();
+    let host = location.host().map_err(|_| anyhow!("can't get host"))?;
+    let protocol = if location.protocol().map(|p| p == "http:").unwrap_or(false) {
+        "ws"
+    } else {
+        "wss"
+    };
+    let websocket = WebSocket::open(&format!(
+        "{protocol}://{host}/terminal?name={name}&cols={}&rows={}",
+        term.cols(),
+        term.rows()
+    ))?;
+
+    let (mut ws_tx, mut ws_rx) = websocket.split();
+    spawn_local(async move {
+        while let Some(msg) = rx.next().await {
+            if let Ok(msg) = serde_json::to_string(&msg) {
+                if ws_tx.send(Message::Text(msg)).await.is_err() {
+                    break;
+                }
+            }
+        }
+        let _ = ws_tx.close().await;
+    });
+
+    while let Some(Ok(msg)) = ws_rx.next().await {
+        if disposed.get() {
+            return Ok(());
+        }
+        match msg {
+            Message::Bytes(data) => term.write(&data),
+            Message::Text(s) => term.write(s.as_bytes()),
+        }
+    }
+    if !disposed.get() {
+        term.write(b"\r\n[terminal closed]\r\n");
+    }
+    Ok(())
+}
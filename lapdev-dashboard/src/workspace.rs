@@ -7,9 +7,9 @@ use gloo_net::{
     websocket::{futures::WebSocket, Message},
 };
 use lapdev_common::{
-    console::Organization, ClusterInfo, GitBranch, NewWorkspace, NewWorkspaceResponse,
-    PrebuildStatus, ProjectInfo, ProjectPrebuild, RepoSource, WorkspaceInfo, WorkspaceStatus,
-    WorkspaceUpdateEvent,
+    console::Organization, utils::workspace_url, ClusterInfo, GitBranch, NewWorkspace,
+    NewWorkspaceResponse, PrebuildStatus, ProjectInfo, ProjectPrebuild, RepoSource, WorkspaceInfo,
+    WorkspacePort, WorkspaceStatus, WorkspaceUpdateEvent,
 };
 use leptos::{
     component, create_action, create_effect, create_local_resource, create_rw_signal, document,
@@ -26,6 +26,7 @@ use web_sys::FocusEvent;
 use crate::{
     modal::{CreationModal, DatetimeModal, DeletionModal, ErrorResponse},
     project::MachineTypeView,
+    ssh_access::WorkspaceSshAccessView,
 };
 
 #[derive(Clone)]
@@ -47,6 +48,15 @@ impl BuildMessage {
     }
 }
 
+/// The scheme the dashboard is opened with, which the workspaces are served with too
+fn location_scheme() -> String {
+    window()
+        .location()
+        .protocol()
+        .map(|p| p.trim_end_matches(':').to_string())
+        .unwrap_or_else(|_| "https".to_string())
+}
+
 async fn all_workspaces() -> Result<Vec<WorkspaceInfo>> {
     let org =
         use_context::<Signal<Option<Organization>>>().ok_or_else(|| anyhow!("can't get org"))?;
@@ -203,9 +213,18 @@ fn OpenWorkspaceView(
         dropdown_hidden.set(true);
         let workspace_name = workspace_name.clone();
         let workspace_hostname = workspace_hostname.clone();
+        let cluster_info = expect_context::<Signal<Option<ClusterInfo>>>();
         move |_| {
+            let path_routing = cluster_info
+                .with_untracked(|i| i.as_ref().map(|i| i.path_routing).unwrap_or(false));
             let _ = window().open_with_url_and_target(
-                &format!("http://{workspace_name}.{workspace_hostname}/"),
+                &workspace_url(
+                    &location_scheme(),
+                    &workspace_hostname,
+                    &workspace_name,
+                    None,
+                    path_routing,
+                ),
                 "_blank",
             );
         }
@@ -267,6 +286,14 @@ fn OpenWorkspaceView(
                             Open in VSCode Desktop
                         </a>
                     </li>
+                    <li>
+                        <a
+                            href={ format!("/workspaces/{workspace_name}/terminal") }
+                            class="block px-4 py-2 hover:bg-gray-100 dark:hover:bg-gray-600 dark:hover:text-white"
+                        >
+                            Open Terminal
+                        </a>
+                    </li>
                 </ul>
             </div>
         </div>
@@ -901,6 +928,7 @@ async fn watch_workspace_updates(
     name: &str,
     status: RwSignal<WorkspaceStatus>,
     build_messages: RwSignal<Vec<BuildMessage>>,
+    ports: RwSignal<Vec<WorkspacePort>>,
 ) -> Result<()> {
     let location = window().location();
     let host = location.host().map_err(|_| anyhow!("can't get host"))?;
@@ -945,6 +973,9 @@ async fn watch_workspace_updates(
                         });
                         let _ = scroll_build_messages();
                     }
+                    WorkspaceUpdateEvent::Ports(new_ports) => {
+                        ports.set(new_ports);
+                    }
                 }
             }
         }
@@ -988,10 +1019,11 @@ pub fn WorkspaceDetails() -> impl IntoView {
 
     let build_messages = create_rw_signal(Vec::new());
     let status = create_rw_signal(WorkspaceStatus::New);
+    let ports = create_rw_signal(Vec::new());
     {
         let name = local_name.clone();
         let _ = spawn_local_with_current_owner(async move {
-            let _ = watch_workspace_updates(&name, status, build_messages).await;
+            let _ = watch_workspace_updates(&name, status, build_messages, ports).await;
         });
     }
     create_effect(move |_| {
@@ -1079,6 +1111,41 @@ pub fn WorkspaceDetails() -> impl IntoView {
                                         move || format!("ssh {workspace_name}@{} -p {}", workspace_hostname.split(':').next().unwrap_or(""), cluster_info.with(|i| i.as_ref().map(|i| i.ssh_proxy_port).unwrap_or(2222)))
                                     }
                                 </span>
+                                {
+                                    let workspace_name = workspace_name.clone();
+                                    let workspace_hostname = workspace_hostname.clone();
+                                    view! {
+                                        <Show
+                                            when=move || { status.get() == WorkspaceStatus::Running && ports.with(|ports| !ports.is_empty()) }
+                                        >
+                                            <span class="mt-2 text-sm flex flex-row items-center rounded me-2">
+                                                <svg class="w-2.5 h-2.5 mr-2" aria-hidden="true" xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 24 24">
+                                                    <path stroke="currentColor" stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M13.2 9.8a3.4 3.4 0 0 0-4.8 0L5 13.2A3.4 3.4 0 0 0 9.8 18l.3-.3m-.3-4.5a3.4 3.4 0 0 0 4.8 0L18 9.8A3.4 3.4 0 0 0 13.2 5l-1 1"/>
+                                                </svg>
+                                                <span class="mr-1 text-gray-500 dark:text-gray-500">{"Ports:"}</span>
+                                                <For
+                                                    each=move || ports.get()
+                                                    key=|p| p.clone()
+                                                    children={
+                                                        let workspace_name = workspace_name.clone();
+                                                        let workspace_hostname = workspace_hostname.clone();
+                                                        move |p| {
+                                                            let path_routing = cluster_info.with(|i| i.as_ref().map(|i| i.path_routing).unwrap_or(false));
+                                                            view! {
+                                                                <a href={ workspace_url(&location_scheme(), &workspace_hostname, &workspace_name, Some(p.port), path_routing) } target="_blank" class="mr-2 text-blue-600 dark:text-blue-500 hover:underline">
+                                                                    { match &p.label {
+                                                                        Some(label) => format!("{} ({label})", p.port),
+                                                                        None => p.port.to_string(),
+                                                                    } }
+                                                                </a>
+                                                            }
+                                                        }
+                                                    }
+                                                />
+                                            </span>
+                                        </Show>
+                                    }
+                                }
                                 <span class="mt-2 text-sm flex flex-row items-center rounded me-2">
                                     <svg class="w-2.5 h-2.5 mr-2" xmlns="http://www.w3.org/2000/svg" width="16" height="16" fill="currentColor" viewBox="0 0 16 16">
                                         <path d="M14 10a1 1 0 0 1 1 1v1a1 1 0 0 1-1 1H2a1 1 0 0 1-1-1v-1a1 1 0 0 1 1-1zM2 9a2 2 0 0 0-2 2v1a2 2 0 0 0 2 2h12a2 2 0 0 0 2-2v-1a2 2 0 0 0-2-2z"/>
@@ -1121,6 +1188,7 @@ pub fn WorkspaceDetails() -> impl IntoView {
                                     children={
                                         let workspace_folder = info.repo_name.clone();
                                         let workspace_hostname = workspace_hostname.clone();
+                                        let workspace_name = workspace_name.clone();
                                         move |ws_service| {
                                             view! {
                                                 <div class="border-t mt-8">
@@ -1139,9 +1207,9 @@ pub fn WorkspaceDetails() -> impl IntoView {
                                                         </svg>
                                                         <span class="mr-1 text-gray-500 dark:text-gray-500">{"SSH Connection:"}</span>
                                                         {
-                                                            let ws_service_name = ws_service.name.clone();
+                                                            let ssh_user = format!("{}.{workspace_name}", ws_service.service);
                                                             let workspace_hostname = workspace_hostname.clone();
-                                                            move || format!("ssh {ws_service_name}@{} -p {}", workspace_hostname.split(':').next().unwrap_or(""), cluster_info.with(|i| i.as_ref().map(|i| i.ssh_proxy_port).unwrap_or(2222)))
+                                                            move || format!("ssh {ssh_user}@{} -p {}", workspace_hostname.split(':').next().unwrap_or(""), cluster_info.with(|i| i.as_ref().map(|i| i.ssh_proxy_port).unwrap_or(2222)))
                                                         }
                                                     </span>
 
@@ -1154,6 +1222,8 @@ pub fn WorkspaceDetails() -> impl IntoView {
                                     }
                                 />
 
+                                <WorkspaceSshAccessView workspace_name=workspace_name.clone() />
+
                                 <Show
                                     when=move || { let status = status.get(); status == WorkspaceStatus::New || status == WorkspaceStatus::Building || status == WorkspaceStatus::PrebuildBuilding || status == WorkspaceStatus::PrebuildCopying || status == WorkspaceStatus::Failed}
                                 >
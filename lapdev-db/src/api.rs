@@ -2,17 +2,18 @@ use anyhow::{anyhow, Result};
 use base64::{engine::general_purpose::STANDARD, Engine};
 use chrono::Utc;
 use lapdev_common::{
-    AuthProvider, ProviderUser, UserRole, WorkspaceStatus, LAPDEV_BASE_HOSTNAME,
-    LAPDEV_ISOLATE_CONTAINER,
+    AuthProvider, OauthProvider, ProviderUser, UserRole, WorkspaceStatus, LAPDEV_BASE_HOSTNAME,
+    LAPDEV_ISOLATE_CONTAINER, LAPDEV_OAUTH_PROVIDERS, LAPDEV_PATH_ROUTING,
 };
 use pasetors::{
     keys::{Generate, SymmetricKey},
     version4::V4,
 };
 use sea_orm::{
+    prelude::DateTimeWithTimeZone,
     sea_query::{Expr, Func, OnConflict},
     ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DatabaseTransaction,
-    EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+    EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
 };
 use sea_orm_migration::MigratorTrait;
 use sqlx::PgPool;
@@ -25,6 +26,19 @@ use super::entities::workspace;
 pub const LAPDEV_CLUSTER_NOT_INITIATED: &str = "lapdev-cluster-not-initiated";
 const LAPDEV_API_AUTH_TOKEN_KEY: &str = "lapdev-api-auth-token-key";
 const LAPDEV_OAUTH_NO_READ_REPO: &str = "lapdev-oauth-no-read-repo";
+/// The client ids and secrets of github.com and gitlab.com from before the OAuth providers could be configured
+const LEGACY_OAUTH_CONFIGS: [(AuthProvider, &str, &str); 2] = [
+    (
+        AuthProvider::Github,
+        "github-client-id",
+        "github-client-secret",
+    ),
+    (
+        AuthProvider::Gitlab,
+        "gitlab-client-id",
+        "gitlab-client-secret",
+    ),
+];
 
 #[derive(Clone)]
 pub struct DbApi {
@@ -62,8 +76,11 @@ impl DbApi {
         // without any authentication,
         // also the user created before cluster is intiated is a cluster admin,
         // so the value is default to true even when we've got an error
+        // the row is locked until the transaction ends, so that concurrent
+        // first users can't all become the cluster admin
         if entities::config::Entity::find()
             .filter(entities::config::Column::Name.eq(LAPDEV_CLUSTER_NOT_INITIATED))
+            .lock_exclusive()
             .one(txn)
             .await
             .as_ref()
@@ -104,6 +121,31 @@ impl DbApi {
             .map(|v| v == "yes")
     }
 
+    /// The configured OAuth providers, github.com and gitlab.com are there by default
+    pub async fn get_oauth_providers(&self) -> Result<Vec<OauthProvider>> {
+        if let Ok(providers) = self.get_config(LAPDEV_OAUTH_PROVIDERS).await {
+            return Ok(serde_json::from_str(&providers)?);
+        }
+
+        let mut providers = Vec::new();
+        for (kind, client_id, client_secret) in LEGACY_OAUTH_CONFIGS {
+            let mut provider = OauthProvider::new(kind);
+            provider.client_id = self.get_config(client_id).await.unwrap_or_default();
+            provider.client_secret = self.get_config(client_secret).await.unwrap_or_default();
+            providers.push(provider);
+        }
+        Ok(providers)
+    }
+
+    pub async fn get_oauth_provider(&self, id: &str) -> Result<Option<OauthProvider>> {
+        let provider = self
+            .get_oauth_providers()
+            .await?
+            .into_iter()
+            .find(|p| p.id == id);
+        Ok(provider)
+    }
+
     async fn get_api_auth_token_key(&self) -> Result<SymmetricKey<V4>> {
         let key = self.get_config(LAPDEV_API_AUTH_TOKEN_KEY).await?;
         let key = STANDARD.decode(key)?;
@@ -135,6 +177,81 @@ impl DbApi {
         Ok(model.value)
     }
 
+    /// Take the lease in the config `name` for `holder` for `duration`, unless another holder
+    /// has it and it hasn't expired. The row is locked while it's checked,
+    /// so that only one of the servers gets it.
+    pub async fn take_config_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        duration: chrono::Duration,
+    ) -> Result<bool> {
+        // the row needs to exist to be locked
+        entities::config::Entity::insert(entities::config::ActiveModel {
+            name: ActiveValue::set(name.to_string()),
+            value: ActiveValue::set(String::new()),
+        })
+        .on_conflict(
+            OnConflict::column(entities::config::Column::Name)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(&self.conn)
+        .await?;
+
+        let txn = self.conn.begin().await?;
+        let lease = entities::config::Entity::find()
+            .filter(entities::config::Column::Name.eq(name))
+            .lock_exclusive()
+            .one(&txn)
+            .await?
+            .and_then(|c| serde_json::from_str::<serde_json::Value>(&c.value).ok());
+        let now = Utc::now();
+        if let Some(lease) = lease {
+            let current = lease["holder"].as_str().unwrap_or_default();
+            let expires_at = lease["expires_at"].as_i64().unwrap_or_default();
+            if current != holder && expires_at > now.timestamp() {
+                txn.commit().await?;
+                return Ok(false);
+            }
+        }
+        let lease = serde_json::json!({
+            "holder": holder,
+            "expires_at": (now + duration).timestamp(),
+        });
+        entities::config::ActiveModel {
+            name: ActiveValue::unchanged(name.to_string()),
+            value: ActiveValue::set(lease.to_string()),
+        }
+        .update(&txn)
+        .await?;
+        txn.commit().await?;
+        Ok(true)
+    }
+
+    /// Give up the lease in the config `name` if `holder` still has it
+    pub async fn release_config_lease(&self, name: &str, holder: &str) -> Result<()> {
+        let txn = self.conn.begin().await?;
+        let lease = entities::config::Entity::find()
+            .filter(entities::config::Column::Name.eq(name))
+            .lock_exclusive()
+            .one(&txn)
+            .await?;
+        if let Some(lease) = lease {
+            let value = serde_json::from_str::<serde_json::Value>(&lease.value).ok();
+            if value.as_ref().and_then(|v| v["holder"].as_str()) == Some(holder) {
+                entities::config::ActiveModel {
+                    name: ActiveValue::unchanged(lease.name),
+                    value: ActiveValue::set(String::new()),
+                }
+                .update(&txn)
+                .await?;
+            }
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+
     pub async fn get_base_hostname(&self) -> Result<String> {
         self.get_config(LAPDEV_BASE_HOSTNAME).await
     }
@@ -148,6 +265,29 @@ impl DbApi {
             .unwrap_or(false))
     }
 
+    /// Clear the last inactivity of the workspaces that had activity
+    pub async fn clear_workspaces_last_inactivity(&self, ids: Vec<Uuid>) -> Result<()> {
+        entities::workspace::Entity::update_many()
+            .col_expr(
+                entities::workspace::Column::LastInactivity,
+                Expr::value(Option::<DateTimeWithTimeZone>::None),
+            )
+            .filter(entities::workspace::Column::Id.is_in(ids))
+            .filter(entities::workspace::Column::LastInactivity.is_not_null())
+            .exec(&self.conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn is_path_routing_enabled(&self) -> Result<bool> {
+        Ok(entities::config::Entity::find()
+            .filter(entities::config::Column::Name.eq(LAPDEV_PATH_ROUTING))
+            .one(&self.conn)
+            .await?
+            .map(|v| v.value == "yes")
+            .unwrap_or(false))
+    }
+
     pub async fn get_all_workspaces(
         &self,
         user_id: Uuid,
@@ -188,6 +328,34 @@ impl DbApi {
         Ok(model)
     }
 
+    pub async fn get_compose_services(
+        &self,
+        parent_id: Uuid,
+    ) -> Result<Vec<entities::workspace::Model>> {
+        let models = workspace::Entity::find()
+            .filter(entities::workspace::Column::ComposeParent.eq(parent_id))
+            .filter(entities::workspace::Column::DeletedAt.is_null())
+            .order_by_asc(entities::workspace::Column::Service)
+            .all(&self.conn)
+            .await?;
+        Ok(models)
+    }
+
+    pub async fn get_compose_service(
+        &self,
+        parent_id: Uuid,
+        service: &str,
+    ) -> Result<entities::workspace::Model> {
+        let model = workspace::Entity::find()
+            .filter(entities::workspace::Column::ComposeParent.eq(parent_id))
+            .filter(entities::workspace::Column::Service.eq(service))
+            .filter(entities::workspace::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?
+            .ok_or_else(|| anyhow!("no compose service found"))?;
+        Ok(model)
+    }
+
     pub async fn get_running_workspaces_on_host(
         &self,
         ws_host_id: Uuid,
@@ -255,7 +423,7 @@ impl DbApi {
     pub async fn create_new_user(
         &self,
         txn: &DatabaseTransaction,
-        provider: &AuthProvider,
+        provider: &str,
         provider_user: ProviderUser,
         token: String,
     ) -> Result<entities::user::Model> {
@@ -288,9 +456,10 @@ impl DbApi {
             id: ActiveValue::Set(Uuid::new_v4()),
             created_at: ActiveValue::Set(now.into()),
             deleted_at: ActiveValue::Set(None),
-            osuser: ActiveValue::Set(format!("{provider}_{}", provider_user.login)),
+            osuser: ActiveValue::Set(osuser(provider, &provider_user.login)),
             provider: ActiveValue::Set(provider.to_string()),
             provider_id: ActiveValue::Set(provider_user.id),
+            provider_subject: ActiveValue::Set(provider_user.subject),
             provider_login: ActiveValue::Set(provider_user.login),
             access_token: ActiveValue::Set(token),
             avatar_url: ActiveValue::Set(provider_user.avatar_url),
@@ -298,6 +467,7 @@ impl DbApi {
             name: ActiveValue::Set(provider_user.name),
             current_organization: ActiveValue::Set(org.id),
             cluster_admin: ActiveValue::Set(cluster_admin),
+            ..Default::default()
         }
         .insert(txn)
         .await?;
@@ -324,6 +494,82 @@ impl DbApi {
         Ok(model)
     }
 
+    /// Store the refreshed oauth tokens of the user
+    pub async fn update_user_oauth_token(
+        &self,
+        user_id: Uuid,
+        access_token: String,
+        refresh_token: Option<String>,
+        expires_at: Option<DateTimeWithTimeZone>,
+    ) -> Result<()> {
+        entities::user::ActiveModel {
+            id: ActiveValue::Set(user_id),
+            access_token: ActiveValue::Set(access_token),
+            oauth_refresh_token: ActiveValue::Set(refresh_token),
+            access_token_expires_at: ActiveValue::Set(expires_at),
+            ..Default::default()
+        }
+        .update(&self.conn)
+        .await?;
+        Ok(())
+    }
+
+    /// The local account with the login, which is stored as the provider subject
+    pub async fn get_local_user(&self, login: &str) -> Result<Option<entities::user::Model>> {
+        let model = entities::user::Entity::find()
+            .filter(entities::user::Column::Provider.eq(AuthProvider::Local.to_string()))
+            .filter(entities::user::Column::ProviderSubject.eq(login.to_lowercase()))
+            .filter(entities::user::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?;
+        Ok(model)
+    }
+
+    pub async fn has_local_users(&self) -> Result<bool> {
+        let model = entities::user::Entity::find()
+            .filter(entities::user::Column::Provider.eq(AuthProvider::Local.to_string()))
+            .filter(entities::user::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?;
+        Ok(model.is_some())
+    }
+
+    pub async fn get_user_git_token(
+        &self,
+        user_id: Uuid,
+        host: &str,
+    ) -> Result<Option<entities::user_git_token::Model>> {
+        let model = entities::user_git_token::Entity::find()
+            .filter(entities::user_git_token::Column::UserId.eq(user_id))
+            .filter(entities::user_git_token::Column::Host.eq(host.to_lowercase()))
+            .filter(entities::user_git_token::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?;
+        Ok(model)
+    }
+
+    pub async fn get_all_user_git_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<entities::user_git_token::Model>> {
+        let model = entities::user_git_token::Entity::find()
+            .filter(entities::user_git_token::Column::UserId.eq(user_id))
+            .filter(entities::user_git_token::Column::DeletedAt.is_null())
+            .order_by_asc(entities::user_git_token::Column::CreatedAt)
+            .all(&self.conn)
+            .await?;
+        Ok(model)
+    }
+
+    pub async fn get_git_token(&self, id: Uuid) -> Result<entities::user_git_token::Model> {
+        let model = entities::user_git_token::Entity::find_by_id(id)
+            .filter(entities::user_git_token::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?
+            .ok_or_else(|| anyhow!("no git token found"))?;
+        Ok(model)
+    }
+
     pub async fn get_user_organizations(
         &self,
         user_id: Uuid,
@@ -359,6 +605,27 @@ impl DbApi {
         Ok(model)
     }
 
+    pub async fn get_deploy_key(&self, id: Uuid) -> Result<entities::project_deploy_key::Model> {
+        let model = entities::project_deploy_key::Entity::find_by_id(id)
+            .filter(entities::project_deploy_key::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?
+            .ok_or_else(|| anyhow!("no deploy key found"))?;
+        Ok(model)
+    }
+
+    pub async fn get_project_deploy_key(
+        &self,
+        project_id: Uuid,
+    ) -> Result<Option<entities::project_deploy_key::Model>> {
+        let model = entities::project_deploy_key::Entity::find()
+            .filter(entities::project_deploy_key::Column::ProjectId.eq(project_id))
+            .filter(entities::project_deploy_key::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?;
+        Ok(model)
+    }
+
     pub async fn get_project(&self, id: Uuid) -> Result<entities::project::Model> {
         let model = entities::project::Entity::find_by_id(id)
             .filter(entities::project::Column::DeletedAt.is_null())
@@ -493,6 +760,114 @@ impl DbApi {
         Ok(model)
     }
 
+    pub async fn get_workspace_ports(
+        &self,
+        ws_id: Uuid,
+    ) -> Result<Vec<entities::workspace_port::Model>> {
+        let models = entities::workspace_port::Entity::find()
+            .filter(entities::workspace_port::Column::WorkspaceId.eq(ws_id))
+            .order_by_asc(entities::workspace_port::Column::Port)
+            .all(&self.conn)
+            .await?;
+        Ok(models)
+    }
+
+    pub async fn get_workspace_port_access(
+        &self,
+        ws_id: Uuid,
+        port: u16,
+        user_id: Uuid,
+    ) -> Result<Option<entities::workspace_port_access::Model>> {
+        let model = entities::workspace_port_access::Entity::find()
+            .filter(entities::workspace_port_access::Column::WorkspaceId.eq(ws_id))
+            .filter(entities::workspace_port_access::Column::Port.eq(port))
+            .filter(entities::workspace_port_access::Column::UserId.eq(user_id))
+            .filter(entities::workspace_port_access::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?;
+        Ok(model)
+    }
+
+    pub async fn get_all_workspace_port_access(
+        &self,
+        ws_id: Uuid,
+        port: u16,
+    ) -> Result<Vec<entities::workspace_port_access::Model>> {
+        let models = entities::workspace_port_access::Entity::find()
+            .filter(entities::workspace_port_access::Column::WorkspaceId.eq(ws_id))
+            .filter(entities::workspace_port_access::Column::Port.eq(port))
+            .filter(entities::workspace_port_access::Column::DeletedAt.is_null())
+            .order_by_asc(entities::workspace_port_access::Column::CreatedAt)
+            .all(&self.conn)
+            .await?;
+        Ok(models)
+    }
+
+    pub async fn get_workspace_port_share_link(
+        &self,
+        id: Uuid,
+    ) -> Result<Option<entities::workspace_port_share_link::Model>> {
+        let model = entities::workspace_port_share_link::Entity::find_by_id(id)
+            .one(&self.conn)
+            .await?;
+        Ok(model)
+    }
+
+    pub async fn get_workspace_port_share_links(
+        &self,
+        ws_id: Uuid,
+    ) -> Result<Vec<entities::workspace_port_share_link::Model>> {
+        let models = entities::workspace_port_share_link::Entity::find()
+            .filter(entities::workspace_port_share_link::Column::WorkspaceId.eq(ws_id))
+            .order_by_desc(entities::workspace_port_share_link::Column::CreatedAt)
+            .all(&self.conn)
+            .await?;
+        Ok(models)
+    }
+
+    pub async fn get_workspace_ssh_access(
+        &self,
+        ws_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<entities::workspace_ssh_access::Model>> {
+        let model = entities::workspace_ssh_access::Entity::find()
+            .filter(entities::workspace_ssh_access::Column::WorkspaceId.eq(ws_id))
+            .filter(entities::workspace_ssh_access::Column::UserId.eq(user_id))
+            .filter(entities::workspace_ssh_access::Column::DeletedAt.is_null())
+            .one(&self.conn)
+            .await?;
+        Ok(model)
+    }
+
+    pub async fn get_all_workspace_ssh_access(
+        &self,
+        ws_id: Uuid,
+    ) -> Result<
+        Vec<(
+            entities::workspace_ssh_access::Model,
+            Option<entities::user::Model>,
+        )>,
+    > {
+        let models = entities::workspace_ssh_access::Entity::find()
+            .find_also_related(entities::user::Entity)
+            .filter(entities::workspace_ssh_access::Column::WorkspaceId.eq(ws_id))
+            .filter(entities::workspace_ssh_access::Column::DeletedAt.is_null())
+            .order_by_asc(entities::workspace_ssh_access::Column::CreatedAt)
+            .all(&self.conn)
+            .await?;
+        Ok(models)
+    }
+
+    /// All the users who have registered this ssh public key
+    pub async fn get_ssh_public_key_users(&self, public_key: &str) -> Result<Vec<Uuid>> {
+        let models = entities::ssh_public_key::Entity::find()
+            .filter(entities::ssh_public_key::Column::ParsedKey.eq(public_key))
+            .filter(entities::ssh_public_key::Column::DeletedAt.is_null())
+            .all(&self.conn)
+            .await?;
+        Ok(models.into_iter().map(|m| m.user_id).collect())
+    }
+
     pub async fn get_workspace_host_by_host(
         &self,
         host: &str,
@@ -539,3 +914,19 @@ impl DbApi {
         Ok(models)
     }
 }
+
+/// The os user name of the user in the workspaces,
+/// the logins of some providers can have characters that aren't allowed in it, e.g. emails
+fn osuser(provider: &str, login: &str) -> String {
+    let login: String = login
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{provider}_{login}")
+}
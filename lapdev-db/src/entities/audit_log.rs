@@ -16,6 +16,7 @@ pub struct Model {
     pub action: String,
     pub ip: Option<String>,
     pub user_agent: Option<String>,
+    pub details: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -10,11 +10,17 @@ pub mod organization_member;
 pub mod prebuild;
 pub mod prebuild_replica;
 pub mod project;
+pub mod project_deploy_key;
 pub mod quota;
 pub mod ssh_public_key;
+pub mod ssh_session_recording;
 pub mod usage;
 pub mod user;
+pub mod user_git_token;
 pub mod user_invitation;
 pub mod workspace;
 pub mod workspace_host;
 pub mod workspace_port;
+pub mod workspace_port_access;
+pub mod workspace_port_share_link;
+pub mod workspace_ssh_access;
@@ -14,6 +14,9 @@ pub struct Model {
     pub auto_stop: Option<i32>,
     pub allow_workspace_change_auto_stop: bool,
     pub last_auto_stop_check: Option<DateTimeWithTimeZone>,
+    pub ssh_session_recording: bool,
+    pub allow_ssh_agent_forwarding: bool,
+    pub activity_ports: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
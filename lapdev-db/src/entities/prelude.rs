@@ -8,11 +8,17 @@ pub use super::organization_member::Entity as OrganizationMember;
 pub use super::prebuild::Entity as Prebuild;
 pub use super::prebuild_replica::Entity as PrebuildReplica;
 pub use super::project::Entity as Project;
+pub use super::project_deploy_key::Entity as ProjectDeployKey;
 pub use super::quota::Entity as Quota;
 pub use super::ssh_public_key::Entity as SshPublicKey;
+pub use super::ssh_session_recording::Entity as SshSessionRecording;
 pub use super::usage::Entity as Usage;
 pub use super::user::Entity as User;
+pub use super::user_git_token::Entity as UserGitToken;
 pub use super::user_invitation::Entity as UserInvitation;
 pub use super::workspace::Entity as Workspace;
 pub use super::workspace_host::Entity as WorkspaceHost;
 pub use super::workspace_port::Entity as WorkspacePort;
+pub use super::workspace_port_access::Entity as WorkspacePortAccess;
+pub use super::workspace_port_share_link::Entity as WorkspacePortShareLink;
+pub use super::workspace_ssh_access::Entity as WorkspaceSshAccess;
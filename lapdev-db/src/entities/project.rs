@@ -16,6 +16,8 @@ pub struct Model {
     pub repo_name: String,
     pub machine_type_id: Uuid,
     pub env: Option<String>,
+    pub git_credential_username: Option<String>,
+    pub git_credential_token: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -30,6 +32,8 @@ pub enum Relation {
     MachineType,
     #[sea_orm(has_many = "super::prebuild::Entity")]
     Prebuild,
+    #[sea_orm(has_many = "super::project_deploy_key::Entity")]
+    ProjectDeployKey,
 }
 
 impl Related<super::machine_type::Entity> for Entity {
@@ -44,4 +48,10 @@ impl Related<super::prebuild::Entity> for Entity {
     }
 }
 
+impl Related<super::project_deploy_key::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ProjectDeployKey.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
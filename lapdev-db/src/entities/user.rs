@@ -11,14 +11,24 @@ pub struct Model {
     pub deleted_at: Option<DateTimeWithTimeZone>,
     pub provider: String,
     pub provider_id: i32,
+    pub provider_subject: Option<String>,
     pub provider_login: String,
     pub access_token: String,
+    pub oauth_refresh_token: Option<String>,
+    pub access_token_expires_at: Option<DateTimeWithTimeZone>,
     pub avatar_url: Option<String>,
     pub email: Option<String>,
     pub name: Option<String>,
     pub osuser: String,
     pub current_organization: Uuid,
     pub cluster_admin: bool,
+    pub password_hash: Option<String>,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_step: Option<i64>,
+    pub setup_token: Option<String>,
+    pub setup_token_expires_at: Option<DateTimeWithTimeZone>,
+    pub session_epoch: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -26,6 +26,7 @@ pub struct Model {
     pub ide_port: Option<i32>,
     pub ssh_private_key: String,
     pub ssh_public_key: String,
+    pub ssh_host_public_key: Option<String>,
     pub env: Option<String>,
     pub cores: String,
     pub usage_id: Option<i32>,
@@ -10,7 +10,9 @@ pub struct Model {
     pub workspace_id: Uuid,
     pub port: i32,
     pub host_port: i32,
-    pub shared: bool,
+    pub auto_detected: bool,
+    pub visibility: String,
+    pub label: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,81 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231106_100804_create_workspace_table::Workspace;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkspaceSshAccess::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WorkspaceSshAccess::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspaceSshAccess::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(WorkspaceSshAccess::DeletedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(WorkspaceSshAccess::WorkspaceId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(WorkspaceSshAccess::UserId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(WorkspaceSshAccess::GrantedBy)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspaceSshAccess::Access)
+                            .string()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from_tbl(WorkspaceSshAccess::Table)
+                            .from_col(WorkspaceSshAccess::WorkspaceId)
+                            .to_tbl(Workspace::Table)
+                            .to_col(Workspace::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("workspace_ssh_access_workspace_id_user_id_deleted_at_idx")
+                    .table(WorkspaceSshAccess::Table)
+                    .col(WorkspaceSshAccess::WorkspaceId)
+                    .col(WorkspaceSshAccess::UserId)
+                    .col(WorkspaceSshAccess::DeletedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum WorkspaceSshAccess {
+    Table,
+    Id,
+    CreatedAt,
+    DeletedAt,
+    WorkspaceId,
+    UserId,
+    GrantedBy,
+    Access,
+}
@@ -0,0 +1,121 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuditLog::Table)
+                    .add_column(ColumnDef::new(AuditLog::Details).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Organization::Table)
+                    .add_column(
+                        ColumnDef::new(Organization::SshSessionRecording)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(SshSessionRecording::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SshSessionRecording::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(SshSessionRecording::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SshSessionRecording::OrganizationId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SshSessionRecording::WorkspaceId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SshSessionRecording::UserId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SshSessionRecording::AuditLogId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SshSessionRecording::Content)
+                            .text()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from_tbl(SshSessionRecording::Table)
+                            .from_col(SshSessionRecording::AuditLogId)
+                            .to_tbl(AuditLog::Table)
+                            .to_col(AuditLog::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("ssh_session_recording_audit_log_id_idx")
+                    .table(SshSessionRecording::Table)
+                    .col(SshSessionRecording::AuditLogId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuditLog {
+    Table,
+    Id,
+    Details,
+}
+
+#[derive(DeriveIden)]
+enum Organization {
+    Table,
+    SshSessionRecording,
+}
+
+#[derive(DeriveIden)]
+enum SshSessionRecording {
+    Table,
+    Id,
+    CreatedAt,
+    OrganizationId,
+    WorkspaceId,
+    UserId,
+    AuditLogId,
+    Content,
+}
@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(WorkspacePort::Table)
+                    .add_column(
+                        ColumnDef::new(WorkspacePort::Visibility)
+                            .string()
+                            .not_null()
+                            .default("Private"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // shared ports were open to the organization members
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(WorkspacePort::Table)
+                    .value(WorkspacePort::Visibility, "Organization")
+                    .and_where(Expr::col(WorkspacePort::Shared).eq(true))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(WorkspacePort::Table)
+                    .drop_column(WorkspacePort::Shared)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum WorkspacePort {
+    Table,
+    Shared,
+    Visibility,
+}
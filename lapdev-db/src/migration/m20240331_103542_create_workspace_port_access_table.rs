@@ -0,0 +1,80 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231106_100804_create_workspace_table::Workspace;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkspacePortAccess::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WorkspacePortAccess::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortAccess::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(WorkspacePortAccess::DeletedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(WorkspacePortAccess::WorkspaceId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortAccess::Port)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortAccess::UserId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from_tbl(WorkspacePortAccess::Table)
+                            .from_col(WorkspacePortAccess::WorkspaceId)
+                            .to_tbl(Workspace::Table)
+                            .to_col(Workspace::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("workspace_port_access_workspace_id_port_user_id_deleted_at_idx")
+                    .table(WorkspacePortAccess::Table)
+                    .col(WorkspacePortAccess::WorkspaceId)
+                    .col(WorkspacePortAccess::Port)
+                    .col(WorkspacePortAccess::UserId)
+                    .col(WorkspacePortAccess::DeletedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum WorkspacePortAccess {
+    Table,
+    Id,
+    CreatedAt,
+    DeletedAt,
+    WorkspaceId,
+    Port,
+    UserId,
+}
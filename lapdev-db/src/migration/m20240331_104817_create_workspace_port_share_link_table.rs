@@ -0,0 +1,87 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231106_100804_create_workspace_table::Workspace;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkspacePortShareLink::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WorkspacePortShareLink::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortShareLink::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortShareLink::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortShareLink::RevokedAt)
+                            .timestamp_with_time_zone(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortShareLink::WorkspaceId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortShareLink::Port)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WorkspacePortShareLink::CreatedBy)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from_tbl(WorkspacePortShareLink::Table)
+                            .from_col(WorkspacePortShareLink::WorkspaceId)
+                            .to_tbl(Workspace::Table)
+                            .to_col(Workspace::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("workspace_port_share_link_workspace_id_port_idx")
+                    .table(WorkspacePortShareLink::Table)
+                    .col(WorkspacePortShareLink::WorkspaceId)
+                    .col(WorkspacePortShareLink::Port)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum WorkspacePortShareLink {
+    Table,
+    Id,
+    CreatedAt,
+    ExpiresAt,
+    RevokedAt,
+    WorkspaceId,
+    Port,
+    CreatedBy,
+}
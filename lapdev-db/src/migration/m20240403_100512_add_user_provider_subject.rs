@@ -0,0 +1,55 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(ColumnDef::new(User::ProviderSubject).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        // the users of the providers without numeric ids share the same provider id,
+        // so they're told apart by the subject
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("user_provider_provider_id_idx")
+                    .table(User::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("user_provider_provider_id_provider_subject_idx")
+                    .table(User::Table)
+                    .unique()
+                    .nulls_not_distinct()
+                    .col(User::Provider)
+                    .col(User::ProviderId)
+                    .col(User::ProviderSubject)
+                    .col(User::DeletedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Provider,
+    ProviderId,
+    ProviderSubject,
+    DeletedAt,
+}
@@ -0,0 +1,57 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // one column per statement, sqlite can't alter multiple columns at once
+        for mut column in [
+            ColumnDef::new(User::PasswordHash).string().to_owned(),
+            ColumnDef::new(User::TotpSecret).string().to_owned(),
+            ColumnDef::new(User::TotpEnabled)
+                .boolean()
+                .not_null()
+                .default(false)
+                .to_owned(),
+            ColumnDef::new(User::TotpLastStep).big_integer().to_owned(),
+            ColumnDef::new(User::SetupToken).string().to_owned(),
+            ColumnDef::new(User::SetupTokenExpiresAt)
+                .timestamp_with_time_zone()
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(User::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("user_setup_token_idx")
+                    .table(User::Table)
+                    .col(User::SetupToken)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    PasswordHash,
+    TotpSecret,
+    TotpEnabled,
+    TotpLastStep,
+    SetupToken,
+    SetupTokenExpiresAt,
+}
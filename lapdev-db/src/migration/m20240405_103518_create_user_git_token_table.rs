@@ -0,0 +1,71 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231106_100019_create_user_table::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserGitToken::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserGitToken::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(UserGitToken::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(UserGitToken::DeletedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(UserGitToken::UserId).uuid().not_null())
+                    .col(ColumnDef::new(UserGitToken::Host).string().not_null())
+                    .col(ColumnDef::new(UserGitToken::Username).string().not_null())
+                    .col(ColumnDef::new(UserGitToken::Token).string().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from_tbl(UserGitToken::Table)
+                            .from_col(UserGitToken::UserId)
+                            .to_tbl(User::Table)
+                            .to_col(User::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("user_git_token_user_id_host_deleted_at_idx")
+                    .table(UserGitToken::Table)
+                    .unique()
+                    .nulls_not_distinct()
+                    .col(UserGitToken::UserId)
+                    .col(UserGitToken::Host)
+                    .col(UserGitToken::DeletedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserGitToken {
+    Table,
+    Id,
+    CreatedAt,
+    DeletedAt,
+    UserId,
+    Host,
+    Username,
+    Token,
+}
@@ -0,0 +1,88 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231109_171859_create_project_table::Project;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ProjectDeployKey::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ProjectDeployKey::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ProjectDeployKey::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ProjectDeployKey::DeletedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(ProjectDeployKey::OrganizationId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ProjectDeployKey::CreatedBy)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ProjectDeployKey::ProjectId).uuid())
+                    .col(
+                        ColumnDef::new(ProjectDeployKey::PrivateKey)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ProjectDeployKey::PublicKey)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ProjectDeployKey::HostKey).string())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from_tbl(ProjectDeployKey::Table)
+                            .from_col(ProjectDeployKey::ProjectId)
+                            .to_tbl(Project::Table)
+                            .to_col(Project::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("project_deploy_key_project_id_deleted_at_idx")
+                    .table(ProjectDeployKey::Table)
+                    .col(ProjectDeployKey::ProjectId)
+                    .col(ProjectDeployKey::DeletedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum ProjectDeployKey {
+    Table,
+    Id,
+    CreatedAt,
+    DeletedAt,
+    OrganizationId,
+    CreatedBy,
+    ProjectId,
+    PrivateKey,
+    PublicKey,
+    HostKey,
+}
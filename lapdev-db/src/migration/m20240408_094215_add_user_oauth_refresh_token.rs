@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // the access tokens of bitbucket and gitlab expire, and are refreshed with these,
+        // one column per statement, sqlite can't alter multiple columns at once
+        for mut column in [
+            ColumnDef::new(User::OauthRefreshToken).string().to_owned(),
+            ColumnDef::new(User::AccessTokenExpiresAt)
+                .timestamp_with_time_zone()
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(User::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    OauthRefreshToken,
+    AccessTokenExpiresAt,
+}
@@ -0,0 +1,39 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // the token scoped to the repository of the project, which the git credential
+        // helper in the workspaces hands out, it's encrypted with the secret key,
+        // one column per statement, sqlite can't alter multiple columns at once
+        for mut column in [
+            ColumnDef::new(Project::GitCredentialUsername)
+                .string()
+                .to_owned(),
+            ColumnDef::new(Project::GitCredentialToken)
+                .string()
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Project::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Project {
+    Table,
+    GitCredentialUsername,
+    GitCredentialToken,
+}
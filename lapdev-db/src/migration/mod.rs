@@ -17,6 +17,23 @@ mod m20240228_141013_create_user_invitation_table;
 mod m20240311_220708_create_prebuild_replica_table;
 mod m20240312_175753_create_table_update_trigger;
 mod m20240316_194115_create_workspace_port_table;
+mod m20240325_101532_add_workspace_port_auto_detected;
+mod m20240326_152214_create_workspace_ssh_access_table;
+mod m20240327_093012_create_ssh_session_recording_table;
+mod m20240328_104522_add_organization_ssh_agent_forwarding;
+mod m20240329_141207_add_workspace_ssh_host_public_key;
+mod m20240331_102311_add_workspace_port_visibility;
+mod m20240331_103542_create_workspace_port_access_table;
+mod m20240331_104817_create_workspace_port_share_link_table;
+mod m20240401_094127_add_workspace_port_label;
+mod m20240402_091536_add_organization_activity_ports;
+mod m20240403_100512_add_user_provider_subject;
+mod m20240405_101224_add_user_local_account;
+mod m20240405_103518_create_user_git_token_table;
+mod m20240407_092318_create_project_deploy_key_table;
+mod m20240408_094215_add_user_oauth_refresh_token;
+mod m20240408_103027_add_user_session_epoch;
+mod m20240408_112936_add_project_git_credential;
 
 pub struct Migrator;
 
@@ -41,6 +58,23 @@ impl MigratorTrait for Migrator {
             Box::new(m20240311_220708_create_prebuild_replica_table::Migration),
             Box::new(m20240312_175753_create_table_update_trigger::Migration),
             Box::new(m20240316_194115_create_workspace_port_table::Migration),
+            Box::new(m20240325_101532_add_workspace_port_auto_detected::Migration),
+            Box::new(m20240326_152214_create_workspace_ssh_access_table::Migration),
+            Box::new(m20240327_093012_create_ssh_session_recording_table::Migration),
+            Box::new(m20240328_104522_add_organization_ssh_agent_forwarding::Migration),
+            Box::new(m20240329_141207_add_workspace_ssh_host_public_key::Migration),
+            Box::new(m20240331_102311_add_workspace_port_visibility::Migration),
+            Box::new(m20240331_103542_create_workspace_port_access_table::Migration),
+            Box::new(m20240331_104817_create_workspace_port_share_link_table::Migration),
+            Box::new(m20240401_094127_add_workspace_port_label::Migration),
+            Box::new(m20240402_091536_add_organization_activity_ports::Migration),
+            Box::new(m20240403_100512_add_user_provider_subject::Migration),
+            Box::new(m20240405_101224_add_user_local_account::Migration),
+            Box::new(m20240405_103518_create_user_git_token_table::Migration),
+            Box::new(m20240407_092318_create_project_deploy_key_table::Migration),
+            Box::new(m20240408_094215_add_user_oauth_refresh_token::Migration),
+            Box::new(m20240408_103027_add_user_session_epoch::Migration),
+            Box::new(m20240408_112936_add_project_git_credential::Migration),
         ]
     }
 }
@@ -233,6 +233,7 @@ pub mod tests {
             osuser: ActiveValue::Set("".to_string()),
             ssh_private_key: ActiveValue::Set("".to_string()),
             ssh_public_key: ActiveValue::Set("".to_string()),
+            ssh_host_public_key: ActiveValue::Set(None),
             cores: ActiveValue::Set(serde_json::to_string(&[1, 2]).unwrap()),
             ssh_port: ActiveValue::Set(None),
             ide_port: ActiveValue::Set(None),
@@ -276,6 +277,7 @@ pub mod tests {
             osuser: ActiveValue::Set("".to_string()),
             ssh_private_key: ActiveValue::Set("".to_string()),
             ssh_public_key: ActiveValue::Set("".to_string()),
+            ssh_host_public_key: ActiveValue::Set(None),
             cores: ActiveValue::Set(serde_json::to_string(&[1, 2]).unwrap()),
             ssh_port: ActiveValue::Set(None),
             ide_port: ActiveValue::Set(None),
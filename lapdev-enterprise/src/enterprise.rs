@@ -6,8 +6,9 @@ use lapdev_common::{AuditLogRecord, LAPDEV_BASE_HOSTNAME};
 use lapdev_common::{AuditLogResult, QuotaKind, QuotaResult};
 use lapdev_db::{api::DbApi, entities};
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseTransaction, EntityTrait, PaginatorTrait,
-    QueryFilter, QueryOrder, QuerySelect,
+    sea_query::{Expr, LikeExpr},
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, DatabaseTransaction, EntityTrait,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
 };
 use uuid::Uuid;
 
@@ -177,25 +178,66 @@ impl Enterprise {
         Ok(None)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_audit_logs(
         &self,
         organization: Uuid,
         start: DateTime<FixedOffset>,
         end: DateTime<FixedOffset>,
+        action: Option<String>,
+        search: Option<String>,
         page_size: u64,
         page: u64,
     ) -> Result<AuditLogResult> {
-        let result = entities::audit_log::Entity::find()
+        let mut query = entities::audit_log::Entity::find()
             .find_also_related(entities::user::Entity)
             .filter(entities::audit_log::Column::OrganizationId.eq(organization))
             .filter(entities::audit_log::Column::Time.gte(start))
-            .filter(entities::audit_log::Column::Time.lte(end))
+            .filter(entities::audit_log::Column::Time.lte(end));
+        if let Some(action) = action.filter(|a| !a.is_empty()) {
+            query = query.filter(entities::audit_log::Column::Action.eq(action));
+        }
+        if let Some(search) = search.filter(|s| !s.is_empty()) {
+            let pattern = contains_pattern(&search);
+            query = query.filter(
+                Condition::any()
+                    .add(
+                        Expr::col((
+                            entities::audit_log::Entity,
+                            entities::audit_log::Column::ResourceName,
+                        ))
+                        .like(LikeExpr::new(&pattern).escape('\\')),
+                    )
+                    .add(
+                        Expr::col((
+                            entities::audit_log::Entity,
+                            entities::audit_log::Column::Details,
+                        ))
+                        .like(LikeExpr::new(&pattern).escape('\\')),
+                    ),
+            );
+        }
+        let result = query
             .order_by_desc(entities::audit_log::Column::Time)
             .paginate(&self.db.conn, page_size);
 
         let items_and_pages = result.num_items_and_pages().await?;
         let records = result.fetch_page(page).await?;
 
+        let recordings: HashMap<i32, Uuid> = entities::ssh_session_recording::Entity::find()
+            .filter(
+                entities::ssh_session_recording::Column::AuditLogId
+                    .is_in(records.iter().map(|(record, _)| record.id)),
+            )
+            .select_only()
+            .column(entities::ssh_session_recording::Column::AuditLogId)
+            .column(entities::ssh_session_recording::Column::Id)
+            .into_tuple::<(i32, Uuid)>()
+            .all(&self.db.conn)
+            .await?
+            .into_iter()
+            .collect();
+
         let records = records
             .into_iter()
             .map(|(record, user)| AuditLogRecord {
@@ -206,6 +248,9 @@ impl Enterprise {
                 resource_kind: record.resource_kind.clone(),
                 resource_name: record.resource_name.clone(),
                 action: record.action.clone(),
+                ip: record.ip.clone(),
+                details: record.details.clone(),
+                recording: recordings.get(&record.id).copied(),
             })
             .collect();
 
@@ -231,6 +276,39 @@ impl Enterprise {
         action: String,
         ip: Option<String>,
         user_agent: Option<String>,
+    ) -> Result<entities::audit_log::Model> {
+        self.insert_audit_log_with_details(
+            txn,
+            time,
+            user_id,
+            org_id,
+            resource_kind,
+            resource_id,
+            resource_name,
+            action,
+            ip,
+            user_agent,
+            None,
+        )
+        .await
+    }
+
+    /// Same as `insert_audit_log`, with extra details of the action,
+    /// e.g. the command of an ssh exec request
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_audit_log_with_details(
+        &self,
+        txn: &DatabaseTransaction,
+        time: DateTime<FixedOffset>,
+        user_id: Uuid,
+        org_id: Uuid,
+        resource_kind: String,
+        resource_id: Uuid,
+        resource_name: String,
+        action: String,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        details: Option<String>,
     ) -> Result<entities::audit_log::Model> {
         Ok(entities::audit_log::ActiveModel {
             time: ActiveValue::Set(time),
@@ -242,6 +320,7 @@ impl Enterprise {
             action: ActiveValue::Set(action),
             ip: ActiveValue::Set(ip),
             user_agent: ActiveValue::Set(user_agent),
+            details: ActiveValue::Set(details),
             ..Default::default()
         }
         .insert(txn)
@@ -284,3 +363,29 @@ impl Enterprise {
         Ok(hostnames)
     }
 }
+
+/// The LIKE pattern for a search, with the wildcards in it matched literally
+fn contains_pattern(search: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in search.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains_pattern() {
+        assert_eq!(contains_pattern("ws-1"), "%ws-1%");
+        assert_eq!(contains_pattern("100%"), "%100\\%%");
+        assert_eq!(contains_pattern("my_ws"), "%my\\_ws%");
+        assert_eq!(contains_pattern("a\\b"), "%a\\\\b%");
+    }
+}
@@ -408,6 +408,7 @@ pub mod tests {
             osuser: ActiveValue::Set("".to_string()),
             ssh_private_key: ActiveValue::Set("".to_string()),
             ssh_public_key: ActiveValue::Set("".to_string()),
+            ssh_host_public_key: ActiveValue::Set(None),
             cores: ActiveValue::Set(serde_json::to_string(&[1, 2])?),
             ssh_port: ActiveValue::Set(None),
             ide_port: ActiveValue::Set(None),
@@ -481,11 +482,12 @@ pub mod tests {
         let user = db
             .create_new_user(
                 &txn,
-                &AuthProvider::Github,
+                &AuthProvider::Github.to_string(),
                 ProviderUser {
                     avatar_url: None,
                     email: None,
                     id: 0,
+                    subject: None,
                     login: "test".to_string(),
                     name: None,
                 },
@@ -552,11 +554,12 @@ pub mod tests {
         let user = db
             .create_new_user(
                 &txn,
-                &AuthProvider::Github,
+                &AuthProvider::Github.to_string(),
                 ProviderUser {
                     avatar_url: None,
                     email: None,
                     id: 0,
+                    subject: None,
                     login: "test".to_string(),
                     name: None,
                 },
@@ -628,11 +631,12 @@ pub mod tests {
         let user = db
             .create_new_user(
                 &txn,
-                &AuthProvider::Github,
+                &AuthProvider::Github.to_string(),
                 ProviderUser {
                     avatar_url: None,
                     email: None,
                     id: 0,
+                    subject: None,
                     login: "test".to_string(),
                     name: None,
                 },
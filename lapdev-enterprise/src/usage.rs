@@ -371,11 +371,12 @@ pub mod tests {
         let user = db
             .create_new_user(
                 &txn,
-                &AuthProvider::Github,
+                &AuthProvider::Github.to_string(),
                 ProviderUser {
                     avatar_url: None,
                     email: None,
                     id: 0,
+                    subject: None,
                     login: "test".to_string(),
                     name: None,
                 },
@@ -518,11 +519,12 @@ pub mod tests {
         let user = db
             .create_new_user(
                 &txn,
-                &AuthProvider::Github,
+                &AuthProvider::Github.to_string(),
                 ProviderUser {
                     avatar_url: None,
                     email: None,
                     id: 0,
+                    subject: None,
                     login: "test".to_string(),
                     name: None,
                 },
@@ -0,0 +1,256 @@
+use std::{
+    io,
+    os::unix::fs::DirBuilderExt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use russh::{server::Handle, ChannelId, CryptoVec};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc, oneshot, Mutex},
+    task::{JoinHandle, JoinSet},
+};
+
+/// OpenSSH doesn't accept agent messages larger than this either
+const MAX_AGENT_MESSAGE_LEN: usize = 256 * 1024;
+/// The `SSH_AGENT_FAILURE` response, for the requests the user's agent can't answer
+const AGENT_FAILURE: [u8; 5] = [0, 0, 0, 1, 5];
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+static SOCKET_DIR_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Forwards the user's ssh agent to the processes in the workspace, i.e. ssh -A.
+///
+/// russh can only open the `auth-agent@openssh.com` channel to the user inside a handler
+/// callback, so each ssh connection has a single one, opened when agent forwarding
+/// is requested. The processes connect to a unix socket, and their requests are
+/// sent over the channel one at a time, since the agent answers each with one response.
+pub struct AgentForward {
+    pub channel: ChannelId,
+    dir: PathBuf,
+    confirmed: Option<oneshot::Sender<()>>,
+    responses: mpsc::UnboundedSender<Vec<u8>>,
+    task: JoinHandle<()>,
+}
+
+impl AgentForward {
+    /// `channel` is the agent channel just opened to the user,
+    /// which isn't confirmed yet.
+    pub fn start(channel: ChannelId, handle: Handle) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "ssh-lapdev-{}-{}",
+            std::process::id(),
+            SOCKET_DIR_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        // a leftover from a previous agent process with the same pid
+        let _ = std::fs::remove_dir_all(&dir);
+        // only root, which the sessions run as, can reach the socket
+        std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        let listener = match UnixListener::bind(dir.join("agent.sock")) {
+            Ok(listener) => listener,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(e.into());
+            }
+        };
+
+        let (confirmed_tx, confirmed_rx) = oneshot::channel();
+        let (responses_tx, responses_rx) = mpsc::unbounded_channel();
+        let relay = Arc::new(Mutex::new(AgentRelay {
+            handle,
+            channel,
+            confirmed: Some(confirmed_rx),
+            responses: responses_rx,
+            buf: Vec::new(),
+            closed: false,
+        }));
+        let task = tokio::spawn(accept_agent_connections(listener, relay));
+        Ok(Self {
+            channel,
+            dir,
+            confirmed: Some(confirmed_tx),
+            responses: responses_tx,
+            task,
+        })
+    }
+
+    /// The path for SSH_AUTH_SOCK
+    pub fn socket_path(&self) -> PathBuf {
+        self.dir.join("agent.sock")
+    }
+
+    /// The user confirmed the agent channel
+    pub fn confirm(&mut self) {
+        if let Some(confirmed) = self.confirmed.take() {
+            let _ = confirmed.send(());
+        }
+    }
+
+    /// Data from the user's agent
+    pub fn response_data(&self, data: &[u8]) {
+        let _ = self.responses.send(data.to_vec());
+    }
+}
+
+impl Drop for AgentForward {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+struct AgentRelay {
+    handle: Handle,
+    channel: ChannelId,
+    confirmed: Option<oneshot::Receiver<()>>,
+    responses: mpsc::UnboundedReceiver<Vec<u8>>,
+    // the part of the response that has come so far
+    buf: Vec<u8>,
+    closed: bool,
+}
+
+impl AgentRelay {
+    async fn request(&mut self, request: Vec<u8>) -> Vec<u8> {
+        match self.try_request(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("ssh agent forwarding error: {e:#}");
+                // the responses can't be matched to the requests anymore
+                self.closed = true;
+                AGENT_FAILURE.to_vec()
+            }
+        }
+    }
+
+    async fn try_request(&mut self, request: Vec<u8>) -> Result<Vec<u8>> {
+        if self.closed {
+            return Err(anyhow!("the agent channel is closed"));
+        }
+        if let Some(confirmed) = self.confirmed.take() {
+            tokio::time::timeout(CONFIRM_TIMEOUT, confirmed)
+                .await
+                .map_err(|_| anyhow!("the agent channel wasn't confirmed"))??;
+        }
+        self.handle
+            .data(self.channel, CryptoVec::from(request))
+            .await
+            .map_err(|_| anyhow!("can't send to the agent channel"))?;
+        loop {
+            if let Some(response) = take_message(&mut self.buf)? {
+                return Ok(response);
+            }
+            let data = self
+                .responses
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("the agent channel is closed"))?;
+            self.buf.extend_from_slice(&data);
+        }
+    }
+}
+
+async fn accept_agent_connections(listener: UnixListener, relay: Arc<Mutex<AgentRelay>>) {
+    // the connections are aborted with this task when the agent forwarding stops
+    let mut conns = JoinSet::new();
+    while let Ok((stream, _)) = listener.accept().await {
+        conns.spawn(serve_agent_connection(stream, relay.clone()));
+        while conns.try_join_next().is_some() {}
+    }
+}
+
+async fn serve_agent_connection(
+    mut stream: UnixStream,
+    relay: Arc<Mutex<AgentRelay>>,
+) -> Result<()> {
+    while let Some(request) = read_message(&mut stream).await? {
+        let response = relay.lock().await.request(request).await;
+        stream.write_all(&response).await?;
+    }
+    Ok(())
+}
+
+/// Reads a whole agent message, i.e. the 4 bytes length and the content.
+/// Returns None when the connection is closed between messages.
+async fn read_message(reader: &mut (impl AsyncRead + Unpin)) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let content_len = u32::from_be_bytes(len) as usize;
+    if content_len > MAX_AGENT_MESSAGE_LEN {
+        return Err(anyhow!("agent message is too large"));
+    }
+    let mut msg = vec![0; 4 + content_len];
+    msg[..4].copy_from_slice(&len);
+    reader.read_exact(&mut msg[4..]).await?;
+    Ok(Some(msg))
+}
+
+/// Takes a whole agent message from the buffer, if it's complete
+fn take_message(buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+    let Some(len) = buf.get(..4) else {
+        return Ok(None);
+    };
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    if len > MAX_AGENT_MESSAGE_LEN {
+        return Err(anyhow!("agent message is too large"));
+    }
+    if buf.len() < 4 + len {
+        return Ok(None);
+    }
+    Ok(Some(buf.drain(..4 + len).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: &[u8]) -> Vec<u8> {
+        let mut msg = (content.len() as u32).to_be_bytes().to_vec();
+        msg.extend_from_slice(content);
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_read_message() {
+        let mut data = message(b"req1");
+        data.extend(message(b""));
+        let mut reader = &data[..];
+        assert_eq!(
+            read_message(&mut reader).await.unwrap(),
+            Some(message(b"req1"))
+        );
+        assert_eq!(read_message(&mut reader).await.unwrap(), Some(message(b"")));
+        assert_eq!(read_message(&mut reader).await.unwrap(), None);
+
+        // cut in the middle of a message
+        let data = message(b"req1");
+        assert!(read_message(&mut &data[..6]).await.is_err());
+
+        let too_large = ((MAX_AGENT_MESSAGE_LEN + 1) as u32).to_be_bytes();
+        assert!(read_message(&mut &too_large[..]).await.is_err());
+    }
+
+    #[test]
+    fn test_take_message() {
+        let resp = message(b"resp");
+        let mut buf = resp[..5].to_vec();
+        assert_eq!(take_message(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&resp[5..]);
+        buf.extend_from_slice(&resp[..2]);
+        assert_eq!(take_message(&mut buf).unwrap(), Some(resp.clone()));
+        assert_eq!(buf, resp[..2]);
+
+        let mut buf = ((MAX_AGENT_MESSAGE_LEN + 1) as u32).to_be_bytes().to_vec();
+        assert!(take_message(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    ffi::CString,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    process::Command,
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+use serde_json::{Map, Value};
+
+use crate::{LapdevGuestAgentError, LAPDEV_WORKSPACE_USER};
+
+/// The name git looks up for `credential.helper=lapdev`
+pub const GIT_CREDENTIAL_HELPER: &str = "git-credential-lapdev";
+const GIT_CREDENTIAL_HELPER_PATH: &str = "/usr/local/bin/git-credential-lapdev";
+const GIT_CREDENTIAL_SOCKET_DIR: &str = "/run/lapdev";
+const GIT_CREDENTIAL_SOCKET: &str = "/run/lapdev/git-credential.sock";
+
+/// The connection lapdev-ws opened with the `credentials` command,
+/// which the credential requests are relayed through one at a time.
+struct Relay {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+static RELAY: Mutex<Option<Relay>> = Mutex::new(None);
+
+/// Keeps the connection from lapdev-ws for relaying the credential requests,
+/// replacing the previous one if lapdev-ws reconnected.
+pub fn set_relay(stream: TcpStream, reader: BufReader<TcpStream>) {
+    if let Ok(mut relay) = RELAY.lock() {
+        *relay = Some(Relay { stream, reader });
+    }
+}
+
+fn relay(request: &str) -> Result<String, LapdevGuestAgentError> {
+    let mut guard = RELAY
+        .lock()
+        .map_err(|_| LapdevGuestAgentError::Credential("relay lock poisoned".to_string()))?;
+    let relay = guard.as_mut().ok_or_else(|| {
+        LapdevGuestAgentError::Credential("lapdev-ws isn't connected".to_string())
+    })?;
+    let result = (|| {
+        relay.stream.write_all(format!("{request}\n").as_bytes())?;
+        relay
+            .stream
+            .set_read_timeout(Some(Duration::from_secs(30)))?;
+        let mut line = String::new();
+        if relay.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        Ok(line)
+    })();
+    match result {
+        Ok(line) => Ok(line.trim().to_string()),
+        Err(e) => {
+            // the connection is unusable after a failed round trip,
+            // lapdev-ws will open a new one
+            *guard = None;
+            Err(e.into())
+        }
+    }
+}
+
+/// Makes the guest agent binary available as the git credential helper,
+/// and configures git to use it for the whole container.
+pub fn install_helper() -> Result<(), LapdevGuestAgentError> {
+    let exe = std::env::current_exe()?;
+    let _ = fs::remove_file(GIT_CREDENTIAL_HELPER_PATH);
+    std::os::unix::fs::symlink(exe, GIT_CREDENTIAL_HELPER_PATH)?;
+    for (key, value) in [
+        ("credential.helper", "lapdev"),
+        // so that git tells us which repository it's asking for
+        ("credential.useHttpPath", "true"),
+    ] {
+        Command::new("git")
+            .args(["config", "--system", key, value])
+            .status()?;
+    }
+    Ok(())
+}
+
+/// Serves the credential helper on a unix socket, so that only the processes inside the container
+/// that run as root, like the workspace sessions, or as the user of the image can ask for credentials.
+pub fn run_credential_server() -> Result<(), LapdevGuestAgentError> {
+    fs::create_dir_all(GIT_CREDENTIAL_SOCKET_DIR)?;
+    let _ = fs::remove_file(GIT_CREDENTIAL_SOCKET);
+    let listener = UnixListener::bind(GIT_CREDENTIAL_SOCKET)?;
+    // the services in the image that run as other users don't get the credentials,
+    // the socket belongs to the user of the image if it isn't root
+    fs::set_permissions(GIT_CREDENTIAL_SOCKET, fs::Permissions::from_mode(0o600))?;
+    if let Ok(user) = std::env::var(LAPDEV_WORKSPACE_USER) {
+        match user_ids(&user) {
+            Some((uid, gid)) => {
+                std::os::unix::fs::chown(GIT_CREDENTIAL_SOCKET, Some(uid), Some(gid))?
+            }
+            None => eprintln!("can't find workspace user {user} for the git credentials"),
+        }
+    }
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept credential connection error: {e:?}");
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            if let Err(e) = handle_credential_conn(stream) {
+                eprintln!("handle credential connection error: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// The uid and gid of a docker `USER`, which is `user`, `uid`, `user:group` or `uid:gid`
+fn user_ids(user: &str) -> Option<(u32, u32)> {
+    let (user, group) = match user.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (user, None),
+    };
+    let passwd = match user.parse::<u32>() {
+        Ok(uid) => unsafe { libc::getpwuid(uid) },
+        Err(_) => {
+            let name = CString::new(user).ok()?;
+            unsafe { libc::getpwnam(name.as_ptr()) }
+        }
+    };
+    let (uid, gid) = if passwd.is_null() {
+        // a numeric uid doesn't need an entry in /etc/passwd
+        let uid = user.parse().ok()?;
+        (uid, uid)
+    } else {
+        unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) }
+    };
+    let gid = match group {
+        None => gid,
+        Some(group) => match group.parse() {
+            Ok(gid) => gid,
+            Err(_) => {
+                let name = CString::new(group).ok()?;
+                let group = unsafe { libc::getgrnam(name.as_ptr()) };
+                if group.is_null() {
+                    return None;
+                }
+                unsafe { (*group).gr_gid }
+            }
+        },
+    };
+    Some((uid, gid))
+}
+
+fn handle_credential_conn(mut stream: UnixStream) -> Result<(), LapdevGuestAgentError> {
+    let mut line = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut line)?;
+    let resp = relay(line.trim())
+        .unwrap_or_else(|e| serde_json::json!({ "error": e.to_string() }).to_string());
+    stream.write_all(format!("{resp}\n").as_bytes())?;
+    Ok(())
+}
+
+/// The entry point when the binary runs as `git-credential-lapdev`.
+///
+/// It only answers `get`, and never stores anything,
+/// so the credential stays in the memory of git.
+pub fn run_helper(operation: Option<&str>) -> Result<(), LapdevGuestAgentError> {
+    let mut attrs = Map::new();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            if matches!(key, "protocol" | "host" | "path") {
+                attrs.insert(key.to_string(), Value::String(value.to_string()));
+            }
+        }
+    }
+    if operation != Some("get") {
+        return Ok(());
+    }
+
+    let mut stream = UnixStream::connect(GIT_CREDENTIAL_SOCKET)?;
+    stream.write_all(format!("{}\n", Value::Object(attrs)).as_bytes())?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let resp: Value = serde_json::from_str(&line)
+        .map_err(|e| LapdevGuestAgentError::Credential(e.to_string()))?;
+    if let Some(error) = resp.get("error").and_then(|e| e.as_str()) {
+        // git goes on with the next helper or asks the user
+        return Err(LapdevGuestAgentError::Credential(error.to_string()));
+    }
+
+    let mut stdout = io::stdout().lock();
+    for key in ["username", "password", "password_expiry_utc"] {
+        match resp.get(key) {
+            Some(Value::String(value)) => writeln!(stdout, "{key}={value}")?,
+            Some(Value::Number(value)) => writeln!(stdout, "{key}={value}")?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::user_ids;
+
+    #[test]
+    fn test_user_ids() {
+        assert_eq!(user_ids("root"), Some((0, 0)));
+        assert_eq!(user_ids("0"), Some((0, 0)));
+        assert_eq!(user_ids("root:5"), Some((0, 5)));
+        assert_eq!(user_ids("4242"), Some((4242, 4242)));
+        assert_eq!(user_ids("4242:4343"), Some((4242, 4343)));
+        assert_eq!(user_ids("lapdev-no-such-user"), None);
+        assert_eq!(user_ids("root:lapdev-no-such-group"), None);
+    }
+}
@@ -1,13 +1,43 @@
-use std::{fs, io, process::Command, thread, time::Duration};
+use std::{fs, io, os::unix::fs::PermissionsExt, process::Command, thread, time::Duration};
+
+mod agent;
+mod credential;
+mod port;
+mod pty;
+mod sftp;
+mod ssh;
+
+pub use credential::GIT_CREDENTIAL_HELPER;
 
 pub const LAPDEV_SSH_PUBLIC_KEY: &str = "LAPDEV_SSH_PUBLIC_KEY";
+/// The private host key for the sshd in the env of the containers created
+/// before it was mounted at `SSH_HOST_KEY_PATH`.
+pub const LAPDEV_SSH_HOST_KEY: &str = "LAPDEV_SSH_HOST_KEY";
+/// lapdev-ws mounts the private host key for the sshd, which the ssh proxy pins, here.
+pub const SSH_HOST_KEY_PATH: &str = "/run/lapdev/ssh_host_key";
 pub const LAPDEV_IDE_CMDS: &str = "LAPDEV_IDE_CMDS";
 pub const LAPDEV_CMDS: &str = "LAPDEV_CMDS";
+pub const LAPDEV_GUEST_AGENT_PORT: u16 = 30001;
+/// The secret lapdev-ws generates for the container,
+/// which every connection to the guest agent port starts with.
+pub const LAPDEV_GUEST_AGENT_SECRET: &str = "LAPDEV_GUEST_AGENT_SECRET";
+/// The `USER` of the image, e.g. `vscode` or `1000:1000`, which gets access to the git credentials.
+pub const LAPDEV_WORKSPACE_USER: &str = "LAPDEV_WORKSPACE_USER";
+/// Set it to use the sshd from the image instead of the embedded ssh server.
+pub const LAPDEV_SYSTEM_SSHD: &str = "LAPDEV_SYSTEM_SSHD";
 
 pub fn run() {
+    // the processes we start don't need to see it
+    let secret = std::env::var(LAPDEV_GUEST_AGENT_SECRET).ok();
+    std::env::remove_var(LAPDEV_GUEST_AGENT_SECRET);
+
     thread::spawn(move || {
-        if let Err(e) = run_sshd() {
-            eprintln!("run sshd error: {e:?}");
+        if use_system_sshd() {
+            if let Err(e) = run_sshd() {
+                eprintln!("run sshd error: {e:?}");
+            }
+        } else if let Err(e) = run_ssh_server() {
+            eprintln!("run ssh server error: {e:?}");
         }
     });
     thread::spawn(move || {
@@ -20,16 +50,39 @@ pub fn run() {
             eprintln!("run cmds error: {e:?}");
         }
     });
+    thread::spawn(move || {
+        if let Err(e) = port::run_port_server(secret) {
+            eprintln!("run port server error: {e:?}");
+        }
+    });
+    thread::spawn(move || {
+        if let Err(e) = credential::install_helper() {
+            eprintln!("install git credential helper error: {e}");
+        }
+        if let Err(e) = credential::run_credential_server() {
+            eprintln!("run credential server error: {e}");
+        }
+    });
 
     loop {
         thread::sleep(Duration::from_secs(60));
     }
 }
 
+/// Runs as the git credential helper, `operation` is the argument git passes to it.
+pub fn run_git_credential_helper(operation: Option<&str>) {
+    if let Err(e) = credential::run_helper(operation) {
+        eprintln!("lapdev credential helper: {e}");
+    }
+}
+
 #[derive(Debug)]
 enum LapdevGuestAgentError {
     SshPublicKey(String),
     Cmds(String),
+    Port(String),
+    Ssh(String),
+    Credential(String),
     IoError(io::Error),
 }
 
@@ -44,21 +97,46 @@ impl std::fmt::Display for LapdevGuestAgentError {
         match self {
             LapdevGuestAgentError::SshPublicKey(err) => f.write_str(err),
             LapdevGuestAgentError::Cmds(err) => f.write_str(err),
+            LapdevGuestAgentError::Port(err) => f.write_str(err),
+            LapdevGuestAgentError::Ssh(err) => f.write_str(err),
+            LapdevGuestAgentError::Credential(err) => f.write_str(err),
             LapdevGuestAgentError::IoError(e) => f.write_str(&e.to_string()),
         }
     }
 }
 
+fn use_system_sshd() -> bool {
+    std::env::var(LAPDEV_SYSTEM_SSHD)
+        .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+fn run_ssh_server() -> Result<(), LapdevGuestAgentError> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(ssh::run_ssh_server())
+        .map_err(|e| LapdevGuestAgentError::Ssh(format!("{e:#}")))
+}
+
+const SYSTEM_SSHD_HOST_KEY_PATH: &str = "/etc/ssh/ssh_host_lapdev_key";
+
 fn run_sshd() -> Result<(), LapdevGuestAgentError> {
     let public_key = std::env::var(LAPDEV_SSH_PUBLIC_KEY)
         .map_err(|e| LapdevGuestAgentError::SshPublicKey(e.to_string()))?;
     fs::create_dir_all("/root/.ssh/")?;
     fs::write("/root/.ssh/authorized_keys", public_key)?;
-    Command::new("/usr/sbin/sshd")
-        .arg("-D")
-        .arg("-o")
-        .arg("AcceptEnv=*")
-        .status()?;
+    let mut cmd = Command::new("/usr/sbin/sshd");
+    cmd.arg("-D").arg("-o").arg("AcceptEnv=*");
+    if fs::metadata(SSH_HOST_KEY_PATH).is_ok() {
+        cmd.arg("-h").arg(SSH_HOST_KEY_PATH);
+    } else if let Ok(host_key) = std::env::var(LAPDEV_SSH_HOST_KEY) {
+        fs::create_dir_all("/etc/ssh/")?;
+        fs::write(SYSTEM_SSHD_HOST_KEY_PATH, host_key)?;
+        fs::set_permissions(SYSTEM_SSHD_HOST_KEY_PATH, fs::Permissions::from_mode(0o600))?;
+        cmd.arg("-h").arg(SYSTEM_SSHD_HOST_KEY_PATH);
+    }
+    cmd.status()?;
     Ok(())
 }
 
@@ -1,3 +1,17 @@
+use std::path::Path;
+
+use lapdev_guest_agent::GIT_CREDENTIAL_HELPER;
+
 pub fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // the binary is symlinked as the git credential helper
+    let name = args
+        .first()
+        .and_then(|arg| Path::new(arg).file_name())
+        .and_then(|name| name.to_str());
+    if name == Some(GIT_CREDENTIAL_HELPER) {
+        lapdev_guest_agent::run_git_credential_helper(args.get(1).map(|s| s.as_str()));
+        return;
+    }
     lapdev_guest_agent::run();
 }
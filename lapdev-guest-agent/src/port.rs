@@ -0,0 +1,182 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use crate::{credential, LapdevGuestAgentError, LAPDEV_GUEST_AGENT_PORT};
+
+// the tcp state value for LISTEN in /proc/net/tcp
+const TCP_LISTEN: &str = "0A";
+
+/// Serves the control port that lapdev-ws connects to.
+///
+/// Each connection starts with the secret of the container on its own line,
+/// and then a single line command:
+/// `ports` streams the listening ports as a json array whenever they change,
+/// `forward <port>` pipes the connection to the port inside the container,
+/// `credentials` keeps the connection for relaying the git credential requests.
+pub fn run_port_server(secret: Option<String>) -> Result<(), LapdevGuestAgentError> {
+    let secret = secret.filter(|s| !s.is_empty()).ok_or_else(|| {
+        LapdevGuestAgentError::Port("no guest agent secret, the control port is disabled".into())
+    })?;
+    let listener = TcpListener::bind(("0.0.0.0", LAPDEV_GUEST_AGENT_PORT))?;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept port server connection error: {e:?}");
+                continue;
+            }
+        };
+        let secret = secret.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_conn(stream, &secret) {
+                eprintln!("handle port server connection error: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_conn(stream: TcpStream, secret: &str) -> Result<(), LapdevGuestAgentError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    // don't wait forever for a client that never authenticates
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    reader.read_line(&mut line)?;
+    if !secret_matches(line.trim_end_matches(['\r', '\n']), secret) {
+        return Err(LapdevGuestAgentError::Port(
+            "connection without the right secret".to_string(),
+        ));
+    }
+    line.clear();
+    reader.read_line(&mut line)?;
+    stream.set_read_timeout(None)?;
+    let line = line.trim();
+    if line == "ports" {
+        watch_ports(stream)
+    } else if let Some(port) = line.strip_prefix("forward ") {
+        let port: u16 = port
+            .trim()
+            .parse()
+            .map_err(|_| LapdevGuestAgentError::Port(format!("invalid port {port}")))?;
+        // anything the client sent after the command line is still in the reader buffer
+        let buffered = reader.buffer().to_vec();
+        forward_port(stream, port, buffered)
+    } else if line == "credentials" {
+        credential::set_relay(stream, reader);
+        Ok(())
+    } else {
+        Err(LapdevGuestAgentError::Port(format!(
+            "unknown command {line}"
+        )))
+    }
+}
+
+/// Compares in constant time, so that the secret can't be guessed byte by byte
+fn secret_matches(given: &str, secret: &str) -> bool {
+    given.len() == secret.len()
+        && given
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn watch_ports(mut stream: TcpStream) -> Result<(), LapdevGuestAgentError> {
+    let mut last: Option<BTreeSet<u16>> = None;
+    loop {
+        let ports = listening_ports();
+        if last.as_ref() != Some(&ports) {
+            let mut msg = serde_json::to_string(&ports)
+                .map_err(|e| LapdevGuestAgentError::Port(e.to_string()))?;
+            msg.push('\n');
+            // a failed write means lapdev-ws went away, so we stop here
+            stream.write_all(msg.as_bytes())?;
+            last = Some(ports);
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+}
+
+fn forward_port(
+    mut stream: TcpStream,
+    port: u16,
+    buffered: Vec<u8>,
+) -> Result<(), LapdevGuestAgentError> {
+    let mut local =
+        TcpStream::connect(("127.0.0.1", port)).or_else(|_| TcpStream::connect(("::1", port)))?;
+    if !buffered.is_empty() {
+        local.write_all(&buffered)?;
+    }
+
+    let mut local_read = local.try_clone()?;
+    let mut stream_write = stream.try_clone()?;
+    let t = thread::spawn(move || {
+        let _ = io::copy(&mut local_read, &mut stream_write);
+        let _ = stream_write.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut stream, &mut local);
+    let _ = local.shutdown(Shutdown::Write);
+    let _ = t.join();
+    Ok(())
+}
+
+/// All tcp ports in the LISTEN state inside the container,
+/// excluding the ones lapdev already handles.
+fn listening_ports() -> BTreeSet<u16> {
+    let mut ports = BTreeSet::new();
+    for path in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        if let Ok(content) = fs::read_to_string(path) {
+            ports.extend(parse_listening_ports(&content));
+        }
+    }
+    ports.remove(&22);
+    ports.remove(&30000);
+    ports.remove(&LAPDEV_GUEST_AGENT_PORT);
+    ports
+}
+
+fn parse_listening_ports(content: &str) -> Vec<u16> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let local_address = parts.nth(1)?;
+            let state = parts.nth(1)?;
+            if state != TCP_LISTEN {
+                return None;
+            }
+            let (_, port) = local_address.rsplit_once(':')?;
+            u16::from_str_radix(port, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listening_ports() {
+        let content = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1 1 0 100 0 0 10 0
+   1: 0100007F:0BB8 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 2 1 0 100 0 0 10 0
+   2: 0100007F:1F90 0100007F:D2F0 01 00000000:00000000 00:00000000 00000000     0        0 3 1 0 20 4 30 10 -1
+";
+        assert_eq!(parse_listening_ports(content), vec![8080, 3000]);
+    }
+
+    #[test]
+    fn test_secret_matches() {
+        assert!(secret_matches("abc123", "abc123"));
+        assert!(!secret_matches("abc124", "abc123"));
+        assert!(!secret_matches("abc12", "abc123"));
+        assert!(!secret_matches("", "abc123"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    process::Stdio,
+    ptr,
+};
+
+use tokio::process::{Child, Command};
+
+pub struct Pty {
+    master: OwnedFd,
+    slave: Option<OwnedFd>,
+}
+
+impl Pty {
+    pub fn open(cols: u32, rows: u32) -> io::Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+        let size = winsize(cols, rows);
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &size,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (master, slave) =
+            unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        Ok(Self {
+            master,
+            slave: Some(slave),
+        })
+    }
+
+    /// Spawn the command with the pty as its controlling terminal.
+    /// The slave side is closed in our process afterwards,
+    /// so reading the master gives an error once the command exits.
+    pub fn spawn(&mut self, mut cmd: Command) -> io::Result<Child> {
+        let slave = self
+            .slave
+            .take()
+            .ok_or_else(|| io::Error::other("pty already used"))?;
+        cmd.stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        cmd.spawn()
+    }
+
+    pub fn resize(&self, cols: u32, rows: u32) -> io::Result<()> {
+        let size = winsize(cols, rows);
+        let ret = unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ as _, &size) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn reader(&self) -> io::Result<tokio::fs::File> {
+        Ok(tokio::fs::File::from_std(self.master.try_clone()?.into()))
+    }
+
+    pub fn writer(&self) -> io::Result<tokio::fs::File> {
+        Ok(tokio::fs::File::from_std(self.master.try_clone()?.into()))
+    }
+}
+
+fn winsize(cols: u32, rows: u32) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows as u16,
+        ws_col: cols as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn read_all(pty: &Pty) -> String {
+        let mut reader = pty.reader().unwrap();
+        let mut output = Vec::new();
+        // it errors instead of eof once the command exits
+        let _ = reader.read_to_end(&mut output).await;
+        String::from_utf8_lossy(&output).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_pty_size() {
+        let mut pty = Pty::open(80, 24).unwrap();
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("sleep 0.2; stty size; tty");
+        let mut child = pty.spawn(cmd).unwrap();
+        pty.resize(120, 40).unwrap();
+        let output = read_all(&pty).await;
+        assert!(child.wait().await.unwrap().success());
+        let mut lines = output.lines();
+        assert_eq!(lines.next().map(|l| l.trim()), Some("40 120"));
+        assert!(lines.next().unwrap().starts_with("/dev/"));
+        // the slave side can only be used once
+        assert!(pty.spawn(Command::new("true")).is_err());
+    }
+}
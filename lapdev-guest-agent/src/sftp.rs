@@ -0,0 +1,425 @@
+use std::{
+    collections::HashMap,
+    io::{self, SeekFrom},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+};
+
+use async_trait::async_trait;
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+enum OpenHandle {
+    File(tokio::fs::File),
+    // the directory entries are sent in one go, so it's None after the first readdir
+    Dir(Option<Vec<File>>),
+}
+
+/// A sftp server on the container's file system.
+#[derive(Default)]
+pub struct SftpSession {
+    version: Option<u32>,
+    handles: HashMap<String, OpenHandle>,
+    next_handle: u64,
+}
+
+impl SftpSession {
+    fn add_handle(&mut self, handle: OpenHandle) -> String {
+        self.next_handle += 1;
+        let name = self.next_handle.to_string();
+        self.handles.insert(name.clone(), handle);
+        name
+    }
+
+    fn file(&mut self, handle: &str) -> Result<&mut tokio::fs::File, StatusCode> {
+        match self.handles.get_mut(handle) {
+            Some(OpenHandle::File(file)) => Ok(file),
+            _ => Err(StatusCode::Failure),
+        }
+    }
+}
+
+fn status_ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn io_status(e: io::Error) -> StatusCode {
+    match e.kind() {
+        io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+        io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        _ => StatusCode::Failure,
+    }
+}
+
+async fn set_attrs(path: &str, attrs: &FileAttributes) -> Result<(), StatusCode> {
+    if let Some(permissions) = attrs.permissions {
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(permissions & 0o7777))
+            .await
+            .map_err(io_status)?;
+    }
+    if let Some(size) = attrs.size {
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .await
+            .map_err(io_status)?;
+        file.set_len(size).await.map_err(io_status)?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl russh_sftp::server::Handler for SftpSession {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+
+    async fn init(
+        &mut self,
+        version: u32,
+        _extensions: HashMap<String, String>,
+    ) -> Result<Version, Self::Error> {
+        if self.version.is_some() {
+            return Err(StatusCode::ConnectionLost);
+        }
+        self.version = Some(version);
+        Ok(Version::new())
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        let mut options = tokio::fs::OpenOptions::new();
+        options
+            .read(pflags.contains(OpenFlags::READ))
+            .write(pflags.contains(OpenFlags::WRITE))
+            .append(pflags.contains(OpenFlags::APPEND))
+            .truncate(pflags.contains(OpenFlags::TRUNCATE));
+        if pflags.contains(OpenFlags::CREATE) {
+            if pflags.contains(OpenFlags::EXCLUDE) {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+            options.mode(attrs.permissions.unwrap_or(0o644) & 0o7777);
+        }
+        let file = options.open(&filename).await.map_err(io_status)?;
+        let handle = self.add_handle(OpenHandle::File(file));
+        Ok(Handle { id, handle })
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        if let Some(OpenHandle::File(mut file)) = self.handles.remove(&handle) {
+            file.flush().await.map_err(io_status)?;
+        }
+        Ok(status_ok(id))
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<Data, Self::Error> {
+        let file = self.file(&handle)?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(io_status)?;
+        let mut data = vec![0; len as usize];
+        let n = file.read(&mut data).await.map_err(io_status)?;
+        if n == 0 {
+            return Err(StatusCode::Eof);
+        }
+        data.truncate(n);
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, Self::Error> {
+        let file = self.file(&handle)?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(io_status)?;
+        file.write_all(&data).await.map_err(io_status)?;
+        Ok(status_ok(id))
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let metadata = tokio::fs::symlink_metadata(&path)
+            .await
+            .map_err(io_status)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let file = self.file(&handle)?;
+        let metadata = file.metadata().await.map_err(io_status)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    async fn setstat(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        set_attrs(&path, &attrs).await?;
+        Ok(status_ok(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        handle: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        let file = self.file(&handle)?;
+        if let Some(permissions) = attrs.permissions {
+            file.set_permissions(std::fs::Permissions::from_mode(permissions & 0o7777))
+                .await
+                .map_err(io_status)?;
+        }
+        if let Some(size) = attrs.size {
+            file.set_len(size).await.map_err(io_status)?;
+        }
+        Ok(status_ok(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let mut dir = tokio::fs::read_dir(&path).await.map_err(io_status)?;
+        let mut files = Vec::new();
+        for name in [".", ".."] {
+            if let Ok(metadata) = tokio::fs::metadata(PathBuf::from(&path).join(name)).await {
+                files.push(File::new(name.to_string(), FileAttributes::from(&metadata)));
+            }
+        }
+        while let Some(entry) = dir.next_entry().await.map_err(io_status)? {
+            let Ok(metadata) = tokio::fs::symlink_metadata(entry.path()).await else {
+                continue;
+            };
+            files.push(File::new(
+                entry.file_name().to_string_lossy().to_string(),
+                FileAttributes::from(&metadata),
+            ));
+        }
+        let handle = self.add_handle(OpenHandle::Dir(Some(files)));
+        Ok(Handle { id, handle })
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        match self.handles.get_mut(&handle) {
+            Some(OpenHandle::Dir(files)) => match files.take() {
+                Some(files) => Ok(Name { id, files }),
+                None => Err(StatusCode::Eof),
+            },
+            _ => Err(StatusCode::Failure),
+        }
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        tokio::fs::remove_file(&filename).await.map_err(io_status)?;
+        Ok(status_ok(id))
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        tokio::fs::create_dir(&path).await.map_err(io_status)?;
+        set_attrs(&path, &attrs).await?;
+        Ok(status_ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        tokio::fs::remove_dir(&path).await.map_err(io_status)?;
+        Ok(status_ok(id))
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        // relative paths are relative to the home folder, like openssh does
+        let path = if path.starts_with('/') {
+            PathBuf::from(path)
+        } else {
+            PathBuf::from(crate::ssh::home_dir()).join(path)
+        };
+        let path = tokio::fs::canonicalize(&path).await.unwrap_or(path);
+        Ok(Name {
+            id,
+            files: vec![File::dummy(path.to_string_lossy().to_string())],
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let metadata = tokio::fs::metadata(&path).await.map_err(io_status)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, Self::Error> {
+        tokio::fs::rename(&oldpath, &newpath)
+            .await
+            .map_err(io_status)?;
+        Ok(status_ok(id))
+    }
+
+    async fn readlink(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        let target = tokio::fs::read_link(&path).await.map_err(io_status)?;
+        Ok(Name {
+            id,
+            files: vec![File::dummy(target.to_string_lossy().to_string())],
+        })
+    }
+
+    async fn symlink(
+        &mut self,
+        id: u32,
+        linkpath: String,
+        targetpath: String,
+    ) -> Result<Status, Self::Error> {
+        tokio::fs::symlink(&targetpath, &linkpath)
+            .await
+            .map_err(io_status)?;
+        Ok(status_ok(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use russh_sftp::server::Handler;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sftp_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file").to_string_lossy().to_string();
+        let mut sftp = SftpSession::default();
+        sftp.init(3, HashMap::new()).await.unwrap();
+
+        let handle = sftp
+            .open(
+                1,
+                path.clone(),
+                OpenFlags::CREATE | OpenFlags::WRITE | OpenFlags::TRUNCATE,
+                FileAttributes {
+                    permissions: Some(0o600),
+                    ..FileAttributes::empty()
+                },
+            )
+            .await
+            .unwrap()
+            .handle;
+        sftp.write(2, handle.clone(), 0, b"hello world".to_vec())
+            .await
+            .unwrap();
+        sftp.close(3, handle).await.unwrap();
+
+        let attrs = sftp.stat(4, path.clone()).await.unwrap().attrs;
+        assert_eq!(attrs.size, Some(11));
+        assert_eq!(attrs.permissions.unwrap() & 0o777, 0o600);
+
+        let handle = sftp
+            .open(5, path.clone(), OpenFlags::READ, FileAttributes::empty())
+            .await
+            .unwrap()
+            .handle;
+        let data = sftp.read(6, handle.clone(), 6, 100).await.unwrap().data;
+        assert_eq!(data, b"world");
+        assert!(matches!(
+            sftp.read(7, handle.clone(), 11, 100).await,
+            Err(StatusCode::Eof)
+        ));
+        sftp.close(8, handle).await.unwrap();
+
+        // a second init on the same session isn't allowed
+        assert!(sftp.init(3, HashMap::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sftp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_string_lossy().to_string();
+        let mut sftp = SftpSession::default();
+        sftp.mkdir(1, format!("{path}/sub"), FileAttributes::empty())
+            .await
+            .unwrap();
+        tokio::fs::write(format!("{path}/file"), b"").await.unwrap();
+
+        let handle = sftp.opendir(2, path.clone()).await.unwrap().handle;
+        let mut names: Vec<String> = sftp
+            .readdir(3, handle.clone())
+            .await
+            .unwrap()
+            .files
+            .into_iter()
+            .map(|f| f.filename)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![".", "..", "file", "sub"]);
+        // all the entries are sent with the first readdir
+        assert!(matches!(
+            sftp.readdir(4, handle).await,
+            Err(StatusCode::Eof)
+        ));
+
+        sftp.rename(5, format!("{path}/file"), format!("{path}/sub/moved"))
+            .await
+            .unwrap();
+        let real = sftp
+            .realpath(6, format!("{path}/sub/../sub/moved"))
+            .await
+            .unwrap();
+        assert_eq!(
+            real.files[0].filename,
+            dir.path()
+                .canonicalize()
+                .unwrap()
+                .join("sub/moved")
+                .to_string_lossy()
+        );
+        assert!(matches!(
+            sftp.rmdir(7, format!("{path}/sub")).await,
+            Err(StatusCode::Failure)
+        ));
+        sftp.remove(8, format!("{path}/sub/moved")).await.unwrap();
+        sftp.rmdir(9, format!("{path}/sub")).await.unwrap();
+        assert!(matches!(
+            sftp.stat(10, format!("{path}/sub")).await,
+            Err(StatusCode::NoSuchFile)
+        ));
+    }
+}
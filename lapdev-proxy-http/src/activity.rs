@@ -0,0 +1,130 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::extract::ws::Message;
+use lapdev_db::api::DbApi;
+use uuid::Uuid;
+
+/// The activities are collected and written to the db once in this interval
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// The header of a vscode remote protocol message: type (1 byte), id (4), ack (4)
+/// and payload length (4). The keep alive and ack messages that openvscode-server's
+/// client keeps sending while the user is idle are just the header, so only the
+/// binary messages that carry a payload count as activity.
+const VSCODE_MESSAGE_HEADER_LEN: usize = 13;
+
+/// Records the http requests and websocket messages that go through the proxy
+/// as workspace activity. The activity clears the workspace's last inactivity,
+/// which lapdev-ws sets again once it sees the workspace idle.
+pub struct ActivityRecorder {
+    db: DbApi,
+    active: Mutex<HashSet<Uuid>>,
+}
+
+impl ActivityRecorder {
+    pub fn new(db: DbApi) -> Self {
+        Self {
+            db,
+            active: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn record(&self, ws_id: Uuid) {
+        if let Ok(mut active) = self.active.lock() {
+            active.insert(ws_id);
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let active: Vec<Uuid> = match self.active.lock() {
+                Ok(mut active) => active.drain().collect(),
+                Err(_) => continue,
+            };
+            if active.is_empty() {
+                continue;
+            }
+            if let Err(e) = self.db.clear_workspaces_last_inactivity(active).await {
+                tracing::error!("clear workspaces last inactivity error: {e:#}");
+            }
+        }
+    }
+}
+
+/// The activity of a workspace that's recorded by the proxy
+#[derive(Clone)]
+pub struct WorkspaceActivity {
+    pub recorder: Arc<ActivityRecorder>,
+    pub ws_id: Uuid,
+}
+
+impl WorkspaceActivity {
+    pub fn record(&self) {
+        self.recorder.record(self.ws_id);
+    }
+
+    /// Record a websocket message from the client if it comes from the user
+    pub fn record_message(&self, msg: &Message) {
+        if is_user_message(msg) {
+            self.record();
+        }
+    }
+}
+
+fn is_user_message(msg: &Message) -> bool {
+    match msg {
+        Message::Text(text) => !text.is_empty(),
+        Message::Binary(binary) => binary.len() > VSCODE_MESSAGE_HEADER_LEN,
+        Message::Ping(_) | Message::Pong(_) | Message::Close(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_user_message() {
+        assert!(is_user_message(&Message::Text("input".to_string())));
+        assert!(!is_user_message(&Message::Text(String::new())));
+
+        // keep alive: type 9, no payload
+        let mut keep_alive = vec![9u8];
+        keep_alive.extend_from_slice(&[0; VSCODE_MESSAGE_HEADER_LEN - 1]);
+        assert!(!is_user_message(&Message::Binary(keep_alive.clone())));
+
+        let mut regular = keep_alive;
+        regular[0] = 1;
+        regular[12] = 2;
+        regular.extend_from_slice(b"{}");
+        assert!(is_user_message(&Message::Binary(regular)));
+
+        assert!(!is_user_message(&Message::Ping(vec![1])));
+        assert!(!is_user_message(&Message::Pong(vec![1])));
+        assert!(!is_user_message(&Message::Close(None)));
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        let db = lapdev_db::tests::prepare_db().await.unwrap();
+        let recorder = Arc::new(ActivityRecorder::new(db));
+        let ws_id = Uuid::new_v4();
+        let activity = WorkspaceActivity {
+            recorder: recorder.clone(),
+            ws_id,
+        };
+        activity.record_message(&Message::Ping(vec![]));
+        assert!(recorder.active.lock().unwrap().is_empty());
+
+        activity.record_message(&Message::Text("input".to_string()));
+        activity.record();
+        let active = recorder.active.lock().unwrap();
+        assert_eq!(active.len(), 1);
+        assert!(active.contains(&ws_id));
+    }
+}
@@ -1,60 +1,552 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
 use anyhow::Result;
 use axum::{
-    extract::{ws::WebSocket, WebSocketUpgrade},
-    http::Uri,
-    response::Response,
+    body::Body,
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        ConnectInfo, WebSocketUpgrade,
+    },
+    http::{
+        header, request::Parts, HeaderMap, HeaderName, HeaderValue, Request, StatusCode, Uri,
+        Version,
+    },
+    response::{IntoResponse, Response},
 };
 use futures::{SinkExt, StreamExt};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{self, client::IntoClientRequest},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::activity::WorkspaceActivity;
+
+/// The headers that only apply to a single connection and aren't forwarded
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+/// How long to wait for the other side to reply to a close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Put into the request extensions by the https server,
+/// so that the forwarded headers carry the right scheme
+#[derive(Clone, Copy, Debug)]
+pub struct TlsConnection;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ForwardConfig {
+    /// How long to wait for the tcp connection to the workspace
+    pub connect_timeout: Duration,
+    /// How long a pooled connection, or a websocket without any messages, is kept open
+    pub idle_timeout: Duration,
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// The reverse proxy to the workspaces. The http connections to the workspace hosts are pooled,
+/// and the bodies are streamed in both directions.
+pub struct Forwarder {
+    client: Client<HttpConnector, Body>,
+    /// gRPC only works over http2, and the dev servers serve it without tls,
+    /// so it's forwarded with http2 prior knowledge
+    h2_client: Client<HttpConnector, Body>,
+    config: ForwardConfig,
+}
+
+impl Forwarder {
+    pub fn new(config: ForwardConfig) -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(config.connect_timeout));
+        connector.set_nodelay(true);
+        let client = Client::builder(TokioExecutor::new())
+            .pool_idle_timeout(config.idle_timeout)
+            .build(connector.clone());
+        let h2_client = Client::builder(TokioExecutor::new())
+            .pool_idle_timeout(config.idle_timeout)
+            .http2_only(true)
+            .build(connector);
+        Self {
+            client,
+            h2_client,
+            config,
+        }
+    }
 
-pub enum ProxyForward {
-    Resp(Response),
-    Proxy(Uri),
-}
-
-pub async fn handler(
-    host: &str,
-    path_query: &str,
-    websocket: Option<WebSocketUpgrade>,
-    port: Option<u16>,
-) -> Option<ProxyForward> {
-    let port = port?;
-    if let Some(websocket) = websocket {
-        println!("proxy ide websocket");
-        let uri = format!("ws://{host}:{port}{}", path_query);
-        let resp = websocket.on_upgrade(move |socket| async move {
-            if let Err(e) = proxy_socket(socket, uri).await {
-                println!("handle websocket error: {e}");
-            } else {
-                println!("handle websocket finished");
+    /// Forward the request to the port on the workspace host,
+    /// `path_query` replaces the path and the query of the request.
+    pub async fn forward(
+        &self,
+        req: Request<Body>,
+        host: &str,
+        port: u16,
+        path_query: &str,
+        websocket: Option<WebSocketUpgrade>,
+        activity: Option<WorkspaceActivity>,
+    ) -> Response {
+        if let Some(activity) = activity.as_ref() {
+            activity.record();
+        }
+        let result = match websocket {
+            Some(websocket) => {
+                self.forward_websocket(req, websocket, host, port, path_query, activity)
+                    .await
+            }
+            None => self.forward_http(req, host, port, path_query).await,
+        };
+        result.unwrap_or_else(|e| {
+            tracing::warn!("forward request to {host}:{port} error: {e:#}");
+            (StatusCode::BAD_GATEWAY, "The workspace isn't reachable").into_response()
+        })
+    }
+
+    async fn forward_http(
+        &self,
+        req: Request<Body>,
+        host: &str,
+        port: u16,
+        path_query: &str,
+    ) -> Result<Response> {
+        let (mut parts, body) = req.into_parts();
+        let original_host = original_host(&parts);
+        let forwarded = forwarded_headers(&parts, original_host.as_ref());
+        let grpc = parts.version == Version::HTTP_2 && is_grpc(&parts.headers);
+
+        remove_hop_by_hop_headers(&mut parts.headers);
+        parts.headers.extend(forwarded);
+        if let Some(original_host) = original_host {
+            parts.headers.insert(header::HOST, original_host);
+        }
+        parts.uri = Uri::try_from(format!("http://{host}:{port}{path_query}"))?;
+        let client = if grpc {
+            parts.version = Version::HTTP_2;
+            &self.h2_client
+        } else {
+            parts.version = Version::HTTP_11;
+            &self.client
+        };
+
+        let resp = client.request(Request::from_parts(parts, body)).await?;
+        let (mut parts, body) = resp.into_parts();
+        remove_hop_by_hop_headers(&mut parts.headers);
+        Ok(Response::from_parts(parts, Body::new(body)))
+    }
+
+    async fn forward_websocket(
+        &self,
+        req: Request<Body>,
+        websocket: WebSocketUpgrade,
+        host: &str,
+        port: u16,
+        path_query: &str,
+        activity: Option<WorkspaceActivity>,
+    ) -> Result<Response> {
+        let (parts, _) = req.into_parts();
+        let original_host = original_host(&parts);
+        let forwarded = forwarded_headers(&parts, original_host.as_ref());
+
+        let mut upstream_req = format!("ws://{host}:{port}{path_query}").into_client_request()?;
+        for (name, value) in parts.headers.iter() {
+            // the handshake headers are generated for the upstream connection,
+            // except for the subprotocols that the workspace chooses from
+            if HOP_BY_HOP_HEADERS.contains(&name.as_str())
+                || name == header::HOST
+                || (name.as_str().starts_with("sec-websocket-")
+                    && name != header::SEC_WEBSOCKET_PROTOCOL)
+            {
+                continue;
+            }
+            upstream_req
+                .headers_mut()
+                .append(name.clone(), value.clone());
+        }
+        upstream_req.headers_mut().extend(forwarded);
+        if let Some(original_host) = original_host {
+            upstream_req
+                .headers_mut()
+                .insert(header::HOST, original_host);
+        }
+
+        let connect = tokio_tungstenite::connect_async(upstream_req);
+        let upstream = match tokio::time::timeout(self.config.connect_timeout, connect).await? {
+            Ok((upstream, resp)) => {
+                let protocol = resp
+                    .headers()
+                    .get(header::SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string());
+                (upstream, protocol)
             }
+            // the workspace rejected the upgrade, so pass its response on
+            Err(tungstenite::Error::Http(resp)) => {
+                let status = StatusCode::from_u16(resp.status().as_u16())?;
+                let body = resp.into_body().unwrap_or_default();
+                return Ok((status, body).into_response());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let (upstream, protocol) = upstream;
+        let websocket = match protocol {
+            Some(protocol) => websocket.protocols([protocol]),
+            None => websocket,
+        };
+
+        let idle_timeout = self.config.idle_timeout;
+        Ok(websocket.on_upgrade(move |socket| async move {
+            if let Err(e) = proxy_socket(socket, upstream, idle_timeout, activity).await {
+                tracing::debug!("proxy websocket error: {e:#}");
+            }
+        }))
+    }
+}
+
+fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            // grpc-web works over http1, so it's left to the default client
+            v == "application/grpc"
+                || v.starts_with("application/grpc+")
+                || v.starts_with("application/grpc;")
+        })
+        .unwrap_or(false)
+}
+
+/// The host the client sent the request to, which is in the uri for http2
+fn original_host(parts: &Parts) -> Option<HeaderValue> {
+    parts.headers.get(header::HOST).cloned().or_else(|| {
+        parts
+            .uri
+            .authority()
+            .and_then(|a| HeaderValue::from_str(a.as_str()).ok())
+    })
+}
+
+/// Remove the hop by hop headers, including the ones listed in the `Connection` header.
+/// `TE: trailers` is kept because gRPC requires it.
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    let trailers = headers
+        .get_all(header::TE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|te| te.trim().eq_ignore_ascii_case("trailers"));
+
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(*name);
+    }
+    if trailers {
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+    }
+}
+
+/// The `X-Forwarded-*` and `Forwarded` headers of the request,
+/// appended to the ones that a proxy in front of lapdev has set.
+fn forwarded_headers(parts: &Parts, original_host: Option<&HeaderValue>) -> HeaderMap {
+    let client_ip = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip());
+    let proto = if parts.extensions.get::<TlsConnection>().is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let host = original_host.and_then(|h| h.to_str().ok());
+
+    let mut headers = HeaderMap::new();
+    if let Some(ip) = client_ip {
+        let value = match parts
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(existing) => format!("{existing}, {ip}"),
+            None => ip.to_string(),
+        };
+        if let Ok(value) = HeaderValue::try_from(value) {
+            headers.insert("x-forwarded-for", value);
+        }
+    }
+    if let Some(host) = original_host {
+        headers.insert("x-forwarded-host", host.clone());
+    }
+    headers.insert("x-forwarded-proto", HeaderValue::from_static(proto));
+
+    let mut forwarded = Vec::new();
+    if let Some(ip) = client_ip {
+        forwarded.push(match ip {
+            IpAddr::V4(ip) => format!("for={ip}"),
+            IpAddr::V6(ip) => format!("for=\"[{ip}]\""),
         });
-        return Some(ProxyForward::Resp(resp));
+    }
+    if let Some(host) = host {
+        forwarded.push(format!("host=\"{host}\""));
+    }
+    forwarded.push(format!("proto={proto}"));
+    let forwarded = forwarded.join(";");
+    let value = match parts
+        .headers
+        .get(header::FORWARDED)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(existing) => format!("{existing}, {forwarded}"),
+        None => forwarded,
+    };
+    if let Ok(value) = HeaderValue::try_from(value) {
+        headers.insert(header::FORWARDED, value);
     }
 
-    let uri = format!("http://{host}:{port}{path_query}");
-    let uri = Uri::try_from(uri).ok()?;
-    Some(ProxyForward::Proxy(uri))
+    headers
 }
 
-async fn proxy_socket(socket: WebSocket, req: String) -> Result<()> {
-    let (ws_stream, _) = tokio_tungstenite::connect_async(req).await?;
-    let (mut server_sender, mut server_receiver) = ws_stream.split();
+/// Rewrite the headers of a response from a workspace that's served under a path prefix,
+/// so that the redirects and the cookies of the workspace stay under the prefix.
+/// `host` is the hostname the request was sent to.
+pub fn rewrite_response_headers(headers: &mut HeaderMap, host: &str, prefix: &str) {
+    if let Some(location) = headers
+        .get(header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| rewrite_location(v, host, prefix))
+        .and_then(|v| HeaderValue::try_from(v).ok())
+    {
+        headers.insert(header::LOCATION, location);
+    }
 
-    let (mut client_sender, mut client_receiver) = socket.split();
+    let cookies: Vec<HeaderValue> = headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|v| HeaderValue::try_from(rewrite_set_cookie(v, prefix)).ok())
+        .collect();
+    if !cookies.is_empty() {
+        headers.remove(header::SET_COOKIE);
+        for cookie in cookies {
+            headers.append(header::SET_COOKIE, cookie);
+        }
+    }
+}
 
-    tokio::spawn(async move {
-        while let Some(Ok(msg)) = server_receiver.next().await {
-            if let Some(msg) = msg_from_tungstenite(msg) {
-                let _ = client_sender.send(msg).await;
-            }
+/// Remove the cookies with the names from the request headers
+pub fn remove_cookies(headers: &mut HeaderMap, names: &[&str]) {
+    let cookies: Vec<String> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .map(|c| c.trim())
+        .filter(|c| {
+            let name = c.split('=').next().unwrap_or("").trim();
+            !c.is_empty() && !names.contains(&name)
+        })
+        .map(|c| c.to_string())
+        .collect();
+    headers.remove(header::COOKIE);
+    if !cookies.is_empty() {
+        if let Ok(value) = HeaderValue::try_from(cookies.join("; ")) {
+            headers.insert(header::COOKIE, value);
+        }
+    }
+}
+
+/// Put the prefix in front of an absolute path redirect, or a redirect to the same host.
+/// Relative redirects already resolve under the prefix.
+fn rewrite_location(location: &str, host: &str, prefix: &str) -> Option<String> {
+    let under_prefix = |path: &str| {
+        path.strip_prefix(prefix)
+            .map(|rest| rest.is_empty() || rest.starts_with(['/', '?']))
+            .unwrap_or(false)
+    };
+
+    if location.starts_with('/') && !location.starts_with("//") {
+        if under_prefix(location) {
+            return None;
         }
-    });
+        return Some(format!("{prefix}{location}"));
+    }
 
-    while let Some(Ok(msg)) = client_receiver.next().await {
-        server_sender.send(msg_into_tungstenite(msg)).await?;
+    let (scheme, rest) = location.split_once("://")?;
+    let path_start = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(path_start);
+    if !authority.eq_ignore_ascii_case(host) || under_prefix(path) {
+        return None;
     }
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{path}")
+    };
+    Some(format!("{scheme}://{authority}{prefix}{path}"))
+}
 
-    Ok(())
+/// Scope a cookie set by the workspace to the prefix, and drop its domain
+/// so that it can't be sent to the other workspaces or lapdev itself.
+fn rewrite_set_cookie(cookie: &str, prefix: &str) -> String {
+    let mut parts = cookie.split(';');
+    let mut rewritten = parts.next().unwrap_or("").trim().to_string();
+    let mut has_path = false;
+    for attr in parts {
+        let attr = attr.trim();
+        let name = attr.split('=').next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("domain") {
+            continue;
+        }
+        if name.eq_ignore_ascii_case("path") {
+            has_path = true;
+            let path = attr.split_once('=').map(|(_, p)| p.trim()).unwrap_or("");
+            let path = if path.starts_with('/') { path } else { "/" };
+            rewritten.push_str(&format!("; Path={prefix}{path}"));
+            continue;
+        }
+        rewritten.push_str("; ");
+        rewritten.push_str(attr);
+    }
+    if !has_path {
+        rewritten.push_str(&format!("; Path={prefix}/"));
+    }
+    rewritten
+}
+
+/// Pass the messages between the client and the workspace until either side closes,
+/// the close frame is passed on to the other side. A message is only read after the previous one
+/// has been sent, so that a slow side holds back the other one.
+async fn proxy_socket(
+    mut client: WebSocket,
+    mut upstream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    idle_timeout: Duration,
+    activity: Option<WorkspaceActivity>,
+) -> Result<()> {
+    let idle = tokio::time::sleep(idle_timeout);
+    tokio::pin!(idle);
+
+    loop {
+        tokio::select! {
+            msg = client.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => {
+                        let _ = upstream
+                            .close(Some(tungstenite_close(close_code::ERROR, "client error")))
+                            .await;
+                        return Err(e.into());
+                    }
+                    None => {
+                        let _ = upstream.close(None).await;
+                        return Ok(());
+                    }
+                };
+                if let Some(activity) = activity.as_ref() {
+                    activity.record_message(&msg);
+                }
+                let is_close = matches!(msg, Message::Close(_));
+                upstream.send(msg_into_tungstenite(msg)).await?;
+                if is_close {
+                    // the client has got the close reply already, so only wait for the workspace's
+                    let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                        while let Some(Ok(msg)) = upstream.next().await {
+                            if msg.is_close() {
+                                break;
+                            }
+                        }
+                    })
+                    .await;
+                    return Ok(());
+                }
+            }
+            msg = upstream.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => {
+                        let _ = client
+                            .send(Message::Close(Some(CloseFrame {
+                                code: close_code::ERROR,
+                                reason: "workspace error".into(),
+                            })))
+                            .await;
+                        return Err(e.into());
+                    }
+                    None => {
+                        let _ = client.send(Message::Close(None)).await;
+                        return Ok(());
+                    }
+                };
+                let Some(msg) = msg_from_tungstenite(msg) else {
+                    continue;
+                };
+                let is_close = matches!(msg, Message::Close(_));
+                client.send(msg).await?;
+                if is_close {
+                    // the workspace has got the close reply already, so only wait for the client's
+                    let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                        while let Some(Ok(msg)) = client.next().await {
+                            if matches!(msg, Message::Close(_)) {
+                                break;
+                            }
+                        }
+                    })
+                    .await;
+                    return Ok(());
+                }
+            }
+            _ = &mut idle => {
+                let _ = client
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "idle timeout".into(),
+                    })))
+                    .await;
+                let _ = upstream
+                    .close(Some(tungstenite_close(close_code::AWAY, "idle timeout")))
+                    .await;
+                return Ok(());
+            }
+        }
+        idle.as_mut()
+            .reset(tokio::time::Instant::now() + idle_timeout);
+    }
+}
+
+fn tungstenite_close(
+    code: u16,
+    reason: &'static str,
+) -> tungstenite::protocol::CloseFrame<'static> {
+    tungstenite::protocol::CloseFrame {
+        code: code.into(),
+        reason: reason.into(),
+    }
 }
 
 fn msg_into_tungstenite(
@@ -119,3 +611,528 @@ fn msg_from_tungstenite(
         tokio_tungstenite::tungstenite::Message::Frame(_) => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        extract::{State, WebSocketUpgrade},
+        http::{header, HeaderValue, Request, Response, StatusCode, Version},
+        Router,
+    };
+    use bytes::Bytes;
+    use futures::{SinkExt, StreamExt};
+    use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+    use hyper::body::{Frame, Incoming};
+    use hyper_util::{
+        client::legacy::Client,
+        rt::{TokioExecutor, TokioIo},
+    };
+    use tokio::{net::TcpListener, sync::mpsc};
+    use tokio_tungstenite::tungstenite::{
+        client::IntoClientRequest,
+        handshake::server::{ErrorResponse, Request as WsRequest, Response as WsResponse},
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    };
+
+    use super::{
+        rewrite_location, rewrite_response_headers, rewrite_set_cookie, ForwardConfig, Forwarder,
+    };
+
+    #[test]
+    fn test_rewrite_location() {
+        let host = "lapdev.example.com";
+        let prefix = "/ws/myws/8080";
+        assert_eq!(
+            rewrite_location("/login?next=/", host, prefix).as_deref(),
+            Some("/ws/myws/8080/login?next=/")
+        );
+        // already under the prefix, or relative
+        assert_eq!(rewrite_location("/ws/myws/8080/login", host, prefix), None);
+        assert_eq!(rewrite_location("/ws/myws/8080?a=b", host, prefix), None);
+        assert_eq!(rewrite_location("login", host, prefix), None);
+        // only a path that starts with the whole prefix segment is under it
+        assert_eq!(
+            rewrite_location("/ws/myws/80800", host, prefix).as_deref(),
+            Some("/ws/myws/8080/ws/myws/80800")
+        );
+        // to the same host
+        assert_eq!(
+            rewrite_location("https://lapdev.example.com/login", host, prefix).as_deref(),
+            Some("https://lapdev.example.com/ws/myws/8080/login")
+        );
+        assert_eq!(
+            rewrite_location("https://LAPDEV.example.com?a=b", host, prefix).as_deref(),
+            Some("https://LAPDEV.example.com/ws/myws/8080/?a=b")
+        );
+        // to another host, scheme relative or not
+        assert_eq!(
+            rewrite_location("https://github.com/login", host, prefix),
+            None
+        );
+        assert_eq!(rewrite_location("//github.com/login", host, prefix), None);
+    }
+
+    #[test]
+    fn test_rewrite_set_cookie() {
+        let prefix = "/ws/myws/8080";
+        assert_eq!(
+            rewrite_set_cookie("sid=abc; Path=/; HttpOnly", prefix),
+            "sid=abc; Path=/ws/myws/8080/; HttpOnly"
+        );
+        assert_eq!(
+            rewrite_set_cookie("sid=abc; path=/app", prefix),
+            "sid=abc; Path=/ws/myws/8080/app"
+        );
+        // without a path, or a relative one
+        assert_eq!(
+            rewrite_set_cookie("sid=abc; Secure", prefix),
+            "sid=abc; Secure; Path=/ws/myws/8080/"
+        );
+        assert_eq!(
+            rewrite_set_cookie("sid=abc; Path=app", prefix),
+            "sid=abc; Path=/ws/myws/8080/"
+        );
+        // the domain is dropped so that it's only for the host
+        assert_eq!(
+            rewrite_set_cookie("sid=abc; Domain=.example.com; Max-Age=60", prefix),
+            "sid=abc; Max-Age=60; Path=/ws/myws/8080/"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_response_headers() {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(header::LOCATION, HeaderValue::from_static("/login"));
+        headers.append(header::SET_COOKIE, HeaderValue::from_static("a=1"));
+        headers.append(header::SET_COOKIE, HeaderValue::from_static("b=2; Path=/x"));
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("default-src 'self'"),
+        );
+        rewrite_response_headers(&mut headers, "lapdev.example.com", "/ws/myws");
+
+        assert_eq!(headers[header::LOCATION], "/ws/myws/login");
+        let cookies: Vec<_> = headers.get_all(header::SET_COOKIE).iter().collect();
+        assert_eq!(cookies, ["a=1; Path=/ws/myws/", "b=2; Path=/ws/myws/x"]);
+        // the workspace's own policy is kept as it is
+        let csp: Vec<_> = headers
+            .get_all(header::CONTENT_SECURITY_POLICY)
+            .iter()
+            .collect();
+        assert_eq!(csp, ["default-src 'self'"]);
+    }
+
+    /// Echoes the request body, and the request headers as `echo-<name>` response headers
+    async fn echo(
+        req: Request<Incoming>,
+    ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, Infallible> {
+        let mut resp = Response::builder()
+            .header("echo-path", req.uri().path_and_query().unwrap().as_str())
+            .header("echo-version", format!("{:?}", req.version()));
+        for (name, value) in req.headers() {
+            resp = resp.header(format!("echo-{name}"), value);
+        }
+        let grpc = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|v| v == "application/grpc")
+            .unwrap_or(false);
+        let body = if grpc {
+            let body = req.into_body().collect().await.unwrap().to_bytes();
+            let mut trailers = axum::http::HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+            let frames: Vec<Result<Frame<Bytes>, hyper::Error>> =
+                vec![Ok(Frame::data(body)), Ok(Frame::trailers(trailers))];
+            BodyExt::boxed(StreamBody::new(futures::stream::iter(frames)))
+        } else {
+            req.into_body().boxed()
+        };
+        Ok(resp.body(body).unwrap())
+    }
+
+    /// A http1 and http2 prior knowledge echo server
+    async fn http_echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let _ = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), hyper::service::service_fn(echo))
+                        .await;
+                });
+            }
+        });
+        port
+    }
+
+    /// A websocket echo server that picks the first subprotocol,
+    /// and reports the close frames it receives. It closes the socket itself on `/close`.
+    async fn ws_echo_server() -> (u16, mpsc::UnboundedReceiver<Option<CloseFrame<'static>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let mut close_first = false;
+                    // the error type is given by tungstenite
+                    #[allow(clippy::result_large_err)]
+                    let callback = |req: &WsRequest, mut resp: WsResponse| {
+                        close_first = req.uri().path() == "/close";
+                        if let Some(protocol) = req
+                            .headers()
+                            .get(header::SEC_WEBSOCKET_PROTOCOL)
+                            .and_then(|v| v.to_str().ok())
+                            .and_then(|v| v.split(',').next())
+                        {
+                            resp.headers_mut().insert(
+                                header::SEC_WEBSOCKET_PROTOCOL,
+                                HeaderValue::from_str(protocol.trim()).unwrap(),
+                            );
+                        }
+                        Ok::<_, ErrorResponse>(resp)
+                    };
+                    let mut socket = tokio_tungstenite::accept_hdr_async(stream, callback)
+                        .await
+                        .unwrap();
+                    if close_first {
+                        let _ = socket
+                            .close(Some(CloseFrame {
+                                code: CloseCode::from(4000),
+                                reason: "bye".into(),
+                            }))
+                            .await;
+                    }
+                    while let Some(Ok(msg)) = socket.next().await {
+                        match msg {
+                            Message::Text(_) | Message::Binary(_) => {
+                                let _ = socket.send(msg).await;
+                            }
+                            Message::Close(frame) => {
+                                let _ = tx.send(frame.map(|f| f.into_owned()));
+                            }
+                            _ => {}
+                        }
+                    }
+                });
+            }
+        });
+        (port, rx)
+    }
+
+    struct Upstream {
+        forwarder: Forwarder,
+        http_port: u16,
+        ws_port: u16,
+    }
+
+    async fn proxy(
+        State(upstream): State<Arc<Upstream>>,
+        websocket: Option<WebSocketUpgrade>,
+        req: Request<Body>,
+    ) -> axum::response::Response {
+        let path_query = req.uri().path_and_query().unwrap().to_string();
+        let port = if websocket.is_some() {
+            upstream.ws_port
+        } else {
+            upstream.http_port
+        };
+        upstream
+            .forwarder
+            .forward(req, "127.0.0.1", port, &path_query, websocket, None)
+            .await
+    }
+
+    /// The proxy in front of the echo servers, returns its port
+    async fn front_server(config: ForwardConfig, http_port: u16, ws_port: u16) -> u16 {
+        let upstream = Arc::new(Upstream {
+            forwarder: Forwarder::new(config),
+            http_port,
+            ws_port,
+        });
+        let app = Router::new().fallback(proxy).with_state(upstream);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let _ = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await;
+        });
+        port
+    }
+
+    fn resp_header<'a>(resp: &'a Response<Incoming>, name: &str) -> Option<&'a str> {
+        resp.headers().get(name).and_then(|v| v.to_str().ok())
+    }
+
+    #[tokio::test]
+    async fn test_forward_http() {
+        let http_port = http_echo_server().await;
+        let port = front_server(ForwardConfig::default(), http_port, 0).await;
+
+        let chunks =
+            futures::stream::iter(["hello ", "from ", "lapdev"]).then(|chunk| async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok::<_, Infallible>(Bytes::from(chunk))
+            });
+        let req = Request::post(format!("http://127.0.0.1:{port}/echo?a=1"))
+            .header(header::HOST, "ws.example.com")
+            .header(header::CONNECTION, "keep-alive, x-hop")
+            .header("x-hop", "1")
+            .header("x-custom", "yes")
+            .header("x-forwarded-for", "10.0.0.1")
+            .body(Body::from_stream(chunks))
+            .unwrap();
+        let client = Client::builder(TokioExecutor::new()).build_http::<Body>();
+        let resp = client.request(req).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp_header(&resp, "echo-path"), Some("/echo?a=1"));
+        assert_eq!(resp_header(&resp, "echo-version"), Some("HTTP/1.1"));
+        assert_eq!(resp_header(&resp, "echo-host"), Some("ws.example.com"));
+        assert_eq!(resp_header(&resp, "echo-x-custom"), Some("yes"));
+        assert_eq!(resp_header(&resp, "echo-x-hop"), None);
+        assert_eq!(
+            resp_header(&resp, "echo-x-forwarded-for"),
+            Some("10.0.0.1, 127.0.0.1")
+        );
+        assert_eq!(
+            resp_header(&resp, "echo-x-forwarded-host"),
+            Some("ws.example.com")
+        );
+        assert_eq!(resp_header(&resp, "echo-x-forwarded-proto"), Some("http"));
+        assert_eq!(
+            resp_header(&resp, "echo-forwarded"),
+            Some("for=127.0.0.1;host=\"ws.example.com\";proto=http")
+        );
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello from lapdev");
+    }
+
+    /// A path routed workspace page keeps its origin, so that its scripts can fetch
+    /// the other paths of the workspace with the cookies the workspace set
+    #[tokio::test]
+    async fn test_path_routed_same_origin_fetch() {
+        let app = Router::new()
+            .route(
+                "/",
+                axum::routing::get(|| async {
+                    ([(header::SET_COOKIE, "sid=1; Path=/")], "<html>")
+                }),
+            )
+            .route(
+                "/api/data",
+                axum::routing::get(|headers: axum::http::HeaderMap| async move {
+                    let cookie = headers.get(header::COOKIE).and_then(|v| v.to_str().ok());
+                    if cookie != Some("sid=1") {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+                    let origin = headers.get(header::ORIGIN).cloned();
+                    Ok(origin
+                        .map(|o| o.to_str().unwrap().to_string())
+                        .unwrap_or_default())
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let prefix = "/ws/myws";
+        let forwarder = Arc::new(Forwarder::new(ForwardConfig::default()));
+        let front = Router::new().fallback(move |req: Request<Body>| {
+            let forwarder = forwarder.clone();
+            async move {
+                let path_query = req.uri().path_and_query().unwrap().as_str();
+                let path_query = path_query.strip_prefix(prefix).unwrap().to_string();
+                let mut resp = forwarder
+                    .forward(req, "127.0.0.1", upstream_port, &path_query, None, None)
+                    .await;
+                rewrite_response_headers(resp.headers_mut(), "ws.lapdev.example.com", prefix);
+                resp
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                front.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        let client = Client::builder(TokioExecutor::new()).build_http::<Body>();
+        let req = Request::get(format!("http://127.0.0.1:{port}{prefix}/"))
+            .header(header::HOST, "ws.lapdev.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let resp = client.request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // no sandbox, which would give the page an opaque origin
+        assert_eq!(resp_header(&resp, "content-security-policy"), None);
+        let cookie = resp_header(&resp, "set-cookie").unwrap();
+        assert_eq!(cookie, "sid=1; Path=/ws/myws/");
+
+        // what the page's fetch sends from its own origin
+        let origin = "http://ws.lapdev.example.com";
+        let req = Request::get(format!("http://127.0.0.1:{port}{prefix}/api/data"))
+            .header(header::HOST, "ws.lapdev.example.com")
+            .header(header::ORIGIN, origin)
+            .header(header::COOKIE, "sid=1")
+            .body(Body::empty())
+            .unwrap();
+        let resp = client.request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, origin);
+    }
+
+    #[tokio::test]
+    async fn test_forward_grpc() {
+        let http_port = http_echo_server().await;
+        let forwarder = Forwarder::new(ForwardConfig::default());
+
+        let message = Bytes::from_static(b"\0\0\0\0\x05hello");
+        let req = Request::post("https://ws.example.com/echo.Echo/Say")
+            .version(Version::HTTP_2)
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .header(header::TE, "trailers")
+            .body(Body::from(message.clone()))
+            .unwrap();
+        let resp = forwarder
+            .forward(req, "127.0.0.1", http_port, "/echo.Echo/Say", None, None)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let headers = resp.headers();
+        assert_eq!(headers.get("echo-version").unwrap(), "HTTP/2.0");
+        assert_eq!(headers.get("echo-te").unwrap(), "trailers");
+        assert_eq!(
+            headers.get("echo-x-forwarded-host").unwrap(),
+            "ws.example.com"
+        );
+        let body = resp.into_body().collect().await.unwrap();
+        assert_eq!(body.trailers().unwrap().get("grpc-status").unwrap(), "0");
+        assert_eq!(body.to_bytes(), message);
+    }
+
+    #[tokio::test]
+    async fn test_forward_unreachable() {
+        let port = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let forwarder = Forwarder::new(ForwardConfig::default());
+        let req = Request::get("http://ws.example.com/")
+            .body(Body::empty())
+            .unwrap();
+        let resp = forwarder
+            .forward(req, "127.0.0.1", port, "/", None, None)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_forward_websocket() {
+        let (ws_port, mut closes) = ws_echo_server().await;
+        let port = front_server(ForwardConfig::default(), 0, ws_port).await;
+
+        let mut req = format!("ws://127.0.0.1:{port}/socket")
+            .into_client_request()
+            .unwrap();
+        req.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("chat, superchat"),
+        );
+        let (mut socket, resp) = tokio_tungstenite::connect_async(req).await.unwrap();
+        assert_eq!(
+            resp.headers().get(header::SEC_WEBSOCKET_PROTOCOL).unwrap(),
+            "chat"
+        );
+
+        socket.send(Message::Text("hello".into())).await.unwrap();
+        assert_eq!(
+            socket.next().await.unwrap().unwrap(),
+            Message::Text("hello".into())
+        );
+        socket.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+        assert_eq!(
+            socket.next().await.unwrap().unwrap(),
+            Message::Binary(vec![1, 2, 3])
+        );
+
+        // the close of the client reaches the workspace
+        socket
+            .close(Some(CloseFrame {
+                code: CloseCode::Normal,
+                reason: "done".into(),
+            }))
+            .await
+            .unwrap();
+        let frame = tokio::time::timeout(Duration::from_secs(5), closes.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.code, CloseCode::Normal);
+        assert_eq!(frame.reason, "done");
+    }
+
+    #[tokio::test]
+    async fn test_forward_websocket_upstream_close() {
+        let (ws_port, _closes) = ws_echo_server().await;
+        let port = front_server(ForwardConfig::default(), 0, ws_port).await;
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/close"))
+                .await
+                .unwrap();
+        let msg = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let Message::Close(Some(frame)) = msg else {
+            panic!("expected a close frame, got {msg:?}");
+        };
+        assert_eq!(frame.code, CloseCode::from(4000));
+        assert_eq!(frame.reason, "bye");
+    }
+
+    #[tokio::test]
+    async fn test_forward_websocket_idle_timeout() {
+        let (ws_port, mut closes) = ws_echo_server().await;
+        let config = ForwardConfig {
+            idle_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let port = front_server(config, 0, ws_port).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/"))
+            .await
+            .unwrap();
+        let msg = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let Message::Close(Some(frame)) = msg else {
+            panic!("expected a close frame, got {msg:?}");
+        };
+        assert_eq!(frame.code, CloseCode::Away);
+        let frame = tokio::time::timeout(Duration::from_secs(5), closes.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.code, CloseCode::Away);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod activity;
 pub mod forward;
 pub mod port;
 pub mod proxy;
@@ -1,5 +1,14 @@
+use lapdev_common::WORKSPACE_PATH_PREFIX;
 use lapdev_db::{api::DbApi, entities};
 
+/// The workspace part of a request path when path based routing is enabled
+pub struct WorkspacePath {
+    /// The path that addresses the workspace, i.e. `/ws/<workspace>` or `/ws/<workspace>/<port>`
+    pub prefix: String,
+    /// The rest of the path and the query that's forwarded to the workspace
+    pub path_query: String,
+}
+
 pub async fn forward_workspace(
     hostname: String,
     db: &DbApi,
@@ -26,3 +35,50 @@ pub async fn forward_workspace(
     let workspace_host = db.get_workspace_host(ws.host_id).await.ok()??;
     Some((ws, workspace_host, None))
 }
+
+/// Find the workspace from the path `/ws/<workspace>/<port>/...` for a port,
+/// or `/ws/<workspace>/...` for the ide.
+/// The segment after the workspace is only taken as the port if the workspace has the port.
+pub async fn forward_workspace_path(
+    path_query: &str,
+    db: &DbApi,
+) -> Option<(
+    entities::workspace::Model,
+    entities::workspace_host::Model,
+    Option<entities::workspace_port::Model>,
+    WorkspacePath,
+)> {
+    let rest = path_query.strip_prefix(WORKSPACE_PATH_PREFIX)?;
+    let (ws_name, rest) = split_segment(rest);
+    if ws_name.is_empty() {
+        return None;
+    }
+    let ws = db.get_workspace_by_name(ws_name).await.ok()?;
+    let workspace_host = db.get_workspace_host(ws.host_id).await.ok()??;
+    let prefix = format!("{WORKSPACE_PATH_PREFIX}{ws_name}");
+
+    if let Some(port_rest) = rest.strip_prefix('/') {
+        let (port, port_rest) = split_segment(port_rest);
+        if let Ok(port) = port.parse::<u16>() {
+            if let Ok(Some(port)) = db.get_workspace_port(ws.id, port).await {
+                let path = WorkspacePath {
+                    prefix: format!("{prefix}/{}", port.port),
+                    path_query: port_rest.to_string(),
+                };
+                return Some((ws, workspace_host, Some(port), path));
+            }
+        }
+    }
+
+    let path = WorkspacePath {
+        prefix,
+        path_query: rest.to_string(),
+    };
+    Some((ws, workspace_host, None, path))
+}
+
+/// Split the first path segment from the rest of the path and query
+fn split_segment(s: &str) -> (&str, &str) {
+    let end = s.find(['/', '?']).unwrap_or(s.len());
+    s.split_at(end)
+}
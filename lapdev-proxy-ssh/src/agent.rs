@@ -0,0 +1,219 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+};
+
+use anyhow::{anyhow, Result};
+
+/// OpenSSH doesn't accept agent messages larger than this either
+const MAX_AGENT_MESSAGE_LEN: usize = 256 * 1024;
+/// The `SSH_AGENT_FAILURE` response, for the requests the user's agent can't answer anymore
+pub const AGENT_FAILURE: [u8; 5] = [0, 0, 0, 1, 5];
+
+/// Relays the ssh agent requests from the workspace to the user's agent.
+///
+/// russh can only open the `auth-agent@openssh.com` channel to the user inside a handler
+/// callback, so each user session has a single one, opened when agent forwarding is requested.
+/// The agent answers every request with exactly one response, so the requests of all
+/// the agent channels the workspace opens are sent over it one at a time.
+pub struct AgentRelay<C> {
+    confirmed: bool,
+    closed: bool,
+    // the incomplete request of each workspace agent channel
+    partial: HashMap<C, Vec<u8>>,
+    queue: VecDeque<(C, Vec<u8>)>,
+    // the workspace channel the user's agent is answering,
+    // `Some(None)` if it was closed before the response came
+    in_flight: Option<Option<C>>,
+    response: Vec<u8>,
+}
+
+impl<C: Copy + Eq + Hash> Default for AgentRelay<C> {
+    fn default() -> Self {
+        Self {
+            confirmed: false,
+            closed: false,
+            partial: HashMap::new(),
+            queue: VecDeque::new(),
+            in_flight: None,
+            response: Vec::new(),
+        }
+    }
+}
+
+impl<C: Copy + Eq + Hash> AgentRelay<C> {
+    /// The user confirmed the agent channel.
+    /// Returns the request to send to the user, if there's one waiting.
+    pub fn confirm(&mut self) -> Option<Vec<u8>> {
+        self.confirmed = true;
+        self.next_request()
+    }
+
+    pub fn open_channel(&mut self, channel: C) {
+        self.partial.insert(channel, Vec::new());
+    }
+
+    pub fn has_channel(&self, channel: C) -> bool {
+        self.partial.contains_key(&channel)
+    }
+
+    pub fn close_channel(&mut self, channel: C) {
+        self.partial.remove(&channel);
+        self.queue.retain(|(c, _)| *c != channel);
+        if self.in_flight == Some(Some(channel)) {
+            self.in_flight = Some(None);
+        }
+    }
+
+    /// The agent channel to the user is closed.
+    /// Returns the workspace channel of each request that won't get a response.
+    pub fn close(&mut self) -> Vec<C> {
+        self.closed = true;
+        let mut waiting: Vec<C> = self.in_flight.take().flatten().into_iter().collect();
+        waiting.extend(self.queue.drain(..).map(|(channel, _)| channel));
+        waiting
+    }
+
+    /// Data from a workspace agent channel.
+    /// Returns the request to send to the user, if it's its turn.
+    pub fn request_data(&mut self, channel: C, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.closed {
+            return Err(anyhow!("the agent channel to the user is closed"));
+        }
+        let buf = self
+            .partial
+            .get_mut(&channel)
+            .ok_or_else(|| anyhow!("not an agent channel"))?;
+        buf.extend_from_slice(data);
+        while let Some(request) = take_message(buf)? {
+            self.queue.push_back((channel, request));
+        }
+        Ok(self.next_request())
+    }
+
+    /// Data from the user's agent.
+    /// Returns the response and the workspace channel it's for, once it's complete.
+    pub fn response_data(&mut self, data: &[u8]) -> Result<Option<(C, Vec<u8>)>> {
+        self.response.extend_from_slice(data);
+        let Some(response) = take_message(&mut self.response)? else {
+            return Ok(None);
+        };
+        let channel = self
+            .in_flight
+            .take()
+            .ok_or_else(|| anyhow!("the agent responded without a request"))?;
+        Ok(channel.map(|channel| (channel, response)))
+    }
+
+    /// The next request to send to the user, unless the agent is still answering one
+    pub fn next_request(&mut self) -> Option<Vec<u8>> {
+        if !self.confirmed || self.closed || self.in_flight.is_some() {
+            return None;
+        }
+        let (channel, request) = self.queue.pop_front()?;
+        self.in_flight = Some(Some(channel));
+        Some(request)
+    }
+}
+
+/// Takes a whole agent message, i.e. the 4 bytes length and the content, from the buffer
+fn take_message(buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+    let Some(len) = buf.get(..4) else {
+        return Ok(None);
+    };
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    if len > MAX_AGENT_MESSAGE_LEN {
+        return Err(anyhow!("agent message is too large"));
+    }
+    if buf.len() < 4 + len {
+        return Ok(None);
+    }
+    Ok(Some(buf.drain(..4 + len).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: &[u8]) -> Vec<u8> {
+        let mut msg = (content.len() as u32).to_be_bytes().to_vec();
+        msg.extend_from_slice(content);
+        msg
+    }
+
+    #[test]
+    fn test_agent_relay_waits_for_confirmation() {
+        let mut relay = AgentRelay::default();
+        relay.open_channel(1);
+        assert_eq!(relay.request_data(1, &message(b"req")).unwrap(), None);
+        assert_eq!(relay.confirm(), Some(message(b"req")));
+    }
+
+    #[test]
+    fn test_agent_relay_one_request_at_a_time() {
+        let mut relay = AgentRelay::default();
+        relay.confirm();
+        relay.open_channel(1);
+        relay.open_channel(2);
+
+        // a request split across packets
+        let req1 = message(b"req1");
+        assert_eq!(relay.request_data(1, &req1[..3]).unwrap(), None);
+        assert_eq!(relay.request_data(1, &req1[3..]).unwrap(), Some(req1));
+        assert_eq!(relay.request_data(2, &message(b"req2")).unwrap(), None);
+
+        let resp1 = message(b"resp1");
+        assert_eq!(relay.response_data(&resp1[..5]).unwrap(), None);
+        assert_eq!(relay.response_data(&resp1[5..]).unwrap(), Some((1, resp1)));
+        assert_eq!(relay.next_request(), Some(message(b"req2")));
+        assert_eq!(
+            relay.response_data(&message(b"resp2")).unwrap(),
+            Some((2, message(b"resp2")))
+        );
+        assert_eq!(relay.next_request(), None);
+    }
+
+    #[test]
+    fn test_agent_relay_closed_channel() {
+        let mut relay = AgentRelay::default();
+        relay.confirm();
+        relay.open_channel(1);
+        relay.open_channel(2);
+        relay.request_data(1, &message(b"req1")).unwrap();
+        relay.request_data(2, &message(b"req2")).unwrap();
+
+        relay.close_channel(1);
+        relay.close_channel(2);
+        assert!(!relay.has_channel(1));
+        // the response to the closed channel is dropped, and the queued request is gone
+        assert_eq!(relay.response_data(&message(b"resp1")).unwrap(), None);
+        assert_eq!(relay.next_request(), None);
+        assert!(relay.request_data(2, &message(b"req2")).is_err());
+    }
+
+    #[test]
+    fn test_agent_relay_close() {
+        let mut relay = AgentRelay::default();
+        relay.confirm();
+        relay.open_channel(1);
+        relay.open_channel(2);
+        relay.request_data(1, &message(b"req1")).unwrap();
+        relay.request_data(2, &message(b"req2")).unwrap();
+        relay.request_data(2, &message(b"req3")).unwrap();
+
+        assert_eq!(relay.close(), vec![1, 2, 2]);
+        assert!(relay.request_data(1, &message(b"req4")).is_err());
+        assert_eq!(relay.next_request(), None);
+    }
+
+    #[test]
+    fn test_agent_relay_rejects_invalid_messages() {
+        let mut relay = AgentRelay::default();
+        relay.confirm();
+        relay.open_channel(1);
+        let too_large = ((MAX_AGENT_MESSAGE_LEN + 1) as u32).to_be_bytes();
+        assert!(relay.request_data(1, &too_large).is_err());
+        // a response nobody asked for
+        assert!(relay.response_data(&message(b"resp")).is_err());
+    }
+}
@@ -1,12 +1,127 @@
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use russh::{Channel, ChannelId, ChannelMsg};
-use russh_keys::key::KeyPair;
+use russh::{Channel, ChannelId, ChannelMsg, CryptoVec};
+use russh_keys::{key::KeyPair, PublicKeyBase64};
 use tracing::debug;
 
-pub struct SshProxyClient {}
+use crate::{agent::AgentRelay, proxy::forward_server_client};
+
+pub struct SshProxyClient {
+    // the base64 of the host key the workspace sshd should have
+    host_key: Option<String>,
+    user_session: Option<UserSession>,
+}
+
+/// The workspace sshd presented a different host key than the one
+/// that was generated for the workspace.
+#[derive(Debug)]
+pub struct HostKeyMismatch;
+
+impl std::fmt::Display for HostKeyMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the workspace ssh host key doesn't match")
+    }
+}
+
+impl std::error::Error for HostKeyMismatch {}
+
+/// The agent channel to the user, and the relay of the workspace's requests over it
+type UserAgent = (ChannelId, AgentRelay<ChannelId>);
+
+/// The user's ssh session on the proxy, which the channels opened by the workspace,
+/// i.e. `forwarded-tcpip` for ssh -R and `auth-agent@openssh.com` for ssh -A,
+/// are relayed back to.
+#[derive(Clone)]
+pub struct UserSession {
+    server_handle: russh::server::Handle,
+    remote_forwards: RemoteForwards,
+    agent_forwarding: bool,
+    // the agent channel to the user, opened when the user asks for agent forwarding
+    agent: Arc<Mutex<Option<UserAgent>>>,
+}
+
+impl UserSession {
+    pub fn new(server_handle: russh::server::Handle, agent_forwarding: bool) -> Self {
+        Self {
+            server_handle,
+            remote_forwards: Default::default(),
+            agent_forwarding,
+            agent: Default::default(),
+        }
+    }
+
+    pub fn has_agent(&self) -> bool {
+        self.agent
+            .lock()
+            .map(|agent| agent.is_some())
+            .unwrap_or(false)
+    }
+
+    pub fn set_agent(&self, channel: ChannelId) {
+        if let Ok(mut agent) = self.agent.lock() {
+            *agent = Some((channel, AgentRelay::default()));
+        }
+    }
+
+    pub fn is_agent_channel(&self, channel: ChannelId) -> bool {
+        self.with_agent(|agent_channel, _| agent_channel == channel)
+            .unwrap_or(false)
+    }
+
+    /// Runs `f` with the agent channel to the user and its relay, if there's one
+    pub fn with_agent<T>(
+        &self,
+        f: impl FnOnce(ChannelId, &mut AgentRelay<ChannelId>) -> T,
+    ) -> Option<T> {
+        let mut agent = self.agent.lock().ok()?;
+        let (channel, relay) = agent.as_mut()?;
+        Some(f(*channel, relay))
+    }
+
+    pub fn add_remote_forward(&self, address: &str, port: u32) {
+        self.remote_forwards.add(address, port);
+    }
+
+    pub fn remove_remote_forward(&self, address: &str, port: u32) {
+        self.remote_forwards.remove(address, port);
+    }
+
+    fn has_remote_forward(&self, address: &str, port: u32) -> bool {
+        self.remote_forwards.contains(address, port)
+    }
+}
+
+/// The addresses and ports the user asked the workspace to listen on with ssh -R
+#[derive(Clone, Default)]
+struct RemoteForwards(Arc<Mutex<HashSet<(String, u32)>>>);
+
+impl RemoteForwards {
+    fn add(&self, address: &str, port: u32) {
+        if let Ok(mut forwards) = self.0.lock() {
+            forwards.insert((address.to_string(), port));
+        }
+    }
+
+    fn remove(&self, address: &str, port: u32) {
+        if let Ok(mut forwards) = self.0.lock() {
+            forwards.remove(&(address.to_string(), port));
+        }
+    }
+
+    fn contains(&self, address: &str, port: u32) -> bool {
+        let Ok(forwards) = self.0.lock() else {
+            return false;
+        };
+        // port 0 lets the workspace sshd choose the port, so we don't know it beforehand
+        forwards.contains(&(address.to_string(), port))
+            || forwards.contains(&(address.to_string(), 0))
+    }
+}
 
 pub struct ClientSession {
     pub handle: russh::client::Handle<SshProxyClient>,
@@ -18,23 +133,172 @@ impl russh::client::Handler for SshProxyClient {
 
     async fn check_server_key(
         self,
-        _server_public_key: &russh_keys::key::PublicKey,
+        server_public_key: &russh_keys::key::PublicKey,
     ) -> Result<(Self, bool), Self::Error> {
+        // the workspaces created before the host keys were pinned don't have one
+        if let Some(host_key) = self.host_key.as_ref() {
+            if host_key != &server_public_key.public_key_base64() {
+                return Err(HostKeyMismatch.into());
+            }
+        }
         Ok((self, true))
     }
+
+    async fn server_channel_open_forwarded_tcpip(
+        self,
+        channel: Channel<russh::client::Msg>,
+        connected_address: &str,
+        connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        session: russh::client::Session,
+    ) -> Result<(Self, russh::client::Session), Self::Error> {
+        let Some(user_session) = self
+            .user_session
+            .as_ref()
+            .filter(|s| s.has_remote_forward(connected_address, connected_port))
+        else {
+            debug!("no remote forwarding for {connected_address}:{connected_port}");
+            let _ = channel.close().await;
+            return Ok((self, session));
+        };
+
+        let server_handle = user_session.server_handle.clone();
+        match server_handle
+            .channel_open_forwarded_tcpip(
+                connected_address,
+                connected_port,
+                originator_address,
+                originator_port,
+            )
+            .await
+        {
+            Ok(server_channel) => {
+                tokio::spawn(async move {
+                    let _ = forward_server_client(
+                        server_channel,
+                        channel,
+                        server_handle,
+                        None,
+                        false,
+                        None,
+                    )
+                    .await;
+                });
+            }
+            Err(e) => {
+                debug!("open forwarded tcpip channel to user error: {e:?}");
+                let _ = channel.close().await;
+            }
+        }
+
+        Ok((self, session))
+    }
+
+    async fn server_channel_open_agent_forward(
+        self,
+        channel: ChannelId,
+        mut session: russh::client::Session,
+    ) -> Result<(Self, russh::client::Session), Self::Error> {
+        let opened = self
+            .user_session
+            .as_ref()
+            .filter(|s| s.agent_forwarding)
+            .and_then(|s| s.with_agent(|_, relay| relay.open_channel(channel)))
+            .is_some();
+        if !opened {
+            debug!("agent forwarding isn't allowed or requested");
+            session.close(channel);
+        }
+        Ok((self, session))
+    }
+
+    async fn data(
+        self,
+        channel: ChannelId,
+        data: &[u8],
+        mut session: russh::client::Session,
+    ) -> Result<(Self, russh::client::Session), Self::Error> {
+        // the data of the other channels is handled with their `Channel`
+        let Some(user_session) = self.user_session.as_ref() else {
+            return Ok((self, session));
+        };
+        let Some(result) = user_session.with_agent(|agent_channel, relay| {
+            relay.has_channel(channel).then(|| {
+                relay
+                    .request_data(channel, data)
+                    .map(|request| request.map(|request| (agent_channel, request)))
+            })
+        }) else {
+            return Ok((self, session));
+        };
+        match result {
+            Some(Ok(Some((agent_channel, request)))) => {
+                let _ = user_session
+                    .server_handle
+                    .data(agent_channel, CryptoVec::from(request))
+                    .await;
+            }
+            Some(Ok(None)) | None => {}
+            Some(Err(e)) => {
+                debug!("workspace agent channel {channel} error: {e:#}");
+                user_session.with_agent(|_, relay| relay.close_channel(channel));
+                session.close(channel);
+            }
+        }
+        Ok((self, session))
+    }
+
+    async fn channel_close(
+        self,
+        channel: ChannelId,
+        session: russh::client::Session,
+    ) -> Result<(Self, russh::client::Session), Self::Error> {
+        if let Some(user_session) = self.user_session.as_ref() {
+            user_session.with_agent(|_, relay| relay.close_channel(channel));
+        }
+        Ok((self, session))
+    }
 }
 
 impl ClientSession {
-    pub async fn connect(addr: &str, key: &KeyPair) -> Result<ClientSession> {
+    /// The host key is in the authorized_keys format, e.g. "ssh-rsa AAAA..."
+    pub async fn connect(
+        addr: &str,
+        key: &KeyPair,
+        host_key: Option<&str>,
+        user_session: Option<UserSession>,
+    ) -> Result<ClientSession> {
+        let host_key = host_key
+            .map(|k| {
+                k.split_whitespace()
+                    .nth(1)
+                    .map(|k| k.to_string())
+                    .ok_or_else(|| anyhow!("invalid workspace ssh host key"))
+            })
+            .transpose()?;
         let config = russh::client::Config {
             inactivity_timeout: Some(std::time::Duration::from_secs(5)),
             ..<_>::default()
         };
         let config = Arc::new(config);
-        let mut handle = russh::client::connect(config, addr, SshProxyClient {}).await?;
-        handle
+        let mut handle = russh::client::connect(
+            config,
+            addr,
+            SshProxyClient {
+                host_key,
+                user_session,
+            },
+        )
+        .await?;
+        // russh waits for the key exchange in `connect`,
+        // so a host key mismatch is the error `connect` returns
+        let authenticated = handle
             .authenticate_publickey("root", Arc::new(key.to_owned()))
             .await?;
+        if !authenticated {
+            return Err(anyhow!("the workspace ssh rejected the key"));
+        }
 
         Ok(ClientSession { handle })
     }
@@ -103,3 +367,96 @@ pub async fn handle_client_msg(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use russh::server::{Auth, Server};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    struct TestServer;
+
+    impl Server for TestServer {
+        type Handler = Self;
+
+        fn new_client(&mut self, _peer_addr: Option<std::net::SocketAddr>) -> Self {
+            TestServer
+        }
+    }
+
+    #[async_trait]
+    impl russh::server::Handler for TestServer {
+        type Error = anyhow::Error;
+
+        async fn auth_publickey(
+            self,
+            _user: &str,
+            _public_key: &russh_keys::key::PublicKey,
+        ) -> Result<(Self, Auth), Self::Error> {
+            Ok((self, Auth::Accept))
+        }
+    }
+
+    /// A workspace sshd with the host key, which accepts any key
+    async fn run_test_sshd(host_key: KeyPair) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = Arc::new(russh::server::Config {
+            keys: vec![host_key],
+            ..Default::default()
+        });
+        tokio::spawn(async move {
+            let _ = russh::server::run_on_socket(config, &listener, TestServer).await;
+        });
+        addr
+    }
+
+    fn authorized_key(key: &KeyPair) -> String {
+        let public_key = key.clone_public_key().unwrap();
+        format!("{} {}", public_key.name(), public_key.public_key_base64())
+    }
+
+    #[tokio::test]
+    async fn test_host_key_pinning() {
+        let host_key = KeyPair::generate_ed25519().unwrap();
+        let other_key = KeyPair::generate_ed25519().unwrap();
+        let client_key = KeyPair::generate_ed25519().unwrap();
+        let pinned = authorized_key(&host_key);
+        let addr = run_test_sshd(host_key).await;
+
+        ClientSession::connect(&addr, &client_key, Some(&pinned), None)
+            .await
+            .unwrap();
+        // the workspaces created before the host keys were pinned
+        ClientSession::connect(&addr, &client_key, None, None)
+            .await
+            .unwrap();
+
+        let e = ClientSession::connect(&addr, &client_key, Some(&authorized_key(&other_key)), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(e.is::<HostKeyMismatch>(), "{e:#}");
+    }
+
+    #[test]
+    fn test_remote_forwards() {
+        let forwards = RemoteForwards::default();
+        assert!(!forwards.contains("localhost", 8080));
+
+        forwards.add("localhost", 8080);
+        assert!(forwards.contains("localhost", 8080));
+        assert!(!forwards.contains("localhost", 8081));
+        assert!(!forwards.contains("0.0.0.0", 8080));
+
+        // the port the workspace sshd chose isn't known
+        forwards.add("0.0.0.0", 0);
+        assert!(forwards.contains("0.0.0.0", 41234));
+
+        forwards.remove("localhost", 8080);
+        assert!(!forwards.contains("localhost", 8080));
+        forwards.remove("0.0.0.0", 0);
+        assert!(!forwards.contains("0.0.0.0", 41234));
+    }
+}
@@ -1,4 +1,10 @@
+mod agent;
 mod client;
 pub mod key;
+mod limit;
 mod proxy;
+mod recording;
 pub mod server;
+pub mod terminal;
+
+pub use client::HostKeyMismatch;
@@ -0,0 +1,351 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+/// The window the failed authentication attempts are counted in
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(600);
+/// An ip gets banned after this many failed attempts in the window
+const MAX_FAILURES_PER_IP: u32 = 20;
+/// A ssh user name gets locked for an ip after this many failed attempts in the window.
+/// It's per ip so that others can't lock the user out of their own workspace.
+const MAX_FAILURES_PER_USER: u32 = 10;
+const BAN_DURATION: Duration = Duration::from_secs(900);
+const MAX_CONNECTIONS_PER_IP: usize = 50;
+const MAX_CONNECTIONS_PER_USER: usize = 50;
+const MAX_SESSIONS_PER_WORKSPACE: usize = 20;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Rejection {
+    IpBanned,
+    UserLocked,
+    TooManyIpConnections,
+    TooManyUserConnections,
+    TooManyWorkspaceSessions,
+    UnknownKey,
+    AuthFailed,
+}
+
+impl Rejection {
+    /// The `reason` label of the rejection metrics
+    fn label(&self) -> &'static str {
+        match self {
+            Rejection::IpBanned => "ip_banned",
+            Rejection::UserLocked => "user_locked",
+            Rejection::TooManyIpConnections => "too_many_ip_connections",
+            Rejection::TooManyUserConnections => "too_many_user_connections",
+            Rejection::TooManyWorkspaceSessions => "too_many_workspace_sessions",
+            Rejection::UnknownKey => "unknown_key",
+            Rejection::AuthFailed => "auth_failed",
+        }
+    }
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Rejection::IpBanned => "ip is temporarily banned",
+            Rejection::UserLocked => "too many failed attempts for the user from the ip",
+            Rejection::TooManyIpConnections => "too many connections from the ip",
+            Rejection::TooManyUserConnections => "too many connections of the user",
+            Rejection::TooManyWorkspaceSessions => "too many sessions to the workspace",
+            Rejection::UnknownKey => "the key isn't added by any user",
+            Rejection::AuthFailed => "authentication failed",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+struct Attempts {
+    since: Instant,
+    failures: u32,
+}
+
+impl Attempts {
+    /// Record a failure and return the number of failures in the current window
+    fn fail(&mut self, now: Instant) -> u32 {
+        if now.duration_since(self.since) > ATTEMPT_WINDOW {
+            self.since = now;
+            self.failures = 0;
+        }
+        self.failures += 1;
+        self.failures
+    }
+
+    fn count(&self, now: Instant) -> u32 {
+        if now.duration_since(self.since) > ATTEMPT_WINDOW {
+            0
+        } else {
+            self.failures
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum LimitKey {
+    Ip(IpAddr),
+    User(Uuid),
+    Workspace(Uuid),
+}
+
+impl LimitKey {
+    fn max(&self) -> usize {
+        match self {
+            LimitKey::Ip(_) => MAX_CONNECTIONS_PER_IP,
+            LimitKey::User(_) => MAX_CONNECTIONS_PER_USER,
+            LimitKey::Workspace(_) => MAX_SESSIONS_PER_WORKSPACE,
+        }
+    }
+
+    fn rejection(&self) -> Rejection {
+        match self {
+            LimitKey::Ip(_) => Rejection::TooManyIpConnections,
+            LimitKey::User(_) => Rejection::TooManyUserConnections,
+            LimitKey::Workspace(_) => Rejection::TooManyWorkspaceSessions,
+        }
+    }
+}
+
+#[derive(Default)]
+struct LimiterState {
+    ip_attempts: HashMap<IpAddr, Attempts>,
+    user_attempts: HashMap<(Option<IpAddr>, String), Attempts>,
+    banned_ips: HashMap<IpAddr, Instant>,
+    connections: HashMap<LimitKey, usize>,
+}
+
+/// Throttles the authentication attempts and caps the concurrent connections of the ssh proxy.
+///
+/// The rejections are counted in the `lapdev_ssh_proxy_rejections_total` metric by `reason`.
+#[derive(Default)]
+pub struct ConnectionLimiter {
+    state: Mutex<LimiterState>,
+}
+
+/// Holds a slot of a connection limit until it's dropped
+pub struct LimitGuard {
+    limiter: Arc<ConnectionLimiter>,
+    key: LimitKey,
+}
+
+impl Drop for LimitGuard {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.limiter.state.lock() {
+            if let Some(n) = state.connections.get_mut(&self.key) {
+                *n = n.saturating_sub(1);
+                if *n == 0 {
+                    state.connections.remove(&self.key);
+                }
+            }
+        }
+    }
+}
+
+impl ConnectionLimiter {
+    /// Check if the ip, or the ssh user name from the ip, is blocked from authenticating
+    pub fn check_attempt(&self, ip: Option<IpAddr>, user: &str) -> Result<(), Rejection> {
+        let now = Instant::now();
+        let result = {
+            let state = self.state.lock().map_err(|_| Rejection::AuthFailed)?;
+            if ip
+                .and_then(|ip| state.banned_ips.get(&ip))
+                .map(|until| *until > now)
+                .unwrap_or(false)
+            {
+                Err(Rejection::IpBanned)
+            } else if state
+                .user_attempts
+                .get(&(ip, user.to_string()))
+                .map(|a| a.count(now) >= MAX_FAILURES_PER_USER)
+                .unwrap_or(false)
+            {
+                Err(Rejection::UserLocked)
+            } else {
+                Ok(())
+            }
+        };
+        if let Err(rejection) = result {
+            self.reject(rejection);
+        }
+        result
+    }
+
+    pub fn record_failure(&self, ip: Option<IpAddr>, user: &str) {
+        self.reject(Rejection::AuthFailed);
+        let now = Instant::now();
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if let Some(ip) = ip {
+            let failures = state
+                .ip_attempts
+                .entry(ip)
+                .or_insert(Attempts {
+                    since: now,
+                    failures: 0,
+                })
+                .fail(now);
+            if failures >= MAX_FAILURES_PER_IP {
+                tracing::warn!("ban ip {ip} for too many failed ssh authentications");
+                state.banned_ips.insert(ip, now + BAN_DURATION);
+                state.ip_attempts.remove(&ip);
+            }
+        }
+        state
+            .user_attempts
+            .entry((ip, user.to_string()))
+            .or_insert(Attempts {
+                since: now,
+                failures: 0,
+            })
+            .fail(now);
+    }
+
+    pub fn acquire_ip(self: &Arc<Self>, ip: IpAddr) -> Result<LimitGuard, Rejection> {
+        self.acquire(LimitKey::Ip(ip))
+    }
+
+    pub fn acquire_user(self: &Arc<Self>, user_id: Uuid) -> Result<LimitGuard, Rejection> {
+        self.acquire(LimitKey::User(user_id))
+    }
+
+    pub fn acquire_workspace(self: &Arc<Self>, ws_id: Uuid) -> Result<LimitGuard, Rejection> {
+        self.acquire(LimitKey::Workspace(ws_id))
+    }
+
+    fn acquire(self: &Arc<Self>, key: LimitKey) -> Result<LimitGuard, Rejection> {
+        let acquired = {
+            let mut state = self.state.lock().map_err(|_| key.rejection())?;
+            let n = state.connections.entry(key).or_insert(0);
+            if *n >= key.max() {
+                false
+            } else {
+                *n += 1;
+                true
+            }
+        };
+        if !acquired {
+            self.reject(key.rejection());
+            return Err(key.rejection());
+        }
+        Ok(LimitGuard {
+            limiter: self.clone(),
+            key,
+        })
+    }
+
+    /// A key was offered that no user has added
+    pub fn record_unknown_key(&self) {
+        self.reject(Rejection::UnknownKey);
+    }
+
+    fn reject(&self, rejection: Rejection) {
+        metrics::counter!("lapdev_ssh_proxy_rejections_total", "reason" => rejection.label())
+            .increment(1);
+    }
+
+    /// Clean up the expired attempts and bans periodically
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            if let Ok(mut state) = self.state.lock() {
+                state.banned_ips.retain(|_, until| *until > now);
+                state
+                    .ip_attempts
+                    .retain(|_, a| now.duration_since(a.since) <= ATTEMPT_WINDOW);
+                state
+                    .user_attempts
+                    .retain(|_, a| now.duration_since(a.since) <= ATTEMPT_WINDOW);
+                metrics::gauge!("lapdev_ssh_proxy_banned_ips").set(state.banned_ips.len() as f64);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attempts_window() {
+        let now = Instant::now();
+        let mut attempts = Attempts {
+            since: now,
+            failures: 0,
+        };
+        assert_eq!(attempts.fail(now), 1);
+        assert_eq!(attempts.fail(now + Duration::from_secs(1)), 2);
+        assert_eq!(attempts.count(now + ATTEMPT_WINDOW), 2);
+
+        let later = now + ATTEMPT_WINDOW + Duration::from_secs(1);
+        assert_eq!(attempts.count(later), 0);
+        assert_eq!(attempts.fail(later), 1);
+    }
+
+    #[test]
+    fn test_user_locked_per_ip() {
+        let limiter = ConnectionLimiter::default();
+        let attacker = Some("10.0.0.1".parse().unwrap());
+        let owner = Some("10.0.0.2".parse().unwrap());
+        for _ in 0..MAX_FAILURES_PER_USER {
+            assert!(limiter.check_attempt(attacker, "ws").is_ok());
+            limiter.record_failure(attacker, "ws");
+        }
+        assert!(matches!(
+            limiter.check_attempt(attacker, "ws"),
+            Err(Rejection::UserLocked)
+        ));
+        // the others can still log in as the user
+        assert!(limiter.check_attempt(owner, "ws").is_ok());
+        // and the attacker as other users, until the ip is banned
+        assert!(limiter.check_attempt(attacker, "ws2").is_ok());
+    }
+
+    #[test]
+    fn test_ip_banned() {
+        let limiter = ConnectionLimiter::default();
+        let ip = Some("10.0.0.1".parse().unwrap());
+        for i in 0..MAX_FAILURES_PER_IP {
+            assert!(limiter.check_attempt(ip, &format!("ws{i}")).is_ok());
+            limiter.record_failure(ip, &format!("ws{i}"));
+        }
+        assert!(matches!(
+            limiter.check_attempt(ip, "other"),
+            Err(Rejection::IpBanned)
+        ));
+        assert!(limiter
+            .check_attempt(Some("10.0.0.2".parse().unwrap()), "other")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let limiter = Arc::new(ConnectionLimiter::default());
+        let ws_id = Uuid::new_v4();
+        let mut guards = Vec::new();
+        for _ in 0..MAX_SESSIONS_PER_WORKSPACE {
+            guards.push(limiter.acquire_workspace(ws_id).unwrap());
+        }
+        assert!(matches!(
+            limiter.acquire_workspace(ws_id),
+            Err(Rejection::TooManyWorkspaceSessions)
+        ));
+        // the limits are separate for each workspace
+        assert!(limiter.acquire_workspace(Uuid::new_v4()).is_ok());
+
+        guards.pop();
+        let guard = limiter.acquire_workspace(ws_id).unwrap();
+        drop(guards);
+        drop(guard);
+        assert!(limiter.state.lock().unwrap().connections.is_empty());
+    }
+}
@@ -1,100 +1,395 @@
-use std::sync::Arc;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use lapdev_common::WorkspaceStatus;
+use chrono::Utc;
+use lapdev_common::{AuditAction, AuditResourceKind, WorkspaceSshAccess, WorkspaceStatus};
 use lapdev_conductor::Conductor;
-use lapdev_db::api::DbApi;
+use lapdev_db::{api::DbApi, entities};
 use russh::{
     server::{Auth, Msg, Session},
-    Channel, ChannelMsg,
+    Channel, ChannelId, ChannelMsg, CryptoVec,
 };
 use russh_keys::{decode_secret_key, key::KeyPair, PublicKeyBase64};
+use sea_orm::{ActiveModelTrait, ActiveValue, TransactionTrait};
 use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    agent::AGENT_FAILURE,
+    client::{handle_client_msg, HostKeyMismatch, UserSession},
+    limit::{ConnectionLimiter, LimitGuard, Rejection},
+    recording::SessionRecorder,
+};
 
-use crate::client::handle_client_msg;
+// the workspace sshd, the ide and the guest agent's control port
+const RESERVED_PORTS: [u32; 3] = [22, 30000, 30001];
 
 pub struct SshProxy {
     pub id: usize,
     pub db: DbApi,
     pub conductor: Arc<Conductor>,
+    pub limiter: Arc<ConnectionLimiter>,
 }
 
 pub struct SshProxyHandler {
     #[allow(dead_code)]
     id: usize,
+    peer_addr: Option<SocketAddr>,
+    access: Option<WorkspaceSshAccess>,
+    ws_id: Option<Uuid>,
     ws_addr: Option<(String, u16)>,
     ws_session: Option<super::client::ClientSession>,
+    user_session: Option<UserSession>,
+    agent_forwarding: bool,
     ws_private_key: Option<KeyPair>,
+    ws_host_key: Option<String>,
     ws_env: Vec<(String, String)>,
+    // shown when a shell is opened on a compose workspace
+    motd: Option<String>,
+    audit: Option<Arc<SessionAudit>>,
+    limiter: Arc<ConnectionLimiter>,
+    // the connection limits the session holds, released when the session ends
+    #[allow(dead_code)]
+    limit_guards: Vec<LimitGuard>,
+    // set when the connection is over a limit before authentication
+    rejected: Option<Rejection>,
     db: DbApi,
     conductor: Arc<Conductor>,
 }
 
+/// Writes the audit logs of what's done in a ssh session,
+/// and stores the recordings of the interactive sessions.
+struct SessionAudit {
+    db: DbApi,
+    conductor: Arc<Conductor>,
+    user_id: Uuid,
+    ws_id: Uuid,
+    ws_name: String,
+    org_id: Uuid,
+    ip: Option<String>,
+    record: bool,
+}
+
+impl SessionAudit {
+    async fn log(&self, details: String) -> Result<i32> {
+        tracing::info!(
+            "ssh session on workspace {} by user {}: {details}",
+            self.ws_name,
+            self.user_id
+        );
+        let txn = self.db.conn.begin().await?;
+        let record = self
+            .conductor
+            .enterprise
+            .insert_audit_log_with_details(
+                &txn,
+                Utc::now().into(),
+                self.user_id,
+                self.org_id,
+                AuditResourceKind::Workspace.to_string(),
+                self.ws_id,
+                self.ws_name.clone(),
+                AuditAction::WorkspaceSshSession.to_string(),
+                self.ip.clone(),
+                None,
+                Some(details),
+            )
+            .await?;
+        txn.commit().await?;
+        Ok(record.id)
+    }
+
+    async fn log_host_key_mismatch(&self) -> Result<()> {
+        let txn = self.db.conn.begin().await?;
+        self.conductor
+            .enterprise
+            .insert_audit_log(
+                &txn,
+                Utc::now().into(),
+                self.user_id,
+                self.org_id,
+                AuditResourceKind::Workspace.to_string(),
+                self.ws_id,
+                self.ws_name.clone(),
+                AuditAction::WorkspaceSshHostKeyMismatch.to_string(),
+                self.ip.clone(),
+                None,
+            )
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn save_recording(&self, audit_log_id: i32, content: String) -> Result<()> {
+        entities::ssh_session_recording::ActiveModel {
+            id: ActiveValue::Set(Uuid::new_v4()),
+            created_at: ActiveValue::Set(Utc::now().into()),
+            organization_id: ActiveValue::Set(self.org_id),
+            workspace_id: ActiveValue::Set(self.ws_id),
+            user_id: ActiveValue::Set(self.user_id),
+            audit_log_id: ActiveValue::Set(audit_log_id),
+            content: ActiveValue::Set(content),
+        }
+        .insert(&self.db.conn)
+        .await?;
+        Ok(())
+    }
+}
+
+/// The audit state of a session channel
+pub(crate) struct ChannelAudit {
+    audit: Arc<SessionAudit>,
+    pty: Option<(u32, u32)>,
+    // the recorder and the audit log id of the shell or command it records
+    recorder: Option<(i32, SessionRecorder)>,
+}
+
+impl ChannelAudit {
+    fn new(audit: Arc<SessionAudit>) -> Self {
+        Self {
+            audit,
+            pty: None,
+            recorder: None,
+        }
+    }
+
+    async fn on_server_msg(&mut self, msg: &ChannelMsg) -> Result<()> {
+        let details = match msg {
+            ChannelMsg::RequestPty {
+                col_width,
+                row_height,
+                ..
+            } => {
+                self.pty = Some((*col_width, *row_height));
+                return Ok(());
+            }
+            ChannelMsg::WindowChange {
+                col_width,
+                row_height,
+                ..
+            } => {
+                if let Some((_, recorder)) = self.recorder.as_mut() {
+                    recorder.resize(*col_width, *row_height);
+                }
+                return Ok(());
+            }
+            ChannelMsg::RequestShell { .. } => "shell".to_string(),
+            ChannelMsg::Exec { command, .. } => {
+                format!("exec: {}", String::from_utf8_lossy(command))
+            }
+            ChannelMsg::RequestSubsystem { name, .. } => format!("subsystem: {name}"),
+            _ => return Ok(()),
+        };
+        let is_subsystem = matches!(msg, ChannelMsg::RequestSubsystem { .. });
+        let audit_log_id = self.audit.log(details).await?;
+        if self.audit.record && !is_subsystem && self.recorder.is_none() {
+            // only the sessions with a terminal are interactive
+            if let Some((cols, rows)) = self.pty {
+                self.recorder = Some((audit_log_id, SessionRecorder::new(cols, rows)));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_client_msg(&mut self, msg: &ChannelMsg) {
+        if let Some((_, recorder)) = self.recorder.as_mut() {
+            match msg {
+                ChannelMsg::Data { data } | ChannelMsg::ExtendedData { data, .. } => {
+                    recorder.output(data);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn finish(self) {
+        if let Some((audit_log_id, recorder)) = self.recorder {
+            if let Err(e) = self
+                .audit
+                .save_recording(audit_log_id, recorder.finish())
+                .await
+            {
+                tracing::error!("save ssh session recording error: {e:#}");
+            }
+        }
+    }
+}
+
 impl russh::server::Server for SshProxy {
     type Handler = SshProxyHandler;
 
-    fn new_client(&mut self, _peer_addr: Option<std::net::SocketAddr>) -> Self::Handler {
+    fn new_client(&mut self, peer_addr: Option<std::net::SocketAddr>) -> Self::Handler {
         self.id = self.id.saturating_add(1);
+        let mut limit_guards = Vec::new();
+        let mut rejected = None;
+        if let Some(addr) = peer_addr {
+            match self.limiter.acquire_ip(addr.ip()) {
+                Ok(guard) => limit_guards.push(guard),
+                Err(rejection) => rejected = Some(rejection),
+            }
+        }
         SshProxyHandler {
             id: self.id,
+            peer_addr,
+            access: None,
+            ws_id: None,
             ws_addr: None,
             ws_session: None,
+            user_session: None,
+            agent_forwarding: false,
             ws_private_key: None,
+            ws_host_key: None,
             ws_env: Vec::new(),
+            motd: None,
+            audit: None,
+            limiter: self.limiter.clone(),
+            limit_guards,
+            rejected,
             db: self.db.clone(),
             conductor: self.conductor.clone(),
         }
     }
 }
 
-#[async_trait]
-impl russh::server::Handler for SshProxyHandler {
-    type Error = anyhow::Error;
+impl SshProxyHandler {
+    /// The access an organization member was granted by the workspace owner.
+    /// Grants are made on the main workspace, so they cover all compose services.
+    async fn granted_access(
+        &self,
+        ws: &entities::workspace::Model,
+        public_key: &str,
+    ) -> Result<(Uuid, WorkspaceSshAccess)> {
+        let grant_ws_id = ws.compose_parent.unwrap_or(ws.id);
+        for user_id in self.db.get_ssh_public_key_users(public_key).await? {
+            let Some(grant) = self
+                .db
+                .get_workspace_ssh_access(grant_ws_id, user_id)
+                .await?
+            else {
+                continue;
+            };
+            if self
+                .db
+                .get_organization_member(user_id, ws.organization_id)
+                .await
+                .is_err()
+            {
+                continue;
+            }
+            if let Ok(access) = WorkspaceSshAccess::from_str(&grant.access) {
+                return Ok((user_id, access));
+            }
+        }
+        Err(anyhow!("no access to workspace {}", ws.name))
+    }
 
-    async fn auth_none(self, _user: &str) -> Result<(Self, Auth), Self::Error> {
-        Ok((
-            self,
-            Auth::Reject {
-                proceed_with_methods: None,
-            },
-        ))
+    fn check_attempt(&self, user: &str) -> Result<(), Rejection> {
+        if let Some(rejection) = self.rejected {
+            return Err(rejection);
+        }
+        self.limiter
+            .check_attempt(self.peer_addr.map(|addr| addr.ip()), user)
     }
 
-    async fn auth_publickey_offered(
-        self,
-        _user: &str,
-        _public_key: &russh_keys::key::PublicKey,
-    ) -> Result<(Self, Auth), Self::Error> {
-        Ok((self, Auth::Accept))
+    /// The ssh user is either the workspace name,
+    /// or `<service>.<workspace>` for a service of a compose workspace.
+    async fn resolve_workspace(&self, user: &str) -> Result<entities::workspace::Model> {
+        if let Ok(ws) = self.db.get_workspace_by_name(user).await {
+            return Ok(ws);
+        }
+        let (service, name) = user
+            .split_once('.')
+            .ok_or_else(|| anyhow!("no workspace found"))?;
+        let ws = self.db.get_workspace_by_name(name).await?;
+        if !ws.is_compose || ws.compose_parent.is_some() {
+            return Err(anyhow!("workspace {name} isn't a compose workspace"));
+        }
+        self.db.get_compose_service(ws.id, service).await
     }
 
-    async fn auth_publickey(
-        mut self,
-        user: &str,
-        public_key: &russh_keys::key::PublicKey,
-    ) -> Result<(Self, Auth), Self::Error> {
-        let public_key = public_key.public_key_base64();
-        println!("auth public key");
-        let ws = self.db.get_workspace_by_name(user).await?;
+    async fn compose_motd(&self, ws: &entities::workspace::Model) -> Result<Option<String>> {
+        if !ws.is_compose || ws.compose_parent.is_some() {
+            return Ok(None);
+        }
+        let services = self.db.get_compose_services(ws.id).await?;
+        if services.is_empty() {
+            return Ok(None);
+        }
+        let mut motd = format!(
+            "This workspace has the following compose services, connect to one with `ssh <service>.{}`:\r\n",
+            ws.name
+        );
+        for service in services {
+            if let Some(service) = service.service {
+                motd.push_str(&format!("  - {service}\r\n"));
+            }
+        }
+        motd.push_str("\r\n");
+        Ok(Some(motd))
+    }
+
+    async fn authenticate(&mut self, user: &str, public_key: &str) -> Result<()> {
+        let ws = self.resolve_workspace(user).await?;
         let workspace_host = self
             .db
             .get_workspace_host(ws.host_id)
             .await?
             .ok_or_else(|| anyhow!("can't find workspace host"))?;
-        self.db
-            .validate_ssh_public_key(ws.user_id, &public_key)
-            .await?;
-        self.ws_addr = Some((
-            workspace_host.host,
-            ws.ssh_port
-                .ok_or_else(|| anyhow!("the workspace doesn't have a ssh port"))?
-                as u16,
-        ));
-        self.ws_private_key = Some(decode_secret_key(&ws.ssh_private_key, None)?);
+        let (user_id, access) = if self
+            .db
+            .validate_ssh_public_key(ws.user_id, public_key)
+            .await
+            .is_ok()
+        {
+            (ws.user_id, WorkspaceSshAccess::Full)
+        } else {
+            self.granted_access(&ws, public_key).await?
+        };
+        // the connection limits are checked after the key is validated,
+        // so that they don't tell if the workspace exists.
+        // The guards are only kept by the handler when the authentication succeeds,
+        // otherwise they're released when this returns.
+        let user_guard = self.limiter.acquire_user(user_id)?;
+        let ws_guard = self.limiter.acquire_workspace(ws.id)?;
+        let ws_port = ws
+            .ssh_port
+            .ok_or_else(|| anyhow!("the workspace doesn't have a ssh port"))?
+            as u16;
+        let ws_private_key = decode_secret_key(&ws.ssh_private_key, None)?;
+        let org = self.db.get_organization(ws.organization_id).await?;
+        self.log_access(&ws, user_id).await?;
+
+        self.limit_guards.push(user_guard);
+        self.limit_guards.push(ws_guard);
+        self.access = Some(access);
+        self.ws_id = Some(ws.id);
+        // the agent gives access to the user's keys, so it's only for full access
+        self.agent_forwarding =
+            org.allow_ssh_agent_forwarding && access == WorkspaceSshAccess::Full;
+        let record =
+            self.conductor.enterprise.has_valid_license().await && org.ssh_session_recording;
+        self.audit = Some(Arc::new(SessionAudit {
+            db: self.db.clone(),
+            conductor: self.conductor.clone(),
+            user_id,
+            ws_id: ws.id,
+            ws_name: ws.name.clone(),
+            org_id: ws.organization_id,
+            ip: self.peer_addr.map(|addr| addr.ip().to_string()),
+            record,
+        }));
+        self.ws_addr = Some((workspace_host.host, ws_port));
+        self.ws_private_key = Some(ws_private_key);
+        self.ws_host_key = ws.ssh_host_public_key.clone();
         if let Some(Ok(env)) = ws.env.as_ref().map(|env| serde_json::from_str(env)) {
             self.ws_env = env;
         }
+        self.motd = self.compose_motd(&ws).await.unwrap_or(None);
 
         if ws.status == WorkspaceStatus::Stopped.to_string()
             && self.conductor.enterprise.has_valid_license().await
@@ -106,24 +401,156 @@ impl russh::server::Handler for SshProxyHandler {
                 .await
                 .unwrap_or(false)
         {
-            let ws = if ws.is_compose {
-                if let Some(parent) = ws.compose_parent {
-                    self.db.get_workspace(parent).await?
-                } else {
-                    ws
-                }
-            } else {
-                ws
+            // the user is authenticated by now, so a failed auto start doesn't reject them
+            let ws = match ws.compose_parent {
+                Some(parent) if ws.is_compose => self.db.get_workspace(parent).await,
+                _ => Ok(ws),
             };
-            tracing::info!("auto start workspace {}", ws.name);
-            let _ = self.conductor.start_workspace(ws, true, None, None).await;
+            if let Ok(ws) = ws {
+                tracing::info!("auto start workspace {}", ws.name);
+                let _ = self.conductor.start_workspace(ws, true, None, None).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The agent channel to the user is gone,
+    /// so the requests waiting for it get a failure instead.
+    async fn close_agent(&self) {
+        let Some(waiting) = self
+            .user_session
+            .as_ref()
+            .and_then(|s| s.with_agent(|_, relay| relay.close()))
+        else {
+            return;
+        };
+        if let Some(ws_session) = self.ws_session.as_ref() {
+            for ws_channel in waiting {
+                let _ = ws_session
+                    .handle
+                    .data(ws_channel, CryptoVec::from_slice(&AGENT_FAILURE))
+                    .await;
+            }
         }
+    }
 
+    async fn log_access(&self, ws: &entities::workspace::Model, user_id: Uuid) -> Result<()> {
+        let txn = self.db.conn.begin().await?;
+        self.conductor
+            .enterprise
+            .insert_audit_log(
+                &txn,
+                Utc::now().into(),
+                user_id,
+                ws.organization_id,
+                AuditResourceKind::Workspace.to_string(),
+                ws.id,
+                ws.name.clone(),
+                AuditAction::WorkspaceSshConnect.to_string(),
+                self.peer_addr.map(|addr| addr.ip().to_string()),
+                None,
+            )
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+}
+
+/// The session channels and the remote forwards are only for full access
+fn has_full_access(access: Option<WorkspaceSshAccess>) -> bool {
+    access == Some(WorkspaceSshAccess::Full)
+}
+
+/// A read only user can only reach the ports forwarded for the workspace,
+/// the same ones they could open in the browser, and nothing else on the network.
+fn read_only_tcpip_allowed(host: &str, port: u32, forwarded: bool) -> bool {
+    let loopback = host == "localhost"
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false);
+    loopback && forwarded && !RESERVED_PORTS.contains(&port)
+}
+
+fn reject() -> Auth {
+    Auth::Reject {
+        proceed_with_methods: None,
+    }
+}
+
+#[async_trait]
+impl russh::server::Handler for SshProxyHandler {
+    type Error = anyhow::Error;
+
+    async fn auth_none(self, _user: &str) -> Result<(Self, Auth), Self::Error> {
+        Ok((
+            self,
+            Auth::Reject {
+                proceed_with_methods: None,
+            },
+        ))
+    }
+
+    async fn auth_publickey_offered(
+        self,
+        user: &str,
+        public_key: &russh_keys::key::PublicKey,
+    ) -> Result<(Self, Auth), Self::Error> {
+        // the key is checked against the workspace in auth_publickey after the client
+        // proves it owns it, but there's no point in going further if the client is
+        // blocked or no user has added the key. The latter doesn't depend on the
+        // workspace, so it doesn't tell if the workspace exists.
+        if let Err(rejection) = self.check_attempt(user) {
+            debug!("reject ssh auth for {user}: {rejection}");
+            return Ok((self, reject()));
+        }
+        let known = self
+            .db
+            .get_ssh_public_key_users(&public_key.public_key_base64())
+            .await
+            .map(|users| !users.is_empty())
+            .unwrap_or(false);
+        if !known {
+            debug!("reject ssh auth for {user}: {}", Rejection::UnknownKey);
+            self.limiter.record_unknown_key();
+            return Ok((self, reject()));
+        }
         Ok((self, Auth::Accept))
     }
 
+    async fn auth_publickey(
+        mut self,
+        user: &str,
+        public_key: &russh_keys::key::PublicKey,
+    ) -> Result<(Self, Auth), Self::Error> {
+        debug!("auth public key for {user}");
+        if let Err(rejection) = self.check_attempt(user) {
+            debug!("reject ssh auth for {user}: {rejection}");
+            return Ok((self, reject()));
+        }
+        // all the failures are rejected the same way,
+        // so that it doesn't tell if the workspace exists
+        match self
+            .authenticate(user, &public_key.public_key_base64())
+            .await
+        {
+            Ok(()) => Ok((self, Auth::Accept)),
+            Err(e) => {
+                debug!("ssh auth for {user} failed: {e:#}");
+                if !e.is::<Rejection>() {
+                    self.limiter
+                        .record_failure(self.peer_addr.map(|addr| addr.ip()), user);
+                }
+                Ok((self, reject()))
+            }
+        }
+    }
+
     async fn auth_succeeded(mut self, session: Session) -> Result<(Self, Session), Self::Error> {
-        println!("auth succedded");
+        debug!("auth succeeded");
         let (addr, port) = self
             .ws_addr
             .as_ref()
@@ -133,12 +560,28 @@ impl russh::server::Handler for SshProxyHandler {
             .ws_private_key
             .as_ref()
             .ok_or_else(|| anyhow!("it doesn't have workspace private key"))?;
-        match super::client::ClientSession::connect(&addr, key).await {
+        let user_session = UserSession::new(session.handle(), self.agent_forwarding);
+        self.user_session = Some(user_session.clone());
+        match super::client::ClientSession::connect(
+            &addr,
+            key,
+            self.ws_host_key.as_deref(),
+            Some(user_session),
+        )
+        .await
+        {
             Ok(session) => {
                 self.ws_session = Some(session);
             }
+            Err(e) if e.is::<HostKeyMismatch>() => {
+                tracing::error!("workspace ssh {addr} host key mismatch");
+                if let Some(audit) = self.audit.as_ref() {
+                    audit.log_host_key_mismatch().await?;
+                }
+                return Err(e);
+            }
             Err(e) => {
-                println!("error connection: {e}");
+                tracing::error!("connect to workspace ssh {addr} error: {e:#}");
             }
         }
         Ok((self, session))
@@ -149,6 +592,9 @@ impl russh::server::Handler for SshProxyHandler {
         channel: Channel<Msg>,
         session: Session,
     ) -> Result<(Self, bool, Session), Self::Error> {
+        if !has_full_access(self.access) {
+            return Ok((self, false, session));
+        }
         let ws_session = self
             .ws_session
             .as_mut()
@@ -159,9 +605,20 @@ impl russh::server::Handler for SshProxyHandler {
         }
 
         let server_handle = session.handle();
+        let audit = self.audit.clone().map(ChannelAudit::new);
+        let agent_forwarding = self.agent_forwarding;
+        let motd = self.motd.clone();
 
         tokio::spawn(async move {
-            let _ = forward_server_client(channel, ws_channel, server_handle).await;
+            let _ = forward_server_client(
+                channel,
+                ws_channel,
+                server_handle,
+                audit,
+                agent_forwarding,
+                motd,
+            )
+            .await;
         });
 
         Ok((self, true, session))
@@ -176,6 +633,28 @@ impl russh::server::Handler for SshProxyHandler {
         originator_port: u32,
         session: Session,
     ) -> Result<(Self, bool, Session), Self::Error> {
+        let allowed = match self.access {
+            Some(WorkspaceSshAccess::Full) => true,
+            Some(WorkspaceSshAccess::ReadOnly) => {
+                let forwarded = match (self.ws_id, u16::try_from(port_to_connect)) {
+                    (Some(ws_id), Ok(port)) => {
+                        self.db.get_workspace_port(ws_id, port).await?.is_some()
+                    }
+                    _ => false,
+                };
+                read_only_tcpip_allowed(host_to_connect, port_to_connect, forwarded)
+            }
+            None => false,
+        };
+        if !allowed {
+            debug!("deny direct-tcpip to {host_to_connect}:{port_to_connect}");
+            return Ok((self, false, session));
+        }
+        if let Some(audit) = self.audit.as_ref() {
+            audit
+                .log(format!("direct-tcpip: {host_to_connect}:{port_to_connect}"))
+                .await?;
+        }
         let ws_session = self
             .ws_session
             .as_mut()
@@ -193,7 +672,8 @@ impl russh::server::Handler for SshProxyHandler {
         let server_handle = session.handle();
 
         tokio::spawn(async move {
-            let _ = forward_server_client(channel, ws_channel, server_handle).await;
+            let _ =
+                forward_server_client(channel, ws_channel, server_handle, None, false, None).await;
         });
 
         Ok((self, true, session))
@@ -205,9 +685,21 @@ impl russh::server::Handler for SshProxyHandler {
         port: &mut u32,
         mut session: Session,
     ) -> Result<(Self, bool, Session), Self::Error> {
+        if !has_full_access(self.access) {
+            return Ok((self, false, session));
+        }
         let address = address.to_string();
         let port = *port;
 
+        if let Some(audit) = self.audit.as_ref() {
+            audit
+                .log(format!("tcpip-forward: {address}:{port}"))
+                .await?;
+        }
+
+        if let Some(user_session) = self.user_session.as_ref() {
+            user_session.add_remote_forward(&address, port);
+        }
         if let Some(ws_session) = self.ws_session.as_mut() {
             ws_session.handle.tcpip_forward(address, port).await?;
         }
@@ -217,6 +709,99 @@ impl russh::server::Handler for SshProxyHandler {
         Ok((self, true, session))
     }
 
+    async fn agent_request(
+        self,
+        channel: ChannelId,
+        mut session: Session,
+    ) -> Result<(Self, bool, Session), Self::Error> {
+        debug!("agent forwarding request on channel {channel}");
+        let allowed = self.agent_forwarding;
+        if allowed {
+            if let Some(audit) = self.audit.as_ref() {
+                audit.log("agent-forwarding".to_string()).await?;
+            }
+            // the agent channels the workspace opens are relayed over this one
+            if let Some(user_session) = self.user_session.as_ref().filter(|s| !s.has_agent()) {
+                user_session.set_agent(session.channel_open_agent()?);
+            }
+        }
+        Ok((self, allowed, session))
+    }
+
+    async fn channel_open_confirmation(
+        self,
+        id: ChannelId,
+        _max_packet_size: u32,
+        _window_size: u32,
+        mut session: Session,
+    ) -> Result<(Self, Session), Self::Error> {
+        if let Some(Some(request)) = self.user_session.as_ref().and_then(|s| {
+            s.with_agent(|agent_channel, relay| (agent_channel == id).then(|| relay.confirm()))
+                .flatten()
+        }) {
+            session.data(id, CryptoVec::from(request));
+        }
+        Ok((self, session))
+    }
+
+    async fn data(
+        self,
+        channel: ChannelId,
+        data: &[u8],
+        mut session: Session,
+    ) -> Result<(Self, Session), Self::Error> {
+        // only the agent channel to the user is opened without a `Channel`
+        let Some(user_session) = self
+            .user_session
+            .as_ref()
+            .filter(|s| s.is_agent_channel(channel))
+        else {
+            return Ok((self, session));
+        };
+        let result = user_session.with_agent(|_, relay| {
+            relay
+                .response_data(data)
+                .map(|response| (response, relay.next_request()))
+        });
+        match result {
+            Some(Ok((response, request))) => {
+                if let (Some((ws_channel, response)), Some(ws_session)) =
+                    (response, self.ws_session.as_ref())
+                {
+                    let _ = ws_session
+                        .handle
+                        .data(ws_channel, CryptoVec::from(response))
+                        .await;
+                }
+                if let Some(request) = request {
+                    session.data(channel, CryptoVec::from(request));
+                }
+            }
+            Some(Err(e)) => {
+                debug!("user agent channel error: {e:#}");
+                session.close(channel);
+                self.close_agent().await;
+            }
+            None => {}
+        }
+        Ok((self, session))
+    }
+
+    async fn channel_close(
+        self,
+        channel: ChannelId,
+        session: Session,
+    ) -> Result<(Self, Session), Self::Error> {
+        if self
+            .user_session
+            .as_ref()
+            .is_some_and(|s| s.is_agent_channel(channel))
+        {
+            self.close_agent().await;
+        }
+        Ok((self, session))
+    }
+
     async fn cancel_tcpip_forward(
         mut self,
         address: &str,
@@ -225,6 +810,9 @@ impl russh::server::Handler for SshProxyHandler {
     ) -> Result<(Self, bool, Session), Self::Error> {
         let address = address.to_string();
 
+        if let Some(user_session) = self.user_session.as_ref() {
+            user_session.remove_remote_forward(&address, port);
+        }
         if let Some(ws_session) = self.ws_session.as_mut() {
             ws_session
                 .handle
@@ -238,51 +826,73 @@ impl russh::server::Handler for SshProxyHandler {
     }
 }
 
-async fn forward_server_client(
+pub(crate) async fn forward_server_client(
     mut channel: Channel<russh::server::Msg>,
     mut ws_channel: Channel<russh::client::Msg>,
     server_handle: russh::server::Handle,
+    mut audit: Option<ChannelAudit>,
+    agent_forwarding: bool,
+    mut motd: Option<String>,
 ) -> Result<()> {
     let channel_id = channel.id();
     debug!("proxy connection started {channel_id}");
     let ws_channel_id = ws_channel.id();
-    loop {
-        tokio::select! {
-            msg = channel.wait() => {
-                if let Some(msg) = msg {
-                    match msg {
-                        ChannelMsg::Close => {
-                            debug!("server received close msg");
-                            break;
-                        },
-                        _ => {
-                            handle_server_msg(&ws_channel, msg).await?;
+    let result = async {
+        loop {
+            tokio::select! {
+                msg = channel.wait() => {
+                    if let Some(msg) = msg {
+                        match msg {
+                            ChannelMsg::Close => {
+                                debug!("server received close msg");
+                                break;
+                            },
+                            _ => {
+                                if let Some(audit) = audit.as_mut() {
+                                    audit.on_server_msg(&msg).await?;
+                                }
+                                if let ChannelMsg::RequestShell { .. } = msg {
+                                    if let Some(motd) = motd.take() {
+                                        channel.data(motd.as_bytes()).await?;
+                                    }
+                                }
+                                handle_server_msg(&ws_channel, msg, agent_forwarding).await?;
+                            }
                         }
+                    } else {
+                        debug!("server msg channel closed");
+                        break;
                     }
-                } else {
-                    debug!("server msg channel closed");
-                    break;
                 }
-            }
-            msg = ws_channel.wait() => {
-                if let Some(msg) = msg {
-                    handle_client_msg(ws_channel_id, &mut channel, &server_handle, msg).await?;
-                } else {
-                    debug!("client msg channel closed");
-                    break;
+                msg = ws_channel.wait() => {
+                    if let Some(msg) = msg {
+                        if let Some(audit) = audit.as_mut() {
+                            audit.on_client_msg(&msg);
+                        }
+                        handle_client_msg(ws_channel_id, &mut channel, &server_handle, msg).await?;
+                    } else {
+                        debug!("client msg channel closed");
+                        break;
+                    }
                 }
             }
         }
+        anyhow::Ok(())
     }
+    .await;
     let _ = channel.close().await;
     let _ = ws_channel.close().await;
+    if let Some(audit) = audit {
+        audit.finish().await;
+    }
     debug!("proxy connection closed {channel_id}");
-    Ok(())
+    result
 }
 
 async fn handle_server_msg(
     client_channel: &Channel<russh::client::Msg>,
     msg: ChannelMsg,
+    agent_forwarding: bool,
 ) -> Result<()> {
     match msg {
         ChannelMsg::Data { data } => {
@@ -366,6 +976,12 @@ async fn handle_server_msg(
                 .window_change(col_width, row_height, pix_width, pix_height)
                 .await?;
         }
+        ChannelMsg::AgentForward { .. } => {
+            // the reply to the user is sent by `agent_request`
+            if agent_forwarding {
+                client_channel.agent_forward(false).await?;
+            }
+        }
         ChannelMsg::WindowAdjusted { .. } => {}
         _ => {
             debug!("unhandled ssh server msg: {msg:?}");
@@ -373,3 +989,36 @@ async fn handle_server_msg(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_only_channels() {
+        assert!(has_full_access(Some(WorkspaceSshAccess::Full)));
+        assert!(!has_full_access(Some(WorkspaceSshAccess::ReadOnly)));
+        assert!(!has_full_access(None));
+    }
+
+    #[test]
+    fn test_read_only_tcpip() {
+        assert!(read_only_tcpip_allowed("localhost", 8080, true));
+        assert!(read_only_tcpip_allowed("127.0.0.1", 8080, true));
+        assert!(read_only_tcpip_allowed("[::1]", 8080, true));
+        // the ports that aren't forwarded for the workspace
+        assert!(!read_only_tcpip_allowed("localhost", 8081, false));
+        // the workspace's own services, even if someone forwarded them
+        for port in RESERVED_PORTS {
+            assert!(!read_only_tcpip_allowed("localhost", port, true));
+        }
+        // anything else reachable from the workspace
+        assert!(!read_only_tcpip_allowed("10.0.0.1", 8080, true));
+        assert!(!read_only_tcpip_allowed("example.com", 8080, true));
+        assert!(!read_only_tcpip_allowed(
+            "localhost.example.com",
+            8080,
+            true
+        ));
+    }
+}
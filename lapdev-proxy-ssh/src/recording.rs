@@ -0,0 +1,71 @@
+use std::time::Instant;
+
+use chrono::Utc;
+use serde_json::json;
+
+/// The recordings are stored in the db, so stop recording when it gets too big
+const MAX_RECORDING_SIZE: usize = 10 * 1024 * 1024;
+
+/// Records the output of an interactive ssh session in the asciicast v2 format,
+/// so that it can be replayed with asciinema player.
+pub struct SessionRecorder {
+    start: Instant,
+    content: String,
+    // the bytes of an utf-8 char that's split across two data messages
+    pending: Vec<u8>,
+    truncated: bool,
+}
+
+impl SessionRecorder {
+    pub fn new(cols: u32, rows: u32) -> Self {
+        let header = json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": Utc::now().timestamp(),
+        });
+        Self {
+            start: Instant::now(),
+            content: format!("{header}\n"),
+            pending: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    pub fn output(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        let valid_up_to = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // the end of the data is an incomplete char, wait for the rest of it
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let rest = self.pending.split_off(valid_up_to);
+        let output = String::from_utf8_lossy(&self.pending).to_string();
+        self.pending = rest;
+        if !output.is_empty() {
+            self.event("o", &output);
+        }
+    }
+
+    pub fn resize(&mut self, cols: u32, rows: u32) {
+        self.event("r", &format!("{cols}x{rows}"));
+    }
+
+    fn event(&mut self, kind: &str, data: &str) {
+        if self.truncated {
+            return;
+        }
+        let event = json!([self.start.elapsed().as_secs_f64(), kind, data]);
+        let event = format!("{event}\n");
+        if self.content.len() + event.len() > MAX_RECORDING_SIZE {
+            self.truncated = true;
+            return;
+        }
+        self.content.push_str(&event);
+    }
+
+    pub fn finish(self) -> String {
+        self.content
+    }
+}
@@ -4,13 +4,16 @@ use anyhow::Result;
 use lapdev_conductor::Conductor;
 use russh::{MethodSet, Preferred};
 
-use crate::key::host_keys;
+use crate::{key::host_keys, limit::ConnectionLimiter};
+
+/// Sessions without any traffic are disconnected after this
+const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3600);
 
 pub async fn run(conductor: Conductor, bind: &str, port: u16) -> Result<()> {
     let keys = host_keys(&conductor.db).await?;
 
     let config = russh::server::Config {
-        inactivity_timeout: Some(std::time::Duration::from_secs(3600)),
+        inactivity_timeout: Some(IDLE_TIMEOUT),
         auth_rejection_time: std::time::Duration::from_secs(3),
         auth_rejection_time_initial: Some(std::time::Duration::from_secs(0)),
         methods: MethodSet::NONE | MethodSet::PUBLICKEY,
@@ -27,6 +30,8 @@ pub async fn run(conductor: Conductor, bind: &str, port: u16) -> Result<()> {
         ..Default::default()
     };
     let config = Arc::new(config);
+    let limiter = Arc::new(ConnectionLimiter::default());
+    tokio::spawn(limiter.clone().run());
     russh::server::run(
         config,
         (bind, port),
@@ -34,6 +39,7 @@ pub async fn run(conductor: Conductor, bind: &str, port: u16) -> Result<()> {
             id: 0,
             db: conductor.db.clone(),
             conductor: Arc::new(conductor),
+            limiter,
         },
     )
     .await?;
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Result};
+use lapdev_db::entities;
+use russh::{Channel, ChannelMsg};
+use russh_keys::decode_secret_key;
+
+use crate::client::ClientSession;
+
+/// A login shell with a pty in the workspace,
+/// connected through the workspace sshd the same way as the ssh proxy.
+pub struct WorkspaceTerminal {
+    _session: ClientSession,
+    channel: Channel<russh::client::Msg>,
+}
+
+impl WorkspaceTerminal {
+    pub async fn open(
+        ws: &entities::workspace::Model,
+        host: &str,
+        cols: u32,
+        rows: u32,
+    ) -> Result<Self> {
+        let port = ws
+            .ssh_port
+            .ok_or_else(|| anyhow!("the workspace doesn't have a ssh port"))?;
+        let key = decode_secret_key(&ws.ssh_private_key, None)?;
+        let session = ClientSession::connect(
+            &format!("{host}:{port}"),
+            &key,
+            ws.ssh_host_public_key.as_deref(),
+            None,
+        )
+        .await?;
+        let channel = session.handle.channel_open_session().await?;
+        if let Some(Ok(env)) = ws
+            .env
+            .as_ref()
+            .map(|env| serde_json::from_str::<Vec<(String, String)>>(env))
+        {
+            for (name, value) in env {
+                let _ = channel.set_env(false, name, value).await;
+            }
+        }
+        channel
+            .request_pty(false, "xterm-256color", cols, rows, 0, 0, &[])
+            .await?;
+        channel.request_shell(false).await?;
+        Ok(Self {
+            _session: session,
+            channel,
+        })
+    }
+
+    pub async fn write(&self, data: &[u8]) -> Result<()> {
+        self.channel.data(data).await?;
+        Ok(())
+    }
+
+    pub async fn resize(&self, cols: u32, rows: u32) -> Result<()> {
+        self.channel.window_change(cols, rows, 0, 0).await?;
+        Ok(())
+    }
+
+    /// The next output from the shell, or None when the shell has exited.
+    pub async fn read(&mut self) -> Option<Vec<u8>> {
+        loop {
+            match self.channel.wait().await? {
+                ChannelMsg::Data { data } | ChannelMsg::ExtendedData { data, .. } => {
+                    return Some(data.to_vec())
+                }
+                ChannelMsg::Eof | ChannelMsg::Close => return None,
+                _ => {}
+            }
+        }
+    }
+
+    pub async fn close(&self) {
+        let _ = self.channel.close().await;
+    }
+}
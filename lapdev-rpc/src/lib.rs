@@ -13,9 +13,9 @@ use futures::{
     Sink, SinkExt, Stream, StreamExt, TryFutureExt, TryStreamExt,
 };
 use lapdev_common::{
-    BuildTarget, ContainerInfo, CreateWorkspaceRequest, DeleteWorkspaceRequest, PrebuildInfo,
-    RepoBuildInfo, RepoBuildOutput, RepoContent, RunningWorkspace, StartWorkspaceRequest,
-    StopWorkspaceRequest,
+    BuildTarget, ContainerInfo, CreateWorkspaceRequest, DeleteWorkspaceRequest, GitCredential,
+    GitCredentialRequest, PrebuildInfo, RepoBuildInfo, RepoBuildOutput, RepoContent,
+    RunningWorkspace, StartWorkspaceRequest, StopWorkspaceRequest,
 };
 use serde::{Deserialize, Serialize};
 use tarpc::transport::channel::UnboundedChannel;
@@ -112,6 +112,20 @@ pub trait ConductorService {
     async fn update_build_repo_stderr(target: BuildTarget, line: String);
 
     async fn running_workspaces() -> Result<Vec<RunningWorkspace>, ApiError>;
+
+    /// The full list of (port, host_port) pairs that the workspace host
+    /// currently forwards for ports detected inside the workspace.
+    async fn update_workspace_ports(
+        workspace_id: Uuid,
+        ports: Vec<(u16, u16)>,
+    ) -> Result<(), ApiError>;
+
+    /// The credential for the git credential helper inside the workspace,
+    /// which is only given for the repository of the workspace.
+    async fn git_credential(
+        workspace_id: Uuid,
+        req: GitCredentialRequest,
+    ) -> Result<GitCredential, ApiError>;
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -133,6 +147,11 @@ pub trait WorkspaceService {
 
     async fn stop_workspace(req: StopWorkspaceRequest) -> Result<(), ApiError>;
 
+    /// Forward a port of a running workspace through the guest agent,
+    /// in addition to the ports that are detected automatically.
+    /// The forward is reported back with `update_workspace_ports`.
+    async fn expose_workspace_port(workspace_id: Uuid, port: u16) -> Result<(), ApiError>;
+
     async fn transfer_repo(info: RepoBuildInfo, repo: RepoContent) -> Result<(), ApiError>;
 
     async fn unarchive_repo(info: RepoBuildInfo) -> Result<(), ApiError>;
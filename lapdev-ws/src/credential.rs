@@ -0,0 +1,56 @@
+use anyhow::{anyhow, Result};
+use lapdev_common::{GitCredential, GitCredentialRequest};
+use tarpc::context::current;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use uuid::Uuid;
+
+use crate::{port::GuestAgent, server::WorkspaceServer};
+
+impl WorkspaceServer {
+    /// Relay the requests of the git credential helper inside the workspace to the conductor.
+    /// It's a no-op if we're already relaying for the workspace.
+    pub async fn relay_workspace_git_credentials(&self, workspace_id: Uuid, agent: GuestAgent) {
+        if !self.credential_relays.lock().await.insert(workspace_id) {
+            return;
+        }
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server.run_credential_relay(workspace_id, &agent).await {
+                tracing::debug!("workspace {workspace_id} credential relay stopped: {e:#}");
+            }
+            server.credential_relays.lock().await.remove(&workspace_id);
+        });
+    }
+
+    async fn run_credential_relay(&self, workspace_id: Uuid, agent: &GuestAgent) -> Result<()> {
+        let stream = agent.connect().await?;
+        let (read, mut write) = stream.into_split();
+        write.write_all(b"credentials\n").await?;
+        let mut lines = BufReader::new(read).lines();
+        while let Some(line) = lines.next_line().await? {
+            let resp = match self.git_credential(workspace_id, &line).await {
+                Ok(credential) => serde_json::to_string(&credential)?,
+                Err(e) => {
+                    tracing::info!("workspace {workspace_id} git credential error: {e:#}");
+                    serde_json::json!({ "error": format!("{e:#}") }).to_string()
+                }
+            };
+            write.write_all(format!("{resp}\n").as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    async fn git_credential(&self, workspace_id: Uuid, line: &str) -> Result<GitCredential> {
+        let req: GitCredentialRequest = serde_json::from_str(line)
+            .map_err(|e| anyhow!("invalid credential request from guest agent: {e}"))?;
+        let rpc = { self.rpcs.read().await.first().cloned() };
+        let rpc = rpc.ok_or_else(|| anyhow!("don't have any conductor connections"))?;
+        let credential = rpc
+            .conductor_client
+            .git_credential(current(), workspace_id, req)
+            .await?
+            .map_err(|e| anyhow!("{e}"))?;
+        Ok(credential)
+    }
+}
@@ -1,2 +1,4 @@
+mod credential;
+mod port;
 pub mod server;
 mod service;
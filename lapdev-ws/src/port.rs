@@ -0,0 +1,383 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use lapdev_common::ContainerInfo;
+use lapdev_guest_agent::{LAPDEV_GUEST_AGENT_PORT, LAPDEV_GUEST_AGENT_SECRET};
+use lapdev_rpc::error::ApiError;
+use tarpc::context::current;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::{JoinHandle, JoinSet},
+};
+use uuid::Uuid;
+
+use crate::server::WorkspaceServer;
+
+struct PortForward {
+    host_port: u16,
+    // `None` for the ports podman already published
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for PortForward {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+/// How lapdev-ws reaches the guest agent in a workspace container
+#[derive(Clone)]
+pub struct GuestAgent {
+    /// The host port podman published the guest agent's control port on
+    port: u16,
+    secret: String,
+    /// The container ports podman already published, with their host ports
+    published: HashMap<u16, u16>,
+}
+
+impl GuestAgent {
+    /// The containers created before the guest agent had a secret don't have one.
+    pub fn from_container(info: &ContainerInfo) -> Option<Self> {
+        let published: HashMap<u16, u16> = info
+            .host_config
+            .port_bindings
+            .iter()
+            .filter_map(|(port, bindings)| {
+                let port = port.strip_suffix("/tcp")?.parse().ok()?;
+                let host_port = bindings.first()?.host_port.parse().ok()?;
+                Some((port, host_port))
+            })
+            .collect();
+        let port = *published.get(&LAPDEV_GUEST_AGENT_PORT)?;
+        let prefix = format!("{LAPDEV_GUEST_AGENT_SECRET}=");
+        let secret = info
+            .config
+            .env
+            .as_ref()?
+            .iter()
+            .find_map(|env| env.strip_prefix(&prefix))?
+            .to_string();
+        Some(Self {
+            port,
+            secret,
+            published,
+        })
+    }
+
+    /// Connects to the control port and authenticates with the container's secret
+    pub async fn connect(&self) -> Result<TcpStream> {
+        let mut stream = self.connect_retry().await?;
+        stream
+            .write_all(format!("{}\n", self.secret).as_bytes())
+            .await?;
+        Ok(stream)
+    }
+
+    async fn connect_retry(&self) -> Result<TcpStream> {
+        // the guest agent might not be listening yet if the container just started
+        let mut retries = 0;
+        loop {
+            match TcpStream::connect(("127.0.0.1", self.port)).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    retries += 1;
+                    if retries >= 30 {
+                        return Err(anyhow!("can't connect to guest agent: {e}"));
+                    }
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+impl WorkspaceServer {
+    /// Forward the port in addition to the detected ones,
+    /// it's only for the workspaces whose ports are being watched.
+    pub async fn expose_port(&self, workspace_id: Uuid, port: u16) -> Result<(), ApiError> {
+        if !self.port_watchers.lock().await.contains(&workspace_id) {
+            return Err(ApiError::InvalidRequest(
+                "The workspace isn't running".to_string(),
+            ));
+        }
+        self.exposed_ports
+            .lock()
+            .await
+            .entry(workspace_id)
+            .or_default()
+            .insert(port);
+        self.exposed_ports_changed.notify_waiters();
+        Ok(())
+    }
+
+    /// Watch the listening ports inside the workspace through the guest agent,
+    /// and forward each of them from a host port.
+    /// It's a no-op if we're already watching the workspace.
+    pub async fn watch_workspace_ports(&self, workspace_id: Uuid, agent: GuestAgent) {
+        if !self.port_watchers.lock().await.insert(workspace_id) {
+            return;
+        }
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server.run_port_watcher(workspace_id, &agent).await {
+                tracing::debug!("workspace {workspace_id} port watcher stopped: {e:#}");
+            }
+            server.port_watchers.lock().await.remove(&workspace_id);
+        });
+    }
+
+    async fn run_port_watcher(&self, workspace_id: Uuid, agent: &GuestAgent) -> Result<()> {
+        let mut stream = agent.connect().await?;
+        stream.write_all(b"ports\n").await?;
+        let mut lines = BufReader::new(stream).lines();
+
+        let mut forwards: HashMap<u16, PortForward> = HashMap::new();
+        let mut detected: Vec<u16> = Vec::new();
+        let mut synced = true;
+        let result = loop {
+            // we wake up periodically so that a failed sync to the conductor gets retried
+            let line = tokio::select! {
+                line = tokio::time::timeout(Duration::from_secs(10), lines.next_line()) => {
+                    match line {
+                        Ok(Ok(Some(line))) => Some(line),
+                        Ok(Ok(None)) => break Ok(()),
+                        Ok(Err(e)) => break Err(e.into()),
+                        Err(_) => None,
+                    }
+                }
+                _ = self.exposed_ports_changed.notified() => None,
+            };
+
+            if let Some(line) = line {
+                detected = match serde_json::from_str(&line) {
+                    Ok(ports) => ports,
+                    Err(e) => break Err(anyhow!("invalid ports from guest agent: {e}")),
+                };
+                synced = false;
+            }
+
+            let ports = ports_to_forward(
+                &detected,
+                self.exposed_ports.lock().await.get(&workspace_id),
+            );
+
+            forwards.retain(|port, _| {
+                // dropping the forward stops it
+                let keep = ports.contains(port);
+                synced &= keep;
+                keep
+            });
+            for port in ports {
+                if forwards.contains_key(&port) {
+                    continue;
+                }
+                if let Some(host_port) = agent.published.get(&port) {
+                    // podman already publishes the ports the image exposes
+                    forwards.insert(
+                        port,
+                        PortForward {
+                            host_port: *host_port,
+                            handle: None,
+                        },
+                    );
+                    synced = false;
+                    continue;
+                }
+                match forward_port(agent.clone(), &self.publish_bind, port).await {
+                    Ok(forward) => {
+                        tracing::info!(
+                            "workspace {workspace_id} port {port} forwarded on host port {}",
+                            forward.host_port
+                        );
+                        forwards.insert(port, forward);
+                        synced = false;
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            "workspace {workspace_id} forward port {port} error: {e:#}"
+                        );
+                    }
+                }
+            }
+
+            if !synced {
+                synced = self
+                    .sync_workspace_ports(workspace_id, &forwards)
+                    .await
+                    .is_ok();
+            }
+        };
+
+        forwards.clear();
+        // the exposed ports don't outlive the workspace container
+        self.exposed_ports.lock().await.remove(&workspace_id);
+        let _ = self
+            .sync_workspace_ports(workspace_id, &HashMap::new())
+            .await;
+
+        result
+    }
+
+    async fn sync_workspace_ports(
+        &self,
+        workspace_id: Uuid,
+        forwards: &HashMap<u16, PortForward>,
+    ) -> Result<()> {
+        let rpc = { self.rpcs.read().await.first().cloned() };
+        let rpc = rpc.ok_or_else(|| anyhow!("don't have any conductor connections"))?;
+        let ports = forwards
+            .iter()
+            .map(|(port, forward)| (*port, forward.host_port))
+            .collect();
+        rpc.conductor_client
+            .update_workspace_ports(current(), workspace_id, ports)
+            .await?
+            .map_err(|e| anyhow!("update workspace ports error: {e}"))?;
+        Ok(())
+    }
+}
+
+/// The detected ports, and the exposed ones that aren't listening yet
+fn ports_to_forward(detected: &[u16], exposed: Option<&HashSet<u16>>) -> Vec<u16> {
+    let mut ports = detected.to_vec();
+    if let Some(exposed) = exposed {
+        let mut exposed: Vec<u16> = exposed
+            .iter()
+            .filter(|p| !detected.contains(p))
+            .copied()
+            .collect();
+        exposed.sort_unstable();
+        ports.extend(exposed);
+    }
+    ports
+}
+
+async fn forward_port(agent: GuestAgent, bind: &str, port: u16) -> Result<PortForward> {
+    let listener = TcpListener::bind((bind, 0)).await?;
+    let host_port = listener.local_addr()?.port();
+    let handle = tokio::spawn(async move {
+        // the connections are aborted with the forward when it's dropped
+        let mut conns = JoinSet::new();
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let agent = agent.clone();
+            conns.spawn(async move {
+                let mut agent = agent.connect().await?;
+                agent
+                    .write_all(format!("forward {port}\n").as_bytes())
+                    .await?;
+                tokio::io::copy_bidirectional(&mut stream, &mut agent).await?;
+                Ok::<(), anyhow::Error>(())
+            });
+            // reap the finished ones so that the set doesn't grow forever
+            while conns.try_join_next().is_some() {}
+        }
+    });
+    Ok(PortForward {
+        host_port,
+        handle: Some(handle),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container_info(env: &[&str]) -> ContainerInfo {
+        serde_json::from_value(serde_json::json!({
+            "Config": {
+                "Hostname": "workspace",
+                "WorkingDir": "/workspaces/repo",
+                "Env": env,
+            },
+            "HostConfig": {
+                "PortBindings": {
+                    "22/tcp": [{ "HostIp": "", "HostPort": "40022" }],
+                    "30001/tcp": [{ "HostIp": "127.0.0.1", "HostPort": "40001" }],
+                    "8080/udp": [{ "HostIp": "", "HostPort": "40080" }],
+                },
+                "Binds": [],
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_guest_agent_from_container() {
+        let agent = GuestAgent::from_container(&container_info(&[
+            "PATH=/bin",
+            "LAPDEV_GUEST_AGENT_SECRET=abc",
+        ]))
+        .unwrap();
+        assert_eq!(agent.port, 40001);
+        assert_eq!(agent.secret, "abc");
+        assert_eq!(
+            agent.published,
+            [(22, 40022), (30001, 40001)].into_iter().collect()
+        );
+
+        // the containers created before the guest agent had a secret
+        assert!(GuestAgent::from_container(&container_info(&["PATH=/bin"])).is_none());
+    }
+
+    #[test]
+    fn test_ports_to_forward() {
+        assert_eq!(ports_to_forward(&[3000, 8080], None), vec![3000, 8080]);
+        let exposed: HashSet<u16> = [9000, 8080, 5000].into_iter().collect();
+        assert_eq!(
+            ports_to_forward(&[3000, 8080], Some(&exposed)),
+            vec![3000, 8080, 5000, 9000]
+        );
+        // the exposed ports are forwarded before anything listens on them
+        assert_eq!(
+            ports_to_forward(&[], Some(&exposed)),
+            vec![5000, 8080, 9000]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expose_port() {
+        let server = WorkspaceServer::default();
+        let ws_id = Uuid::new_v4();
+        assert!(server.expose_port(ws_id, 8080).await.is_err());
+        assert!(server.exposed_ports.lock().await.is_empty());
+
+        server.port_watchers.lock().await.insert(ws_id);
+        let changed = server.exposed_ports_changed.clone();
+        let notified = changed.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        server.expose_port(ws_id, 8080).await.unwrap();
+        server.expose_port(ws_id, 8080).await.unwrap();
+        assert_eq!(
+            server.exposed_ports.lock().await.get(&ws_id),
+            Some(&[8080].into_iter().collect())
+        );
+        // the port watcher is woken up to forward it
+        tokio::time::timeout(Duration::from_secs(1), notified)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_port_forward_aborts_on_drop() {
+        let agent = GuestAgent {
+            port: 0,
+            secret: "abc".to_string(),
+            published: HashMap::new(),
+        };
+        let forward = forward_port(agent, "127.0.0.1", 8080).await.unwrap();
+        let host_port = forward.host_port;
+        assert!(TcpStream::connect(("127.0.0.1", host_port)).await.is_ok());
+        drop(forward);
+        // the listener is dropped once the aborted task is cancelled
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(TcpStream::connect(("127.0.0.1", host_port)).await.is_err());
+    }
+}
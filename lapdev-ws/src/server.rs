@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     process::Stdio,
     sync::Arc,
@@ -18,7 +18,8 @@ use lapdev_common::{
     devcontainer::{
         DevContainerCmd, DevContainerConfig, DevContainerCwd, DevContainerLifeCycleCmd,
     },
-    BuildTarget, ContainerImageInfo, RepoBuildInfo, RepoBuildOutput, RepoComposeService,
+    BuildTarget, ContainerImageInfo, ContainerInfo, RepoBuildInfo, RepoBuildOutput,
+    RepoComposeService,
 };
 use lapdev_rpc::{
     error::ApiError, spawn_twoway, ConductorServiceClient, InterWorkspaceService, WorkspaceService,
@@ -33,11 +34,14 @@ use tarpc::{
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     process::Command,
-    sync::{Mutex, RwLock},
+    sync::{Mutex, Notify, RwLock},
 };
 use uuid::Uuid;
 
-use crate::service::{InterWorkspaceRpcService, WorkspaceRpcService};
+use crate::{
+    port::GuestAgent,
+    service::{InterWorkspaceRpcService, WorkspaceRpcService},
+};
 
 pub const LAPDEV_WS_VERSION: &str = env!("CARGO_PKG_VERSION");
 const INSTALL_SCRIPT: &[u8] = include_bytes!("../scripts/install_guest_agent.sh");
@@ -49,6 +53,8 @@ struct LapdevWsConfig {
     bind: Option<String>,
     ws_port: Option<u16>,
     inter_ws_port: Option<u16>,
+    /// The address the workspace ports are forwarded on, defaults to 0.0.0.0
+    publish_bind: Option<String>,
 }
 
 #[derive(Parser)]
@@ -63,11 +69,20 @@ struct Cli {
 #[derive(Clone)]
 pub struct WorkspaceServer {
     pub rpcs: Arc<RwLock<Vec<WorkspaceRpcService>>>,
+    pub port_watchers: Arc<Mutex<HashSet<Uuid>>>,
+    /// The workspaces we're relaying git credential requests for
+    pub credential_relays: Arc<Mutex<HashSet<Uuid>>>,
+    /// The ports users asked to forward in addition to the detected ones
+    pub exposed_ports: Arc<Mutex<HashMap<Uuid, HashSet<u16>>>>,
+    /// Wakes up the port watchers when the exposed ports change
+    pub exposed_ports_changed: Arc<Notify>,
+    /// The address the workspace ports are forwarded on
+    pub publish_bind: String,
 }
 
 impl Default for WorkspaceServer {
     fn default() -> Self {
-        Self::new()
+        Self::new("0.0.0.0")
     }
 }
 
@@ -84,15 +99,21 @@ pub async fn run() -> Result<()> {
     let bind = config.bind.as_deref().unwrap_or("0.0.0.0");
     let ws_port = config.ws_port.unwrap_or(6123);
     let inter_ws_port = config.inter_ws_port.unwrap_or(6122);
-    WorkspaceServer::new()
+    let publish_bind = config.publish_bind.as_deref().unwrap_or("0.0.0.0");
+    WorkspaceServer::new(publish_bind)
         .run(bind, ws_port, inter_ws_port)
         .await
 }
 
 impl WorkspaceServer {
-    fn new() -> Self {
+    fn new(publish_bind: &str) -> Self {
         Self {
             rpcs: Default::default(),
+            port_watchers: Default::default(),
+            credential_relays: Default::default(),
+            exposed_ports: Default::default(),
+            exposed_ports_changed: Default::default(),
+            publish_bind: publish_bind.to_string(),
         }
     }
 
@@ -186,6 +207,25 @@ impl WorkspaceServer {
         }
 
         for workspace in &workspaces {
+            // pick up the port watching and credential relaying again for workspaces
+            // that were started before lapdev-ws was restarted
+            if !self.port_watchers.lock().await.contains(&workspace.id)
+                || !self.credential_relays.lock().await.contains(&workspace.id)
+            {
+                if let Ok(info) = self
+                    .container_info(&workspace.osuser, &workspace.name)
+                    .await
+                {
+                    if let Some(agent) = GuestAgent::from_container(&info) {
+                        self.relay_workspace_git_credentials(workspace.id, agent.clone())
+                            .await;
+                        self.watch_workspace_ports(workspace.id, agent).await;
+                    }
+                }
+            }
+
+            // the http proxy records the ide activity itself, the open ide connections
+            // are still checked for the ones that don't go through it
             let mut active = false;
             if let Some(port) = workspace.ssh_port {
                 active |= active_ports.contains_key(&port);
@@ -744,6 +784,45 @@ impl WorkspaceServer {
         format!("/home/{osuser}/workspaces/{workspace_name}")
     }
 
+    /// The files that are mounted in the workspace container on their own,
+    /// outside of the workspace folder which other workspaces might share.
+    pub fn workspace_secrets_folder(&self, osuser: &str, workspace_name: &str) -> String {
+        format!("/home/{osuser}/.lapdev/workspaces/{workspace_name}")
+    }
+
+    /// Writes the private ssh host key of the workspace to a file only the os user,
+    /// i.e. root in the rootless container, can read, and returns its path.
+    pub async fn write_workspace_host_key(
+        &self,
+        osuser: &str,
+        workspace_name: &str,
+        host_key: &str,
+    ) -> Result<String> {
+        let folder = self.workspace_secrets_folder(osuser, workspace_name);
+        tokio::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&folder)
+            .await?;
+        let path = format!("{folder}/ssh_host_key");
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .await?;
+        file.write_all(host_key.as_bytes()).await?;
+        file.flush().await?;
+        tokio::process::Command::new("chown")
+            .arg("-R")
+            .arg(format!("{osuser}:{osuser}"))
+            .arg(format!("/home/{osuser}/.lapdev"))
+            .output()
+            .await?;
+        Ok(path)
+    }
+
     pub fn prebuild_folder(&self, osuser: &str, prebuild_id: Uuid) -> String {
         format!("/home/{osuser}/workspaces/{prebuild_id}")
     }
@@ -845,6 +924,26 @@ impl WorkspaceServer {
         stderr_log
     }
 
+    pub async fn container_info(&self, osuser: &str, name: &str) -> Result<ContainerInfo> {
+        let uid = self._os_user_uid(osuser).await?;
+        let socket = self.podman_socket(&uid);
+        let client = unix_client();
+        let url = Uri::new(&socket, &format!("/containers/{name}/json"));
+        let req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(url)
+            .body(Full::<Bytes>::new(Bytes::new()))?;
+        let resp = client.request(req).await?;
+        let status = resp.status();
+        let body = resp.collect().await?.to_bytes();
+        if status != 200 {
+            let err = String::from_utf8(body.to_vec())?;
+            return Err(anyhow!("get container info error: {err}"));
+        }
+        let info: ContainerInfo = serde_json::from_slice(&body)?;
+        Ok(info)
+    }
+
     pub async fn delete_image(&self, osuser: &str, image: &str) -> Result<()> {
         let uid = {
             let stdout = Command::new("id")
@@ -10,12 +10,15 @@ use docker_compose_types::AdvancedBuildStep;
 use http_body_util::{BodyExt, Full};
 use hyperlocal::Uri;
 use lapdev_common::{
-    BuildTarget, Container, ContainerInfo, CreateWorkspaceRequest, DeleteWorkspaceRequest,
-    NewContainer, NewContainerEndpointSettings, NewContainerHostConfig, NewContainerNetwork,
-    NewContainerNetworkingConfig, PrebuildInfo, RepoBuildInfo, RepoBuildOutput, RepoContent,
-    RepoContentPosition, StartWorkspaceRequest, StopWorkspaceRequest,
+    BuildTarget, Container, ContainerInfo, ContainerPortBinding, CreateWorkspaceRequest,
+    DeleteWorkspaceRequest, NewContainer, NewContainerEndpointSettings, NewContainerHostConfig,
+    NewContainerNetwork, NewContainerNetworkingConfig, PrebuildInfo, RepoBuildInfo,
+    RepoBuildOutput, RepoContent, RepoContentPosition, StartWorkspaceRequest, StopWorkspaceRequest,
+};
+use lapdev_guest_agent::{
+    LAPDEV_CMDS, LAPDEV_GUEST_AGENT_PORT, LAPDEV_GUEST_AGENT_SECRET, LAPDEV_IDE_CMDS,
+    LAPDEV_SSH_PUBLIC_KEY, LAPDEV_WORKSPACE_USER, SSH_HOST_KEY_PATH,
 };
-use lapdev_guest_agent::{LAPDEV_CMDS, LAPDEV_IDE_CMDS, LAPDEV_SSH_PUBLIC_KEY};
 use lapdev_rpc::{
     error::ApiError, ConductorServiceClient, InterWorkspaceService, InterWorkspaceServiceClient,
     WorkspaceService,
@@ -27,7 +30,10 @@ use tokio::{
 };
 use uuid::Uuid;
 
-use crate::server::{unix_client, WorkspaceServer, LAPDEV_WS_VERSION};
+use crate::{
+    port::GuestAgent,
+    server::{unix_client, WorkspaceServer, LAPDEV_WS_VERSION},
+};
 
 #[derive(Clone)]
 pub struct WorkspaceRpcService {
@@ -135,6 +141,12 @@ impl WorkspaceService for WorkspaceRpcService {
             &format!("/workspaces/{}", ws_req.repo_name),
         ])?;
 
+        // the private host key isn't in the env, where podman inspect would show it
+        let host_key_path = self
+            .server
+            .write_workspace_host_key(&ws_req.osuser, &ws_req.workspace_name, &ws_req.ssh_host_key)
+            .await?;
+
         let url = Uri::new(
             socket,
             &format!("/containers/create?name={}", ws_req.workspace_name),
@@ -148,11 +160,16 @@ impl WorkspaceService for WorkspaceRpcService {
             format!("{LAPDEV_SSH_PUBLIC_KEY}={}", ws_req.ssh_public_key),
             format!("{LAPDEV_IDE_CMDS}={}", ide_cmds),
             format!("{LAPDEV_CMDS}={}", cmds),
+            format!("{LAPDEV_GUEST_AGENT_SECRET}={}", Uuid::new_v4().simple()),
         ]);
+        if let Some(user) = image_info.config.user.as_ref().filter(|u| !u.is_empty()) {
+            env.push(format!("{LAPDEV_WORKSPACE_USER}={user}"));
+        }
 
         let mut exposed_ports = image_info.config.exposed_ports.unwrap_or_default();
         exposed_ports.insert("22/tcp".to_string(), HashMap::new());
         exposed_ports.insert("30000/tcp".to_string(), HashMap::new());
+        exposed_ports.insert(format!("{LAPDEV_GUEST_AGENT_PORT}/tcp"), HashMap::new());
         let body = serde_json::to_string(&NewContainer {
             hostname: ws_req.workspace_name.clone(),
             user: "root".to_string(),
@@ -163,11 +180,14 @@ impl WorkspaceService for WorkspaceRpcService {
             working_dir: format!("/workspaces/{}", ws_req.repo_name),
             host_config: NewContainerHostConfig {
                 publish_all_ports: true,
-                binds: vec![format!(
-                    "{}:/workspaces",
-                    self.server
-                        .workspace_folder(&ws_req.osuser, &ws_req.volume_name,)
-                )],
+                binds: vec![
+                    format!(
+                        "{}:/workspaces",
+                        self.server
+                            .workspace_folder(&ws_req.osuser, &ws_req.volume_name,)
+                    ),
+                    format!("{host_key_path}:{SSH_HOST_KEY_PATH}:ro"),
+                ],
                 cpuset_cpus: ws_req
                     .cpus
                     .into_iter()
@@ -179,6 +199,16 @@ impl WorkspaceService for WorkspaceRpcService {
                 cap_add: vec!["NET_RAW".to_string()],
                 security_opt: Some(vec!["label=disable".to_string()]),
                 storage_opt: None,
+                // the guest agent's control port is only for lapdev-ws
+                port_bindings: [(
+                    format!("{LAPDEV_GUEST_AGENT_PORT}/tcp"),
+                    vec![ContainerPortBinding {
+                        host_ip: "127.0.0.1".to_string(),
+                        host_port: "".to_string(),
+                    }],
+                )]
+                .into_iter()
+                .collect(),
             },
             networking_config: NewContainerNetworkingConfig {
                 endpoints_config: if let Some(service) = ws_req.service.as_ref() {
@@ -241,22 +271,16 @@ impl WorkspaceService for WorkspaceRpcService {
             return Err(anyhow!("start container error: {err}").into());
         }
 
-        let url = Uri::new(
-            socket,
-            &format!("/containers/{}/json", ws_req.workspace_name),
-        );
-        let req = hyper::Request::builder()
-            .method(hyper::Method::GET)
-            .uri(url)
-            .body(Full::<Bytes>::new(Bytes::new()))?;
-        let resp = client.request(req).await?;
-        let status = resp.status();
-        let body = resp.collect().await?.to_bytes();
-        if status != 200 {
-            let err = String::from_utf8(body.to_vec())?;
-            return Err(anyhow!("get container info error: {err}").into());
+        let info = self
+            .server
+            .container_info(&ws_req.osuser, &ws_req.workspace_name)
+            .await?;
+        if let Some(agent) = GuestAgent::from_container(&info) {
+            self.server
+                .relay_workspace_git_credentials(ws_req.id, agent.clone())
+                .await;
+            self.server.watch_workspace_ports(ws_req.id, agent).await;
         }
-        let info: ContainerInfo = serde_json::from_slice(&body)?;
         Ok(info)
     }
 
@@ -304,6 +328,10 @@ impl WorkspaceService for WorkspaceRpcService {
                 .server
                 .workspace_folder(&req.osuser, &req.workspace_name);
             let _ = tokio::fs::remove_dir_all(&folder).await;
+            let secrets_folder = self
+                .server
+                .workspace_secrets_folder(&req.osuser, &req.workspace_name);
+            let _ = tokio::fs::remove_dir_all(&secrets_folder).await;
         }
 
         for image in req.images {
@@ -321,6 +349,15 @@ impl WorkspaceService for WorkspaceRpcService {
         Ok(())
     }
 
+    async fn expose_workspace_port(
+        self,
+        _context: context::Context,
+        workspace_id: Uuid,
+        port: u16,
+    ) -> Result<(), ApiError> {
+        self.server.expose_port(workspace_id, port).await
+    }
+
     async fn stop_workspace(
         self,
         _context: context::Context,